@@ -0,0 +1,136 @@
+use anyhow::Result;
+
+use geom::Time;
+use sim::{Event, EventLogReader, TripID};
+
+/// Prints events from a log matching all of the filters as newline-delimited JSON.
+pub fn run(
+    input: String,
+    event_types: Vec<String>,
+    start_time: Option<String>,
+    end_time: Option<String>,
+    trip: Option<usize>,
+) -> Result<()> {
+    let filter = Filter {
+        event_types,
+        start_time: start_time.map(|x| Time::parse(&x)).transpose()?,
+        end_time: end_time.map(|x| Time::parse(&x)).transpose()?,
+        trip: trip.map(TripID),
+    };
+    filter.apply(EventLogReader::open(&input)?, |time, ev| {
+        println!("{}", abstutil::to_json_terse(&(time, ev)));
+    })
+}
+
+struct Filter {
+    event_types: Vec<String>,
+    start_time: Option<Time>,
+    end_time: Option<Time>,
+    trip: Option<TripID>,
+}
+
+impl Filter {
+    /// Calls `output` with every event matching the filter, in order.
+    fn apply<I: Iterator<Item = Result<(Time, Event)>>, F: FnMut(Time, Event)>(
+        &self,
+        events: I,
+        mut output: F,
+    ) -> Result<()> {
+        for result in events {
+            let (time, ev) = result?;
+            if self.start_time.map(|t| time < t).unwrap_or(false) {
+                continue;
+            }
+            if self.end_time.map(|t| time > t).unwrap_or(false) {
+                // Events are written in order
+                break;
+            }
+            if !self.event_types.is_empty() && !self.event_types.iter().any(|x| x == ev.name()) {
+                continue;
+            }
+            if self.trip.is_some() && ev.trip() != self.trip {
+                continue;
+            }
+            output(time, ev);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geom::Duration;
+    use sim::TripMode;
+
+    use super::*;
+
+    fn filter(filter: Filter, events: &[(Time, Event)]) -> Vec<(Time, Event)> {
+        let mut results = Vec::new();
+        filter
+            .apply(events.iter().cloned().map(Ok), |time, ev| {
+                results.push((time, ev))
+            })
+            .unwrap();
+        results
+    }
+
+    #[test]
+    fn test_filter_by_name_and_trip() {
+        let t = |mins| Time::START_OF_DAY + Duration::minutes(mins);
+        let events = vec![
+            (t(1), Event::TripCancelled(TripID(1), TripMode::Drive)),
+            (
+                t(2),
+                Event::TripFinished {
+                    trip: TripID(2),
+                    mode: TripMode::Bike,
+                    total_time: Duration::minutes(2),
+                    blocked_time: Duration::ZERO,
+                },
+            ),
+            (t(3), Event::TripCancelled(TripID(2), TripMode::Bike)),
+            (t(4), Event::MicromobilityRebalanced(3)),
+        ];
+        let everything = || Filter {
+            event_types: Vec::new(),
+            start_time: None,
+            end_time: None,
+            trip: None,
+        };
+
+        assert_eq!(filter(everything(), &events), events);
+        assert_eq!(
+            filter(
+                Filter {
+                    event_types: vec!["TripCancelled".to_string()],
+                    ..everything()
+                },
+                &events
+            ),
+            vec![events[0].clone(), events[2].clone()]
+        );
+        // Events without a trip don't match a trip filter
+        assert_eq!(
+            filter(
+                Filter {
+                    trip: Some(TripID(2)),
+                    ..everything()
+                },
+                &events
+            ),
+            vec![events[1].clone(), events[2].clone()]
+        );
+        assert_eq!(
+            filter(
+                Filter {
+                    event_types: vec!["TripCancelled".to_string()],
+                    trip: Some(TripID(2)),
+                    start_time: Some(t(2)),
+                    end_time: Some(t(3)),
+                },
+                &events
+            ),
+            vec![events[2].clone()]
+        );
+    }
+}
@@ -6,12 +6,16 @@ extern crate log;
 
 mod augment_scenario;
 mod clip_osm;
+mod filter_events;
 mod generate_houses;
 mod geojson_to_osmosis;
 mod import_grid2demand;
 mod import_scenario;
 mod one_step_import;
 mod pick_geofabrik;
+mod run_ensemble;
+mod traffic_assignment;
+mod validate_edits;
 
 use anyhow::Result;
 use structopt::StructOpt;
@@ -70,6 +74,26 @@ enum Command {
         #[structopt(long)]
         out_path: String,
     },
+    /// Reads an event log written by the simulation's `--event_log` option and prints matching
+    /// events as newline-delimited JSON.
+    FilterEvents {
+        /// The path to the event log
+        #[structopt()]
+        input: String,
+        /// Only print events of this type, like `TripFinished`. Can be repeated; if omitted, all
+        /// types are included.
+        #[structopt(long = "event-type")]
+        event_types: Vec<String>,
+        /// Skip events before this time, like `07:30:00`
+        #[structopt(long)]
+        start_time: Option<String>,
+        /// Stop after this time
+        #[structopt(long)]
+        end_time: Option<String>,
+        /// Only print events about this trip ID
+        #[structopt(long)]
+        trip: Option<usize>,
+    },
     /// Reads a GeoJSON file, extracts a polygon from every feature, and writes numbered files in
     /// the https://wiki.openstreetmap.org/wiki/Osmosis/Polygon_Filter_File_Format format as
     /// output.
@@ -111,6 +135,66 @@ enum Command {
         #[structopt(long)]
         output: String,
     },
+    /// Runs a scenario with many random seeds, optionally with and without some map edits, and
+    /// reports travel time changes with confidence intervals.
+    RunEnsemble {
+        /// The path to a scenario to simulate
+        #[structopt(long)]
+        scenario: String,
+        /// The path to map edits to compare against the baseline. If omitted, only the baseline
+        /// is simulated.
+        #[structopt(long)]
+        edits: Option<String>,
+        /// How many different random seeds to simulate
+        #[structopt(long, default_value = "10")]
+        num_seeds: usize,
+        /// The first seed to use. Seeds are consecutive from here.
+        #[structopt(long, default_value = "42")]
+        rng_seed: u64,
+        /// Write per-run and per-trip results as JSON to this path
+        #[structopt(long)]
+        output: Option<String>,
+    },
+    /// Repeatedly simulates a scenario, feeding observed travel times back into routing, until
+    /// drivers can't do much better by switching routes.
+    TrafficAssignment {
+        /// The path to a scenario to simulate
+        #[structopt(long)]
+        scenario: String,
+        /// Stop after this many iterations, even if the assignment hasn't converged
+        #[structopt(long, default_value = "10")]
+        max_iterations: usize,
+        /// After each iteration, this fraction of drivers switches to the best route given the
+        /// latest observed travel times
+        #[structopt(long, default_value = "0.1")]
+        replan_fraction: f64,
+        /// Stop once the relative gap drops below this
+        #[structopt(long, default_value = "0.01")]
+        gap_threshold: f64,
+        /// The random seed used for every iteration
+        #[structopt(long, default_value = "42")]
+        rng_seed: u64,
+        /// Write per-iteration stats and the final travel times as JSON to this path
+        #[structopt(long)]
+        output: Option<String>,
+        /// Write travel times by time of day from the last iteration to this path. Pass this to
+        /// other tools with `--travel_time_profiles` to route trips depending on when they leave.
+        #[structopt(long)]
+        travel_time_profiles_output: Option<String>,
+    },
+    /// Checks map edits for problems like disconnected sidewalks, broken bus routes, or invalid
+    /// traffic signals. Fails if there are any errors, so proposals can be checked automatically.
+    ValidateEdits {
+        /// The path to a map
+        #[structopt(long)]
+        map: String,
+        /// The path to map edits for that map
+        #[structopt(long)]
+        edits: String,
+        /// Write all problems as JSON to this path
+        #[structopt(long)]
+        output: Option<String>,
+    },
     /// Removes nonessential parts of a Map, for the bike network tool.
     MinifyMap {
         /// The path to a map to shrink
@@ -190,6 +274,13 @@ async fn main() -> Result<()> {
             clip_path,
             out_path,
         } => clip_osm::run(pbf_path, clip_path, out_path)?,
+        Command::FilterEvents {
+            input,
+            event_types,
+            start_time,
+            end_time,
+            trip,
+        } => filter_events::run(input, event_types, start_time, end_time, trip)?,
         Command::GeoJSONToOsmosis { input } => geojson_to_osmosis::run(input)?,
         Command::ImportGrid2Demand { input, map } => import_grid2demand::run(input, map)?,
         Command::ImportScenario {
@@ -198,6 +289,31 @@ async fn main() -> Result<()> {
             skip_problems,
         } => import_scenario::run(input, map, skip_problems),
         Command::ImportJSONMap { input, output } => import_json_map(input, output),
+        Command::RunEnsemble {
+            scenario,
+            edits,
+            num_seeds,
+            rng_seed,
+            output,
+        } => run_ensemble::run(scenario, edits, num_seeds, rng_seed, output)?,
+        Command::TrafficAssignment {
+            scenario,
+            max_iterations,
+            replan_fraction,
+            gap_threshold,
+            rng_seed,
+            output,
+            travel_time_profiles_output,
+        } => traffic_assignment::run(
+            scenario,
+            max_iterations,
+            replan_fraction,
+            gap_threshold,
+            rng_seed,
+            output,
+            travel_time_profiles_output,
+        )?,
+        Command::ValidateEdits { map, edits, output } => validate_edits::run(map, edits, output)?,
         Command::MinifyMap { map } => minify_map(map),
         Command::GenerateHouses {
             map,
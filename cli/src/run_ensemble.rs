@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+use abstutil::Timer;
+use sim::{Ensemble, SimFlags, SimOptions};
+
+pub fn run(
+    scenario: String,
+    edits: Option<String>,
+    num_seeds: usize,
+    rng_seed: u64,
+    output: Option<String>,
+) -> Result<()> {
+    let mut timer = Timer::new("run ensemble");
+    let ensemble = Ensemble {
+        flags: SimFlags {
+            load: scenario,
+            modifiers: Vec::new(),
+            rng_seed,
+            opts: SimOptions::new("ensemble"),
+            travel_time_profiles: None,
+        },
+        num_seeds,
+        edits,
+    };
+    let results = ensemble.run(&mut timer)?;
+    for line in results.describe() {
+        println!("{}", line);
+    }
+    if let Some(path) = output {
+        abstio::write_json(path.clone(), &results);
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
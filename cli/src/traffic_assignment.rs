@@ -0,0 +1,62 @@
+use anyhow::Result;
+
+use abstutil::{prettyprint_usize, Timer};
+use sim::{SimFlags, SimOptions, TrafficAssignment};
+
+pub fn run(
+    scenario: String,
+    max_iterations: usize,
+    replan_fraction: f64,
+    gap_threshold: f64,
+    rng_seed: u64,
+    output: Option<String>,
+    travel_time_profiles_output: Option<String>,
+) -> Result<()> {
+    let mut timer = Timer::new("traffic assignment");
+    let assignment = TrafficAssignment {
+        flags: SimFlags {
+            load: scenario,
+            modifiers: Vec::new(),
+            rng_seed,
+            opts: SimOptions::new("traffic assignment"),
+            travel_time_profiles: None,
+        },
+        max_iterations,
+        replan_fraction,
+        gap_threshold,
+    };
+    let results = assignment.run(&mut timer)?;
+    for iter in &results.iterations {
+        println!(
+            "Iteration {}: relative gap {}, {} trips finished ({} cancelled), {:.0}s total trip \
+             time",
+            iter.iteration,
+            iter.relative_gap
+                .map(|x| format!("{:.4}", x))
+                .unwrap_or_else(|| "unknown".to_string()),
+            prettyprint_usize(iter.finished_trips),
+            prettyprint_usize(iter.cancelled_trips),
+            iter.total_trip_time_seconds
+        );
+    }
+    if results.converged {
+        println!("Converged after {} iterations", results.iterations.len());
+    } else {
+        println!(
+            "Didn't converge to a relative gap under {} after {} iterations",
+            gap_threshold,
+            results.iterations.len()
+        );
+    }
+    if let Some(path) = output {
+        abstio::write_json(path.clone(), &results);
+        println!("Wrote {}", path);
+    }
+    if let (Some(path), Some(profiles)) =
+        (travel_time_profiles_output, &results.travel_time_profiles)
+    {
+        abstio::write_json(path.clone(), profiles);
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use anyhow::{bail, Result};
+
+use abstutil::Timer;
+use map_model::{Map, MapEdits};
+
+pub fn run(map: String, edits: String, output: Option<String>) -> Result<()> {
+    let mut timer = Timer::new("validate edits");
+    let mut map = Map::load_synchronously(map, &mut timer);
+    let edits = MapEdits::load_from_file(&map, edits, &mut timer)?;
+    let report = map.validate_edits(edits, &mut timer);
+
+    for problem in &report.problems {
+        println!("{}", problem.describe());
+    }
+    if let Some(path) = output {
+        abstio::write_json(path.clone(), &report);
+        println!("Wrote {}", path);
+    }
+    let errors = report.errors().count();
+    if errors > 0 {
+        bail!("The edits have {} errors", errors);
+    }
+    println!("No errors, {} warnings", report.warnings().count());
+    Ok(())
+}
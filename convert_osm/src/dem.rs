@@ -0,0 +1,146 @@
+//! Reads a digital elevation model (DEM) from a local GeoTIFF file. Only single-band rasters using
+//! WGS84 longitude/latitude are supported; use something like `gdalwarp -t_srs EPSG:4326` to
+//! reproject anything else first.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::Result;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+
+use geom::{Distance, LonLat};
+
+// See http://docs.opengeospatial.org/is/19-008r4/19-008r4.html
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GDAL_NODATA: u16 = 42113;
+
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+pub struct Dem {
+    width: usize,
+    height: usize,
+    /// Row-major, starting from the northwest corner
+    values: Vec<f64>,
+    nodata: Option<f64>,
+
+    /// The longitude and latitude of the center of the first pixel
+    origin: LonLat,
+    /// Degrees per pixel
+    scale_x: f64,
+    scale_y: f64,
+}
+
+impl Dem {
+    pub fn read(path: &str) -> Result<Dem> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+        let (width, height) = decoder.dimensions()?;
+        let (width, height) = (width as usize, height as usize);
+
+        let scale = decoder.get_tag_f64_vec(Tag::from_u16_exhaustive(MODEL_PIXEL_SCALE))?;
+        let tiepoint = decoder.get_tag_f64_vec(Tag::from_u16_exhaustive(MODEL_TIEPOINT))?;
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            bail!("{} has malformed georeferencing tags", path);
+        }
+
+        let mut pixel_is_point = false;
+        if let Ok(keys) = decoder.get_tag_u16_vec(Tag::from_u16_exhaustive(GEO_KEY_DIRECTORY)) {
+            // The header is 4 values, then each key is 4 values: ID, location, count, value
+            for key in keys.chunks(4).skip(1) {
+                if key.len() != 4 || key[1] != 0 {
+                    continue;
+                }
+                if key[0] == GT_MODEL_TYPE && key[3] == MODEL_TYPE_PROJECTED {
+                    bail!(
+                        "{} uses a projected coordinate system. Reproject it to WGS84 first.",
+                        path
+                    );
+                }
+                if key[0] == GT_RASTER_TYPE && key[3] == RASTER_PIXEL_IS_POINT {
+                    pixel_is_point = true;
+                }
+            }
+        }
+        let nodata = decoder
+            .get_tag_ascii_string(Tag::from_u16_exhaustive(GDAL_NODATA))
+            .ok()
+            .and_then(|x| x.trim_end_matches('\0').trim().parse::<f64>().ok());
+
+        let values: Vec<f64> = match decoder.read_image()? {
+            DecodingResult::U8(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::U16(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::I16(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::U32(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::I32(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::F32(x) => x.into_iter().map(|x| x as f64).collect(),
+            DecodingResult::F64(x) => x,
+            _ => bail!("{} has an unsupported sample format", path),
+        };
+        if values.len() != width * height {
+            bail!(
+                "{} has {} values for a {}x{} raster; only one band is supported",
+                path,
+                values.len(),
+                width,
+                height
+            );
+        }
+
+        // The tiepoint maps the raster position (I, J) to the model position (X, Y). Unless the
+        // raster is marked otherwise, raster positions refer to the corner of a pixel.
+        let (scale_x, scale_y) = (scale[0], scale[1]);
+        let offset = if pixel_is_point { 0.0 } else { 0.5 };
+        let origin = LonLat::new(
+            tiepoint[3] + (offset - tiepoint[0]) * scale_x,
+            tiepoint[4] - (offset - tiepoint[1]) * scale_y,
+        );
+
+        Ok(Dem {
+            width,
+            height,
+            values,
+            nodata,
+            origin,
+            scale_x,
+            scale_y,
+        })
+    }
+
+    /// Bilinearly interpolates between the nearest pixels. Returns `None` outside of the raster or
+    /// where there's no data.
+    pub fn elevation_at(&self, gps: LonLat) -> Option<Distance> {
+        let x = (gps.x() - self.origin.x()) / self.scale_x;
+        let y = (self.origin.y() - gps.y()) / self.scale_y;
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (col, row) = (x.floor() as usize, y.floor() as usize);
+        if col >= self.width || row >= self.height {
+            return None;
+        }
+        // At the last row or column, just repeat the edge
+        let col2 = (col + 1).min(self.width - 1);
+        let row2 = (row + 1).min(self.height - 1);
+        let (dx, dy) = (x - col as f64, y - row as f64);
+
+        let mut value = 0.0;
+        for (c, r, weight) in [
+            (col, row, (1.0 - dx) * (1.0 - dy)),
+            (col2, row, dx * (1.0 - dy)),
+            (col, row2, (1.0 - dx) * dy),
+            (col2, row2, dx * dy),
+        ] {
+            let sample = self.values[r * self.width + c];
+            if Some(sample) == self.nodata || !sample.is_finite() {
+                return None;
+            }
+            value += weight * sample;
+        }
+        Some(Distance::meters(value))
+    }
+}
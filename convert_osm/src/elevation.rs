@@ -7,6 +7,83 @@ use anyhow::Result;
 use geom::{Distance, PolyLine};
 use map_model::raw::{OriginalRoad, RawMap};
 
+use crate::dem::Dem;
+
+/// Smaller step size gives more detail, but is slower.
+const STEP_SIZE: Distance = Distance::const_meters(5.0);
+
+/// Samples elevation along every road from a local GeoTIFF DEM, producing a grade for every few
+/// meters of each road.
+pub fn add_data_from_dem(map: &mut RawMap, path: &str) -> Result<()> {
+    let dem = Dem::read(path)?;
+
+    for (id, i) in &mut map.intersections {
+        match dem.elevation_at(i.point.to_gps(&map.gps_bounds)) {
+            Some(elevation) => {
+                i.elevation = elevation;
+            }
+            None => {
+                warn!("No elevation data for {}", id);
+            }
+        }
+    }
+
+    let mut missing = 0;
+    for (id, road) in &mut map.roads {
+        // TODO Handle cul-de-sacs
+        let pl = match PolyLine::new(road.center_points.clone()) {
+            Ok(pl) => pl,
+            Err(_) => continue,
+        };
+        let mut dists = Vec::new();
+        let mut dist = Distance::ZERO;
+        while dist < pl.length() {
+            dists.push(dist);
+            dist += STEP_SIZE;
+        }
+        dists.push(pl.length());
+        let pts: Vec<_> = dists.iter().map(|d| pl.must_dist_along(*d).0).collect();
+        let elevations: Option<Vec<Distance>> = map
+            .gps_bounds
+            .convert_back(&pts)
+            .into_iter()
+            .map(|gps| dem.elevation_at(gps))
+            .collect();
+        let elevations = match elevations {
+            Some(x) => x,
+            None => {
+                missing += 1;
+                continue;
+            }
+        };
+
+        road.grades.clear();
+        for (pair_dist, pair_elevation) in dists.windows(2).zip(elevations.windows(2)) {
+            let run = pair_dist[1] - pair_dist[0];
+            if run == Distance::ZERO {
+                continue;
+            }
+            road.grades
+                .push((run, (pair_elevation[1] - pair_elevation[0]) / run));
+        }
+        road.percent_incline = (elevations[elevations.len() - 1] - elevations[0]) / pl.length();
+        // Same sanity check as for the other data source
+        if road.percent_incline.abs() > 0.3 {
+            error!(
+                "{} is unexpectedly steep! Incline is {}%",
+                id,
+                road.percent_incline * 100.0
+            );
+        }
+    }
+    if missing > 0 {
+        warn!("{} roads aren't covered by {}", missing, path);
+    }
+
+    Ok(())
+}
+
+/// Looks up elevation at intersections with an external tool that runs through Docker.
 pub fn add_data(map: &mut RawMap) -> Result<()> {
     // TODO It'd be nice to include more timing breakdown here, but if we bail out early,
     // it's tedious to call timer.stop().
@@ -74,9 +151,9 @@ fn generate_input(map: &RawMap) -> Result<Vec<OriginalRoad>> {
         // TODO Handle cul-de-sacs
         if let Ok(pl) = PolyLine::new(r.center_points.clone()) {
             ids.push(*id);
-            // Sample points along the road. Smaller step size gives more detail, but is slower.
+            // Sample points along the road.
             let mut pts = Vec::new();
-            for (pt, _) in pl.step_along(Distance::meters(5.0), Distance::ZERO) {
+            for (pt, _) in pl.step_along(STEP_SIZE, Distance::ZERO) {
                 pts.push(pt);
             }
             // Always ask for the intersection
@@ -107,6 +107,7 @@ pub fn extract_osm(map: &mut RawMap, opts: &Options, timer: &mut Timer) -> OsmEx
                     turn_restrictions: Vec::new(),
                     complicated_turn_restrictions: Vec::new(),
                     percent_incline: 0.0,
+                    grades: Vec::new(),
                 },
             ));
             continue;
@@ -13,6 +13,7 @@ use map_model::{osm, raw, Amenity, MapConfig};
 use serde::{Deserialize, Serialize};
 
 mod clip;
+mod dem;
 mod elevation;
 mod extract;
 pub mod osm_geom;
@@ -36,6 +37,10 @@ pub struct Options {
     pub include_railroads: bool,
     /// If provided, read polygons from this GeoJSON file and add them to the RawMap as buildings.
     pub extra_buildings: Option<String>,
+    /// If provided, sample elevation along roads from this GeoTIFF DEM, which must use WGS84
+    /// longitude/latitude. Otherwise, elevation is only looked up at intersections using an
+    /// external Docker-based tool.
+    pub elevation: Option<String>,
     /// Only include highways and arterials. This may make sense for some region-wide maps for
     /// particular use cases.
     pub skip_local_roads: bool,
@@ -114,7 +119,12 @@ pub fn convert(opts: Options, timer: &mut abstutil::Timer) -> RawMap {
 
     // TODO Make this bail out on failure, after the new dependencies are clearly explained.
     timer.start("add elevation data");
-    if let Err(err) = elevation::add_data(&mut map) {
+    let result = if let Some(ref path) = opts.elevation {
+        elevation::add_data_from_dem(&mut map, path)
+    } else {
+        elevation::add_data(&mut map)
+    };
+    if let Err(err) = result {
         error!("No elevation data: {}", err);
     }
     timer.stop("add elevation data");
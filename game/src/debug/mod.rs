@@ -671,7 +671,13 @@ impl ContextualActions for Actions {
                 Transition::Keep
             }
             (ID::Car(c), "forcibly delete this car") => {
-                app.primary.sim.delete_car(c, &app.primary.map);
+                if let Err(err) = app.primary.sim.delete_car(c, &app.primary.map) {
+                    return Transition::Push(PopupMsg::new_state(
+                        ctx,
+                        "Error",
+                        vec![err.to_string()],
+                    ));
+                }
                 app.primary
                     .sim
                     .tiny_step(&app.primary.map, &mut app.primary.sim_cb);
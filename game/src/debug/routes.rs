@@ -220,6 +220,18 @@ fn params_to_controls(ctx: &mut EventCtx, mode: TripMode, params: &RoutingParams
             ),
         ]));
     }
+    if mode == TripMode::Drive {
+        rows.push(Widget::row(vec![
+            "Value of time ($/hour):".text_widget(ctx).margin_right(20),
+            Spinner::f64_widget(
+                ctx,
+                "value_of_time",
+                (1.0, 200.0),
+                params.value_of_time,
+                1.0,
+            ),
+        ]));
+    }
     if mode == TripMode::Bike {
         rows.push(Widget::row(vec![
             "Bike lane penalty:".text_widget(ctx).margin_right(20),
@@ -252,7 +264,7 @@ fn params_to_controls(ctx: &mut EventCtx, mode: TripMode, params: &RoutingParams
             ),
         ]));
         rows.push(Widget::row(vec![
-            "Avoid steep inclines (>= 8%):"
+            "Avoid steep inclines (per 8% uphill):"
                 .text_widget(ctx)
                 .margin_right(20),
             Spinner::f64_widget(
@@ -281,6 +293,7 @@ fn controls_to_params(panel: &Panel) -> (TripMode, RoutingParams) {
     let mut params = RoutingParams::default();
     if !panel.is_button_enabled("cars") {
         params.unprotected_turn_penalty = panel.spinner("unprotected_turn_penalty");
+        params.value_of_time = panel.spinner::<RoundedF64>("value_of_time").0;
         return (TripMode::Drive, params);
     }
     if !panel.is_button_enabled("pedestrians") {
@@ -1,5 +1,5 @@
 use abstutil::Tags;
-use map_model::{Direction, EditRoad, LaneSpec, LaneType};
+use map_model::{Direction, EditRoad, LaneChange, LaneSpec, LaneType};
 
 /// Returns the index where the new lane was inserted
 pub fn add_new_lane(road: &mut EditRoad, lt: LaneType, osm_tags: &Tags) -> usize {
@@ -91,6 +91,8 @@ pub fn add_new_lane(road: &mut EditRoad, lt: LaneType, osm_tags: &Tags) -> usize
             lt,
             dir,
             width: LaneSpec::typical_lane_widths(lt, osm_tags)[0].0,
+            allowed_turns: None,
+            lane_change: LaneChange::default(),
         },
     );
     idx
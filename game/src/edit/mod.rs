@@ -6,7 +6,7 @@ use map_gui::options::OptionsPanel;
 use map_gui::render::DrawMap;
 use map_gui::tools::{grey_out_map, ChooseSomething, ColorLegend, PopupMsg};
 use map_gui::ID;
-use map_model::{EditCmd, IntersectionID, LaneID, MapEdits};
+use map_model::{EditCmd, IntersectionID, LaneID, MapEdits, ParkingRegulationTarget};
 use widgetry::mapspace::ToggleZoomed;
 use widgetry::{
     lctrl, Choice, Color, ControlState, EventCtx, GfxCtx, HorizontalAlignment, Image, Key, Line,
@@ -82,9 +82,10 @@ impl EditMode {
         }
 
         ctx.loading_screen("apply edits", move |ctx, mut timer| {
+            // Don't make the player wait for contraction hierarchies to be re-prepared
             app.primary
                 .map
-                .recalculate_pathfinding_after_edits(&mut timer);
+                .recalculate_pathfinding_after_edits_in_background(&mut timer);
             if GameplayMode::FixTrafficSignals == self.mode {
                 app.primary.sim = old_sim;
                 app.primary.dirty_from_edits = true;
@@ -92,7 +93,12 @@ impl EditMode {
                     .sim
                     .handle_live_edited_traffic_signals(&app.primary.map);
                 Transition::Pop
-            } else if app.primary.current_flags.live_map_edits {
+            } else if app.primary.current_flags.live_map_edits
+                // The simulation's per-lane and per-intersection state can't handle roads
+                // appearing or disappearing, so structural edits always reset.
+                && !app.primary.map.get_edits().changes_topology()
+                && !self.orig_edits.changes_topology()
+            {
                 app.primary.sim = old_sim;
                 app.primary.dirty_from_edits = true;
                 app.primary
@@ -711,6 +717,9 @@ pub fn apply_map_edits(ctx: &mut EventCtx, app: &mut App, edits: MapEdits) {
         let effects = app.primary.map.must_apply_edits(edits, timer);
         timer.stop("edit map");
 
+        // Undoing structural edits may remove roads and intersections
+        app.primary.draw_map.forget_removed_objects(&app.primary.map);
+
         if !effects.changed_roads.is_empty() || !effects.changed_intersections.is_empty() {
             app.primary
                 .draw_map
@@ -883,9 +892,17 @@ fn make_changelist(ctx: &mut EventCtx, app: &App) -> Panel {
 // TODO Ideally a Tab.
 fn cmd_to_id(cmd: &EditCmd) -> Option<ID> {
     match cmd {
-        EditCmd::ChangeRoad { r, .. } => Some(ID::Road(*r)),
+        EditCmd::ChangeRoad { r, .. }
+        | EditCmd::AddRoad { r, .. }
+        | EditCmd::SplitRoad { r, .. } => Some(ID::Road(*r)),
+        EditCmd::DeleteRoad { .. } => None,
         EditCmd::ChangeIntersection { i, .. } => Some(ID::Intersection(*i)),
         EditCmd::ChangeRouteSchedule { .. } => None,
+        EditCmd::ChangeParkingRegulation { target, .. } => Some(match target {
+            ParkingRegulationTarget::Road(r) => ID::Road(*r),
+            ParkingRegulationTarget::Lot(pl) => ID::ParkingLot(*pl),
+            ParkingRegulationTarget::Garage(b) => ID::Building(*b),
+        }),
     }
 }
 
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use geom::{Bounds, CornerRadii, Distance, Polygon, Pt2D, UnitFmt};
 use map_gui::render::{Renderable, OUTLINE_THICKNESS};
@@ -6,11 +6,13 @@ use map_gui::tools::PopupMsg;
 use map_gui::ID;
 use map_model::{
     BufferType, Direction, EditCmd, EditRoad, LaneID, LaneSpec, LaneType, MapEdits, Road, RoadID,
+    TurnType,
 };
 use widgetry::{
     lctrl, Choice, Color, ControlState, DragDrop, Drawable, EdgeInsets, EventCtx, GeomBatch,
     GeomBatchStack, GfxCtx, HorizontalAlignment, Image, Key, Line, Outcome, Panel, PersistentSplit,
-    Spinner, StackAxis, State, Text, TextExt, VerticalAlignment, Widget, DEFAULT_CORNER_RADIUS,
+    Spinner, StackAxis, State, Text, TextExt, Toggle, VerticalAlignment, Widget,
+    DEFAULT_CORNER_RADIUS,
 };
 
 use crate::app::{App, Transition};
@@ -19,6 +21,13 @@ use crate::edit::heuristics::add_new_lane;
 use crate::edit::zones::ZoneEditor;
 use crate::edit::{apply_map_edits, can_edit_lane, speed_limit_choices};
 
+const TURN_TYPES: [(&str, TurnType); 4] = [
+    ("allow left turns", TurnType::Left),
+    ("allow straight", TurnType::Straight),
+    ("allow right turns", TurnType::Right),
+    ("allow U-turns", TurnType::UTurn),
+];
+
 pub struct RoadEditor {
     r: RoadID,
     selected_lane: Option<LaneID>,
@@ -284,6 +293,8 @@ impl State<App> for RoadEditor {
                 } else if x == "flip direction" {
                     return self.modify_current_lane(ctx, app, Some(0), |new, idx| {
                         new.lanes_ltr[idx].dir = new.lanes_ltr[idx].dir.opposite();
+                        // Turn markings apply to the other end of the road
+                        new.lanes_ltr[idx].allowed_turns = None;
                     });
                 } else if let Some(lt) = x.strip_prefix("change to ") {
                     let lt = if lt == "buffer" {
@@ -381,6 +392,23 @@ impl State<App> for RoadEditor {
                         new.lanes_ltr[idx].width = width;
                     });
                 }
+                "allow left turns" | "allow straight" | "allow right turns" | "allow U-turns" => {
+                    let mut allowed_turns = BTreeSet::new();
+                    for (label, turn_type) in TURN_TYPES {
+                        if self.main_panel.is_checked(label) {
+                            allowed_turns.insert(turn_type);
+                        }
+                    }
+                    // Everything allowed is the same as no restrictions
+                    let allowed_turns = if allowed_turns.len() == TURN_TYPES.len() {
+                        None
+                    } else {
+                        Some(allowed_turns)
+                    };
+                    return self.modify_current_lane(ctx, app, Some(0), |new, idx| {
+                        new.lanes_ltr[idx].allowed_turns = allowed_turns.clone();
+                    });
+                }
                 "lane cards" => {
                     // hovering index changed
                     panels_need_recalc = true;
@@ -833,6 +861,31 @@ fn make_main_panel(
                 ])
                 .section(ctx),
             ]),
+            if lane.is_driving() || lane.is_bus() {
+                Widget::row({
+                    let mut row = vec![Line("Turns at the end of the road")
+                        .secondary()
+                        .into_widget(ctx)
+                        .centered_vert()];
+                    for (label, turn_type) in TURN_TYPES {
+                        row.push(
+                            Toggle::checkbox(
+                                ctx,
+                                label,
+                                None,
+                                lane.allowed_turns
+                                    .as_ref()
+                                    .map(|set| set.contains(&turn_type))
+                                    .unwrap_or(true),
+                            )
+                            .centered_vert(),
+                        );
+                    }
+                    row
+                })
+            } else {
+                Widget::nothing()
+            },
         ])
     } else {
         Widget::nothing()
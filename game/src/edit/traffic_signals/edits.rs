@@ -2,6 +2,7 @@ use geom::Duration;
 use map_gui::tools::{ChooseSomething, FilePicker, PopupMsg};
 use map_model::{
     ControlStopSign, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, StageType,
+    TransitSignalPriority,
 };
 use widgetry::{
     Choice, DrawBaselayer, EventCtx, Key, Line, Panel, SimpleState, Spinner, State, Text, TextExt,
@@ -187,6 +188,17 @@ pub fn edit_entire_signal(
     let use_template = "use template";
     let all_walk = "add an all-walk stage at the end";
     let major_minor_timing = "use timing pattern for a major/minor intersection";
+    let enable_transit_priority = app
+        .primary
+        .map
+        .get_traffic_signal(i)
+        .transit_priority
+        .is_none();
+    let transit_priority = if enable_transit_priority {
+        "give buses and trains priority"
+    } else {
+        "stop giving buses and trains priority"
+    };
     let stop_sign = "convert to stop signs";
     let close = "close intersection for construction";
     let reset = "reset to default";
@@ -203,6 +215,7 @@ pub fn edit_entire_signal(
         choices.push(all_walk.to_string());
     }
     choices.push(major_minor_timing.to_string());
+    choices.push(transit_priority.to_string());
     // TODO Conflating stop signs and construction here
     if mode.can_edit_stop_signs() {
         choices.push(stop_sign.to_string());
@@ -285,6 +298,19 @@ pub fn edit_entire_signal(
                     }
                 }),
             )),
+            x if x == transit_priority => Transition::Multi(vec![
+                Transition::Pop,
+                Transition::ModifyState(Box::new(move |state, ctx, app| {
+                    let editor = state.downcast_mut::<TrafficSignalEditor>().unwrap();
+                    editor.add_new_edit(ctx, app, 0, |ts| {
+                        ts.transit_priority = if enable_transit_priority {
+                            Some(TransitSignalPriority::new())
+                        } else {
+                            None
+                        };
+                    });
+                })),
+            ]),
             x if x == stop_sign => {
                 original.apply(app);
 
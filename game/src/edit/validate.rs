@@ -1,8 +1,6 @@
-use std::collections::BTreeSet;
-
 use abstutil::Timer;
 use map_gui::tools::PopupMsg;
-use map_model::{connectivity, EditCmd, PathConstraints};
+use map_model::{EditCmd, EditRule, SidewalkConnectivityRule, VehicleConnectivityRule};
 use widgetry::{EventCtx, State};
 
 use crate::app::App;
@@ -16,40 +14,12 @@ pub fn check_sidewalk_connectivity(
     app: &mut App,
     cmd: EditCmd,
 ) -> Option<Box<dyn State<App>>> {
-    let orig_edits = app.primary.map.get_edits().clone();
-    let (_, disconnected_before) =
-        connectivity::find_scc(&app.primary.map, PathConstraints::Pedestrian);
-
-    let mut edits = orig_edits.clone();
-    edits.commands.push(cmd);
-    app.primary
-        .map
-        .try_apply_edits(edits, &mut Timer::throwaway());
-
-    let (_, disconnected_after) =
-        connectivity::find_scc(&app.primary.map, PathConstraints::Pedestrian);
-    app.primary
-        .map
-        .must_apply_edits(orig_edits, &mut Timer::throwaway());
-
-    let newly_disconnected = disconnected_after
-        .difference(&disconnected_before)
-        .collect::<Vec<_>>();
-    if newly_disconnected.is_empty() {
-        return None;
-    }
-
-    // TODO Think through a proper UI for showing editing errors to the user and letting them
-    // understand the problem. We used to just draw problems in red and mostly cover it up with the
-    // popup.
-    Some(PopupMsg::new_state(
+    check(
         ctx,
-        "Error",
-        vec![format!(
-            "Can't close this intersection; {} sidewalks disconnected",
-            newly_disconnected.len()
-        )],
-    ))
+        app,
+        cmd,
+        vec![Box::new(SidewalkConnectivityRule::default())],
+    )
 }
 
 #[allow(unused)]
@@ -59,49 +29,40 @@ pub fn check_blackholes(
     app: &mut App,
     cmd: EditCmd,
 ) -> Option<Box<dyn State<App>>> {
-    let orig_edits = app.primary.map.get_edits().clone();
-    let mut driving_ok_originally = BTreeSet::new();
-    let mut biking_ok_originally = BTreeSet::new();
-    for l in app.primary.map.all_lanes() {
-        if !l.driving_blackhole {
-            driving_ok_originally.insert(l.id);
-        }
-        if !l.biking_blackhole {
-            biking_ok_originally.insert(l.id);
-        }
-    }
+    check(
+        ctx,
+        app,
+        cmd,
+        vec![Box::new(VehicleConnectivityRule::default())],
+    )
+}
 
-    let mut edits = orig_edits.clone();
+fn check(
+    ctx: &mut EventCtx,
+    app: &mut App,
+    cmd: EditCmd,
+    rules: Vec<Box<dyn EditRule>>,
+) -> Option<Box<dyn State<App>>> {
+    let mut edits = app.primary.map.get_edits().clone();
     edits.commands.push(cmd);
-    app.primary
+    let report = app
+        .primary
         .map
-        .try_apply_edits(edits, &mut Timer::throwaway());
-
-    let mut newly_disconnected = BTreeSet::new();
-    for l in connectivity::find_scc(&app.primary.map, PathConstraints::Car).1 {
-        if driving_ok_originally.contains(&l) {
-            newly_disconnected.insert(l);
-        }
-    }
-    for l in connectivity::find_scc(&app.primary.map, PathConstraints::Bike).1 {
-        if biking_ok_originally.contains(&l) {
-            newly_disconnected.insert(l);
-        }
-    }
-    app.primary
-        .map
-        .must_apply_edits(orig_edits, &mut Timer::throwaway());
-
-    if newly_disconnected.is_empty() {
+        .validate_edits_with_rules(edits, rules, &mut Timer::throwaway());
+    if report.problems.is_empty() {
         return None;
     }
 
+    // TODO Think through a proper UI for showing editing errors to the user and letting them
+    // understand the problem. We used to just draw problems in red and mostly cover it up with the
+    // popup.
     Some(PopupMsg::new_state(
         ctx,
         "Error",
-        vec![format!(
-            "{} lanes have been disconnected",
-            newly_disconnected.len()
-        )],
+        report
+            .problems
+            .into_iter()
+            .map(|p| format!("Can't make this change; {}", p.message))
+            .collect(),
     ))
 }
@@ -21,6 +21,8 @@ pub struct ZoneEditor {
     panel: Panel,
     selector: RoadSelector,
     allow_through_traffic: BTreeSet<TripMode>,
+    // The schedule and cap aren't editable here yet, so keep whatever the zone had
+    orig_restrictions: AccessRestrictions,
     draw: ToggleZoomed,
 
     orig_members: BTreeSet<RoadID>,
@@ -41,6 +43,7 @@ impl ZoneEditor {
             .into_iter()
             .map(TripMode::from_constraints)
             .collect();
+        let orig_restrictions = start.access_restrictions.clone();
 
         let (draw, legend) = draw_zone(ctx, app, &members);
         let orig_members = members.clone();
@@ -74,6 +77,7 @@ impl ZoneEditor {
             orig_members,
             selector,
             allow_through_traffic,
+            orig_restrictions,
             draw,
         })
     }
@@ -106,6 +110,7 @@ impl State<App> for ZoneEditor {
                     allow_through_traffic.insert(PathConstraints::Train);
                     let new_access_restrictions = AccessRestrictions {
                         allow_through_traffic,
+                        ..self.orig_restrictions.clone()
                     };
                     for r in &self.selector.roads {
                         let old_access_restrictions =
@@ -1,5 +1,5 @@
 use abstutil::{prettyprint_usize, Counter};
-use geom::{Circle, Distance, Time};
+use geom::{Circle, Distance, Duration, Time};
 use map_gui::tools::ColorNetwork;
 use map_gui::ID;
 use map_model::{BusRoute, BusRouteID, BusStopID, PathStep};
@@ -225,6 +225,24 @@ fn route_body(ctx: &mut EventCtx, app: &App, details: &mut Details, id: BusRoute
         }
     }
 
+    if let Some((arrivals, avg_late, pct_on_time)) = app
+        .primary
+        .sim
+        .get_analytics()
+        .bus_schedule_adherence_per_route(Duration::minutes(1))
+        .remove(&id)
+    {
+        rows.push(
+            format!(
+                "{} stop arrivals, {} late on average, {}% on time",
+                prettyprint_usize(arrivals),
+                avg_late,
+                (pct_on_time * 100.0).round()
+            )
+            .text_widget(ctx),
+        );
+    }
+
     let mut boardings: Counter<BusStopID> = Counter::new();
     let mut alightings: Counter<BusStopID> = Counter::new();
     let mut waiting: Counter<BusStopID> = Counter::new();
@@ -0,0 +1,59 @@
+use map_gui::ID;
+use sim::{AgentID, CarID};
+use widgetry::{EventCtx, Line, TextExt, Widget};
+
+use crate::app::App;
+use crate::info::{header_btns, make_tabs, Details, Tab};
+
+pub fn status(ctx: &mut EventCtx, app: &App, details: &mut Details, id: CarID) -> Widget {
+    if let Some(pt) = app
+        .primary
+        .sim
+        .canonical_pt_for_agent(AgentID::Car(id), &app.primary.map)
+    {
+        ctx.canvas.center_on_map_pt(pt);
+    }
+
+    let header = Widget::custom_col(vec![
+        Widget::row(vec![
+            Line(id.to_string()).small_heading().into_widget(ctx),
+            header_btns(ctx),
+        ]),
+        make_tabs(
+            ctx,
+            &mut details.hyperlinks,
+            Tab::DeliveryStatus(id),
+            vec![("Status", Tab::DeliveryStatus(id))],
+        ),
+    ]);
+
+    Widget::custom_col(vec![
+        header,
+        status_body(ctx, app, details, id).tab_body(ctx),
+    ])
+}
+
+fn status_body(ctx: &mut EventCtx, app: &App, details: &mut Details, id: CarID) -> Widget {
+    let mut rows = vec![];
+
+    match app.primary.sim.delivery_tour_progress(id) {
+        Some((next, done, total)) => {
+            rows.push(format!("Made {} of {} stops", done, total).text_widget(ctx));
+            match next {
+                Some(b) => {
+                    let label = format!("Headed to {}", app.primary.map.get_b(b).address);
+                    rows.push(ctx.style().btn_outline.text(&label).build_def(ctx));
+                    details.warpers.insert(label, ID::Building(b));
+                }
+                None => {
+                    rows.push("Returning to the depot".text_widget(ctx));
+                }
+            }
+        }
+        None => {
+            rows.push("Finished the tour".text_widget(ctx));
+        }
+    }
+
+    Widget::col(rows)
+}
@@ -36,6 +36,12 @@ fn info_body(ctx: &EventCtx, app: &App, id: LaneID) -> Widget {
         if !ban.is_empty() {
             kv.push(("No through-traffic for", ban.join(", ")));
         }
+        if let Some(ref toll) = r.access_restrictions.cordon_toll {
+            kv.push(("Zone toll", toll.describe()));
+        }
+    }
+    if let Some(ref toll) = r.toll {
+        kv.push(("Toll", toll.describe()));
     }
 
     if l.is_parking() {
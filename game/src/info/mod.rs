@@ -26,10 +26,12 @@ use crate::sandbox::{dashboards, GameplayMode, SandboxMode, TimeWarpScreen};
 mod building;
 mod bus;
 mod debug;
+mod freight;
 mod intersection;
 mod lane;
 mod parking_lot;
 mod person;
+mod ridehail;
 mod trip;
 
 pub struct InfoPanel {
@@ -63,6 +65,10 @@ pub enum Tab {
 
     ParkedCar(CarID),
 
+    RideHailStatus(CarID),
+
+    DeliveryStatus(CarID),
+
     BldgInfo(BuildingID),
     BldgPeople(BuildingID),
 
@@ -154,6 +160,10 @@ impl Tab {
                         "status" => Tab::BusStatus(c),
                         _ => unreachable!(),
                     }
+                } else if c.vehicle_type == VehicleType::RideHail {
+                    Tab::RideHailStatus(c)
+                } else if c.vehicle_type == VehicleType::Truck {
+                    Tab::DeliveryStatus(c)
                 } else {
                     Tab::ParkedCar(c)
                 }
@@ -196,7 +206,9 @@ impl Tab {
                     _ => None,
                 }
             }
-            Tab::BusStatus(c) => Some(ID::Car(*c)),
+            Tab::BusStatus(c) | Tab::RideHailStatus(c) | Tab::DeliveryStatus(c) => {
+                Some(ID::Car(*c))
+            }
             Tab::BusStop(bs) => Some(ID::BusStop(*bs)),
             Tab::BusRoute(_) => None,
             // TODO If a parked car becomes in use while the panel is open, should update the
@@ -266,6 +278,8 @@ impl Tab {
             Tab::BusStop(_) => ("bus stop", "info"),
             Tab::BusRoute(_) => ("bus route", "info"),
             Tab::ParkedCar(_) => ("parked car", "info"),
+            Tab::RideHailStatus(_) => ("ride-hail", "status"),
+            Tab::DeliveryStatus(_) => ("delivery", "status"),
             Tab::BldgInfo(_) => ("bldg", "info"),
             Tab::BldgPeople(_) => ("bldg", "people"),
             Tab::ParkingLot(_) => ("parking lot", "info"),
@@ -335,6 +349,8 @@ impl InfoPanel {
             Tab::BusStatus(c) => (bus::bus_status(ctx, app, &mut details, c), true),
             Tab::BusStop(bs) => (bus::stop(ctx, app, &mut details, bs), true),
             Tab::BusRoute(br) => (bus::route(ctx, app, &mut details, br), true),
+            Tab::RideHailStatus(c) => (ridehail::status(ctx, app, &mut details, c), true),
+            Tab::DeliveryStatus(c) => (freight::status(ctx, app, &mut details, c), true),
             Tab::ParkedCar(c) => (
                 person::parked_car(ctx, app, &mut details, c, ctx_actions.is_paused()),
                 true,
@@ -0,0 +1,73 @@
+use geom::Distance;
+use sim::{AgentID, CarID};
+use widgetry::{EventCtx, Line, TextExt, Widget};
+
+use crate::app::App;
+use crate::info::{header_btns, make_tabs, Details, OpenTrip, Tab};
+
+pub fn status(ctx: &mut EventCtx, app: &App, details: &mut Details, id: CarID) -> Widget {
+    if let Some(pt) = app
+        .primary
+        .sim
+        .canonical_pt_for_agent(AgentID::Car(id), &app.primary.map)
+    {
+        ctx.canvas.center_on_map_pt(pt);
+    }
+
+    let header = Widget::custom_col(vec![
+        Widget::row(vec![
+            Line(id.to_string()).small_heading().into_widget(ctx),
+            header_btns(ctx),
+        ]),
+        make_tabs(
+            ctx,
+            &mut details.hyperlinks,
+            Tab::RideHailStatus(id),
+            vec![("Status", Tab::RideHailStatus(id))],
+        ),
+    ]);
+
+    Widget::custom_col(vec![
+        header,
+        status_body(ctx, app, details, id).tab_body(ctx),
+    ])
+}
+
+fn status_body(ctx: &mut EventCtx, app: &App, details: &mut Details, id: CarID) -> Widget {
+    let mut rows = vec![];
+    let sim = &app.primary.sim;
+
+    match sim.ride_hail_serving_trip(id) {
+        Some(trip) => {
+            rows.push(format!("Serving {}", trip).text_widget(ctx));
+            if let Some(person) = sim.trip_to_person(trip) {
+                let label = format!("Open {}", person);
+                rows.push(ctx.style().btn_outline.text(&label).build_def(ctx));
+                details
+                    .hyperlinks
+                    .insert(label, Tab::PersonTrips(person, OpenTrip::single(trip)));
+            }
+        }
+        None => {
+            rows.push("Idle".text_widget(ctx));
+        }
+    }
+
+    let (rides, deadhead, revenue) = sim
+        .get_analytics()
+        .ride_hail_vehicle_summary()
+        .get(&id)
+        .cloned()
+        .unwrap_or((0, Distance::ZERO, Distance::ZERO));
+    rows.push(format!("{} rides completed so far", rides).text_widget(ctx));
+    rows.push(
+        format!(
+            "Drove {} with passengers, {} empty",
+            revenue.to_string(&app.opts.units),
+            deadhead.to_string(&app.opts.units)
+        )
+        .text_widget(ctx),
+    );
+
+    Widget::col(rows)
+}
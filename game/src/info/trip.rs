@@ -118,6 +118,17 @@ pub fn ongoing(
             ]),
         ]));
     }
+    if let Some(wait) = app.primary.sim.get_analytics().ride_hail_wait_time(id) {
+        col.push(Widget::custom_row(vec![
+            Widget::custom_row(vec![Line("Pickup").secondary().into_widget(ctx)])
+                .force_width_window_pct(ctx, col_width),
+            format!(
+                "waited {} for a ride-hail vehicle",
+                wait.to_string(&app.opts.units)
+            )
+            .text_widget(ctx),
+        ]));
+    }
     col.push(describe_problems(
         ctx,
         app.primary.sim.get_analytics(),
@@ -428,7 +439,7 @@ fn describe_problems(
                 txt.into_widget(ctx),
             ])
         }
-        TripMode::Bike => {
+        TripMode::Bike | TripMode::Micromobility => {
             let mut count_complex_intersections = 0;
             let mut count_overtakes = 0;
             let empty = Vec::new();
@@ -440,8 +451,9 @@ fn describe_problems(
                     Problem::OvertakeDesired(_) => {
                         count_overtakes += 1;
                     }
-                    Problem::ArterialIntersectionCrossing(_) => {}
-                    Problem::IntersectionDelay(_, _) => {}
+                    Problem::ArterialIntersectionCrossing(_)
+                    | Problem::IntersectionDelay(_, _)
+                    | Problem::BlockedByDoubleParking(_) => {}
                 }
             }
             let mut txt = Text::new();
@@ -587,6 +599,24 @@ fn draw_problems(
                     ]),
                 ));
             }
+            Problem::BlockedByDoubleParking(l) => {
+                let pt = map.get_l(*l).lane_center_pts.middle();
+                details.draw_extra.unzoomed.append(
+                    GeomBatch::load_svg(ctx, "system/assets/tools/alert.svg")
+                        .centered_on(pt)
+                        .color(RewriteColor::ChangeAlpha(0.8)),
+                );
+                details.draw_extra.zoomed.append(
+                    GeomBatch::load_svg(ctx, "system/assets/tools/alert.svg")
+                        .scale(0.5)
+                        .color(RewriteColor::ChangeAlpha(0.5))
+                        .centered_on(pt),
+                );
+                details.tooltips.push((
+                    map.get_parent(*l).get_thick_polygon(),
+                    Text::from("Stuck behind a double-parked delivery vehicle near here."),
+                ));
+            }
         }
     }
 }
@@ -689,6 +719,10 @@ fn make_timeline(
                         "system/assets/timeline/waiting_for_bus.svg"
                     }
                     TripPhaseType::RidingBus(_, _, _) => "system/assets/timeline/riding_bus.svg",
+                    TripPhaseType::WaitingForRideHail => {
+                        "system/assets/timeline/waiting_for_bus.svg"
+                    }
+                    TripPhaseType::RidingRideHail(_) => "system/assets/timeline/driving.svg",
                     TripPhaseType::Cancelled | TripPhaseType::Finished => unreachable!(),
                     TripPhaseType::DelayedStart => "system/assets/timeline/delayed_start.svg",
                 },
@@ -68,6 +68,9 @@ impl ProblemMap {
                             app.primary.map.get_i(*i).polygon.center()
                         }
                         Problem::OvertakeDesired(on) => on.get_polyline(&app.primary.map).middle(),
+                        Problem::BlockedByDoubleParking(l) => {
+                            app.primary.map.get_l(*l).lane_center_pts.middle()
+                        }
                         Problem::ArterialIntersectionCrossing(t) => {
                             app.primary.map.get_t(*t).geom.middle()
                         }
@@ -132,6 +135,9 @@ impl ProblemMap {
             show_arterial_crossings: self
                 .panel
                 .is_checked("show where pedestrians cross arterial intersections"),
+            show_double_parking: self
+                .panel
+                .is_checked("show where delivery vehicles block traffic"),
         }
     }
 }
@@ -147,6 +153,7 @@ pub struct Options {
     show_complex_crossings: bool,
     show_overtakes: bool,
     show_arterial_crossings: bool,
+    show_double_parking: bool,
     // TODO Time range
 }
 
@@ -161,6 +168,7 @@ impl Options {
             show_complex_crossings: true,
             show_overtakes: true,
             show_arterial_crossings: true,
+            show_double_parking: true,
         }
     }
 
@@ -173,6 +181,7 @@ impl Options {
             Problem::ComplexIntersectionCrossing(_) => self.show_complex_crossings,
             Problem::OvertakeDesired(_) => self.show_overtakes,
             Problem::ArterialIntersectionCrossing(_) => self.show_arterial_crossings,
+            Problem::BlockedByDoubleParking(_) => self.show_double_parking,
         }
     }
 }
@@ -236,6 +245,12 @@ fn make_controls(
         None,
         opts.show_arterial_crossings,
     ));
+    col.push(Toggle::checkbox(
+        ctx,
+        "show where delivery vehicles block traffic",
+        None,
+        opts.show_double_parking,
+    ));
 
     col.push(Toggle::choice(
         ctx,
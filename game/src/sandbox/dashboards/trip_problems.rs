@@ -17,6 +17,7 @@ pub enum ProblemType {
     ComplexIntersectionCrossing,
     OvertakeDesired,
     ArterialIntersectionCrossing,
+    BlockedByDoubleParking,
 }
 
 impl From<&Problem> for ProblemType {
@@ -26,6 +27,7 @@ impl From<&Problem> for ProblemType {
             Problem::ComplexIntersectionCrossing(_) => Self::ComplexIntersectionCrossing,
             Problem::OvertakeDesired(_) => Self::OvertakeDesired,
             Problem::ArterialIntersectionCrossing(_) => Self::ArterialIntersectionCrossing,
+            Problem::BlockedByDoubleParking(_) => Self::BlockedByDoubleParking,
         }
     }
 }
@@ -47,6 +49,7 @@ impl ProblemType {
             ProblemType::ComplexIntersectionCrossing,
             ProblemType::OvertakeDesired,
             ProblemType::ArterialIntersectionCrossing,
+            ProblemType::BlockedByDoubleParking,
         ]
     }
 }
@@ -176,7 +176,10 @@ impl GameplayMode {
     pub fn allows(&self, edits: &MapEdits) -> bool {
         for cmd in &edits.commands {
             match cmd {
-                EditCmd::ChangeRoad { .. } => {
+                EditCmd::ChangeRoad { .. }
+                | EditCmd::AddRoad { .. }
+                | EditCmd::SplitRoad { .. }
+                | EditCmd::DeleteRoad { .. } => {
                     if !self.can_edit_roads() {
                         return false;
                     }
@@ -190,7 +193,7 @@ impl GameplayMode {
                     }
                     _ => {}
                 },
-                EditCmd::ChangeRouteSchedule { .. } => {}
+                EditCmd::ChangeRouteSchedule { .. } | EditCmd::ChangeParkingRegulation { .. } => {}
             }
         }
         true
@@ -379,7 +379,6 @@ impl ModeShiftData {
         // Find all high-stress roads, since we'll filter by them next
         let high_stress: HashSet<RoadID> = map
             .all_roads()
-            .iter()
             .filter_map(|r| {
                 if r.high_stress_for_bikes(map) {
                     Some(r.id)
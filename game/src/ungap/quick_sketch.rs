@@ -1,7 +1,7 @@
 use abstutil::Tags;
 use map_gui::tools::PopupMsg;
 use map_model::{
-    BufferType, Direction, DrivingSide, EditCmd, EditRoad, LaneSpec, LaneType, RoadID,
+    BufferType, Direction, DrivingSide, EditCmd, EditRoad, LaneChange, LaneSpec, LaneType, RoadID,
 };
 use widgetry::{Choice, EventCtx, GfxCtx, Key, Outcome, Panel, State, TextExt, Widget};
 
@@ -235,6 +235,8 @@ fn maybe_add_bike_lanes(
                 lt: LaneType::Biking,
                 dir,
                 width: LaneSpec::typical_lane_widths(LaneType::Biking, &dummy_tags)[0].0,
+                allowed_turns: None,
+                lane_change: LaneChange::default(),
             };
             if let Some(buffer) = buffer_type {
                 side.insert(
@@ -245,6 +247,8 @@ fn maybe_add_bike_lanes(
                         width: LaneSpec::typical_lane_widths(LaneType::Buffer(buffer), &dummy_tags)
                             [0]
                         .0,
+                        allowed_turns: None,
+                        lane_change: LaneChange::default(),
                     },
                 );
             }
@@ -8,6 +8,29 @@
 // it's now 01:01:00.0
 // > curl http://localhost:1234/data/get-road-thruput
 // ... huge JSON blob
+//
+// The server can run many independent simulations at once. Add session=name to any command to use
+// a session besides the default one:
+//
+// > curl http://localhost:1234/sessions/create?name=experiment
+// > curl http://localhost:1234/sim/goto-time?t=01:01:00&session=experiment
+// > curl http://localhost:1234/sessions/list
+// > curl http://localhost:1234/sessions/delete?name=experiment
+//
+// Snapshots let you return to an earlier point without replaying from midnight, or branch off
+// experiments with different edits:
+//
+// > curl http://localhost:1234/sim/save-snapshot?name=morning
+// > curl -X POST -d @edits.json http://localhost:1234/sessions/fork?name=variant&snapshot=morning
+// > curl http://localhost:1234/sim/restore-snapshot?name=morning
+//
+// To try out changes to roads, intersections, and bus schedules without restarting, POST a command
+// like the ones from /map/get-edit-road-command to /map/apply-edit. This returns the trips
+// cancelled because they crossed something that changed. /map/list-edits and /map/undo-edit work
+// on the session's current edits. Edits that add, split, or delete roads need /sim/load.
+//
+// To follow a simulation live instead of polling, see the stream module. To control traffic
+// signals with reinforcement learning, see the rl module.
 
 #[macro_use]
 extern crate anyhow;
@@ -16,39 +39,49 @@ extern crate log;
 
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryFrom;
-use std::sync::RwLock;
 
 use anyhow::Result;
 use hyper::{Body, Request, Response, Server, StatusCode};
 use rand::SeedableRng;
 use rand_xorshift::XorShiftRng;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 
-use abstio::MapName;
 use abstutil::{serialize_btreemap, CmdArgs, Timer};
 use geom::{Distance, Duration, LonLat, Time};
 use map_model::{
     CompressedMovementID, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, Map,
-    MovementID, PermanentMapEdits, RoadID, TurnID,
+    MovementID, PermanentEditCmd, PermanentMapEdits, RoadID, TurnID,
 };
 use sim::{
-    AgentID, AgentType, DelayCause, ExternalPerson, PersonID, Scenario, ScenarioModifier, Sim,
-    SimFlags, SimOptions, TripID, TripMode, VehicleType,
+    AgentID, AgentType, DelayCause, ExternalPerson, PersonID, Scenario, Sim, SimFlags, SimOptions,
+    TripID, TripMode, VehicleType,
 };
 
-lazy_static::lazy_static! {
-    static ref MAP: RwLock<Map> = RwLock::new(Map::blank());
-    static ref SIM: RwLock<Sim> = RwLock::new(Sim::new(&Map::blank(), SimOptions::new("tmp")));
-    static ref LOAD: RwLock<LoadSim> = RwLock::new({
-        LoadSim {
-            scenario: abstio::path_scenario(&MapName::seattle("montlake"), "weekday"),
-            modifiers: Vec::new(),
-            edits: None,
-            rng_seed: SimFlags::RNG_SEED,
-            opts: SimOptions::default(),
-        }
-    });
-}
+use crate::sessions::{LoadSim, Session, Snapshot, DEFAULT_SESSION};
+
+mod rl;
+mod sessions;
+mod stream;
+#[cfg(test)]
+mod tests;
+
+/// These modify a session, so they need exclusive access to it. All other commands only read.
+const MUTATING_COMMANDS: [&str; 14] = [
+    "/sim/reset",
+    "/sim/load",
+    "/sim/goto-time",
+    "/sim/new-person",
+    "/sim/save-snapshot",
+    "/sim/restore-snapshot",
+    "/sim/delete-snapshot",
+    "/traffic-signals/set",
+    "/map/validate-edits",
+    "/map/apply-edit",
+    "/map/undo-edit",
+    "/rl/configure",
+    "/rl/reset",
+    "/rl/step",
+];
 
 #[tokio::main]
 async fn main() {
@@ -61,15 +94,7 @@ async fn main() {
     let port = args.required("--port").parse::<u16>().unwrap();
     args.done();
 
-    {
-        let mut load = LOAD.write().unwrap();
-        load.rng_seed = rng_seed;
-        load.opts = opts;
-
-        let (map, sim) = load.setup(&mut timer);
-        *MAP.write().unwrap() = map;
-        *SIM.write().unwrap() = sim;
-    }
+    sessions::initialize(rng_seed, opts, &mut timer);
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
     info!("Listening on http://{}", addr);
@@ -92,64 +117,191 @@ async fn serve_req(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
             .collect();
     let body = hyper::body::to_bytes(req).await?.to_vec();
     info!("Handling {}", path);
-    Ok(
-        match handle_command(
-            &path,
-            &params,
-            &body,
-            &mut SIM.write().unwrap(),
-            &mut MAP.write().unwrap(),
-            &mut LOAD.write().unwrap(),
-        ) {
-            Ok(resp) => Response::new(Body::from(resp)),
-            Err(err) => {
-                error!("{}: {}", path, err);
-                Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from(format!("Bad command {}: {}", path, err)))
-                    .unwrap()
+
+    // Commands can take a long time, so don't block the server's other work
+    let result = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || {
+            if path == "/sim/stream" {
+                stream::start(session_name(&params), &params)
+            } else {
+                dispatch(&path, &params, &body).map(|resp| Response::new(Body::from(resp)))
             }
-        },
-    )
+        })
+        .await
+        .unwrap_or_else(|err| Err(anyhow!("command crashed: {}", err)))
+    };
+    Ok(match result {
+        Ok(resp) => resp,
+        Err(err) => {
+            error!("{}: {}", path, err);
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Bad command {}: {}", path, err)))
+                .unwrap()
+        }
+    })
+}
+
+fn dispatch(path: &str, params: &HashMap<String, String>, body: &[u8]) -> Result<String> {
+    if path.starts_with("/sessions/") {
+        return handle_session_command(path, params, body);
+    }
+
+    let name = session_name(params);
+    let session = sessions::get(name)?;
+    if MUTATING_COMMANDS.contains(&path) {
+        handle_command(path, params, body, &mut sessions::write(name, &session)?)
+    } else {
+        handle_query(path, params, &sessions::read(name, &session)?)
+    }
+}
+
+fn session_name(params: &HashMap<String, String>) -> &str {
+    params
+        .get("session")
+        .map(|x| x.as_str())
+        .unwrap_or(DEFAULT_SESSION)
+}
+
+fn handle_session_command(
+    path: &str,
+    params: &HashMap<String, String>,
+    body: &[u8],
+) -> Result<String> {
+    let get = |key: &str| {
+        params
+            .get(key)
+            .ok_or_else(|| anyhow!("missing GET parameter {}", key))
+    };
+
+    match path {
+        "/sessions/list" => Ok(abstutil::to_json(&sessions::list())),
+        "/sessions/create" => {
+            let name = get("name")?;
+            sessions::check_unused(name)?;
+
+            // Optionally override the scenario, modifiers, edits, and RNG seed
+            let mut load = sessions::default_load();
+            if !body.is_empty() {
+                let args: LoadSim = abstutil::from_json(body)?;
+                load.scenario = args.scenario;
+                load.modifiers = args.modifiers;
+                load.edits = args.edits;
+            }
+            if let Some(seed) = params.get("rng_seed") {
+                load.rng_seed = seed.parse::<u64>()?;
+            }
+
+            let session = Session::new(load, &mut Timer::new(format!("create session {}", name)))?;
+            sessions::insert(name, session)?;
+            Ok(format!("session {} created", name))
+        }
+        "/sessions/fork" => {
+            // Copy the session specified the usual way
+            let name = get("name")?;
+            sessions::check_unused(name)?;
+            let source = session_name(params);
+            let mut timer = Timer::new(format!("fork session {} from {}", name, source));
+            let mut session = {
+                let source_session = sessions::get(source)?;
+                let source_session = sessions::read(source, &source_session)?;
+                source_session.fork(params.get("snapshot").map(|x| x.as_str()), &mut timer)?
+            };
+
+            // Optionally switch to different edits
+            let mut result = format!(
+                "session {} forked from {} at {}",
+                name,
+                source,
+                session.sim.time()
+            );
+            if !body.is_empty() {
+                let perma: PermanentMapEdits = abstutil::from_json(body)?;
+                let edited = session.apply_live_edits(perma, &mut timer)?;
+                result = format!(
+                    "{}. The new edits interrupted {} trips and displaced {} parked cars",
+                    result,
+                    edited.cancelled_trips.len(),
+                    edited.parked_cars_displaced
+                );
+            }
+
+            sessions::insert(name, session)?;
+            Ok(result)
+        }
+        "/sessions/delete" => {
+            let name = get("name")?;
+            sessions::delete(name)?;
+            Ok(format!("session {} deleted", name))
+        }
+        _ => Err(anyhow!("Unknown command")),
+    }
 }
 
 fn handle_command(
     path: &str,
     params: &HashMap<String, String>,
     body: &[u8],
-    sim: &mut Sim,
-    map: &mut Map,
-    load: &mut LoadSim,
+    session: &mut Session,
 ) -> Result<String> {
     let get = |key: &str| {
         params
             .get(key)
             .ok_or_else(|| anyhow!("missing GET parameter {}", key))
     };
+    if path.starts_with("/rl/") {
+        return rl::handle_command(path, body, session);
+    }
+    // These need the whole session
+    match path {
+        "/map/apply-edit" => {
+            let cmd: PermanentEditCmd = abstutil::from_json(body)?;
+            let result = session.apply_edit_cmd(cmd, &mut Timer::new("apply edit"))?;
+            return Ok(abstutil::to_json(&result));
+        }
+        "/map/undo-edit" => {
+            let result = session.undo_edit_cmd(&mut Timer::new("undo edit"))?;
+            return Ok(abstutil::to_json(&result));
+        }
+        _ => {}
+    }
+    let Session {
+        map,
+        sim,
+        load,
+        snapshots,
+        rl,
+    } = session;
 
     match path {
         // Controlling the simulation
         "/sim/reset" => {
-            let (new_map, new_sim) = load.setup(&mut Timer::new("reset sim"));
+            let (new_map, new_sim) = load.setup(&mut Timer::new("reset sim"))?;
             *map = new_map;
             *sim = new_sim;
             Ok("sim reloaded".to_string())
         }
         "/sim/load" => {
             let args: LoadSim = abstutil::from_json(body)?;
+            let new_load = LoadSim {
+                scenario: args.scenario,
+                modifiers: args.modifiers,
+                edits: args.edits,
+                ..load.clone()
+            };
 
-            load.scenario = args.scenario;
-            load.modifiers = args.modifiers;
-            load.edits = args.edits;
-
-            // Also reset
-            let (new_map, new_sim) = load.setup(&mut Timer::new("reset sim"));
+            // Also reset. If the new configuration doesn't work, keep the session as it was.
+            let (new_map, new_sim) = new_load.setup(&mut Timer::new("reset sim"))?;
+            *load = new_load;
             *map = new_map;
             *sim = new_sim;
+            // These might be for a different map
+            snapshots.clear();
+            *rl = None;
 
             Ok("flags changed and sim reloaded".to_string())
         }
-        "/sim/get-time" => Ok(sim.time().to_string()),
         "/sim/goto-time" => {
             let t = Time::parse(get("t")?)?;
             if t <= sim.time() {
@@ -181,15 +333,31 @@ fn handle_command(
                 sim.get_all_people().last().unwrap().id
             ))
         }
-        // Traffic signals
-        "/traffic-signals/get" => {
-            let i = IntersectionID(get("id")?.parse::<usize>()?);
-            if let Some(ts) = map.maybe_get_traffic_signal(i) {
-                Ok(abstutil::to_json(ts))
-            } else {
-                bail!("{} isn't a traffic signal", i)
+        "/sim/save-snapshot" => {
+            let name = get("name")?;
+            snapshots.insert(name.to_string(), Snapshot::new(map, sim, load));
+            Ok(format!("snapshot {} saved at {}", name, sim.time()))
+        }
+        "/sim/restore-snapshot" => {
+            let name = get("name")?;
+            let snapshot = snapshots
+                .get(name)
+                .ok_or_else(|| anyhow!("no snapshot named {}", name))?;
+            snapshot.restore(map, sim, load, &mut Timer::new("restore snapshot"));
+            Ok(format!(
+                "restored snapshot {}; it's now {}",
+                name,
+                sim.time()
+            ))
+        }
+        "/sim/delete-snapshot" => {
+            let name = get("name")?;
+            if snapshots.remove(name).is_none() {
+                bail!("no snapshot named {}", name);
             }
+            Ok(format!("snapshot {} deleted", name))
         }
+        // Traffic signals
         "/traffic-signals/set" => {
             let ts: ControlTrafficSignal = abstutil::from_json(body)?;
             let id = ts.id;
@@ -207,6 +375,52 @@ fn handle_command(
 
             Ok(format!("{} has been updated", id))
         }
+        // Controlling the map
+        "/map/validate-edits" => {
+            let perma: PermanentMapEdits = abstutil::from_json(body)?;
+            let edits = perma.into_edits(map)?;
+            let mut timer = Timer::new("validate edits");
+            let report = map.validate_edits(edits, &mut timer);
+            Ok(abstutil::to_json(&report))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn handle_query(path: &str, params: &HashMap<String, String>, session: &Session) -> Result<String> {
+    let get = |key: &str| {
+        params
+            .get(key)
+            .ok_or_else(|| anyhow!("missing GET parameter {}", key))
+    };
+    if path.starts_with("/rl/") {
+        return rl::handle_query(path, session);
+    }
+    let Session {
+        map,
+        sim,
+        snapshots,
+        ..
+    } = session;
+
+    match path {
+        // Controlling the simulation
+        "/sim/get-time" => Ok(sim.time().to_string()),
+        "/sim/list-snapshots" => Ok(abstutil::to_json(
+            &snapshots
+                .iter()
+                .map(|(name, snapshot)| (name.clone(), snapshot.time()))
+                .collect::<BTreeMap<String, Time>>(),
+        )),
+        // Traffic signals
+        "/traffic-signals/get" => {
+            let i = IntersectionID(get("id")?.parse::<usize>()?);
+            if let Some(ts) = map.maybe_get_traffic_signal(i) {
+                Ok(abstutil::to_json(ts))
+            } else {
+                bail!("{} isn't a traffic signal", i)
+            }
+        }
         "/traffic-signals/get-delays" => {
             let i = map.get_i(IntersectionID(get("id")?.parse::<usize>()?));
             let t1 = Time::parse(get("t1")?)?;
@@ -301,19 +515,7 @@ fn handle_command(
             Ok(abstutil::to_json(&trips))
         }
         "/data/get-agent-positions" => Ok(abstutil::to_json(&AgentPositions {
-            agents: sim
-                .get_unzoomed_agents(map)
-                .into_iter()
-                .chain(sim.get_unzoomed_transit_riders(map))
-                .map(|a| AgentPosition {
-                    id: a.id,
-                    trip: sim.agent_to_trip(a.id),
-                    person: a.person,
-                    vehicle_type: a.id.to_vehicle_type(),
-                    pos: a.pos.to_gps(map.get_gps_bounds()),
-                    distance_crossed: sim.agent_properties(map, a.id).dist_crossed,
-                })
-                .collect(),
+            agents: agent_positions(map, sim),
         })),
         "/data/get-road-thruput" => Ok(abstutil::to_json(&RoadThroughput {
             counts: sim
@@ -360,10 +562,25 @@ fn handle_command(
         // Controlling the map
         "/map/get-edits" => {
             let mut edits = map.get_edits().clone();
-            edits.commands.clear();
-            edits.compress(map);
+            edits.compress();
             Ok(abstutil::to_json(&edits.to_permanent(map)))
         }
+        "/map/list-edits" => {
+            let list: Vec<EditSummary> = map
+                .get_edits()
+                .commands
+                .iter()
+                .map(|cmd| {
+                    let (summary, details) = cmd.describe(map);
+                    EditSummary {
+                        summary,
+                        details,
+                        cmd: cmd.to_perma(map),
+                    }
+                })
+                .collect();
+            Ok(abstutil::to_json(&list))
+        }
         "/map/get-edit-road-command" => {
             let r = RoadID(get("id")?.parse::<usize>()?);
             Ok(abstutil::to_json(
@@ -379,6 +596,21 @@ fn handle_command(
     }
 }
 
+fn agent_positions(map: &Map, sim: &Sim) -> Vec<AgentPosition> {
+    sim.get_unzoomed_agents(map)
+        .into_iter()
+        .chain(sim.get_unzoomed_transit_riders(map))
+        .map(|a| AgentPosition {
+            id: a.id,
+            trip: sim.agent_to_trip(a.id),
+            person: a.person,
+            vehicle_type: a.id.to_vehicle_type(),
+            pos: a.pos.to_gps(map.get_gps_bounds()),
+            distance_crossed: sim.agent_properties(map, a.id).dist_crossed,
+        })
+        .collect()
+}
+
 // TODO I think specifying the API with protobufs or similar will be a better idea.
 
 #[derive(Serialize)]
@@ -457,39 +689,12 @@ struct BlockedByGraph {
     blocked_by: BTreeMap<AgentID, (Duration, DelayCause, Option<TripID>, Option<PersonID>)>,
 }
 
-#[derive(Deserialize)]
-struct LoadSim {
-    scenario: String,
-    modifiers: Vec<ScenarioModifier>,
-    edits: Option<PermanentMapEdits>,
-    // These are fixed from the initial command line flags
-    #[serde(skip_deserializing)]
-    rng_seed: u64,
-    #[serde(skip_deserializing)]
-    opts: SimOptions,
-}
-
-impl LoadSim {
-    fn setup(&self, timer: &mut Timer) -> (Map, Sim) {
-        let mut scenario: Scenario = abstio::must_read_object(self.scenario.clone(), timer);
-
-        let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
-        if let Some(perma) = self.edits.clone() {
-            let edits = perma.into_edits(&map).unwrap();
-            map.must_apply_edits(edits, timer);
-            map.recalculate_pathfinding_after_edits(timer);
-        }
-
-        for m in &self.modifiers {
-            scenario = m.apply(&map, scenario);
-        }
-
-        let mut rng = XorShiftRng::seed_from_u64(self.rng_seed);
-        let mut sim = Sim::new(&map, self.opts.clone());
-        scenario.instantiate(&mut sim, &map, &mut rng, timer);
-
-        (map, sim)
-    }
+#[derive(Serialize)]
+struct EditSummary {
+    summary: String,
+    details: Vec<String>,
+    /// Can be passed to /map/apply-edit
+    cmd: PermanentEditCmd,
 }
 
 fn export_geometry(map: &Map, i: IntersectionID) -> geojson::GeoJson {
@@ -0,0 +1,424 @@
+//! A reinforcement learning environment for traffic signal control, following the usual Gym
+//! contract of reset, observe, and step. Each session can have one environment.
+//!
+//! 1. POST a `Config` to `/rl/configure`. The signals listed (or all of them) are taken over by
+//!    the environment on the next reset. Signals controlled by a previous environment go back to
+//!    their normal timing.
+//! 2. `/rl/reset` starts an episode and returns the first observation. The first reset simulates
+//!    up to the episode's start time and caches a snapshot; later resets restore that instantly.
+//! 3. POST `{"actions": [0, 1, ...]}` to `/rl/step`, with one action per signal in the order of
+//!    `/rl/describe`. 0 keeps the current stage, 1 advances to the next. The simulation then runs
+//!    for the configured interval, and the new observation, reward, and whether the episode is
+//!    done are returned.
+//!
+//! The reward is the negative total delay, in seconds, that agents experienced at the controlled
+//! signals during the step, as measured by `Analytics::intersection_delays`. A delay is only
+//! recorded once the agent finally crosses, so the reward covers completed delays only. Agents
+//! still stuck at a red light count for nothing until they're let through, so a policy holding
+//! one stage forever can look good for a while; the `queues` and `waiting` observations show who
+//! is still waiting.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::Timer;
+use geom::{Duration, Time};
+use map_model::{IntersectionID, Map, MovementID, PathStep, Traversable, TurnID};
+use sim::{AgentID, Sim};
+
+use crate::sessions::{Session, Snapshot};
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// The traffic signals to control. If empty, use all of them.
+    #[serde(default)]
+    signals: Vec<IntersectionID>,
+    /// How much simulated time passes after each action
+    #[serde(default = "default_step_seconds")]
+    step_seconds: f64,
+    /// When each episode begins and ends, like "07:00:00"
+    episode_start: String,
+    episode_end: String,
+    #[serde(default)]
+    observation: ObservationConfig,
+}
+
+fn default_step_seconds() -> f64 {
+    5.0
+}
+
+/// Which features to include in each signal's observation. By default, everything.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ObservationConfig {
+    /// Per movement, the number of vehicles on lanes leading to it that'll take it next
+    queues: bool,
+    /// Per movement, the number of agents at the front of a lane or crosswalk waiting to start it
+    waiting: bool,
+    /// The index of the current stage
+    stage: bool,
+    /// How long the current stage has lasted, in seconds
+    time_in_stage: bool,
+}
+
+impl Default for ObservationConfig {
+    fn default() -> ObservationConfig {
+        ObservationConfig {
+            queues: true,
+            waiting: true,
+            stage: true,
+            time_in_stage: true,
+        }
+    }
+}
+
+pub struct Environment {
+    signals: Vec<IntersectionID>,
+    step: Duration,
+    episode_start: Time,
+    episode_end: Time,
+    observation: ObservationConfig,
+    /// The state at the start of an episode, once it's been simulated once
+    start: Option<Snapshot>,
+}
+
+#[derive(Serialize)]
+struct Description {
+    signals: Vec<SignalDescription>,
+    step_seconds: f64,
+    episode_start: String,
+    episode_end: String,
+}
+
+#[derive(Serialize)]
+struct SignalDescription {
+    id: IntersectionID,
+    /// The order of values in the queues and waiting observations
+    movements: Vec<MovementID>,
+    num_stages: usize,
+}
+
+#[derive(Serialize)]
+struct SignalObservation {
+    id: IntersectionID,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queues: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waiting: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_in_stage: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct StepInput {
+    actions: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct StepResult {
+    observation: Vec<SignalObservation>,
+    reward: f64,
+    done: bool,
+    info: StepInfo,
+}
+
+#[derive(Serialize)]
+struct StepInfo {
+    time: String,
+    /// The reward split up by signal, in the same order as the observation
+    reward_per_signal: Vec<f64>,
+}
+
+pub fn handle_command(path: &str, body: &[u8], session: &mut Session) -> Result<String> {
+    match path {
+        "/rl/configure" => {
+            let config: Config = abstutil::from_json(body)?;
+            let env = Environment::new(config, &session.map)?;
+            // The new environment only takes over its signals on reset, so hand back all of the
+            // old environment's signals to their normal timing. Live edits may have removed some.
+            if let Some(old) = session.rl.take() {
+                for i in old.signals {
+                    if session.map.maybe_get_traffic_signal(i).is_some() {
+                        session
+                            .sim
+                            .set_external_signal_control(&session.map, i, false)?;
+                    }
+                }
+            }
+            session.rl = Some(env);
+            Ok(abstutil::to_json(&describe(session)?))
+        }
+        "/rl/reset" => {
+            reset(session, &mut Timer::new("reset RL episode"))?;
+            Ok(abstutil::to_json(&observe(session)?))
+        }
+        "/rl/step" => {
+            let input: StepInput = abstutil::from_json(body)?;
+            Ok(abstutil::to_json(&step(session, input.actions)?))
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn handle_query(path: &str, session: &Session) -> Result<String> {
+    match path {
+        "/rl/describe" => Ok(abstutil::to_json(&describe(session)?)),
+        "/rl/observe" => Ok(abstutil::to_json(&observe(session)?)),
+        _ => Err(anyhow!("Unknown command")),
+    }
+}
+
+impl Environment {
+    fn new(config: Config, map: &Map) -> Result<Environment> {
+        if config.step_seconds <= 0.0 {
+            bail!("step_seconds must be positive");
+        }
+        let episode_start = Time::parse(&config.episode_start)?;
+        let episode_end = Time::parse(&config.episode_end)?;
+        if episode_end <= episode_start {
+            bail!("episode_end must be after episode_start");
+        }
+        let signals = if config.signals.is_empty() {
+            map.all_intersections()
+                .iter()
+                .filter(|i| i.is_traffic_signal())
+                .map(|i| i.id)
+                .collect()
+        } else {
+            for i in &config.signals {
+                if map.maybe_get_traffic_signal(*i).is_none() {
+                    bail!("{} isn't a traffic signal", i);
+                }
+            }
+            config.signals.clone()
+        };
+        if signals.is_empty() {
+            bail!("this map doesn't have any traffic signals");
+        }
+        Ok(Environment {
+            signals,
+            step: Duration::seconds(config.step_seconds),
+            episode_start,
+            episode_end,
+            observation: config.observation,
+            start: None,
+        })
+    }
+}
+
+fn get_env(session: &Session) -> Result<&Environment> {
+    session
+        .rl
+        .as_ref()
+        .ok_or_else(|| anyhow!("call /rl/configure first"))
+}
+
+fn describe(session: &Session) -> Result<Description> {
+    let env = get_env(session)?;
+    Ok(Description {
+        signals: env
+            .signals
+            .iter()
+            .map(|i| SignalDescription {
+                id: *i,
+                movements: session.map.get_i(*i).movements.keys().cloned().collect(),
+                num_stages: session.map.get_traffic_signal(*i).stages.len(),
+            })
+            .collect(),
+        step_seconds: env.step.inner_seconds(),
+        episode_start: env.episode_start.to_string(),
+        episode_end: env.episode_end.to_string(),
+    })
+}
+
+fn reset(session: &mut Session, timer: &mut Timer) -> Result<()> {
+    let mut env = session
+        .rl
+        .take()
+        .ok_or_else(|| anyhow!("call /rl/configure first"))?;
+
+    if let Some(ref snapshot) = env.start {
+        snapshot.restore(&mut session.map, &mut session.sim, &mut session.load, timer);
+    } else {
+        let (map, mut sim) = match session.load.setup(timer) {
+            Ok(pair) => pair,
+            Err(err) => {
+                session.rl = Some(env);
+                return Err(err);
+            }
+        };
+        let start = env.episode_start;
+        if start > sim.time() {
+            let dt = start - sim.time();
+            sim.timed_step(&map, dt, &mut None, timer);
+        }
+        env.start = Some(Snapshot::new(&map, &sim, &session.load));
+        session.map = map;
+        session.sim = sim;
+    }
+
+    let result = env.signals.iter().try_for_each(|i| {
+        session
+            .sim
+            .set_external_signal_control(&session.map, *i, true)
+    });
+    session.rl = Some(env);
+    result
+}
+
+fn observe(session: &Session) -> Result<Vec<SignalObservation>> {
+    let env = get_env(session)?;
+    Ok(env
+        .signals
+        .iter()
+        .map(|i| observe_signal(*i, &env.observation, &session.map, &session.sim))
+        .collect())
+}
+
+fn observe_signal(
+    id: IntersectionID,
+    config: &ObservationConfig,
+    map: &Map,
+    sim: &Sim,
+) -> SignalObservation {
+    let i = map.get_i(id);
+    // Some turns, like shared sidewalk corners, don't belong to any movement
+    let mut turn_to_movement: HashMap<TurnID, usize> = HashMap::new();
+    for (idx, movement) in i.movements.values().enumerate() {
+        for t in &movement.members {
+            turn_to_movement.insert(*t, idx);
+        }
+    }
+
+    let queues = if config.queues {
+        let mut counts = vec![0; i.movements.len()];
+        for l in &i.incoming_lanes {
+            for car in sim.get_draw_cars(Traversable::Lane(*l), map) {
+                let next_step = sim
+                    .get_path(AgentID::Car(car.id))
+                    .and_then(|path| path.maybe_next_step());
+                if let Some(PathStep::Turn(t)) = next_step {
+                    if let Some(idx) = turn_to_movement.get(&t) {
+                        counts[*idx] += 1;
+                    }
+                }
+            }
+        }
+        Some(counts)
+    } else {
+        None
+    };
+
+    let waiting = if config.waiting {
+        let mut counts = vec![0; i.movements.len()];
+        for (_, t, _) in sim.get_waiting_agents(id) {
+            if let Some(idx) = turn_to_movement.get(&t) {
+                counts[*idx] += 1;
+            }
+        }
+        Some(counts)
+    } else {
+        None
+    };
+
+    SignalObservation {
+        id,
+        queues,
+        waiting,
+        stage: if config.stage {
+            Some(sim.current_stage_and_remaining_time(id).0)
+        } else {
+            None
+        },
+        time_in_stage: if config.time_in_stage {
+            Some(sim.time_in_current_stage(id).inner_seconds())
+        } else {
+            None
+        },
+    }
+}
+
+fn step(session: &mut Session, actions: Vec<u8>) -> Result<StepResult> {
+    let env = get_env(session)?;
+    if actions.len() != env.signals.len() {
+        bail!(
+            "got {} actions, but there are {} signals",
+            actions.len(),
+            env.signals.len()
+        );
+    }
+    let signals = env.signals.clone();
+    let episode_end = env.episode_end;
+    let dt = env.step;
+    let start = session.sim.time();
+    if start >= episode_end {
+        bail!("the episode is over; call /rl/reset");
+    }
+    // Check every action before changing anything
+    for (i, action) in signals.iter().zip(actions.iter()) {
+        if *action > 1 {
+            bail!(
+                "action {} for {} should be 0 (keep) or 1 (advance)",
+                action,
+                i
+            );
+        }
+        if !session.sim.is_signal_externally_controlled(*i) {
+            bail!("{} isn't controlled by the environment; call /rl/reset", i);
+        }
+    }
+
+    for (i, action) in signals.iter().zip(actions) {
+        if action == 1 {
+            session.sim.advance_traffic_signal(&session.map, *i)?;
+        }
+    }
+    // Delays are only ever appended, so just look at the ones recorded during this step
+    let delays_before = num_delays(&session.sim, &signals);
+    session.sim.timed_step(
+        &session.map,
+        dt.min(episode_end - start),
+        &mut None,
+        &mut Timer::throwaway(),
+    );
+    let end = session.sim.time();
+
+    let delays = &session.sim.get_analytics().intersection_delays;
+    let reward_per_signal: Vec<f64> = signals
+        .iter()
+        .zip(delays_before)
+        .map(|(i, before)| {
+            -delays
+                .get(i)
+                .map(|list| {
+                    list[before..]
+                        .iter()
+                        .map(|(_, _, delay, _)| delay.inner_seconds())
+                        .sum::<f64>()
+                })
+                .unwrap_or(0.0)
+        })
+        .collect();
+
+    Ok(StepResult {
+        observation: observe(session)?,
+        reward: reward_per_signal.iter().sum(),
+        done: end >= episode_end || session.sim.is_done(),
+        info: StepInfo {
+            time: end.to_string(),
+            reward_per_signal,
+        },
+    })
+}
+
+fn num_delays(sim: &Sim, signals: &[IntersectionID]) -> Vec<usize> {
+    let delays = &sim.get_analytics().intersection_delays;
+    signals
+        .iter()
+        .map(|i| delays.get(i).map(|list| list.len()).unwrap_or(0))
+        .collect()
+}
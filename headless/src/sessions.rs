@@ -0,0 +1,339 @@
+//! The headless server manages any number of independent, named sessions. Each one has its own
+//! map, simulation, edits, and scenario. Commands in different sessions run in parallel, and
+//! read-only commands in the same session can too.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use anyhow::Result;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+use abstio::MapName;
+use abstutil::Timer;
+use geom::Time;
+use map_model::{EditProblem, Map, MapEdits, PermanentEditCmd, PermanentMapEdits};
+use sim::{Scenario, ScenarioModifier, Sim, SimFlags, SimOptions, TripID};
+
+use crate::rl::Environment;
+
+/// Requests that don't specify a session use this one, which always exists at startup.
+pub const DEFAULT_SESSION: &str = "default";
+
+lazy_static::lazy_static! {
+    static ref SESSIONS: RwLock<BTreeMap<String, Arc<RwLock<Session>>>> =
+        RwLock::new(BTreeMap::new());
+    // New sessions start with this configuration, unless the request overrides it.
+    static ref DEFAULT_LOAD: RwLock<LoadSim> = RwLock::new({
+        LoadSim {
+            scenario: abstio::path_scenario(&MapName::seattle("montlake"), "weekday"),
+            modifiers: Vec::new(),
+            edits: None,
+            rng_seed: SimFlags::RNG_SEED,
+            opts: SimOptions::default(),
+        }
+    });
+}
+
+pub struct Session {
+    pub map: Map,
+    pub sim: Sim,
+    pub load: LoadSim,
+    /// Kept in memory, keyed by name
+    pub snapshots: BTreeMap<String, Snapshot>,
+    pub rl: Option<Environment>,
+}
+
+impl Session {
+    pub fn new(load: LoadSim, timer: &mut Timer) -> Result<Session> {
+        let (map, sim) = load.setup(timer)?;
+        Ok(Session {
+            map,
+            sim,
+            load,
+            snapshots: BTreeMap::new(),
+            rl: None,
+        })
+    }
+
+    /// Copy the session as it is now, or as it was at one of its snapshots. The new session
+    /// doesn't get any snapshots or reinforcement learning environment.
+    pub fn fork(&self, snapshot: Option<&str>, timer: &mut Timer) -> Result<Session> {
+        let mut map = self.map.clone();
+        let (sim, load) = match snapshot {
+            Some(name) => {
+                let snapshot = self.get_snapshot(name)?;
+                snapshot.restore_edits(&mut map, timer);
+                (snapshot.sim.clone(), snapshot.load.clone())
+            }
+            None => {
+                let mut sim = self.sim.clone();
+                sim.capture_events(false);
+                (sim, self.load.clone())
+            }
+        };
+        Ok(Session {
+            map,
+            sim,
+            load,
+            snapshots: BTreeMap::new(),
+            rl: None,
+        })
+    }
+
+    pub fn get_snapshot(&self, name: &str) -> Result<&Snapshot> {
+        self.snapshots
+            .get(name)
+            .ok_or_else(|| anyhow!("no snapshot named {}", name))
+    }
+
+    /// Switch to different map edits without restarting the simulation. Trips crossing anything
+    /// that changed are cancelled.
+    pub fn apply_live_edits(
+        &mut self,
+        perma: PermanentMapEdits,
+        timer: &mut Timer,
+    ) -> Result<LiveEditResult> {
+        let edits = perma.into_edits(&self.map)?;
+        self.switch_edits(edits, timer)
+    }
+
+    /// Add one command to the current edits, without restarting the simulation.
+    pub fn apply_edit_cmd(
+        &mut self,
+        perma: PermanentEditCmd,
+        timer: &mut Timer,
+    ) -> Result<LiveEditResult> {
+        let cmd = perma.into_edit_cmd(&self.map)?;
+        let mut edits = self.map.get_edits().clone();
+        edits.commands.push(cmd);
+        self.switch_edits(edits, timer)
+    }
+
+    /// Revert the most recent command of the current edits, without restarting the simulation.
+    pub fn undo_edit_cmd(&mut self, timer: &mut Timer) -> Result<LiveEditResult> {
+        let mut edits = self.map.get_edits().clone();
+        if edits.commands.pop().is_none() {
+            bail!("there are no edits to undo");
+        }
+        self.switch_edits(edits, timer)
+    }
+
+    fn switch_edits(&mut self, edits: MapEdits, timer: &mut Timer) -> Result<LiveEditResult> {
+        // The simulation's per-lane and per-intersection state can't handle roads appearing or
+        // disappearing
+        if edits.changes_topology() || self.map.get_edits().changes_topology() {
+            bail!("edits that add, split, or delete roads need /sim/load instead");
+        }
+        let report = self.map.apply_edits_if_valid(edits, timer);
+        if !report.is_ok() {
+            let errors: Vec<String> = report.errors().map(|p| p.describe()).collect();
+            bail!("the edits have problems: {}", errors.join("; "));
+        }
+        self.map.recalculate_pathfinding_after_edits(timer);
+        // Resetting the session should keep these edits
+        self.load.edits = Some(self.map.get_edits().to_permanent(&self.map));
+
+        self.sim.handle_live_edited_traffic_signals(&self.map);
+        let (cancelled_trips, parked_cars_displaced) =
+            self.sim.handle_live_edits_listing_trips(&self.map, timer);
+        Ok(LiveEditResult {
+            cancelled_trips,
+            parked_cars_displaced,
+            warnings: report.warnings().cloned().collect(),
+        })
+    }
+}
+
+/// What happened to a running simulation when the map was edited.
+#[derive(Serialize)]
+pub struct LiveEditResult {
+    /// Trips that crossed something that changed
+    pub cancelled_trips: Vec<TripID>,
+    pub parked_cars_displaced: usize,
+    /// Problems with the new edits that aren't bad enough to reject them
+    pub warnings: Vec<EditProblem>,
+}
+
+/// A copy of a simulation at some moment, to return to later. The map itself is big and only
+/// changes through edits, so just the edits are kept.
+pub struct Snapshot {
+    sim: Sim,
+    edits: MapEdits,
+    load: LoadSim,
+}
+
+impl Snapshot {
+    pub fn new(map: &Map, sim: &Sim, load: &LoadSim) -> Snapshot {
+        let mut sim = sim.clone();
+        sim.capture_events(false);
+        Snapshot {
+            sim,
+            edits: map.get_edits().clone(),
+            load: load.clone(),
+        }
+    }
+
+    pub fn time(&self) -> Time {
+        self.sim.time()
+    }
+
+    /// Anything streaming events from the current simulation keeps receiving them.
+    pub fn restore(&self, map: &mut Map, sim: &mut Sim, load: &mut LoadSim, timer: &mut Timer) {
+        self.restore_edits(map, timer);
+        *load = self.load.clone();
+        let capturing = sim.is_capturing_events();
+        *sim = self.sim.clone();
+        sim.capture_events(capturing);
+    }
+
+    fn restore_edits(&self, map: &mut Map, timer: &mut Timer) {
+        if map.get_edits() != &self.edits {
+            map.must_apply_edits(self.edits.clone(), timer);
+            map.recalculate_pathfinding_after_edits(timer);
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LoadSim {
+    pub scenario: String,
+    pub modifiers: Vec<ScenarioModifier>,
+    pub edits: Option<PermanentMapEdits>,
+    // These are fixed from the initial command line flags, or when the session is created
+    #[serde(skip_deserializing)]
+    pub rng_seed: u64,
+    #[serde(skip_deserializing)]
+    pub opts: SimOptions,
+}
+
+impl LoadSim {
+    pub fn setup(&self, timer: &mut Timer) -> Result<(Map, Sim)> {
+        let mut scenario: Scenario = abstio::read_object(self.scenario.clone(), timer)?;
+
+        let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
+        if let Some(perma) = self.edits.clone() {
+            let edits = perma.into_edits(&map)?;
+            map.must_apply_edits(edits, timer);
+            map.recalculate_pathfinding_after_edits(timer);
+        }
+
+        for m in &self.modifiers {
+            scenario = m.apply(&map, scenario);
+        }
+
+        let mut rng = XorShiftRng::seed_from_u64(self.rng_seed);
+        let mut sim = Sim::new(&map, self.opts.clone());
+        scenario.instantiate(&mut sim, &map, &mut rng, timer);
+
+        Ok((map, sim))
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    name: String,
+    scenario: String,
+    /// None if the session is busy with a command
+    time: Option<Time>,
+}
+
+/// Set the configuration for new sessions, then create the default session.
+pub fn initialize(rng_seed: u64, opts: SimOptions, timer: &mut Timer) {
+    let load = {
+        let mut load = DEFAULT_LOAD.write().unwrap();
+        load.rng_seed = rng_seed;
+        load.opts = opts;
+        load.clone()
+    };
+    insert(DEFAULT_SESSION, Session::new(load, timer).unwrap()).unwrap();
+}
+
+/// The configuration a new session starts with. Callers can then override parts of it.
+pub fn default_load() -> LoadSim {
+    DEFAULT_LOAD.read().unwrap().clone()
+}
+
+pub fn get(name: &str) -> Result<Arc<RwLock<Session>>> {
+    SESSIONS
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| anyhow!("no session named {}", name))
+}
+
+/// Fails if a session with this name already exists.
+pub fn check_unused(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("session names can't be empty");
+    }
+    if SESSIONS.read().unwrap().contains_key(name) {
+        bail!("a session named {} already exists", name);
+    }
+    Ok(())
+}
+
+/// Sessions are slow to set up, so callers should prepare them before calling this, to avoid
+/// blocking everybody else.
+pub fn insert(name: &str, session: Session) -> Result<()> {
+    if name.is_empty() {
+        bail!("session names can't be empty");
+    }
+    // Another request may have used the name while this session was being prepared, so check
+    // again while holding the lock.
+    match SESSIONS.write().unwrap().entry(name.to_string()) {
+        Entry::Occupied(_) => bail!("a session named {} already exists", name),
+        Entry::Vacant(entry) => {
+            entry.insert(Arc::new(RwLock::new(session)));
+        }
+    }
+    Ok(())
+}
+
+/// Commands already running in the session will finish, but no new ones can start.
+pub fn delete(name: &str) -> Result<()> {
+    if SESSIONS.write().unwrap().remove(name).is_none() {
+        bail!("no session named {}", name);
+    }
+    Ok(())
+}
+
+/// Describes every session without waiting for busy ones.
+pub fn list() -> Vec<SessionInfo> {
+    let mut results = Vec::new();
+    for (name, session) in SESSIONS.read().unwrap().iter() {
+        match session.try_read() {
+            Ok(session) => results.push(SessionInfo {
+                name: name.clone(),
+                scenario: session.load.scenario.clone(),
+                time: Some(session.sim.time()),
+            }),
+            Err(_) => results.push(SessionInfo {
+                name: name.clone(),
+                scenario: String::new(),
+                time: None,
+            }),
+        }
+    }
+    results
+}
+
+/// If a command panicked while modifying a session, its state can't be trusted anymore.
+pub fn read<'a>(name: &str, session: &'a RwLock<Session>) -> Result<RwLockReadGuard<'a, Session>> {
+    session
+        .read()
+        .map_err(|_| anyhow!("session {} crashed earlier; delete and recreate it", name))
+}
+
+/// If a command panicked while modifying a session, its state can't be trusted anymore.
+pub fn write<'a>(
+    name: &str,
+    session: &'a RwLock<Session>,
+) -> Result<RwLockWriteGuard<'a, Session>> {
+    session
+        .write()
+        .map_err(|_| anyhow!("session {} crashed earlier; delete and recreate it", name))
+}
@@ -0,0 +1,256 @@
+//! Advance a session's simulation in steps, pushing what changed to the client using server-sent
+//! events. This lets visualizations follow a run live, without polling for full snapshots.
+//!
+//! > curl -N "http://localhost:1234/sim/stream?t=08:00:00&step=5&events=TripFinished,Alert"
+//!
+//! After each step, a `step` message has the current time, agents that appeared or moved, agents
+//! that disappeared, and any events requested. `events` is a comma-separated list of `Event`
+//! names, or `all`; by default, no events are sent. A final `done` or `error` message ends the
+//! stream.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Response};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use abstutil::Timer;
+use geom::{Duration, LonLat, Time};
+use sim::{AgentID, Event};
+
+use crate::sessions::{self, Session};
+use crate::AgentPosition;
+
+// How many messages can be waiting for a slow client before the simulation pauses
+const BUFFER_SIZE: usize = 16;
+
+#[derive(Serialize)]
+struct StepUpdate {
+    time: Time,
+    /// Agents that appeared or moved since the last update
+    moved: Vec<AgentPosition>,
+    /// Agents that finished or otherwise vanished since the last update
+    removed: Vec<AgentID>,
+    events: Vec<(Time, Event)>,
+}
+
+enum EventFilter {
+    Nothing,
+    Everything,
+    Named(BTreeSet<String>),
+}
+
+impl EventFilter {
+    fn new(param: Option<&String>) -> EventFilter {
+        match param.map(|x| x.as_str()) {
+            None | Some("") => EventFilter::Nothing,
+            Some("all") => EventFilter::Everything,
+            Some(list) => EventFilter::Named(list.split(',').map(|x| x.to_string()).collect()),
+        }
+    }
+
+    fn matches(&self, ev: &Event) -> bool {
+        match self {
+            EventFilter::Nothing => false,
+            EventFilter::Everything => true,
+            EventFilter::Named(names) => names.contains(ev.name()),
+        }
+    }
+}
+
+/// Validates the request and starts the stream. Must be called from within the tokio runtime.
+pub fn start(name: &str, params: &HashMap<String, String>) -> Result<Response<Body>> {
+    let session = sessions::get(name)?;
+    let end_time = Time::parse(
+        params
+            .get("t")
+            .ok_or_else(|| anyhow!("missing GET parameter t"))?,
+    )?;
+    let step = Duration::seconds(match params.get("step") {
+        Some(x) => x.parse::<f64>()?,
+        None => 1.0,
+    });
+    if step <= Duration::ZERO {
+        bail!("step must be positive");
+    }
+    let filter = EventFilter::new(params.get("events"));
+    let capture = !matches!(filter, EventFilter::Nothing);
+
+    {
+        let mut session = sessions::write(name, &session)?;
+        if end_time <= session.sim.time() {
+            bail!("{} is in the past. call /sim/reset first?", end_time);
+        }
+        if capture {
+            // Two streams would steal each other's events
+            if session.sim.is_capturing_events() {
+                bail!(
+                    "another stream is already sending events from session {}",
+                    name
+                );
+            }
+            session.sim.capture_events(true);
+        }
+    }
+
+    let (tx, mut rx) = mpsc::channel::<String>(BUFFER_SIZE);
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            if sender.send_data(msg.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        match run(&name, &session, end_time, step, &filter, &tx) {
+            Ok(()) => {
+                let _ = tx.blocking_send(message("done", &end_time));
+            }
+            Err(err) => {
+                error!("Streaming session {} failed: {}", name, err);
+                let _ = tx.blocking_send(message("error", &err.to_string()));
+            }
+        }
+        if capture {
+            if let Ok(mut session) = session.write() {
+                session.sim.capture_events(false);
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap())
+}
+
+/// Only holds the session's lock during each step, so other commands can run in between.
+fn run(
+    name: &str,
+    session: &Arc<RwLock<Session>>,
+    end_time: Time,
+    step: Duration,
+    filter: &EventFilter,
+    tx: &mpsc::Sender<String>,
+) -> Result<()> {
+    let mut last_positions: BTreeMap<AgentID, LonLat> = BTreeMap::new();
+    loop {
+        let update = {
+            let mut session = sessions::write(name, session)?;
+            let Session { map, sim, .. } = &mut *session;
+            if sim.time() >= end_time {
+                return Ok(());
+            }
+            let before = sim.time();
+            sim.timed_step(
+                map,
+                step.min(end_time - before),
+                &mut None,
+                &mut Timer::throwaway(),
+            );
+            if sim.time() == before {
+                bail!("the simulation is stuck at {}", before);
+            }
+
+            let mut moved = Vec::new();
+            let mut current_positions = BTreeMap::new();
+            for agent in crate::agent_positions(map, sim) {
+                current_positions.insert(agent.id, agent.pos);
+                if last_positions.get(&agent.id) != Some(&agent.pos) {
+                    moved.push(agent);
+                }
+            }
+            let removed = last_positions
+                .keys()
+                .filter(|id| !current_positions.contains_key(id))
+                .cloned()
+                .collect();
+            last_positions = current_positions;
+
+            StepUpdate {
+                time: sim.time(),
+                moved,
+                removed,
+                events: sim
+                    .take_captured_events()
+                    .into_iter()
+                    .filter(|(_, ev)| filter.matches(ev))
+                    .collect(),
+            }
+        };
+        if tx.blocking_send(message("step", &update)).is_err() {
+            // The client disconnected
+            return Ok(());
+        }
+    }
+}
+
+fn message<T: Serialize>(event: &str, data: &T) -> String {
+    // Each line of data needs a prefix, so keep the JSON on one line
+    format!(
+        "event: {}\ndata: {}\n\n",
+        event,
+        serde_json::to_string(data).unwrap()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_session;
+
+    #[test]
+    fn test_stream_updates() {
+        create_session("stream");
+        let session = sessions::get("stream").unwrap();
+        session.write().unwrap().sim.capture_events(true);
+        // Big enough to never pause the simulation
+        let (tx, mut rx) = mpsc::channel::<String>(1000);
+        let end_time = Time::START_OF_DAY + Duration::minutes(10);
+        let filter = EventFilter::new(Some(&"TripFinished".to_string()));
+        run(
+            "stream",
+            &session,
+            end_time,
+            Duration::seconds(30.0),
+            &filter,
+            &tx,
+        )
+        .unwrap();
+        drop(tx);
+
+        let mut num_steps = 0;
+        let mut num_finished = 0;
+        let mut appeared = BTreeSet::new();
+        while let Some(msg) = rx.blocking_recv() {
+            let data = msg.strip_prefix("event: step\ndata: ").unwrap();
+            let update: serde_json::Value = serde_json::from_str(data.trim_end()).unwrap();
+            num_steps += 1;
+
+            for agent in update["moved"].as_array().unwrap() {
+                appeared.insert(agent["id"].to_string());
+            }
+            // Agents are only removed after they've been sent
+            for id in update["removed"].as_array().unwrap() {
+                assert!(appeared.contains(&id.to_string()));
+            }
+            for pair in update["events"].as_array().unwrap() {
+                assert!(pair[1].get("TripFinished").is_some());
+                num_finished += 1;
+            }
+        }
+        assert_eq!(num_steps, 20);
+        assert!(!appeared.is_empty());
+        assert!(num_finished > 0);
+        assert_eq!(session.read().unwrap().sim.time(), end_time);
+
+        sessions::delete("stream").unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+//! These run the server's commands directly, without going through HTTP. They share the global
+//! sessions, so every test uses its own session names.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Once;
+
+use anyhow::Result;
+
+use abstio::MapName;
+use abstutil::Timer;
+use geom::{Distance, Duration, Speed, Time};
+use map_model::{ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, Map};
+use sim::{
+    IndividTrip, PersonSpec, Scenario, SimFlags, SimOptions, TripEndpoint, TripMode, TripPurpose,
+};
+
+use crate::sessions::{self, LoadSim, Session};
+
+static SAVE_INPUT: Once = Once::new();
+
+/// Sessions load their map and scenario from files, so the first call imports a small synthetic
+/// map and saves it, along with a scenario of cars driving between every pair of borders.
+fn test_load() -> LoadSim {
+    let name = MapName::new("zz", "oneshot", "parallel_routes");
+    SAVE_INPUT.call_once(|| {
+        let map = import_map(abstio::path("../tests/input/parallel_routes.osm"));
+        assert_eq!(map.get_name(), &name);
+        map.save();
+        cars_scenario(&map).save();
+    });
+
+    let mut opts = SimOptions::new("headless_tests");
+    opts.alerts = sim::AlertHandler::Silence;
+    LoadSim {
+        scenario: abstio::path_scenario(&name, "cars"),
+        modifiers: Vec::new(),
+        edits: None,
+        rng_seed: SimFlags::RNG_SEED,
+        opts,
+    }
+}
+
+fn import_map(path: String) -> Map {
+    let mut timer = Timer::throwaway();
+    let raw = convert_osm::convert(
+        convert_osm::Options {
+            name: MapName::new("zz", "oneshot", &abstutil::basename(&path)),
+            osm_input: path,
+            clip: None,
+            map_config: map_model::MapConfig {
+                driving_side: map_model::DrivingSide::Right,
+                bikes_can_use_bus_lanes: true,
+                inferred_sidewalks: true,
+                street_parking_spot_length: Distance::meters(8.0),
+            },
+            onstreet_parking: convert_osm::OnstreetParking::JustOSM,
+            public_offstreet_parking: convert_osm::PublicOffstreetParking::None,
+            private_offstreet_parking: convert_osm::PrivateOffstreetParking::FixedPerBldg(0),
+            include_railroads: true,
+            extra_buildings: None,
+            elevation: None,
+            skip_local_roads: false,
+        },
+        &mut timer,
+    );
+    Map::create_from_raw(raw, map_model::RawToMapOptions::default(), &mut timer)
+}
+
+/// Every minute for half an hour, somebody drives between each pair of borders.
+fn cars_scenario(map: &Map) -> Scenario {
+    let mut scenario = Scenario::empty(map, "cars");
+    for minute in 0..30 {
+        for from in map.all_incoming_borders() {
+            for to in map.all_outgoing_borders() {
+                if from.id == to.id {
+                    continue;
+                }
+                scenario.people.push(PersonSpec {
+                    orig_id: None,
+                    trips: vec![IndividTrip::new(
+                        Time::START_OF_DAY + Duration::minutes(minute),
+                        TripPurpose::Work,
+                        TripEndpoint::Border(from.id),
+                        TripEndpoint::Border(to.id),
+                        TripMode::Drive,
+                    )],
+                });
+            }
+        }
+    }
+    scenario
+}
+
+pub fn create_session(name: &str) {
+    sessions::insert(
+        name,
+        Session::new(test_load(), &mut Timer::throwaway()).unwrap(),
+    )
+    .unwrap();
+}
+
+/// Runs a command, like the server would.
+fn run(path: &str, params: &[(&str, &str)], body: &str) -> Result<String> {
+    let params: HashMap<String, String> = params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    crate::dispatch(path, &params, body.as_bytes())
+}
+
+fn get_time(session: &str) -> Time {
+    Time::parse(&run("/sim/get-time", &[("session", session)], "").unwrap()).unwrap()
+}
+
+fn time(hms: &str) -> Time {
+    Time::parse(hms).unwrap()
+}
+
+#[test]
+fn test_sessions_are_isolated() {
+    let load = test_load();
+    let body = format!(
+        r#"{{"scenario": "{}", "modifiers": [], "edits": null}}"#,
+        load.scenario
+    );
+    for name in ["isolated_1", "isolated_2"] {
+        run("/sessions/create", &[("name", name)], &body).unwrap();
+    }
+    assert!(run("/sessions/create", &[("name", "isolated_1")], &body).is_err());
+
+    run(
+        "/sim/goto-time",
+        &[("session", "isolated_1"), ("t", "00:10:00")],
+        "",
+    )
+    .unwrap();
+    assert_eq!(get_time("isolated_1"), time("00:10:00"));
+    assert_eq!(get_time("isolated_2"), Time::START_OF_DAY);
+
+    // Loading a scenario that doesn't exist fails, without disturbing the session
+    assert!(run(
+        "/sim/load",
+        &[("session", "isolated_1")],
+        r#"{"scenario": "no_such_scenario.bin", "modifiers": [], "edits": null}"#,
+    )
+    .is_err());
+    assert_eq!(get_time("isolated_1"), time("00:10:00"));
+
+    // A session prepared while another one took the name can't replace it
+    let duplicate = Session::new(test_load(), &mut Timer::throwaway()).unwrap();
+    assert!(sessions::insert("isolated_2", duplicate).is_err());
+
+    // Trips only finish in the session that ran
+    let num_finished = |session: &str| {
+        let trips: Vec<serde_json::Value> = serde_json::from_str(
+            &run("/data/get-finished-trips", &[("session", session)], "").unwrap(),
+        )
+        .unwrap();
+        trips.len()
+    };
+    assert!(num_finished("isolated_1") > 0);
+    assert_eq!(num_finished("isolated_2"), 0);
+
+    let list: Vec<serde_json::Value> =
+        serde_json::from_str(&run("/sessions/list", &[], "").unwrap()).unwrap();
+    for name in ["isolated_1", "isolated_2"] {
+        assert!(list.iter().any(|s| s["name"] == name));
+    }
+
+    for name in ["isolated_1", "isolated_2"] {
+        run("/sessions/delete", &[("name", name)], "").unwrap();
+    }
+    assert!(run("/sim/get-time", &[("session", "isolated_1")], "").is_err());
+}
+
+#[test]
+fn test_delete_busy_session() {
+    create_session("busy");
+    let session = sessions::get("busy").unwrap();
+    {
+        // Pretend a long command is running
+        let mut busy = sessions::write("busy", &session).unwrap();
+
+        let list: Vec<serde_json::Value> =
+            serde_json::from_str(&run("/sessions/list", &[], "").unwrap()).unwrap();
+        let info = list.iter().find(|s| s["name"] == "busy").unwrap();
+        assert!(info["time"].is_null());
+
+        // Deleting doesn't wait for the command
+        run("/sessions/delete", &[("name", "busy")], "").unwrap();
+        assert!(run("/sim/get-time", &[("session", "busy")], "").is_err());
+
+        // But the command can still finish
+        let Session { map, sim, .. } = &mut *busy;
+        sim.timed_step(
+            map,
+            Duration::minutes(5),
+            &mut None,
+            &mut Timer::throwaway(),
+        );
+        assert_eq!(sim.time(), time("00:05:00"));
+    }
+
+    // The name can be reused right away
+    create_session("busy");
+    assert_eq!(get_time("busy"), Time::START_OF_DAY);
+    run("/sessions/delete", &[("name", "busy")], "").unwrap();
+}
+
+#[test]
+fn test_snapshots_and_forks() {
+    create_session("snapshots");
+    let goto = |session: &str, t: &str| {
+        run("/sim/goto-time", &[("session", session), ("t", t)], "").unwrap();
+    };
+    let finished_trips =
+        |session: &str| run("/data/get-finished-trips", &[("session", session)], "").unwrap();
+
+    goto("snapshots", "00:05:00");
+    run(
+        "/sim/save-snapshot",
+        &[("session", "snapshots"), ("name", "five")],
+        "",
+    )
+    .unwrap();
+    goto("snapshots", "00:10:00");
+    let list: BTreeMap<String, Time> =
+        serde_json::from_str(&run("/sim/list-snapshots", &[("session", "snapshots")], "").unwrap())
+            .unwrap();
+    assert_eq!(list["five"], time("00:05:00"));
+
+    // Forking without a snapshot copies the current state
+    run(
+        "/sessions/fork",
+        &[("session", "snapshots"), ("name", "fork_now")],
+        "",
+    )
+    .unwrap();
+    assert_eq!(get_time("fork_now"), time("00:10:00"));
+    assert_eq!(finished_trips("fork_now"), finished_trips("snapshots"));
+
+    run(
+        "/sessions/fork",
+        &[
+            ("session", "snapshots"),
+            ("name", "fork_five"),
+            ("snapshot", "five"),
+        ],
+        "",
+    )
+    .unwrap();
+    assert_eq!(get_time("fork_five"), time("00:05:00"));
+    assert!(run(
+        "/sessions/fork",
+        &[
+            ("session", "snapshots"),
+            ("name", "fork_missing"),
+            ("snapshot", "missing"),
+        ],
+        "",
+    )
+    .is_err());
+
+    run(
+        "/sim/restore-snapshot",
+        &[("session", "snapshots"), ("name", "five")],
+        "",
+    )
+    .unwrap();
+    assert_eq!(get_time("snapshots"), time("00:05:00"));
+
+    // The restored session and the fork from the same snapshot play out the same way
+    for session in ["snapshots", "fork_five"] {
+        goto(session, "00:15:00");
+    }
+    assert_eq!(finished_trips("snapshots"), finished_trips("fork_five"));
+
+    for name in ["snapshots", "fork_now", "fork_five"] {
+        run("/sessions/delete", &[("name", name)], "").unwrap();
+    }
+}
+
+#[test]
+fn test_rl_episodes() {
+    create_session("rl");
+    // The synthetic map only has stop signs, so make a traffic signal first. Resetting keeps it.
+    let (i, cmd) = {
+        let session = sessions::get("rl").unwrap();
+        let session = session.read().unwrap();
+        let map = &session.map;
+        let i = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.roads.len() >= 3)
+            .unwrap()
+            .id;
+        let cmd = EditCmd::ChangeIntersection {
+            i,
+            old: map.get_i_edit(i),
+            new: EditIntersection::TrafficSignal(ControlTrafficSignal::new(map, i).export(map)),
+        };
+        (i, abstutil::to_json(&cmd.to_perma(map)))
+    };
+    run("/map/apply-edit", &[("session", "rl")], &cmd).unwrap();
+
+    assert!(run("/rl/reset", &[("session", "rl")], "").is_err());
+    let config = serde_json::json!({
+        "signals": [i.0],
+        "step_seconds": 10.0,
+        "episode_start": "00:05:00",
+        "episode_end": "00:10:00"
+    });
+    run("/rl/configure", &[("session", "rl")], &config.to_string()).unwrap();
+
+    // The second episode restores the cached start
+    for _ in 0..2 {
+        let observation: Vec<serde_json::Value> =
+            serde_json::from_str(&run("/rl/reset", &[("session", "rl")], "").unwrap()).unwrap();
+        assert_eq!(observation.len(), 1);
+        assert_eq!(get_time("rl"), time("00:05:00"));
+        let num_delays_before = delays_at("rl", i).len();
+
+        let mut num_steps = 0;
+        let mut total_reward = 0.0;
+        loop {
+            // Alternate between keeping and advancing the stage
+            let body = format!(r#"{{"actions": [{}]}}"#, num_steps % 2);
+            let result: serde_json::Value =
+                serde_json::from_str(&run("/rl/step", &[("session", "rl")], &body).unwrap())
+                    .unwrap();
+            let reward = result["reward"].as_f64().unwrap();
+            assert!(reward <= 0.0);
+            total_reward += reward;
+            num_steps += 1;
+            if result["done"].as_bool().unwrap() {
+                break;
+            }
+        }
+        assert_eq!(num_steps, 30);
+        assert_eq!(get_time("rl"), time("00:10:00"));
+        assert!(run("/rl/step", &[("session", "rl")], r#"{"actions": [0]}"#).is_err());
+
+        // Every delay during the episode counts exactly once
+        let expected: f64 = delays_at("rl", i)[num_delays_before..]
+            .iter()
+            .map(|dt| -dt.inner_seconds())
+            .sum();
+        assert!((total_reward - expected).abs() < 1e-6);
+    }
+
+    // Configuring a new environment hands the signal back to its normal timing
+    let is_controlled = || {
+        let session = sessions::get("rl").unwrap();
+        let session = session.read().unwrap();
+        session.sim.is_signal_externally_controlled(i)
+    };
+    assert!(is_controlled());
+    run("/rl/configure", &[("session", "rl")], &config.to_string()).unwrap();
+    assert!(!is_controlled());
+
+    run("/sessions/delete", &[("name", "rl")], "").unwrap();
+}
+
+fn delays_at(session: &str, i: IntersectionID) -> Vec<Duration> {
+    let session = sessions::get(session).unwrap();
+    let session = session.read().unwrap();
+    session
+        .sim
+        .get_analytics()
+        .intersection_delays
+        .get(&i)
+        .map(|list| list.iter().map(|(_, _, dt, _)| *dt).collect())
+        .unwrap_or_else(Vec::new)
+}
+
+#[test]
+fn test_live_edits() {
+    create_session("edits");
+    let goto = |t: &str| {
+        run("/sim/goto-time", &[("session", "edits"), ("t", t)], "").unwrap();
+    };
+    let list_edits = || {
+        let list: Vec<serde_json::Value> =
+            serde_json::from_str(&run("/map/list-edits", &[("session", "edits")], "").unwrap())
+                .unwrap();
+        list.len()
+    };
+
+    let (slow_down, close, close_edits) = {
+        let session = sessions::get("edits").unwrap();
+        let session = session.read().unwrap();
+        let map = &session.map;
+        let slow_down = map.edit_road_cmd(map.all_roads().next().unwrap().id, |new| {
+            new.speed_limit = Speed::miles_per_hour(10.0);
+        });
+        // Every intersection with 3 roads has one going to a border. Closing it strands that
+        // road's sidewalks.
+        let i = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.roads.len() >= 3)
+            .unwrap()
+            .id;
+        let close = EditCmd::ChangeIntersection {
+            i,
+            old: map.get_i_edit(i),
+            new: EditIntersection::Closed,
+        };
+        let mut close_edits = map.get_edits().clone();
+        close_edits.commands.push(close.clone());
+        (
+            abstutil::to_json(&slow_down.to_perma(map)),
+            abstutil::to_json(&close.to_perma(map)),
+            abstutil::to_json(&close_edits.to_permanent(map)),
+        )
+    };
+
+    goto("00:02:00");
+    assert_eq!(list_edits(), 0);
+    assert!(run("/map/undo-edit", &[("session", "edits")], "").is_err());
+
+    let result: serde_json::Value =
+        serde_json::from_str(&run("/map/apply-edit", &[("session", "edits")], &slow_down).unwrap())
+            .unwrap();
+    assert!(result["cancelled_trips"].is_array());
+    assert_eq!(list_edits(), 1);
+    goto("00:04:00");
+
+    // Rejected edits don't change anything, and new trips can still be routed afterwards
+    let err = run("/map/apply-edit", &[("session", "edits")], &close).unwrap_err();
+    assert!(err.to_string().contains("sidewalks disconnected"));
+    assert_eq!(list_edits(), 1);
+    goto("00:06:00");
+
+    // Same for just checking edits
+    let report: serde_json::Value = serde_json::from_str(
+        &run("/map/validate-edits", &[("session", "edits")], &close_edits).unwrap(),
+    )
+    .unwrap();
+    assert!(!report["problems"].as_array().unwrap().is_empty());
+    goto("00:08:00");
+
+    run("/map/undo-edit", &[("session", "edits")], "").unwrap();
+    assert_eq!(list_edits(), 0);
+    goto("00:10:00");
+
+    run("/sessions/delete", &[("name", "edits")], "").unwrap();
+}
@@ -29,6 +29,12 @@ pub struct GenericCityImporter {
     pub include_railroads: bool,
     /// If provided, read polygons from this GeoJSON file and add them to the RawMap as buildings.
     pub extra_buildings: Option<String>,
+    /// If provided, sample elevation from this local GeoTIFF DEM, which must use WGS84
+    /// longitude/latitude.
+    pub elevation: Option<String>,
+    /// If provided, replace transit routes from OSM with ones from this GTFS feed. This should be
+    /// a local path to a .zip file.
+    pub gtfs_zip: Option<String>,
 }
 
 impl GenericCityImporter {
@@ -79,6 +85,7 @@ impl GenericCityImporter {
                 private_offstreet_parking: self.private_offstreet_parking.clone(),
                 include_railroads: self.include_railroads,
                 extra_buildings: self.extra_buildings.clone(),
+                elevation: self.elevation.clone(),
                 // TODO Total hack! Need to figure out how to express per-map config overrides
                 skip_local_roads: name == MapName::new("us", "phoenix", "loop101"),
             },
@@ -0,0 +1,493 @@
+//! Import public transit routes and their schedules from any GTFS feed. See
+//! <https://gtfs.org/reference/static> for the format. Only stops.txt, routes.txt, trips.txt,
+//! stop_times.txt, and shapes.txt are used.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use abstutil::{MultiMap, Timer};
+use geom::{Distance, Duration, LonLat, Pt2D, Time};
+use map_model::{Map, ScheduledRoute, ScheduledStop, Timetable};
+
+/// Replace all transit routes in the map with ones from a GTFS feed, stored as a .zip file.
+pub fn import(map: &mut Map, zip_path: &str, timer: &mut Timer) -> Result<()> {
+    timer.start(format!("read GTFS from {}", zip_path));
+    let mut archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+    let stops: Vec<StopRecord> = read_csv(&mut archive, "stops.txt")?;
+    let routes: Vec<RouteRecord> = read_csv(&mut archive, "routes.txt")?;
+    let trips: Vec<TripRecord> = read_csv(&mut archive, "trips.txt")?;
+    let stop_times: Vec<StopTimeRecord> = read_csv(&mut archive, "stop_times.txt")?;
+    // shapes.txt is optional
+    let shapes: Vec<ShapeRecord> = if archive.by_name("shapes.txt").is_ok() {
+        read_csv(&mut archive, "shapes.txt")?
+    } else {
+        Vec::new()
+    };
+    timer.stop(format!("read GTFS from {}", zip_path));
+
+    let routes = make_routes(map, stops, routes, trips, stop_times, shapes, timer)?;
+    info!(
+        "Matching {} GTFS routes to {}",
+        routes.len(),
+        map.get_name().describe()
+    );
+    map.hack_replace_transit_routes(routes, timer);
+    Ok(())
+}
+
+fn read_csv<T: DeserializeOwned>(
+    archive: &mut zip::ZipArchive<File>,
+    name: &str,
+) -> Result<Vec<T>> {
+    let mut raw = Vec::new();
+    archive.by_name(name)?.read_to_end(&mut raw)?;
+    parse_csv(&raw, name)
+}
+
+fn parse_csv<T: DeserializeOwned>(raw: &[u8], name: &str) -> Result<Vec<T>> {
+    let mut results = Vec::new();
+    for rec in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(raw)
+        .deserialize()
+    {
+        results.push(rec.map_err(|err| anyhow!("{}: {}", name, err))?);
+    }
+    Ok(results)
+}
+
+fn make_routes(
+    map: &Map,
+    stops: Vec<StopRecord>,
+    routes: Vec<RouteRecord>,
+    trips: Vec<TripRecord>,
+    stop_times: Vec<StopTimeRecord>,
+    shapes: Vec<ShapeRecord>,
+    timer: &mut Timer,
+) -> Result<Vec<ScheduledRoute>> {
+    let gps_bounds = map.get_gps_bounds();
+    let boundary = map.get_boundary_polygon();
+
+    let mut stop_lookup: HashMap<String, (String, LonLat)> = HashMap::new();
+    for s in stops {
+        // Stations and entrances don't have trips stopping at them directly.
+        if s.location_type.unwrap_or(0) != 0 {
+            continue;
+        }
+        if let (Some(lon), Some(lat)) = (s.stop_lon, s.stop_lat) {
+            stop_lookup.insert(
+                s.stop_id.clone(),
+                (s.stop_name.unwrap_or(s.stop_id), LonLat::new(lon, lat)),
+            );
+        }
+    }
+
+    let route_lookup: HashMap<String, RouteRecord> = routes
+        .into_iter()
+        .filter(|r| r.is_bus().is_some())
+        .map(|r| (r.route_id.clone(), r))
+        .collect();
+
+    let mut shape_pts: BTreeMap<String, Vec<(usize, LonLat)>> = BTreeMap::new();
+    for s in shapes {
+        shape_pts.entry(s.shape_id).or_insert_with(Vec::new).push((
+            s.shape_pt_sequence,
+            LonLat::new(s.shape_pt_lon, s.shape_pt_lat),
+        ));
+    }
+    let shape_pts: BTreeMap<String, Vec<Pt2D>> = shape_pts
+        .into_iter()
+        .map(|(id, mut pts)| {
+            pts.sort_by_key(|(seq, _)| *seq);
+            (
+                id,
+                pts.into_iter()
+                    .map(|(_, pt)| pt.to_pt(gps_bounds))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    // A feed covers many days, but we only simulate one. Without calendar.txt, just pick the
+    // service with the most trips, which is usually a typical weekday.
+    let mut trips_per_service: BTreeMap<String, usize> = BTreeMap::new();
+    for trip in &trips {
+        if route_lookup.contains_key(&trip.route_id) {
+            *trips_per_service
+                .entry(trip.service_id.clone())
+                .or_insert(0) += 1;
+        }
+    }
+    let service = trips_per_service
+        .into_iter()
+        .max_by_key(|(_, cnt)| *cnt)
+        .map(|(id, _)| id)
+        .ok_or_else(|| anyhow!("no bus or light rail trips"))?;
+    let trips: HashMap<String, TripRecord> = trips
+        .into_iter()
+        .filter(|trip| trip.service_id == service && route_lookup.contains_key(&trip.route_id))
+        .map(|trip| (trip.trip_id.clone(), trip))
+        .collect();
+
+    let mut times_per_trip: BTreeMap<String, Vec<StopTimeRecord>> = BTreeMap::new();
+    for st in stop_times {
+        if trips.contains_key(&st.trip_id) && stop_lookup.contains_key(&st.stop_id) {
+            times_per_trip
+                .entry(st.trip_id.clone())
+                .or_insert_with(Vec::new)
+                .push(st);
+        }
+    }
+
+    // Trips visiting the same stops in the same order become one route.
+    let mut patterns: MultiMap<(String, Option<String>, Vec<String>), TripTimes> = MultiMap::new();
+    timer.start_iter("group GTFS trips", times_per_trip.len());
+    for (trip_id, mut times) in times_per_trip {
+        timer.next();
+        times.sort_by_key(|st| st.stop_sequence);
+        let pts: Vec<Pt2D> = times
+            .iter()
+            .map(|st| stop_lookup[&st.stop_id].1.to_pt(gps_bounds))
+            .collect();
+        match interpolate_times(&times, &pts) {
+            Ok(trip_times) => {
+                let trip = &trips[&trip_id];
+                patterns.insert(
+                    (
+                        trip.route_id.clone(),
+                        trip.shape_id.clone(),
+                        times.into_iter().map(|st| st.stop_id).collect(),
+                    ),
+                    trip_times,
+                );
+            }
+            Err(err) => {
+                warn!("Skipping GTFS trip {}: {}", trip_id, err);
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for ((route_id, shape_id, stop_ids), trip_times) in patterns.consume() {
+        let route = &route_lookup[&route_id];
+
+        // Only keep the longest contiguous stretch of stops inside the map.
+        let inside: Vec<bool> = stop_ids
+            .iter()
+            .map(|id| {
+                let gps = stop_lookup[id].1;
+                gps_bounds.contains(gps) && boundary.contains_pt(gps.to_pt(gps_bounds))
+            })
+            .collect();
+        let (first, last) = match longest_run(&inside) {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let trip_times: Vec<TripTimes> = trip_times.into_iter().collect();
+        let mut first_arrivals = Vec::new();
+        let mut arrival_offsets = Vec::new();
+        let mut dwell_times = Vec::new();
+        for idx in first..=last {
+            arrival_offsets.push(median(
+                trip_times.iter().map(|t| t[idx].0 - t[first].0).collect(),
+            ));
+            dwell_times.push(median(
+                trip_times.iter().map(|t| t[idx].1 - t[idx].0).collect(),
+            ));
+        }
+        for t in &trip_times {
+            let mut time = t[first].0;
+            // Maybe we should duplicate these to handle beginning and end of the simulation
+            if time > Time::START_OF_DAY + Duration::hours(24) {
+                time = time - Duration::hours(24);
+            }
+            first_arrivals.push(time);
+        }
+        first_arrivals.sort();
+        first_arrivals.dedup();
+
+        let stops: Vec<ScheduledStop> = stop_ids[first..=last]
+            .iter()
+            .map(|id| {
+                let (name, gps) = &stop_lookup[id];
+                ScheduledStop {
+                    name: name.clone(),
+                    pt: gps.to_pt(gps_bounds),
+                }
+            })
+            .collect();
+        let name = route
+            .route_long_name
+            .clone()
+            .or_else(|| route.route_short_name.clone())
+            .unwrap_or_else(|| route_id.clone());
+        results.push(ScheduledRoute {
+            full_name: format!("{} to {}", name, stop_lookup[stop_ids.last().unwrap()].0),
+            short_name: route
+                .route_short_name
+                .clone()
+                .unwrap_or_else(|| route_id.clone()),
+            gtfs_trip_marker: shape_id.clone(),
+            is_bus: route.is_bus().unwrap(),
+            stops,
+            shape: shape_id
+                .and_then(|id| shape_pts.get(&id).cloned())
+                .unwrap_or_else(Vec::new),
+            starts_off_map: first > 0,
+            ends_off_map: last < stop_ids.len() - 1,
+            first_arrivals,
+            timetable: Timetable {
+                arrival_offsets,
+                dwell_times,
+            },
+        });
+    }
+    Ok(results)
+}
+
+/// (arrival, departure) at each stop
+type TripTimes = Vec<(Time, Time)>;
+
+/// Only the first and last stop of a trip must have times. Fill in the rest based on distance
+/// between stops.
+fn interpolate_times(times: &[StopTimeRecord], pts: &[Pt2D]) -> Result<TripTimes> {
+    let mut known: Vec<Option<(Time, Time)>> = Vec::new();
+    for st in times {
+        let arrival = st
+            .arrival_time
+            .as_ref()
+            .or_else(|| st.departure_time.as_ref());
+        let departure = st
+            .departure_time
+            .as_ref()
+            .or_else(|| st.arrival_time.as_ref());
+        if let (Some(a), Some(d)) = (arrival, departure) {
+            let (a, d) = (Time::parse(a)?, Time::parse(d)?);
+            if d < a {
+                bail!("departs {} before arriving {}", d, a);
+            }
+            known.push(Some((a, d)));
+        } else {
+            known.push(None);
+        }
+    }
+    if known.len() < 2 || known[0].is_none() || known.last().unwrap().is_none() {
+        bail!("first and last stops need times");
+    }
+
+    let mut dist_so_far = vec![Distance::ZERO];
+    for pair in pts.windows(2) {
+        let prev = *dist_so_far.last().unwrap();
+        dist_so_far.push(prev + pair[0].dist_to(pair[1]));
+    }
+
+    let mut result = Vec::new();
+    let mut prev_known = 0;
+    for idx in 0..known.len() {
+        if let Some(pair) = known[idx] {
+            if pair.0 < known[prev_known].unwrap().1 {
+                bail!("goes backwards in time at stop {}", times[idx].stop_id);
+            }
+            result.push(pair);
+            prev_known = idx;
+            continue;
+        }
+        let next_known = (idx..known.len()).find(|i| known[*i].is_some()).unwrap();
+        let depart = known[prev_known].unwrap().1;
+        let arrive = known[next_known].unwrap().0;
+        let total = dist_so_far[next_known] - dist_so_far[prev_known];
+        let pct = if total == Distance::ZERO {
+            0.0
+        } else {
+            (dist_so_far[idx] - dist_so_far[prev_known]) / total
+        };
+        let time = depart + (arrive - depart) * pct;
+        result.push((time, time));
+    }
+    Ok(result)
+}
+
+/// Returns the first and last index of the longest stretch of true values.
+fn longest_run(values: &[bool]) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = None;
+    for (idx, value) in values.iter().enumerate() {
+        if *value {
+            let s = *start.get_or_insert(idx);
+            if best.map(|(a, b)| idx - s > b - a).unwrap_or(true) {
+                best = Some((s, idx));
+            }
+        } else {
+            start = None;
+        }
+    }
+    best
+}
+
+fn median(mut durations: Vec<Duration>) -> Duration {
+    durations.sort();
+    durations[durations.len() / 2]
+}
+
+#[derive(Debug, Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    stop_name: Option<String>,
+    stop_lat: Option<f64>,
+    stop_lon: Option<f64>,
+    location_type: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    route_short_name: Option<String>,
+    route_long_name: Option<String>,
+    route_type: usize,
+}
+
+impl RouteRecord {
+    /// True for buses, false for light rail, None for anything else.
+    fn is_bus(&self) -> Option<bool> {
+        // Also handle the extended route types
+        // (https://developers.google.com/transit/gtfs/reference/extended-route-types)
+        match self.route_type {
+            3 | 700..=799 => Some(true),
+            0 | 900..=999 => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TripRecord {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    shape_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    arrival_time: Option<String>,
+    departure_time: Option<String>,
+    stop_id: String,
+    stop_sequence: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShapeRecord {
+    shape_id: String,
+    shape_pt_lat: f64,
+    shape_pt_lon: f64,
+    shape_pt_sequence: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop_times(csv: &str) -> Vec<StopTimeRecord> {
+        parse_csv(csv.as_bytes(), "stop_times.txt").unwrap()
+    }
+
+    #[test]
+    fn test_interpolate_times() {
+        let pts = vec![
+            Pt2D::new(0.0, 0.0),
+            Pt2D::new(150.0, 0.0),
+            Pt2D::new(300.0, 0.0),
+        ];
+        let at =
+            |h: usize, m: usize| Time::START_OF_DAY + Duration::hours(h) + Duration::minutes(m);
+
+        // The middle stop has no time, so it's halfway between leaving the first and reaching
+        // the last
+        let times = interpolate_times(
+            &stop_times(
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+                t1,08:00:00,08:01:00,a,1
+                t1,,,b,2
+                t1,08:05:00,08:05:00,c,3",
+            ),
+            &pts,
+        )
+        .unwrap();
+        assert_eq!(
+            times,
+            vec![
+                (at(8, 0), at(8, 1)),
+                (at(8, 3), at(8, 3)),
+                (at(8, 5), at(8, 5))
+            ]
+        );
+
+        // Trips running past midnight use times after 24:00:00
+        let times = interpolate_times(
+            &stop_times(
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+                t1,23:58:00,23:58:00,a,1
+                t1,24:00:00,24:00:00,b,2
+                t1,24:02:00,24:02:00,c,3",
+            ),
+            &pts,
+        )
+        .unwrap();
+        assert_eq!(times[2].0, at(24, 2));
+
+        assert!(interpolate_times(
+            &stop_times(
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+                t1,08:00:00,08:00:00,a,1
+                t1,07:59:00,07:59:00,b,2
+                t1,08:05:00,08:05:00,c,3",
+            ),
+            &pts,
+        )
+        .is_err());
+        assert!(interpolate_times(
+            &stop_times(
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+                t1,08:00:00,08:00:00,a,1
+                t1,08:01:00,08:01:00,b,2
+                t1,,,c,3",
+            ),
+            &pts,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_longest_run() {
+        assert_eq!(
+            longest_run(&[true, false, true, true, false, true]),
+            Some((2, 3))
+        );
+        assert_eq!(longest_run(&[true, true, true]), Some((0, 2)));
+        assert_eq!(longest_run(&[false, false]), None);
+    }
+
+    #[test]
+    fn test_route_types() {
+        let routes: Vec<RouteRecord> = parse_csv(
+            "route_id,route_short_name,route_long_name,route_type
+            bus,8,,3
+            tram,T,Tram Line,0
+            express,E,,702
+            ferry,F,,4"
+                .as_bytes(),
+            "routes.txt",
+        )
+        .unwrap();
+        let types: Vec<Option<bool>> = routes.iter().map(|r| r.is_bus()).collect();
+        assert_eq!(types, vec![Some(true), Some(false), Some(true), None]);
+        assert_eq!(routes[0].route_long_name, None);
+    }
+}
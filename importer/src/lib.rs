@@ -19,6 +19,7 @@ use configuration::{load_configuration, ImporterConfiguration};
 mod berlin;
 mod configuration;
 mod generic;
+mod gtfs;
 mod seattle;
 mod soundcast;
 mod uk;
@@ -266,6 +267,21 @@ impl Job {
                         seattle::add_gtfs_schedules(&mut map);
                         timer.stop(format!("add GTFS schedules for {}", name.describe()));
                     }
+                } else if let Ok(city_cfg) = abstio::maybe_read_json::<generic::GenericCityImporter>(
+                    format!(
+                        "importer/config/{}/{}/cfg.json",
+                        self.city.country, self.city.city
+                    ),
+                    timer,
+                ) {
+                    if let Some(path) = city_cfg.gtfs_zip {
+                        timer.start(format!("import GTFS for {}", name.describe()));
+                        match gtfs::import(&mut map, &path, timer) {
+                            Ok(()) => map.save(),
+                            Err(err) => error!("Couldn't import GTFS from {}: {}", path, err),
+                        }
+                        timer.stop(format!("import GTFS for {}", name.describe()));
+                    }
                 }
 
                 Some(map)
@@ -378,6 +394,7 @@ fn oneshot(osm_path: String, clip: Option<String>, drive_on_right: bool, opts: R
             private_offstreet_parking: convert_osm::PrivateOffstreetParking::FixedPerBldg(1),
             include_railroads: true,
             extra_buildings: None,
+            elevation: None,
             skip_local_roads: false,
         },
         &mut timer,
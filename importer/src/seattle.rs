@@ -158,6 +158,7 @@ pub async fn osm_to_raw(name: &str, timer: &mut Timer<'_>, config: &ImporterConf
             // They mess up 16th and E Marginal badly enough to cause gridlock.
             include_railroads: false,
             extra_buildings: None,
+            elevation: None,
             skip_local_roads: false,
         },
         timer,
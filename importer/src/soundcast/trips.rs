@@ -205,8 +205,8 @@ fn clip_trips(
                 borders.for_mode(orig.mode),
                 match orig.mode {
                     TripMode::Walk | TripMode::Transit => PathConstraints::Pedestrian,
-                    TripMode::Drive => PathConstraints::Car,
-                    TripMode::Bike => PathConstraints::Bike,
+                    TripMode::Drive | TripMode::RideHail => PathConstraints::Car,
+                    TripMode::Bike | TripMode::Micromobility => PathConstraints::Bike,
                 },
                 maybe_huge_map.as_ref(),
                 only_passthrough_trips,
@@ -296,6 +296,8 @@ pub fn make_scenario(
         map_name: map.get_name().clone(),
         people,
         only_seed_buses: None,
+        deliveries: Vec::new(),
+        micromobility: None,
     }
     .remove_weird_schedules()
 }
@@ -305,6 +305,7 @@ impl Model {
                 turn_restrictions: Vec::new(),
                 complicated_turn_restrictions: Vec::new(),
                 percent_incline: 0.0,
+                grades: Vec::new(),
             },
         );
         self.road_added(ctx, id);
@@ -115,6 +115,8 @@ pub struct ColorScheme {
 
     // Unzoomed dynamic elements
     pub unzoomed_car: Color,
+    pub unzoomed_ride_hail: Color,
+    pub unzoomed_truck: Color,
     pub unzoomed_bike: Color,
     pub unzoomed_bus: Color,
     pub unzoomed_pedestrian: Color,
@@ -244,6 +246,8 @@ impl ColorScheme {
 
             // Unzoomed dynamic elements
             unzoomed_car: hex("#FE5f55"),
+            unzoomed_ride_hail: hex("#F4A261"),
+            unzoomed_truck: hex("#8D6A9F"),
             unzoomed_bike: hex("#90BE6D"),
             unzoomed_bus: hex("#FFD166"),
             unzoomed_pedestrian: hex("#457B9D"),
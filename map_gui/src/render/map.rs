@@ -54,8 +54,9 @@ impl DrawMap {
         let mut roads: Vec<DrawRoad> = Vec::new();
         let mut low_z = 0;
         let mut high_z = 0;
-        timer.start_iter("make DrawRoads", map.all_roads().len());
-        for r in map.all_roads() {
+        timer.start_iter("make DrawRoads", map.all_roads_with_deleted().len());
+        // Indexed by RoadID, so deleted roads need a placeholder too
+        for r in map.all_roads_with_deleted() {
             timer.next();
             roads.push(DrawRoad::new(r));
             low_z = low_z.min(r.zorder);
@@ -135,6 +136,10 @@ impl DrawMap {
         let mut quadtree_ids = HashMap::new();
         // TODO use iter chain if everything was boxed as a renderable...
         for obj in &roads {
+            // Roads deleted by map edits can't be selected
+            if map.is_road_deleted(obj.id) {
+                continue;
+            }
             let item_id =
                 quadtree.insert_with_box(obj.get_id(), obj.get_outline(map).get_bounds().as_bbox());
             quadtree_ids.insert(obj.get_id(), item_id);
@@ -472,28 +477,58 @@ impl DrawMap {
         batch
     }
 
+    /// Also handles intersections newly created by map edits.
     pub fn recreate_intersection(&mut self, i: IntersectionID, map: &Map) {
-        let item_id = self.quadtree_ids.remove(&ID::Intersection(i)).unwrap();
-        self.quadtree.remove(item_id).unwrap();
+        if let Some(item_id) = self.quadtree_ids.remove(&ID::Intersection(i)) {
+            self.quadtree.remove(item_id).unwrap();
+        }
 
         let draw = DrawIntersection::new(map.get_i(i), map);
         let item_id = self
             .quadtree
             .insert_with_box(draw.get_id(), draw.get_outline(map).get_bounds().as_bbox());
         self.quadtree_ids.insert(draw.get_id(), item_id);
-        self.intersections[i.0] = draw;
+        if i.0 == self.intersections.len() {
+            self.intersections.push(draw);
+        } else {
+            self.intersections[i.0] = draw;
+        }
     }
 
+    /// Also handles roads newly created or deleted by map edits.
     pub fn recreate_road(&mut self, road: &Road, map: &Map) {
-        let item_id = self.quadtree_ids.remove(&ID::Road(road.id)).unwrap();
-        self.quadtree.remove(item_id).unwrap();
+        if let Some(item_id) = self.quadtree_ids.remove(&ID::Road(road.id)) {
+            self.quadtree.remove(item_id).unwrap();
+        }
 
         let draw = DrawRoad::new(road);
-        let item_id = self
-            .quadtree
-            .insert_with_box(draw.get_id(), draw.get_outline(map).get_bounds().as_bbox());
-        self.quadtree_ids.insert(draw.get_id(), item_id);
-        self.roads[road.id.0] = draw;
+        if !map.is_road_deleted(road.id) {
+            let item_id = self
+                .quadtree
+                .insert_with_box(draw.get_id(), draw.get_outline(map).get_bounds().as_bbox());
+            self.quadtree_ids.insert(draw.get_id(), item_id);
+        }
+        if road.id.0 == self.roads.len() {
+            self.roads.push(draw);
+        } else {
+            self.roads[road.id.0] = draw;
+        }
+    }
+
+    /// Undoing structural map edits removes the most recently created roads and intersections.
+    pub fn forget_removed_objects(&mut self, map: &Map) {
+        while self.roads.len() > map.all_roads_with_deleted().len() {
+            let draw = self.roads.pop().unwrap();
+            if let Some(item_id) = self.quadtree_ids.remove(&draw.get_id()) {
+                self.quadtree.remove(item_id).unwrap();
+            }
+        }
+        while self.intersections.len() > map.all_intersections().len() {
+            let draw = self.intersections.pop().unwrap();
+            if let Some(item_id) = self.quadtree_ids.remove(&draw.get_id()) {
+                self.quadtree.remove(item_id).unwrap();
+            }
+        }
     }
 
     pub fn free_memory(&mut self) {
@@ -26,7 +26,6 @@ impl Navigator {
                     ctx,
                     app.map()
                         .all_roads()
-                        .iter()
                         .map(|r| (r.get_name(app.opts().language.as_ref()), r.id))
                         .collect(),
                     10,
@@ -387,12 +387,22 @@ fn fix_lane_widths(value: &mut Value, map: &Map) -> Result<()> {
                     let pair = pair.as_array_mut().unwrap();
                     let lt: LaneType = serde_json::from_value(pair[0].clone()).unwrap();
                     let dir: Direction = serde_json::from_value(pair[1].clone()).unwrap();
+                    // Turn lane restrictions weren't editable either, but they don't make sense
+                    // for a reversed lane.
+                    let orig_lane = &road.lanes[idx];
+                    let allowed_turns = if dir == orig_lane.dir {
+                        orig_lane.allowed_turns.clone()
+                    } else {
+                        None
+                    };
                     lanes_ltr.push(LaneSpec {
                         lt,
                         dir,
                         // Before this commit, lane widths weren't modifiable, so this lookup works
                         // for both "old" and "new".
-                        width: road.lanes[idx].width,
+                        width: orig_lane.width,
+                        allowed_turns,
+                        lane_change: orig_lane.lane_change,
                     });
                 }
                 cmd[key]["lanes_ltr"] = serde_json::to_value(lanes_ltr).unwrap();
@@ -10,18 +10,24 @@ use serde::{Deserialize, Serialize};
 use abstutil::Timer;
 use geom::{Distance, HashablePt2D, Line, Speed, Time};
 
-pub use self::perma::PermanentMapEdits;
+pub use self::perma::{PermanentEditCmd, PermanentMapEdits};
+pub use self::validate::{
+    default_edit_rules, AffectedID, BusRouteRule, EditProblem, EditRule, ProblemSeverity,
+    SidewalkConnectivityRule, TrafficSignalRule, ValidationReport, VehicleConnectivityRule,
+};
 use crate::make::initial::lane_specs::get_lane_specs_ltr;
 use crate::make::{match_points_to_lanes, snap_driveway, trim_path};
 use crate::{
     connectivity, AccessRestrictions, BuildingID, BusRouteID, ControlStopSign,
-    ControlTrafficSignal, Direction, IntersectionID, IntersectionType, LaneID, LaneSpec, LaneType,
-    Map, MapConfig, Movement, ParkingLotID, PathConstraints, Pathfinder, Road, RoadID, TurnID,
-    Zone,
+    ControlTrafficSignal, Direction, IntersectionID, IntersectionType, LaneChange, LaneID,
+    LaneSpec, LaneType, Map, MapConfig, Movement, ParkingLotID, ParkingRegulation,
+    ParkingRegulationTarget, PathConstraints, Pathfinder, Road, RoadID, Toll, TurnID, Zone,
 };
 
 mod compat;
 mod perma;
+mod structural;
+mod validate;
 
 /// Represents changes to a map. Note this isn't serializable -- that's what `PermanentMapEdits`
 /// does.
@@ -40,6 +46,9 @@ pub struct MapEdits {
     pub changed_roads: BTreeSet<RoadID>,
     pub original_intersections: BTreeMap<IntersectionID, EditIntersection>,
     pub changed_routes: BTreeSet<BusRouteID>,
+    pub changed_parking_regulations: BTreeSet<ParkingRegulationTarget>,
+    /// Commands that add, split, or delete roads, in their original order.
+    pub structural_commands: Vec<EditCmd>,
 
     /// Some edits are included in the game by default, in data/system/proposals, as "community
     /// proposals." They require a description and may have a link to a write-up.
@@ -61,6 +70,8 @@ pub struct EditRoad {
     pub lanes_ltr: Vec<LaneSpec>,
     pub speed_limit: Speed,
     pub access_restrictions: AccessRestrictions,
+    #[serde(default)]
+    pub toll: Option<Toll>,
 }
 
 impl EditRoad {
@@ -69,6 +80,7 @@ impl EditRoad {
             lanes_ltr: get_lane_specs_ltr(&r.osm_tags, cfg),
             speed_limit: r.speed_limit_from_osm(),
             access_restrictions: r.access_restrictions_from_osm(),
+            toll: None,
         }
     }
 
@@ -77,6 +89,7 @@ impl EditRoad {
         let mut lt = 0;
         let mut dir = 0;
         let mut width = 0;
+        let mut turns = 0;
         for (spec1, spec2) in self.lanes_ltr.iter().zip(other.lanes_ltr.iter()) {
             if spec1.lt != spec2.lt {
                 lt += 1;
@@ -87,6 +100,10 @@ impl EditRoad {
             if spec1.width != spec2.width {
                 width += 1;
             }
+            if spec1.allowed_turns != spec2.allowed_turns || spec1.lane_change != spec2.lane_change
+            {
+                turns += 1;
+            }
         }
 
         let mut changes = Vec::new();
@@ -105,12 +122,20 @@ impl EditRoad {
         } else {
             changes.push(format!("{} lane widths", width));
         }
+        if turns == 1 {
+            changes.push("1 lane's turn markings".to_string());
+        } else if turns > 1 {
+            changes.push(format!("{} lanes' turn markings", turns));
+        }
         if self.speed_limit != other.speed_limit {
             changes.push("speed limit".to_string());
         }
         if self.access_restrictions != other.access_restrictions {
             changes.push("access restrictions".to_string());
         }
+        if self.toll != other.toll {
+            changes.push("toll".to_string());
+        }
         changes
     }
 
@@ -131,10 +156,13 @@ impl EditRoad {
                     },
                     // Dummy
                     width: Distance::ZERO,
+                    allowed_turns: None,
+                    lane_change: LaneChange::default(),
                 })
                 .collect(),
             speed_limit: Speed::ZERO,
             access_restrictions: AccessRestrictions::new(),
+            toll: None,
         }
     }
 
@@ -190,8 +218,33 @@ pub enum EditCmd {
         old: Vec<Time>,
         new: Vec<Time>,
     },
+    ChangeParkingRegulation {
+        target: ParkingRegulationTarget,
+        old: ParkingRegulation,
+        new: ParkingRegulation,
+    },
+    /// Connects two intersections with a new, straight road. `r` must be the next unused RoadID;
+    /// use `Map::add_road_cmd` to produce this.
+    AddRoad {
+        r: RoadID,
+        i1: IntersectionID,
+        i2: IntersectionID,
+        road: EditRoad,
+    },
+    /// Cuts a road `dist` along its untrimmed length, creating a new intersection `new_i` there.
+    /// `r` keeps the piece before the cut, and a new road `new_r` covers the rest. Use
+    /// `Map::split_road_cmd` to produce this.
+    SplitRoad {
+        r: RoadID,
+        dist: Distance,
+        new_i: IntersectionID,
+        new_r: RoadID,
+    },
+    /// Disconnects a road from everything. The RoadID remains valid.
+    DeleteRoad { r: RoadID },
 }
 
+#[derive(Default)]
 pub struct EditEffects {
     pub changed_roads: BTreeSet<RoadID>,
     pub deleted_lanes: BTreeSet<LaneID>,
@@ -214,6 +267,8 @@ impl MapEdits {
             changed_roads: BTreeSet::new(),
             original_intersections: BTreeMap::new(),
             changed_routes: BTreeSet::new(),
+            changed_parking_regulations: BTreeSet::new(),
+            structural_commands: Vec::new(),
         }
     }
 
@@ -260,6 +315,8 @@ impl MapEdits {
         self.changed_roads.clear();
         self.original_intersections.clear();
         self.changed_routes.clear();
+        self.changed_parking_regulations.clear();
+        self.structural_commands.clear();
 
         for cmd in &self.commands {
             match cmd {
@@ -274,44 +331,56 @@ impl MapEdits {
                 EditCmd::ChangeRouteSchedule { id, .. } => {
                     self.changed_routes.insert(*id);
                 }
+                EditCmd::ChangeParkingRegulation { target, .. } => {
+                    self.changed_parking_regulations.insert(*target);
+                }
+                EditCmd::AddRoad { .. }
+                | EditCmd::SplitRoad { .. }
+                | EditCmd::DeleteRoad { .. } => {
+                    self.structural_commands.push(cmd.clone());
+                }
             }
         }
 
+        // When loading edits, this runs before structural edits have been applied, so the roads
+        // and intersections they create don't exist yet. Assume those are changed.
         self.changed_roads.retain(|r| {
-            map.get_r_edit(*r) != EditRoad::get_orig_from_osm(map.get_r(*r), &map.config)
+            map.maybe_get_r(*r)
+                .map(|road| map.get_r_edit(*r) != EditRoad::get_orig_from_osm(road, &map.config))
+                .unwrap_or(true)
         });
         self.original_intersections
-            .retain(|i, orig| map.get_i_edit(*i) != orig.clone());
+            .retain(|i, orig| map.maybe_get_i(*i).is_none() || map.get_i_edit(*i) != orig.clone());
         self.changed_routes.retain(|br| {
             let r = map.get_br(*br);
             r.spawn_times != r.orig_spawn_times
         });
+        // The basemap has no regulations, so anything still set was changed.
+        self.changed_parking_regulations
+            .retain(|target| map.get_parking_regulation(*target).is_some());
     }
 
-    /// Assumes update_derived has been called.
-    pub fn compress(&mut self, map: &Map) {
-        for r in &self.changed_roads {
-            self.commands.push(EditCmd::ChangeRoad {
-                r: *r,
-                old: EditRoad::get_orig_from_osm(map.get_r(*r), &map.config),
-                new: map.get_r_edit(*r),
-            });
-        }
-        for (i, old) in &self.original_intersections {
-            self.commands.push(EditCmd::ChangeIntersection {
-                i: *i,
-                old: old.clone(),
-                new: map.get_i_edit(*i),
-            });
-        }
-        for r in &self.changed_routes {
-            let r = map.get_br(*r);
-            self.commands.push(EditCmd::ChangeRouteSchedule {
-                id: r.id,
-                new: r.spawn_times.clone(),
-                old: r.orig_spawn_times.clone(),
-            });
+    /// Merge all commands touching the same object into one. Structural commands stay where they
+    /// are, and nothing is merged across them, because splitting a road copies its lanes as they
+    /// are at that point.
+    pub fn compress(&mut self) {
+        let mut result = Vec::new();
+        let mut run = Vec::new();
+        for cmd in self.commands.drain(..) {
+            match cmd {
+                EditCmd::AddRoad { .. }
+                | EditCmd::SplitRoad { .. }
+                | EditCmd::DeleteRoad { .. } => {
+                    result.extend(compress_run(std::mem::take(&mut run)));
+                    result.push(cmd);
+                }
+                _ => {
+                    run.push(cmd);
+                }
+            }
         }
+        result.extend(compress_run(run));
+        self.commands = result;
     }
 
     /// Pick apart changed_roads and figure out if an entire road was edited, or just a few lanes.
@@ -325,6 +394,7 @@ impl MapEdits {
             // What exactly changed?
             if r.speed_limit != orig.speed_limit
                 || r.access_restrictions != orig.access_restrictions
+                || r.toll != orig.toll
                 // If a lane was added or deleted, figuring out if any were modified is kind of
                 // unclear -- just mark the entire road.
                 || r.lanes.len() != orig.lanes_ltr.len()
@@ -332,7 +402,12 @@ impl MapEdits {
                 roads.insert(r.id);
             } else {
                 for (l, spec) in r.lanes.iter().zip(orig.lanes_ltr.iter()) {
-                    if l.dir != spec.dir || l.lane_type != spec.lt || l.width != spec.width {
+                    if l.dir != spec.dir
+                        || l.lane_type != spec.lt
+                        || l.width != spec.width
+                        || l.allowed_turns != spec.allowed_turns
+                        || l.lane_change != spec.lane_change
+                    {
                         lanes.insert(l.id);
                     }
                 }
@@ -341,6 +416,11 @@ impl MapEdits {
         (lanes, roads)
     }
 
+    /// Do these edits add, split, or delete any roads?
+    pub fn changes_topology(&self) -> bool {
+        !self.structural_commands.is_empty()
+    }
+
     /// Produces an md5sum of the contents of the edits.
     pub fn get_checksum(&self, map: &Map) -> String {
         let bytes = abstutil::to_json(&self.to_permanent(map));
@@ -356,6 +436,82 @@ impl Default for MapEdits {
     }
 }
 
+/// Merge a sequence of non-structural commands, keeping the original state of each object from the
+/// first command and the final state from the last. Objects that wind up unchanged are dropped.
+fn compress_run(run: Vec<EditCmd>) -> Vec<EditCmd> {
+    let mut result: Vec<EditCmd> = Vec::new();
+    for cmd in run {
+        let mut merged = false;
+        for prev in &mut result {
+            merged = match (prev, &cmd) {
+                (
+                    EditCmd::ChangeRoad {
+                        r: r1, new: new1, ..
+                    },
+                    EditCmd::ChangeRoad {
+                        r: r2, new: new2, ..
+                    },
+                ) if r1 == r2 => {
+                    *new1 = new2.clone();
+                    true
+                }
+                (
+                    EditCmd::ChangeIntersection {
+                        i: i1, new: new1, ..
+                    },
+                    EditCmd::ChangeIntersection {
+                        i: i2, new: new2, ..
+                    },
+                ) if i1 == i2 => {
+                    *new1 = new2.clone();
+                    true
+                }
+                (
+                    EditCmd::ChangeRouteSchedule {
+                        id: id1, new: new1, ..
+                    },
+                    EditCmd::ChangeRouteSchedule {
+                        id: id2, new: new2, ..
+                    },
+                ) if id1 == id2 => {
+                    *new1 = new2.clone();
+                    true
+                }
+                (
+                    EditCmd::ChangeParkingRegulation {
+                        target: t1,
+                        new: new1,
+                        ..
+                    },
+                    EditCmd::ChangeParkingRegulation {
+                        target: t2,
+                        new: new2,
+                        ..
+                    },
+                ) if t1 == t2 => {
+                    *new1 = new2.clone();
+                    true
+                }
+                _ => false,
+            };
+            if merged {
+                break;
+            }
+        }
+        if !merged {
+            result.push(cmd);
+        }
+    }
+    result.retain(|cmd| match cmd {
+        EditCmd::ChangeRoad { old, new, .. } => old != new,
+        EditCmd::ChangeIntersection { old, new, .. } => old != new,
+        EditCmd::ChangeRouteSchedule { old, new, .. } => old != new,
+        EditCmd::ChangeParkingRegulation { old, new, .. } => old != new,
+        _ => true,
+    });
+    result
+}
+
 impl EditCmd {
     /// (summary, details)
     pub fn describe(&self, map: &Map) -> (String, Vec<String>) {
@@ -374,41 +530,38 @@ impl EditCmd {
             EditCmd::ChangeRouteSchedule { id, .. } => {
                 format!("reschedule route {}", map.get_br(*id).short_name)
             }
+            EditCmd::ChangeParkingRegulation { target, new, .. } => {
+                details = new.describe();
+                match target {
+                    ParkingRegulationTarget::Road(r) => format!("parking on road #{}", r.0),
+                    ParkingRegulationTarget::Lot(pl) => format!("parking lot #{}", pl.0),
+                    ParkingRegulationTarget::Garage(b) => format!("garage #{}", b.0),
+                }
+            }
+            EditCmd::AddRoad { i1, i2, .. } => format!("new road from {} to {}", i1, i2),
+            EditCmd::SplitRoad { r, .. } => format!("split road #{}", r.0),
+            EditCmd::DeleteRoad { r } => format!("delete road #{}", r.0),
         };
         (summary, details)
     }
 
     // Must be idempotent
-    fn apply(&self, effects: &mut EditEffects, map: &mut Map) {
+    fn apply(&self, effects: &mut EditEffects, map: &mut Map) -> Result<()> {
         match self {
             EditCmd::ChangeRoad { r, ref new, .. } => {
                 if map.get_r_edit(*r) == new.clone() {
-                    return;
+                    return Ok(());
                 }
 
                 modify_lanes(map, *r, new.lanes_ltr.clone(), effects);
                 let road = &mut map.roads[r.0];
                 road.speed_limit = new.speed_limit;
                 road.access_restrictions = new.access_restrictions.clone();
+                road.toll = new.toll.clone();
 
                 effects.changed_roads.insert(road.id);
                 for i in [road.src_i, road.dst_i] {
-                    effects.changed_intersections.insert(i);
-                    let i = &mut map.intersections[i.0];
-                    i.outgoing_lanes.clear();
-                    i.incoming_lanes.clear();
-                    for r in &i.roads {
-                        for lane in &map.roads[r.0].lanes {
-                            if lane.src_i == i.id {
-                                i.outgoing_lanes.push(lane.id);
-                            } else {
-                                assert_eq!(lane.dst_i, i.id);
-                                i.incoming_lanes.push(lane.id);
-                            }
-                        }
-                    }
-
-                    recalculate_turns(i.id, map, effects);
+                    reconnect_intersection(map, i, effects);
                 }
             }
             EditCmd::ChangeIntersection {
@@ -417,7 +570,7 @@ impl EditCmd {
                 ref old,
             } => {
                 if map.get_i_edit(*i) == new.clone() {
-                    return;
+                    return Ok(());
                 }
 
                 map.stop_signs.remove(i);
@@ -450,28 +603,105 @@ impl EditCmd {
             EditCmd::ChangeRouteSchedule { id, new, .. } => {
                 map.bus_routes[id.0].spawn_times = new.clone();
             }
+            EditCmd::ChangeParkingRegulation { target, new, .. } => {
+                if new == &ParkingRegulation::default() {
+                    map.parking_regulations.remove(target);
+                } else {
+                    map.parking_regulations.insert(*target, new.clone());
+                }
+            }
+            EditCmd::AddRoad {
+                r,
+                i1,
+                i2,
+                ref road,
+            } => {
+                if map.maybe_get_r(*r).is_some() {
+                    return Ok(());
+                }
+                structural::add_road(map, *r, *i1, *i2, road, effects)?;
+            }
+            EditCmd::SplitRoad {
+                r,
+                dist,
+                new_i,
+                new_r,
+            } => {
+                if map.maybe_get_r(*new_r).is_some() {
+                    return Ok(());
+                }
+                structural::split_road(map, *r, *dist, *new_i, *new_r, effects)?;
+            }
+            EditCmd::DeleteRoad { r } => {
+                if map.is_road_deleted(*r) {
+                    return Ok(());
+                }
+                structural::delete_road(map, *r, effects);
+            }
         }
+        Ok(())
     }
 
-    fn undo(self) -> EditCmd {
+    /// Reverts a command that was previously applied. Structural edits append roads and
+    /// intersections, so commands must be reverted in the opposite order they were applied.
+    fn unapply(self, effects: &mut EditEffects, map: &mut Map) -> Result<()> {
         match self {
             EditCmd::ChangeRoad { r, old, new } => EditCmd::ChangeRoad {
                 r,
                 old: new,
                 new: old,
-            },
+            }
+            .apply(effects, map),
             EditCmd::ChangeIntersection { i, old, new } => EditCmd::ChangeIntersection {
                 i,
                 old: new,
                 new: old,
-            },
+            }
+            .apply(effects, map),
             EditCmd::ChangeRouteSchedule { id, old, new } => EditCmd::ChangeRouteSchedule {
                 id,
                 old: new,
                 new: old,
-            },
+            }
+            .apply(effects, map),
+            EditCmd::ChangeParkingRegulation { target, old, new } => {
+                EditCmd::ChangeParkingRegulation {
+                    target,
+                    old: new,
+                    new: old,
+                }
+                .apply(effects, map)
+            }
+            EditCmd::AddRoad { r, .. } => structural::remove_added_road(map, r, effects),
+            EditCmd::SplitRoad {
+                r, new_i, new_r, ..
+            } => structural::unsplit_road(map, r, new_i, new_r, effects),
+            EditCmd::DeleteRoad { r } => {
+                structural::restore_road(map, r, effects);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Recalculate the lanes entering and leaving an intersection, then its turns.
+fn reconnect_intersection(map: &mut Map, id: IntersectionID, effects: &mut EditEffects) {
+    effects.changed_intersections.insert(id);
+    let i = &mut map.intersections[id.0];
+    i.outgoing_lanes.clear();
+    i.incoming_lanes.clear();
+    for r in &i.roads {
+        for lane in &map.roads[r.0].lanes {
+            if lane.src_i == i.id {
+                i.outgoing_lanes.push(lane.id);
+            } else {
+                assert_eq!(lane.dst_i, i.id);
+                i.incoming_lanes.push(lane.id);
+            }
         }
     }
+
+    recalculate_turns(id, map, effects);
 }
 
 // This clobbers previously set traffic signal overrides.
@@ -532,14 +762,12 @@ fn modify_lanes(map: &mut Map, r: RoadID, lanes_ltr: Vec<LaneSpec>, effects: &mu
         let changed_road_width = lanes_ltr.iter().map(|spec| spec.width).sum();
         road_geom_changed.extend(recalculate_intersection_polygon(
             map,
-            r,
-            changed_road_width,
+            Some((r, changed_road_width)),
             src_i,
         ));
         road_geom_changed.extend(recalculate_intersection_polygon(
             map,
-            r,
-            changed_road_width,
+            Some((r, changed_road_width)),
             dst_i,
         ));
     }
@@ -555,7 +783,16 @@ fn modify_lanes(map: &mut Map, r: RoadID, lanes_ltr: Vec<LaneSpec>, effects: &mu
     }
 
     // We might've affected the geometry of other nearby roads.
-    for r in road_geom_changed {
+    recreate_lanes_after_geometry_change(map, road_geom_changed, effects);
+    effects.modified_lanes.extend(effects.deleted_lanes.clone());
+}
+
+fn recreate_lanes_after_geometry_change(
+    map: &mut Map,
+    roads: Vec<RoadID>,
+    effects: &mut EditEffects,
+) {
+    for r in roads {
         effects.changed_roads.insert(r);
         let lane_specs = map.get_r(r).lane_specs();
         let road = &mut map.roads[r.0];
@@ -564,14 +801,13 @@ fn modify_lanes(map: &mut Map, r: RoadID, lanes_ltr: Vec<LaneSpec>, effects: &mu
             effects.modified_lanes.insert(lane.id);
         }
     }
-    effects.modified_lanes.extend(effects.deleted_lanes.clone());
 }
 
-// Returns the other roads affected by this change, not counting changed_road.
+// Returns the other roads affected by this change, not counting changed_road. If a road's width
+// changed, pass it in, since the road itself hasn't been updated yet.
 fn recalculate_intersection_polygon(
     map: &mut Map,
-    changed_road: RoadID,
-    changed_road_width: Distance,
+    changed_road: Option<(RoadID, Distance)>,
     i: IntersectionID,
 ) -> Vec<RoadID> {
     use crate::make::initial;
@@ -585,10 +821,9 @@ fn recalculate_intersection_polygon(
         let r = map.get_r(*r);
         modify_roads.push((r.orig_id, r.id));
         intersection_roads.insert(r.orig_id);
-        let half_width = if r.id == changed_road {
-            changed_road_width / 2.0
-        } else {
-            r.get_half_width()
+        let half_width = match changed_road {
+            Some((id, width)) if id == r.id => width / 2.0,
+            _ => r.get_half_width(),
         };
 
         let mut trimmed_center_pts = r.center_pts.clone();
@@ -615,8 +850,10 @@ fn recalculate_intersection_polygon(
             r.orig_id,
             initial::Road {
                 id: r.orig_id,
-                src_i: r.orig_id.i1,
-                dst_i: r.orig_id.i2,
+                // Not r.orig_id.i1 and i2; splitting a road keeps the original ID but changes an
+                // endpoint.
+                src_i: map.get_i(r.src_i).orig_id,
+                dst_i: map.get_i(r.dst_i).orig_id,
                 trimmed_center_pts,
                 half_width,
                 // Unused
@@ -642,7 +879,7 @@ fn recalculate_intersection_polygon(
     let mut affected = Vec::new();
     for (orig_id, id) in modify_roads {
         map.roads[id.0].center_pts = roads.remove(&orig_id).unwrap().trimmed_center_pts;
-        if id != changed_road {
+        if Some(id) != changed_road.map(|(r, _)| r) {
             affected.push(id);
         }
     }
@@ -661,6 +898,7 @@ fn fix_building_driveways(map: &mut Map, input: Vec<BuildingID>, effects: &mut E
     }
 
     let sidewalk_buffer = Distance::meters(7.5);
+    // Note all_lanes skips deleted roads
     let mut sidewalk_pts = match_points_to_lanes(
         map,
         query,
@@ -760,6 +998,7 @@ impl Map {
             lanes_ltr: r.lane_specs(),
             speed_limit: r.speed_limit,
             access_restrictions: r.access_restrictions.clone(),
+            toll: r.toll.clone(),
         }
     }
 
@@ -770,6 +1009,15 @@ impl Map {
         EditCmd::ChangeRoad { r, old, new }
     }
 
+    pub fn get_parking_regulation_edit(
+        &self,
+        target: ParkingRegulationTarget,
+    ) -> ParkingRegulation {
+        self.get_parking_regulation(target)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     /// Panics on borders
     pub fn get_i_edit(&self, i: IntersectionID) -> EditIntersection {
         match self.get_i(i).intersection_type {
@@ -786,8 +1034,7 @@ impl Map {
         // Don't overwrite the current edits with the compressed first. Otherwise, undo/redo order
         // in the UI gets messed up.
         let mut edits = self.edits.clone();
-        edits.commands.clear();
-        edits.compress(self);
+        edits.compress();
         edits.save(self);
     }
 
@@ -841,19 +1088,47 @@ impl Map {
         timer.start_iter("undo old edits", self.edits.commands.len() - start_at_idx);
         for _ in start_at_idx..self.edits.commands.len() {
             timer.next();
+            // These commands were applied successfully, so undoing them can't fail
             self.edits
                 .commands
                 .pop()
                 .unwrap()
-                .undo()
-                .apply(&mut effects, self);
+                .unapply(&mut effects, self)
+                .unwrap();
         }
 
         timer.start_iter("apply new edits", new_edits.commands.len() - start_at_idx);
+        let mut num_applied = start_at_idx;
         for cmd in &new_edits.commands[start_at_idx..] {
             timer.next();
-            cmd.apply(&mut effects, self);
+            if let Err(err) = cmd.apply(&mut effects, self) {
+                if enforce_valid {
+                    panic!("Can't apply {}: {}", cmd.describe(self).0, err);
+                }
+                // Later commands may refer to objects this one would've created
+                warn!(
+                    "Can't apply {}, dropping it and all later edits: {}",
+                    cmd.describe(self).0,
+                    err
+                );
+                break;
+            }
+            num_applied += 1;
         }
+        new_edits.commands.truncate(num_applied);
+        // Undoing structural edits removes roads and intersections entirely.
+        effects
+            .changed_roads
+            .retain(|r| self.maybe_get_r(*r).is_some());
+        effects
+            .changed_intersections
+            .retain(|i| self.maybe_get_i(*i).is_some());
+        effects
+            .deleted_turns
+            .retain(|t| self.maybe_get_i(t.parent).is_some());
+        effects
+            .added_turns
+            .retain(|t| self.maybe_get_i(t.parent).is_some());
 
         timer.start("re-snap buildings");
         let mut recalc_buildings = Vec::new();
@@ -907,6 +1182,7 @@ impl Map {
         if !effects.changed_roads.is_empty() || merge_zones_changed {
             self.zones = Zone::make_all(self);
         }
+        self.time_of_day_costs = self.find_time_of_day_costs();
 
         // Some of these might've been added, then later deleted.
         effects
@@ -931,15 +1207,38 @@ impl Map {
     }
 
     /// This can expensive, so don't constantly do it while editing in the UI. But this must happen
-    /// before the simulation resumes.
+    /// before the simulation resumes. Modes whose routing graph didn't change are skipped; the
+    /// others are fully re-prepared, seeded with their previous node ordering.
     pub fn recalculate_pathfinding_after_edits(&mut self, timer: &mut Timer) {
+        self.update_pathfinding(false, timer);
+    }
+
+    /// Like `recalculate_pathfinding_after_edits`, but returns quickly after small edits.
+    /// Contraction hierarchies are re-prepared in the background; until they're done, pathfinding
+    /// is slower but still correct. Paths may differ from the ones the blocking version would
+    /// choose, so use that when a simulation has to be reproducible.
+    pub fn recalculate_pathfinding_after_edits_in_background(&mut self, timer: &mut Timer) {
+        self.update_pathfinding(true, timer);
+    }
+
+    fn update_pathfinding(&mut self, in_background: bool, timer: &mut Timer) {
         if !self.pathfinder_dirty {
+            if !in_background {
+                self.pathfinder.finish_background_work();
+            }
             return;
         }
 
         let mut pathfinder = std::mem::replace(&mut self.pathfinder, Pathfinder::empty());
-        pathfinder.apply_edits(self, timer);
+        let structure_changed = std::mem::take(&mut self.pathfinder_structure_changed);
+        if in_background {
+            pathfinder.apply_edits_in_background(self, structure_changed, timer);
+        } else {
+            pathfinder.apply_edits(self, structure_changed, timer);
+        }
         self.pathfinder = pathfinder;
+        // Transit schedules and travel times between stops may have changed
+        self.transit_router.clear();
 
         // Also recompute blackholes. This is cheap enough to do from scratch.
         timer.start("recompute blackholes");
@@ -4,12 +4,14 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 use abstio::MapName;
-use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::Time;
+use abstutil::{deserialize_btreemap, serialize_btreemap, Timer};
+use geom::{Distance, Time};
 
-use crate::edits::{EditCmd, EditIntersection, EditRoad, MapEdits};
+use crate::edits::{EditCmd, EditEffects, EditIntersection, EditRoad, MapEdits};
 use crate::raw::OriginalRoad;
-use crate::{osm, ControlStopSign, IntersectionID, Map};
+use crate::{
+    osm, ControlStopSign, IntersectionID, Map, ParkingRegulation, ParkingRegulationTarget, RoadID,
+};
 
 /// MapEdits are converted to this before serializing. Referencing things like LaneID in a Map won't
 /// work if the basemap is rebuilt from new OSM data, so instead we use stabler OSM IDs that're less
@@ -62,6 +64,35 @@ pub enum PermanentEditCmd {
         old: Vec<Time>,
         new: Vec<Time>,
     },
+    ChangeParkingRegulation {
+        target: PermanentParkingRegulationTarget,
+        old: ParkingRegulation,
+        new: ParkingRegulation,
+    },
+    /// `r` is the synthetic ID given to the new road, so later commands can refer to it.
+    AddRoad {
+        r: OriginalRoad,
+        i1: osm::NodeID,
+        i2: osm::NodeID,
+        road: EditRoad,
+    },
+    /// `new_i` and `new_r` are the synthetic IDs given to the objects created.
+    SplitRoad {
+        r: OriginalRoad,
+        dist: Distance,
+        new_i: osm::NodeID,
+        new_r: OriginalRoad,
+    },
+    DeleteRoad {
+        r: OriginalRoad,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum PermanentParkingRegulationTarget {
+    Road(OriginalRoad),
+    Lot(osm::OsmID),
+    Garage(osm::OsmID),
 }
 
 impl EditCmd {
@@ -84,37 +115,187 @@ impl EditCmd {
                     new: new.clone(),
                 }
             }
+            EditCmd::ChangeParkingRegulation { target, old, new } => {
+                PermanentEditCmd::ChangeParkingRegulation {
+                    target: match target {
+                        ParkingRegulationTarget::Road(r) => {
+                            PermanentParkingRegulationTarget::Road(map.get_r(*r).orig_id)
+                        }
+                        ParkingRegulationTarget::Lot(pl) => {
+                            PermanentParkingRegulationTarget::Lot(map.get_pl(*pl).osm_id)
+                        }
+                        ParkingRegulationTarget::Garage(b) => {
+                            PermanentParkingRegulationTarget::Garage(map.get_b(*b).orig_id)
+                        }
+                    },
+                    old: old.clone(),
+                    new: new.clone(),
+                }
+            }
+            EditCmd::AddRoad { r, i1, i2, road } => PermanentEditCmd::AddRoad {
+                r: map.get_r(*r).orig_id,
+                i1: map.get_i(*i1).orig_id,
+                i2: map.get_i(*i2).orig_id,
+                road: road.clone(),
+            },
+            EditCmd::SplitRoad {
+                r,
+                dist,
+                new_i,
+                new_r,
+            } => PermanentEditCmd::SplitRoad {
+                r: map.get_r(*r).orig_id,
+                dist: *dist,
+                new_i: map.get_i(*new_i).orig_id,
+                new_r: map.get_r(*new_r).orig_id,
+            },
+            EditCmd::DeleteRoad { r } => PermanentEditCmd::DeleteRoad {
+                r: map.get_r(*r).orig_id,
+            },
+        }
+    }
+}
+
+/// Structural edits create roads and intersections that don't exist in the basemap, but later
+/// commands may refer to them. Track the IDs they'll have once the edits are applied.
+struct CreatedObjects {
+    roads: BTreeMap<OriginalRoad, RoadID>,
+    intersections: BTreeMap<osm::NodeID, IntersectionID>,
+    num_roads: usize,
+    num_intersections: usize,
+    /// Roads that can't be checked against the basemap, because these edits created them or
+    /// already changed them once
+    skip_basemap_check: BTreeSet<RoadID>,
+}
+
+impl CreatedObjects {
+    /// For commands appended to the map's current edits
+    fn new(map: &Map) -> CreatedObjects {
+        CreatedObjects {
+            roads: BTreeMap::new(),
+            intersections: BTreeMap::new(),
+            num_roads: map.all_roads_with_deleted().len(),
+            num_intersections: map.all_intersections().len(),
+            skip_basemap_check: BTreeSet::new(),
+        }
+    }
+
+    /// For commands replacing the map's current edits. Applying them first undoes the current
+    /// edits, so new objects are numbered from the end of the basemap.
+    fn from_basemap(map: &Map) -> CreatedObjects {
+        let mut created = CreatedObjects::new(map);
+        for cmd in &map.get_edits().commands {
+            match cmd {
+                EditCmd::AddRoad { .. } => {
+                    created.num_roads -= 1;
+                }
+                EditCmd::SplitRoad { .. } => {
+                    created.num_roads -= 1;
+                    created.num_intersections -= 1;
+                }
+                _ => {}
+            }
+        }
+        created
+    }
+
+    fn find_r(&self, map: &Map, id: OriginalRoad) -> Result<RoadID> {
+        match self.roads.get(&id) {
+            Some(r) => Ok(*r),
+            None => map.find_r_by_osm_id(id),
+        }
+    }
+
+    fn find_i(&self, map: &Map, id: osm::NodeID) -> Result<IntersectionID> {
+        match self.intersections.get(&id) {
+            Some(i) => Ok(*i),
+            None => map.find_i_by_osm_id(id),
+        }
+    }
+
+    /// Call after a command has been successfully translated.
+    fn record(&mut self, perma: &PermanentEditCmd, cmd: &EditCmd) {
+        match (perma, cmd) {
+            (PermanentEditCmd::ChangeRoad { .. }, EditCmd::ChangeRoad { r, .. }) => {
+                self.skip_basemap_check.insert(*r);
+            }
+            (PermanentEditCmd::AddRoad { r: orig, .. }, EditCmd::AddRoad { r, .. }) => {
+                self.roads.insert(*orig, *r);
+                self.skip_basemap_check.insert(*r);
+                self.num_roads += 1;
+            }
+            (
+                PermanentEditCmd::SplitRoad {
+                    new_i: orig_i,
+                    new_r: orig_r,
+                    ..
+                },
+                EditCmd::SplitRoad { new_i, new_r, .. },
+            ) => {
+                self.intersections.insert(*orig_i, *new_i);
+                self.roads.insert(*orig_r, *new_r);
+                self.skip_basemap_check.insert(*new_r);
+                self.num_intersections += 1;
+                self.num_roads += 1;
+            }
+            _ => {}
         }
     }
 }
 
 impl PermanentEditCmd {
-    pub fn into_cmd(self, map: &Map) -> Result<EditCmd> {
+    /// Translate a single command to refer to the current map, so it can be appended to the
+    /// current edits.
+    pub fn into_edit_cmd(self, map: &Map) -> Result<EditCmd> {
+        self.into_cmd(map, &CreatedObjects::new(map))
+    }
+
+    /// Does translating this command need a copy of the map with the earlier commands applied?
+    fn needs_working_map(&self, created: &CreatedObjects) -> bool {
+        match self {
+            PermanentEditCmd::AddRoad { .. }
+            | PermanentEditCmd::SplitRoad { .. }
+            | PermanentEditCmd::DeleteRoad { .. } => true,
+            PermanentEditCmd::ChangeIntersection { i, new, old } => {
+                created.intersections.contains_key(i)
+                    && (matches!(new, PermanentEditIntersection::StopSign { .. })
+                        || matches!(old, PermanentEditIntersection::StopSign { .. }))
+            }
+            _ => false,
+        }
+    }
+
+    fn into_cmd(self, map: &Map, created: &CreatedObjects) -> Result<EditCmd> {
         match self {
             PermanentEditCmd::ChangeRoad { r, new, old } => {
-                let id = map.find_r_by_osm_id(r)?;
-                let num_current = map.get_r(id).lanes.len();
-                // The basemap changed -- it'd be pretty hard to understand the original
-                // intent of the edit.
-                if num_current != old.lanes_ltr.len() {
-                    bail!(
-                        "number of lanes in {} is {} now, but {} in the edits",
-                        r,
-                        num_current,
-                        old.lanes_ltr.len()
-                    );
+                let id = created.find_r(map, r)?;
+                if let Some(road) = map
+                    .maybe_get_r(id)
+                    .filter(|_| !created.skip_basemap_check.contains(&id))
+                {
+                    let num_current = road.lanes.len();
+                    // The basemap changed -- it'd be pretty hard to understand the original
+                    // intent of the edit.
+                    if num_current != old.lanes_ltr.len() {
+                        bail!(
+                            "number of lanes in {} is {} now, but {} in the edits",
+                            r,
+                            num_current,
+                            old.lanes_ltr.len()
+                        );
+                    }
                 }
                 Ok(EditCmd::ChangeRoad { r: id, new, old })
             }
             PermanentEditCmd::ChangeIntersection { i, new, old } => {
-                let id = map.find_i_by_osm_id(i)?;
+                let id = created.find_i(map, i)?;
                 Ok(EditCmd::ChangeIntersection {
                     i: id,
                     new: new
-                        .with_permanent(id, map)
+                        .with_permanent(id, map, created)
                         .with_context(|| format!("new ChangeIntersection of {} invalid", i))?,
                     old: old
-                        .with_permanent(id, map)
+                        .with_permanent(id, map, created)
                         .with_context(|| format!("old ChangeIntersection of {} invalid", i))?,
                 })
             }
@@ -128,10 +309,92 @@ impl PermanentEditCmd {
                     .ok_or_else(|| anyhow!("can't find {}", osm_rel_id))?;
                 Ok(EditCmd::ChangeRouteSchedule { id, old, new })
             }
+            PermanentEditCmd::ChangeParkingRegulation { target, old, new } => {
+                let target = match target {
+                    PermanentParkingRegulationTarget::Road(r) => {
+                        ParkingRegulationTarget::Road(created.find_r(map, r)?)
+                    }
+                    PermanentParkingRegulationTarget::Lot(id) => ParkingRegulationTarget::Lot(
+                        map.all_parking_lots()
+                            .iter()
+                            .find(|pl| pl.osm_id == id)
+                            .ok_or_else(|| anyhow!("can't find parking lot {}", id))?
+                            .id,
+                    ),
+                    PermanentParkingRegulationTarget::Garage(id) => {
+                        ParkingRegulationTarget::Garage(
+                            map.find_b_by_osm_id(id)
+                                .ok_or_else(|| anyhow!("can't find building {}", id))?,
+                        )
+                    }
+                };
+                Ok(EditCmd::ChangeParkingRegulation { target, old, new })
+            }
+            PermanentEditCmd::AddRoad { i1, i2, road, .. } => Ok(EditCmd::AddRoad {
+                r: RoadID(created.num_roads),
+                i1: created.find_i(map, i1)?,
+                i2: created.find_i(map, i2)?,
+                road,
+            }),
+            PermanentEditCmd::SplitRoad { r, dist, .. } => Ok(EditCmd::SplitRoad {
+                r: created.find_r(map, r)?,
+                dist,
+                new_i: IntersectionID(created.num_intersections),
+                new_r: RoadID(created.num_roads),
+            }),
+            PermanentEditCmd::DeleteRoad { r } => Ok(EditCmd::DeleteRoad {
+                r: created.find_r(map, r)?,
+            }),
         }
     }
 }
 
+/// Translate commands in order, since structural edits affect the IDs of later commands. If
+/// `permissive`, skip broken commands instead of failing.
+fn translate_commands(
+    commands: Vec<PermanentEditCmd>,
+    map: &Map,
+    permissive: bool,
+) -> Result<Vec<EditCmd>> {
+    let mut created = CreatedObjects::from_basemap(map);
+    let mut result = Vec::new();
+    // Structural edits have to be checked against the map with the commands so far applied, and
+    // stop signs at intersections they create are defined in terms of the roads connected. Most
+    // edits aren't structural, so only copy the map once one is needed, then keep applying
+    // commands to that one copy.
+    let mut working: Option<Map> = None;
+    for perma in commands {
+        if working.is_none() && perma.needs_working_map(&created) {
+            let mut copy = map.clone();
+            let mut edits = MapEdits::new();
+            edits.commands = result.clone();
+            copy.try_apply_edits(edits, &mut Timer::throwaway());
+            working = Some(copy);
+        }
+        let translated = perma
+            .clone()
+            .into_cmd(working.as_ref().unwrap_or(map), &created)
+            .and_then(|cmd| {
+                if let Some(ref mut working) = working {
+                    cmd.apply(&mut EditEffects::default(), working)?;
+                }
+                Ok(cmd)
+            });
+        match translated {
+            Ok(cmd) => {
+                created.record(&perma, &cmd);
+                result.push(cmd);
+            }
+            Err(err) => {
+                if !permissive {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
 impl MapEdits {
     /// Encode the edits in a permanent format, referring to more-stable OSM IDs.
     pub fn to_permanent(&self, map: &Map) -> PermanentMapEdits {
@@ -156,16 +419,14 @@ impl PermanentMapEdits {
             edits_name: self.edits_name,
             proposal_description: self.proposal_description,
             proposal_link: self.proposal_link,
-            commands: self
-                .commands
-                .into_iter()
-                .map(|cmd| cmd.into_cmd(map))
-                .collect::<Result<Vec<EditCmd>>>()?,
+            commands: translate_commands(self.commands, map, false)?,
             merge_zones: self.merge_zones,
 
             changed_roads: BTreeSet::new(),
             original_intersections: BTreeMap::new(),
             changed_routes: BTreeSet::new(),
+            changed_parking_regulations: BTreeSet::new(),
+            structural_commands: Vec::new(),
         };
         edits.update_derived(map);
         Ok(edits)
@@ -178,16 +439,14 @@ impl PermanentMapEdits {
             edits_name: self.edits_name,
             proposal_description: self.proposal_description,
             proposal_link: self.proposal_link,
-            commands: self
-                .commands
-                .into_iter()
-                .filter_map(|cmd| cmd.into_cmd(map).ok())
-                .collect(),
+            commands: translate_commands(self.commands, map, true).unwrap(),
             merge_zones: self.merge_zones,
 
             changed_roads: BTreeSet::new(),
             original_intersections: BTreeMap::new(),
             changed_routes: BTreeSet::new(),
+            changed_parking_regulations: BTreeSet::new(),
+            structural_commands: Vec::new(),
         };
         edits.update_derived(map);
         edits
@@ -213,12 +472,24 @@ impl EditIntersection {
 }
 
 impl PermanentEditIntersection {
-    fn with_permanent(self, i: IntersectionID, map: &Map) -> Result<EditIntersection> {
+    fn with_permanent(
+        self,
+        i: IntersectionID,
+        map: &Map,
+        created: &CreatedObjects,
+    ) -> Result<EditIntersection> {
         match self {
             PermanentEditIntersection::StopSign { must_stop } => {
+                // The caller makes sure structural edits creating this intersection are applied
+                if map.maybe_get_i(i).is_none() {
+                    bail!(
+                        "can't restore the stop sign at {}, created by structural edits",
+                        i
+                    );
+                }
                 let mut translated_must_stop = BTreeMap::new();
                 for (r, stop) in must_stop {
-                    translated_must_stop.insert(map.find_r_by_osm_id(r)?, stop);
+                    translated_must_stop.insert(created.find_r(map, r)?, stop);
                 }
 
                 // Make sure the roads exactly match up
@@ -0,0 +1,393 @@
+//! Edits that change the topology of the road network: adding, splitting, and deleting roads.
+//!
+//! New roads and intersections are appended to the map, so every ID that existed before stays
+//! valid. `apply_edits` reverts commands in the opposite order they were applied, so reverting an
+//! addition always removes the most recently created objects. Deleted roads can't be removed
+//! without shifting IDs, so they stay in the map, disconnected from their intersections.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+
+use abstutil::Tags;
+use geom::{Circle, Distance, PolyLine};
+
+use super::{
+    modify_lanes, recalculate_intersection_polygon, reconnect_intersection,
+    recreate_lanes_after_geometry_change, EditCmd, EditEffects, EditRoad,
+};
+use crate::raw::OriginalRoad;
+use crate::{
+    osm, Intersection, IntersectionID, IntersectionType, Map, ParkingRegulationTarget, Road, RoadID,
+};
+
+/// Roads and intersections created by edits don't exist in OSM. Their IDs count down from here,
+/// far away from the negative IDs the map editor assigns.
+const SYNTHETIC_OSM_ID_START: i64 = -1_000_000_000;
+
+/// Don't leave a piece of a split road too short to hold lanes.
+const MIN_SPLIT_LENGTH: Distance = Distance::const_meters(5.0);
+
+impl Map {
+    /// Produces a command to connect two intersections with a new road, configured by `road`.
+    pub fn add_road_cmd(
+        &self,
+        i1: IntersectionID,
+        i2: IntersectionID,
+        road: EditRoad,
+    ) -> Result<EditCmd> {
+        if i1 == i2 {
+            bail!("can't connect {} to itself", i1);
+        }
+        for i in [i1, i2] {
+            if self.get_i(i).is_border() {
+                bail!("can't connect a new road to border {}", i);
+            }
+        }
+        if road.lanes_ltr.is_empty() {
+            bail!("a new road needs at least one lane");
+        }
+        if self.get_i(i1).polygon.center() == self.get_i(i2).polygon.center() {
+            bail!("{} and {} are in the same place", i1, i2);
+        }
+        Ok(EditCmd::AddRoad {
+            r: RoadID(self.roads.len()),
+            i1,
+            i2,
+            road,
+        })
+    }
+
+    /// Produces a command to split a road `dist` along its untrimmed length.
+    pub fn split_road_cmd(&self, r: RoadID, dist: Distance) -> Result<EditCmd> {
+        if self.is_road_deleted(r) {
+            bail!("{} has been deleted", r);
+        }
+        let road = self.get_r(r);
+        // TODO Bus stops would have to move to the new road
+        if !road.all_bus_stops().is_empty() {
+            bail!("can't split {}, because it has bus stops", r);
+        }
+        let length = road.untrimmed_center_pts.length();
+        if dist < MIN_SPLIT_LENGTH || dist > length - MIN_SPLIT_LENGTH {
+            bail!(
+                "can't split {} at {}; it's {} long, and each piece must be at least {}",
+                r,
+                dist,
+                length,
+                MIN_SPLIT_LENGTH
+            );
+        }
+        Ok(EditCmd::SplitRoad {
+            r,
+            dist,
+            new_i: IntersectionID(self.intersections.len()),
+            new_r: RoadID(self.roads.len()),
+        })
+    }
+
+    /// Produces a command to disconnect a road from the network.
+    pub fn delete_road_cmd(&self, r: RoadID) -> Result<EditCmd> {
+        if self.is_road_deleted(r) {
+            bail!("{} has already been deleted", r);
+        }
+        if !self.get_r(r).all_bus_stops().is_empty() {
+            bail!("can't delete {}, because it has bus stops", r);
+        }
+        Ok(EditCmd::DeleteRoad { r })
+    }
+}
+
+pub(crate) fn add_road(
+    map: &mut Map,
+    id: RoadID,
+    i1: IntersectionID,
+    i2: IntersectionID,
+    spec: &EditRoad,
+    effects: &mut EditEffects,
+) -> Result<()> {
+    if id.0 != map.roads.len() {
+        bail!("{} isn't the next RoadID", id);
+    }
+    let untrimmed_center_pts = PolyLine::must_new(vec![
+        map.get_i(i1).polygon.center(),
+        map.get_i(i2).polygon.center(),
+    ]);
+    let percent_incline =
+        (map.get_i(i2).elevation - map.get_i(i1).elevation) / untrimmed_center_pts.length();
+    let mut osm_tags = Tags::empty();
+    osm_tags.insert(osm::HIGHWAY, "residential");
+
+    map.roads.push(Road {
+        id,
+        osm_tags,
+        turn_restrictions: Vec::new(),
+        complicated_turn_restrictions: Vec::new(),
+        orig_id: OriginalRoad {
+            osm_way_id: osm::WayID(SYNTHETIC_OSM_ID_START - id.0 as i64),
+            i1: map.get_i(i1).orig_id,
+            i2: map.get_i(i2).orig_id,
+        },
+        speed_limit: spec.speed_limit,
+        access_restrictions: spec.access_restrictions.clone(),
+        toll: spec.toll.clone(),
+        zorder: 0,
+        percent_incline,
+        grades: Vec::new(),
+        // Created by modify_lanes
+        lanes: Vec::new(),
+        center_pts: untrimmed_center_pts.clone(),
+        untrimmed_center_pts,
+        src_i: i1,
+        dst_i: i2,
+    });
+    map.intersections[i1.0].roads.insert(id);
+    map.intersections[i2.0].roads.insert(id);
+
+    // This trims the new road and recalculates both intersection polygons
+    modify_lanes(map, id, spec.lanes_ltr.clone(), effects);
+    effects.changed_roads.insert(id);
+    reconnect_intersection(map, i1, effects);
+    reconnect_intersection(map, i2, effects);
+    map.pathfinder_structure_changed = true;
+    Ok(())
+}
+
+pub(crate) fn remove_added_road(
+    map: &mut Map,
+    id: RoadID,
+    effects: &mut EditEffects,
+) -> Result<()> {
+    if id.0 + 1 != map.roads.len() {
+        bail!("{} isn't the most recently added road", id);
+    }
+    map.deleted_roads.remove(&id);
+    map.parking_regulations
+        .remove(&ParkingRegulationTarget::Road(id));
+    let road = map.roads.pop().unwrap();
+    for lane in &road.lanes {
+        effects.deleted_lanes.insert(lane.id);
+        effects.modified_lanes.insert(lane.id);
+    }
+
+    for i in [road.src_i, road.dst_i] {
+        map.intersections[i.0].roads.remove(&id);
+        regenerate_intersection_geometry(map, i, effects);
+        reconnect_intersection(map, i, effects);
+    }
+    map.pathfinder_structure_changed = true;
+    Ok(())
+}
+
+pub(crate) fn split_road(
+    map: &mut Map,
+    r: RoadID,
+    dist: Distance,
+    new_i: IntersectionID,
+    new_r: RoadID,
+    effects: &mut EditEffects,
+) -> Result<()> {
+    if new_i.0 != map.intersections.len() {
+        bail!("{} isn't the next IntersectionID", new_i);
+    }
+    if new_r.0 != map.roads.len() {
+        bail!("{} isn't the next RoadID", new_r);
+    }
+
+    let road = map.get_r(r);
+    let (src_i, old_dst_i) = (road.src_i, road.dst_i);
+    let untrimmed = road.untrimmed_center_pts.clone();
+    let first = untrimmed.exact_slice(Distance::ZERO, dist);
+    let second = untrimmed.exact_slice(dist, untrimmed.length());
+    let split_pt = first.last_pt();
+    let elevation = map.get_i(src_i).elevation
+        + (map.get_i(old_dst_i).elevation - map.get_i(src_i).elevation)
+            * (dist / untrimmed.length());
+    let new_osm_node = osm::NodeID(SYNTHETIC_OSM_ID_START - new_i.0 as i64);
+    let lane_specs = road.lane_specs();
+    let (first_grades, second_grades) = road.split_grades(dist / untrimmed.length());
+
+    let mut second_road = Road {
+        id: new_r,
+        osm_tags: road.osm_tags.clone(),
+        turn_restrictions: road.turn_restrictions.clone(),
+        complicated_turn_restrictions: road.complicated_turn_restrictions.clone(),
+        orig_id: OriginalRoad {
+            osm_way_id: road.orig_id.osm_way_id,
+            i1: new_osm_node,
+            i2: map.get_i(old_dst_i).orig_id,
+        },
+        speed_limit: road.speed_limit,
+        access_restrictions: road.access_restrictions.clone(),
+        toll: road.toll.clone(),
+        zorder: road.zorder,
+        percent_incline: average_grade(&second_grades, road.percent_incline),
+        grades: second_grades,
+        lanes: Vec::new(),
+        center_pts: second.clone(),
+        untrimmed_center_pts: second,
+        src_i: new_i,
+        dst_i: old_dst_i,
+    };
+    // The geometry is fixed by modify_lanes below, but the width is needed before then.
+    second_road.recreate_lanes(lane_specs.clone());
+
+    let mut roads = BTreeSet::new();
+    roads.insert(r);
+    roads.insert(new_r);
+    map.intersections.push(Intersection {
+        id: new_i,
+        // Calculated properly below, once both roads are connected
+        polygon: Circle::new(split_pt, Distance::meters(1.0)).to_polygon(),
+        turns: Vec::new(),
+        elevation,
+        intersection_type: IntersectionType::StopSign,
+        orig_id: new_osm_node,
+        incoming_lanes: Vec::new(),
+        outgoing_lanes: Vec::new(),
+        roads,
+        merged: false,
+        movements: BTreeMap::new(),
+    });
+    map.roads.push(second_road);
+
+    {
+        let road = &mut map.roads[r.0];
+        road.percent_incline = average_grade(&first_grades, road.percent_incline);
+        road.grades = first_grades;
+        road.untrimmed_center_pts = first.clone();
+        road.center_pts = first;
+        road.dst_i = new_i;
+    }
+    replace_road_at_intersection(map, old_dst_i, r, new_r);
+
+    modify_lanes(map, r, lane_specs.clone(), effects);
+    modify_lanes(map, new_r, lane_specs, effects);
+    effects.changed_roads.insert(r);
+    effects.changed_roads.insert(new_r);
+    for i in [src_i, new_i, old_dst_i] {
+        reconnect_intersection(map, i, effects);
+    }
+    map.pathfinder_structure_changed = true;
+    Ok(())
+}
+
+pub(crate) fn unsplit_road(
+    map: &mut Map,
+    r: RoadID,
+    new_i: IntersectionID,
+    new_r: RoadID,
+    effects: &mut EditEffects,
+) -> Result<()> {
+    if new_r.0 + 1 != map.roads.len() {
+        bail!("{} isn't the latest road", new_r);
+    }
+    if new_i.0 + 1 != map.intersections.len() {
+        bail!("{} isn't the latest intersection", new_i);
+    }
+
+    let second_road = map.roads.pop().unwrap();
+    map.intersections.pop();
+    map.stop_signs.remove(&new_i);
+    map.traffic_signals.remove(&new_i);
+    map.parking_regulations
+        .remove(&ParkingRegulationTarget::Road(new_r));
+    for lane in &second_road.lanes {
+        effects.deleted_lanes.insert(lane.id);
+        effects.modified_lanes.insert(lane.id);
+    }
+    let old_dst_i = second_road.dst_i;
+    replace_road_at_intersection(map, old_dst_i, new_r, r);
+
+    let lane_specs = {
+        let road = &mut map.roads[r.0];
+        road.untrimmed_center_pts = road
+            .untrimmed_center_pts
+            .clone()
+            .must_extend(second_road.untrimmed_center_pts);
+        road.center_pts = road.untrimmed_center_pts.clone();
+        road.dst_i = old_dst_i;
+        road.grades.extend(second_road.grades.clone());
+        road.percent_incline = average_grade(&road.grades, road.percent_incline);
+        road.lane_specs()
+    };
+    modify_lanes(map, r, lane_specs, effects);
+    effects.changed_roads.insert(r);
+    let src_i = map.get_r(r).src_i;
+    reconnect_intersection(map, src_i, effects);
+    reconnect_intersection(map, old_dst_i, effects);
+    map.pathfinder_structure_changed = true;
+    Ok(())
+}
+
+/// Weighted by length. If there's no profile, there's no change from the original incline.
+fn average_grade(grades: &[(Distance, f64)], orig: f64) -> f64 {
+    let total: Distance = grades.iter().map(|(len, _)| *len).sum();
+    if total == Distance::ZERO {
+        return orig;
+    }
+    grades
+        .iter()
+        .map(|(len, grade)| (*len / total) * grade)
+        .sum()
+}
+
+pub(crate) fn delete_road(map: &mut Map, r: RoadID, effects: &mut EditEffects) {
+    map.deleted_roads.insert(r);
+    let road = map.get_r(r);
+    let (src_i, dst_i) = (road.src_i, road.dst_i);
+    // The lanes still exist, but nothing can reach them anymore. Anything snapped to them has to
+    // move.
+    for lane in &road.lanes {
+        effects.deleted_lanes.insert(lane.id);
+        effects.modified_lanes.insert(lane.id);
+    }
+    effects.changed_roads.insert(r);
+
+    for i in [src_i, dst_i] {
+        map.intersections[i.0].roads.remove(&r);
+        regenerate_intersection_geometry(map, i, effects);
+        reconnect_intersection(map, i, effects);
+    }
+    // Uber-turns might have gone through this road
+    map.pathfinder_structure_changed = true;
+}
+
+pub(crate) fn restore_road(map: &mut Map, r: RoadID, effects: &mut EditEffects) {
+    map.deleted_roads.remove(&r);
+    let road = map.get_r(r);
+    let (src_i, dst_i) = (road.src_i, road.dst_i);
+    let lane_specs = road.lane_specs();
+    map.intersections[src_i.0].roads.insert(r);
+    map.intersections[dst_i.0].roads.insert(r);
+
+    modify_lanes(map, r, lane_specs, effects);
+    effects.changed_roads.insert(r);
+    reconnect_intersection(map, src_i, effects);
+    reconnect_intersection(map, dst_i, effects);
+    map.pathfinder_structure_changed = true;
+}
+
+/// After a road leaves an intersection, reshape the intersection around the roads remaining.
+fn regenerate_intersection_geometry(map: &mut Map, i: IntersectionID, effects: &mut EditEffects) {
+    // An isolated intersection keeps its old shape; there's nothing to trim.
+    if map.get_i(i).roads.is_empty() {
+        return;
+    }
+    let affected = recalculate_intersection_polygon(map, None, i);
+    recreate_lanes_after_geometry_change(map, affected, effects);
+}
+
+/// Swap which road connects to an intersection, also fixing turn restrictions that mention it.
+fn replace_road_at_intersection(map: &mut Map, i: IntersectionID, old: RoadID, new: RoadID) {
+    let intersection = &mut map.intersections[i.0];
+    intersection.roads.remove(&old);
+    intersection.roads.insert(new);
+    for other in intersection.roads.clone() {
+        for (_, to) in &mut map.roads[other.0].turn_restrictions {
+            if *to == old {
+                *to = new;
+            }
+        }
+    }
+}
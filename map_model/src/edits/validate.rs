@@ -0,0 +1,466 @@
+//! Before accepting some proposed `MapEdits`, check them for problems that would otherwise only
+//! show up once the simulation misbehaves -- disconnected sidewalks, bus routes that can't be
+//! followed anymore, broken traffic signals, etc. Each check is an `EditRule`. Only problems that
+//! the proposal introduces are reported; anything already broken before is ignored.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::Timer;
+
+use crate::{
+    connectivity, BusRouteID, BusStopID, IntersectionID, LaneID, Map, MapEdits, PathConstraints,
+    RoadID,
+};
+
+/// The results of validating some edits.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub problems: Vec<EditProblem>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EditProblem {
+    /// The name of the rule that found this
+    pub rule: String,
+    pub severity: ProblemSeverity,
+    pub message: String,
+    /// The map objects involved. Always refers to the map with the proposed edits applied.
+    pub affected: Vec<AffectedID>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ProblemSeverity {
+    /// The edits shouldn't be accepted.
+    Error,
+    /// Something probably unintended, but the simulation can cope.
+    Warning,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AffectedID {
+    Lane(LaneID),
+    Road(RoadID),
+    Intersection(IntersectionID),
+    BusStop(BusStopID),
+    BusRoute(BusRouteID),
+}
+
+/// One check to run on proposed edits.
+pub trait EditRule {
+    /// A short identifier, like "sidewalk_connectivity"
+    fn name(&self) -> &'static str;
+    /// Called on the map before the proposed edits are applied, so the rule can remember what was
+    /// already broken.
+    fn before(&mut self, map: &Map);
+    /// Called on the map with the proposed edits applied. The `rule` of each problem will be
+    /// filled out by the caller.
+    fn check(&self, map: &Map) -> Vec<EditProblem>;
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> impl Iterator<Item = &EditProblem> {
+        self.problems
+            .iter()
+            .filter(|p| p.severity == ProblemSeverity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &EditProblem> {
+        self.problems
+            .iter()
+            .filter(|p| p.severity == ProblemSeverity::Warning)
+    }
+
+    /// True if there are no errors. There may still be warnings.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+}
+
+impl EditProblem {
+    pub fn error(message: String, affected: Vec<AffectedID>) -> EditProblem {
+        EditProblem {
+            rule: String::new(),
+            severity: ProblemSeverity::Error,
+            message,
+            affected,
+        }
+    }
+
+    pub fn warning(message: String, affected: Vec<AffectedID>) -> EditProblem {
+        EditProblem {
+            rule: String::new(),
+            severity: ProblemSeverity::Warning,
+            message,
+            affected,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} ({}): {} [{} affected]",
+            self.severity,
+            self.rule,
+            self.message,
+            self.affected.len()
+        )
+    }
+}
+
+/// All of the rules that are checked by default.
+pub fn default_edit_rules() -> Vec<Box<dyn EditRule>> {
+    vec![
+        Box::new(SidewalkConnectivityRule::default()),
+        Box::new(VehicleConnectivityRule::default()),
+        Box::new(BusRouteRule::default()),
+        Box::new(TrafficSignalRule::default()),
+    ]
+}
+
+impl Map {
+    /// Checks some proposed edits, replacing the current ones, using all of the default rules.
+    /// See `validate_edits_with_rules`.
+    pub fn validate_edits(&mut self, proposed: MapEdits, timer: &mut Timer) -> ValidationReport {
+        self.validate_edits_with_rules(proposed, default_edit_rules(), timer)
+    }
+
+    /// Temporarily applies some proposed edits, replacing the current ones, and checks them. Only
+    /// problems that aren't already present with the current edits are reported. The map is left
+    /// with the original edits. If pathfinding was ready to use before, it still is afterwards.
+    ///
+    /// This applies and reverts the edits, then updates pathfinding, so it costs about as much as
+    /// applying the edits twice. To apply the edits when they're fine, use
+    /// `apply_edits_if_valid` instead of applying them a third time.
+    pub fn validate_edits_with_rules(
+        &mut self,
+        proposed: MapEdits,
+        rules: Vec<Box<dyn EditRule>>,
+        timer: &mut Timer,
+    ) -> ValidationReport {
+        self.validate_edits_and_maybe_keep(proposed, rules, false, timer)
+    }
+
+    /// Checks some proposed edits, replacing the current ones, using all of the default rules. If
+    /// there are no errors, the proposed edits stay applied, and the caller must
+    /// `recalculate_pathfinding_after_edits`. Otherwise, the map is left as in
+    /// `validate_edits_with_rules`.
+    pub fn apply_edits_if_valid(
+        &mut self,
+        proposed: MapEdits,
+        timer: &mut Timer,
+    ) -> ValidationReport {
+        self.validate_edits_and_maybe_keep(proposed, default_edit_rules(), true, timer)
+    }
+
+    fn validate_edits_and_maybe_keep(
+        &mut self,
+        proposed: MapEdits,
+        mut rules: Vec<Box<dyn EditRule>>,
+        keep_if_ok: bool,
+        timer: &mut Timer,
+    ) -> ValidationReport {
+        timer.start("validate edits");
+        for rule in &mut rules {
+            rule.before(self);
+        }
+
+        let orig_edits = self.get_edits().clone();
+        let was_dirty = self.pathfinder_dirty;
+        self.try_apply_edits(proposed, timer);
+        let mut report = ValidationReport::default();
+        for rule in &rules {
+            for mut problem in rule.check(self) {
+                problem.rule = rule.name().to_string();
+                report.problems.push(problem);
+            }
+        }
+        if !(keep_if_ok && report.is_ok()) {
+            self.must_apply_edits(orig_edits, timer);
+            if !was_dirty {
+                // The routing graphs are back to how they were, so this is usually quick
+                self.recalculate_pathfinding_after_edits(timer);
+            }
+        }
+
+        report.problems.sort_by_key(|p| p.severity);
+        timer.stop("validate edits");
+        report
+    }
+}
+
+/// Sidewalks and crosswalks disconnected from the rest of the map. Usually caused by closing
+/// intersections.
+#[derive(Default)]
+pub struct SidewalkConnectivityRule {
+    disconnected_before: HashSet<LaneID>,
+}
+
+impl EditRule for SidewalkConnectivityRule {
+    fn name(&self) -> &'static str {
+        "sidewalk_connectivity"
+    }
+
+    fn before(&mut self, map: &Map) {
+        self.disconnected_before = connectivity::find_scc(map, PathConstraints::Pedestrian).1;
+    }
+
+    fn check(&self, map: &Map) -> Vec<EditProblem> {
+        let newly_disconnected: BTreeSet<LaneID> =
+            connectivity::find_scc(map, PathConstraints::Pedestrian)
+                .1
+                .difference(&self.disconnected_before)
+                .cloned()
+                .collect();
+        if newly_disconnected.is_empty() {
+            return Vec::new();
+        }
+        vec![EditProblem::error(
+            format!("{} sidewalks disconnected", newly_disconnected.len()),
+            newly_disconnected
+                .into_iter()
+                .map(AffectedID::Lane)
+                .collect(),
+        )]
+    }
+}
+
+/// Driving and biking lanes that can't reach the rest of the map, or can't be reached from it.
+/// Usually caused by closing intersections, changing lane types, or reversing lanes.
+#[derive(Default)]
+pub struct VehicleConnectivityRule {
+    disconnected_before: BTreeMap<PathConstraints, HashSet<LaneID>>,
+}
+
+impl EditRule for VehicleConnectivityRule {
+    fn name(&self) -> &'static str {
+        "vehicle_connectivity"
+    }
+
+    fn before(&mut self, map: &Map) {
+        for constraints in [PathConstraints::Car, PathConstraints::Bike] {
+            self.disconnected_before
+                .insert(constraints, connectivity::find_scc(map, constraints).1);
+        }
+    }
+
+    fn check(&self, map: &Map) -> Vec<EditProblem> {
+        let mut problems = Vec::new();
+        for (constraints, before) in &self.disconnected_before {
+            let newly_disconnected: BTreeSet<LaneID> = connectivity::find_scc(map, *constraints)
+                .1
+                .difference(before)
+                .cloned()
+                .collect();
+            if !newly_disconnected.is_empty() {
+                problems.push(EditProblem::warning(
+                    format!(
+                        "{} lanes disconnected for {:?}",
+                        newly_disconnected.len(),
+                        constraints
+                    ),
+                    newly_disconnected
+                        .into_iter()
+                        .map(AffectedID::Lane)
+                        .collect(),
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Bus and train routes that can't visit all of their stops in order anymore, or stops that lost
+/// the lane serving them.
+#[derive(Default)]
+pub struct BusRouteRule {
+    broken_before: BTreeSet<BusRouteID>,
+}
+
+impl EditRule for BusRouteRule {
+    fn name(&self) -> &'static str {
+        "bus_routes"
+    }
+
+    fn before(&mut self, map: &Map) {
+        self.broken_before = broken_routes(map).keys().cloned().collect();
+    }
+
+    fn check(&self, map: &Map) -> Vec<EditProblem> {
+        let mut problems = Vec::new();
+        for (route, problem) in broken_routes(map) {
+            if !self.broken_before.contains(&route) {
+                problems.push(problem);
+            }
+        }
+        problems
+    }
+}
+
+fn broken_routes(map: &Map) -> BTreeMap<BusRouteID, EditProblem> {
+    let mut main_component: BTreeMap<PathConstraints, HashSet<LaneID>> = BTreeMap::new();
+    let mut results = BTreeMap::new();
+    for route in map.all_bus_routes() {
+        let main = main_component
+            .entry(route.route_type)
+            .or_insert_with(|| connectivity::find_scc(map, route.route_type).0);
+
+        let mut lanes = vec![route.start];
+        for bs in &route.stops {
+            match serving_lane(map, *bs, route.route_type) {
+                Some(l) => lanes.push(l),
+                None => {
+                    results.insert(
+                        route.id,
+                        EditProblem::error(
+                            format!(
+                                "{} has no lane to stop at {}",
+                                route.short_name,
+                                map.get_bs(*bs).name
+                            ),
+                            vec![AffectedID::BusRoute(route.id), AffectedID::BusStop(*bs)],
+                        ),
+                    );
+                    break;
+                }
+            }
+        }
+        if results.contains_key(&route.id) {
+            continue;
+        }
+        lanes.extend(route.end_border);
+
+        for pair in lanes.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            if (main.contains(&from) && main.contains(&to))
+                || can_reach(map, from, to, route.route_type)
+            {
+                continue;
+            }
+            results.insert(
+                route.id,
+                EditProblem::error(
+                    format!("{} can't get from {} to {}", route.short_name, from, to),
+                    vec![
+                        AffectedID::BusRoute(route.id),
+                        AffectedID::Lane(from),
+                        AffectedID::Lane(to),
+                    ],
+                ),
+            );
+            break;
+        }
+    }
+    results
+}
+
+/// The lane a vehicle would use to serve a stop. Edits don't move stops until they're actually
+/// committed, so this finds the lane the stop would be moved to.
+fn serving_lane(map: &Map, bs: BusStopID, constraints: PathConstraints) -> Option<LaneID> {
+    let stop = map.get_bs(bs);
+    if let Some(lane) = map.maybe_get_l(stop.driving_pos.lane()) {
+        if constraints.can_use(lane, map) {
+            return Some(lane.id);
+        }
+    }
+    let sidewalk = stop.sidewalk_pos.lane();
+    map.maybe_get_l(sidewalk)?;
+    map.get_parent(sidewalk)
+        .find_closest_lane(sidewalk, |l| constraints.can_use(l, map))
+}
+
+fn can_reach(map: &Map, from: LaneID, to: LaneID, constraints: PathConstraints) -> bool {
+    if from == to {
+        return true;
+    }
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(from);
+    queue.push_back(from);
+    while let Some(l) = queue.pop_front() {
+        for turn in map.get_turns_from_lane(l) {
+            let next = turn.id.dst;
+            if next == to {
+                return true;
+            }
+            if constraints.can_use(map.get_l(next), map) && visited.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+/// Traffic signals that don't cover exactly the movements of their intersection, have no stages
+/// or movements at all, or otherwise fail validation.
+#[derive(Default)]
+pub struct TrafficSignalRule {
+    broken_before: BTreeSet<IntersectionID>,
+}
+
+impl EditRule for TrafficSignalRule {
+    fn name(&self) -> &'static str {
+        "traffic_signals"
+    }
+
+    fn before(&mut self, map: &Map) {
+        self.broken_before = broken_signals(map).into_iter().map(|(i, _)| i).collect();
+    }
+
+    fn check(&self, map: &Map) -> Vec<EditProblem> {
+        broken_signals(map)
+            .into_iter()
+            .filter(|(i, _)| !self.broken_before.contains(i))
+            .map(|(i, message)| EditProblem::error(message, vec![AffectedID::Intersection(i)]))
+            .collect()
+    }
+}
+
+fn broken_signals(map: &Map) -> Vec<(IntersectionID, String)> {
+    let mut results = Vec::new();
+    for i in map.all_intersections() {
+        if !i.is_traffic_signal() {
+            continue;
+        }
+        let signal = match map.maybe_get_traffic_signal(i.id) {
+            Some(ts) => ts,
+            None => {
+                results.push((i.id, format!("{} has no traffic signal", i.id)));
+                continue;
+            }
+        };
+        if i.movements.is_empty() {
+            results.push((i.id, format!("Traffic signal at {} has no movements", i.id)));
+        } else if signal.stages.is_empty() {
+            results.push((i.id, format!("Traffic signal at {} has no stages", i.id)));
+        } else if let Err(err) = signal.validate(i) {
+            results.push((i.id, err.to_string()));
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warnings_only_report_is_ok() {
+        let mut report = ValidationReport::default();
+        assert!(report.is_ok());
+
+        report.problems.push(EditProblem::warning(
+            "2 lanes disconnected for Car".to_string(),
+            vec![AffectedID::Road(RoadID(0))],
+        ));
+        assert!(report.is_ok());
+        assert_eq!(report.warnings().count(), 1);
+        assert_eq!(report.errors().count(), 0);
+
+        report.problems.push(EditProblem::error(
+            "1 sidewalks disconnected".to_string(),
+            vec![AffectedID::Road(RoadID(1))],
+        ));
+        assert!(!report.is_ok());
+    }
+}
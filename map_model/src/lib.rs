@@ -28,7 +28,7 @@ extern crate anyhow;
 #[macro_use]
 extern crate log;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 
@@ -38,32 +38,42 @@ use geom::{Bounds, GPSBounds, Polygon};
 
 pub use crate::city::City;
 pub use crate::edits::{
-    EditCmd, EditEffects, EditIntersection, EditRoad, MapEdits, PermanentMapEdits,
+    default_edit_rules, AffectedID, BusRouteRule, EditCmd, EditEffects, EditIntersection,
+    EditProblem, EditRoad, EditRule, MapEdits, PermanentEditCmd, PermanentMapEdits,
+    ProblemSeverity, SidewalkConnectivityRule, TrafficSignalRule, ValidationReport,
+    VehicleConnectivityRule,
 };
-pub use crate::make::RawToMapOptions;
+pub use crate::make::{RawToMapOptions, ScheduledRoute, ScheduledStop};
 pub use crate::map::{DrivingSide, MapConfig};
 pub use crate::objects::area::{Area, AreaID, AreaType};
 pub use crate::objects::building::{
     Amenity, AmenityType, Building, BuildingID, BuildingType, NamePerLanguage, OffstreetParking,
 };
-pub use crate::objects::bus_stop::{BusRoute, BusRouteID, BusStop, BusStopID};
+pub use crate::objects::bus_stop::{
+    BusRoute, BusRouteID, BusStop, BusStopID, Timetable, TransitStation, TransitStationID,
+};
 pub use crate::objects::intersection::{Intersection, IntersectionID, IntersectionType};
 pub use crate::objects::lane::{
-    BufferType, Lane, LaneID, LaneSpec, LaneType, NORMAL_LANE_THICKNESS, PARKING_LOT_SPOT_LENGTH,
-    SIDEWALK_THICKNESS,
+    BufferType, Lane, LaneChange, LaneID, LaneSpec, LaneType, NORMAL_LANE_THICKNESS,
+    PARKING_LOT_SPOT_LENGTH, SIDEWALK_THICKNESS,
 };
 pub use crate::objects::movement::{CompressedMovementID, Movement, MovementID};
 pub use crate::objects::parking_lot::{ParkingLot, ParkingLotID};
+pub use crate::objects::parking_regulation::{ParkingRegulation, ParkingRegulationTarget};
 pub use crate::objects::road::{DirectedRoadID, Direction, Road, RoadID};
 pub use crate::objects::stop_signs::{ControlStopSign, RoadWithStopSign};
-pub use crate::objects::traffic_signals::{ControlTrafficSignal, Stage, StageType};
+pub use crate::objects::toll::{Toll, TollWindow};
+pub use crate::objects::traffic_signals::{
+    ControlTrafficSignal, Stage, StageType, TransitSignalPriority,
+};
 pub use crate::objects::turn::{Turn, TurnID, TurnPriority, TurnType};
-pub use crate::objects::zone::{AccessRestrictions, Zone};
+pub use crate::objects::zone::{AccessRestrictions, RestrictionWindow, Zone};
 pub use crate::pathfind::uber_turns::{IntersectionCluster, UberTurn};
-use crate::pathfind::Pathfinder;
 pub use crate::pathfind::{
-    Path, PathConstraints, PathRequest, PathStep, PathStepV2, PathV2, RoutingParams,
+    Itinerary, ItineraryLeg, ObservedTravelTimes, Path, PathConstraints, PathRequest, PathStep,
+    PathStepV2, PathV2, RoutingParams, TransitRide, TravelTimeProfiles,
 };
+use crate::pathfind::{Pathfinder, TimeOfDayCosts, TransitRouterCache};
 pub use crate::traversable::{Position, Traversable, MAX_BIKE_SPEED, MAX_WALKING_SPEED};
 
 mod city;
@@ -90,6 +100,7 @@ pub struct Map {
     )]
     bus_stops: BTreeMap<BusStopID, BusStop>,
     bus_routes: Vec<BusRoute>,
+    transit_stations: Vec<TransitStation>,
     areas: Vec<Area>,
     parking_lots: Vec<ParkingLot>,
     boundary_polygon: Polygon,
@@ -104,9 +115,29 @@ pub struct Map {
 
     pathfinder: Pathfinder,
     pathfinder_dirty: bool,
+    // Structural edits add new roads and intersections, so the pathfinder's graphs need new nodes,
+    // and uber-turns may change. Set by edits, so not saved with the map.
+    #[serde(skip_serializing, skip_deserializing)]
+    pathfinder_structure_changed: bool,
+    // Built lazily from the transit schedules, never saved with the map.
+    #[serde(skip_serializing, skip_deserializing)]
+    transit_router: TransitRouterCache,
     routing_params: RoutingParams,
+    // Set from a previous simulation at runtime, never saved with the map.
+    #[serde(skip_serializing, skip_deserializing)]
+    observed_travel_times: Option<ObservedTravelTimes>,
     // Not the source of truth, just cached.
     zones: Vec<Zone>,
+    // Derived from edits, so not saved with the map.
+    #[serde(skip_serializing, skip_deserializing)]
+    time_of_day_costs: TimeOfDayCosts,
+    // Only set through edits, so not saved with the map. Unregulated parking isn't stored.
+    #[serde(skip_serializing, skip_deserializing)]
+    parking_regulations: BTreeMap<ParkingRegulationTarget, ParkingRegulation>,
+    // Roads removed by edits stay in `roads` so IDs remain stable, but they're disconnected from
+    // everything. Only set through edits, so not saved with the map.
+    #[serde(skip_serializing, skip_deserializing)]
+    deleted_roads: BTreeSet<RoadID>,
 
     name: MapName,
 
@@ -175,31 +175,49 @@ pub fn collapse_intersection(raw: &mut RawMap, i: NodeID) {
 
     info!("Collapsing degenerate {}", i);
     raw.intersections.remove(&i).unwrap();
-    // We could be more careful merging percent_incline and osm_tags, but in practice, it doesn't
-    // matter for the short segments we're merging.
+    // We could be more careful merging osm_tags, but in practice, it doesn't matter for the short
+    // segments we're merging.
     let mut new_road = raw.roads.remove(&r1).unwrap();
     let mut road2 = raw.roads.remove(&r2).unwrap();
+    let mut grades1 = new_road.grade_profile();
+    let mut grades2 = road2.grade_profile();
+    let had_grades = !new_road.grades.is_empty() || !road2.grades.is_empty();
 
     // There are 4 cases, easy to understand on paper. Preserve the original direction of r1
     let (new_i1, new_i2) = if r1.i2 == r2.i1 {
         new_road.center_points.extend(road2.center_points);
+        grades1.extend(grades2);
         (r1.i1, r2.i2)
     } else if r1.i2 == r2.i2 {
         road2.center_points.reverse();
         new_road.center_points.extend(road2.center_points);
+        grades1.extend(reverse_grades(grades2));
         (r1.i1, r2.i1)
     } else if r1.i1 == r2.i1 {
         road2.center_points.reverse();
         road2.center_points.extend(new_road.center_points);
         new_road.center_points = road2.center_points;
+        grades2 = reverse_grades(grades2);
+        grades2.extend(grades1);
+        grades1 = grades2;
         (r2.i2, r1.i2)
     } else if r1.i1 == r2.i2 {
         road2.center_points.extend(new_road.center_points);
         new_road.center_points = road2.center_points;
+        grades2.extend(grades1);
+        grades1 = grades2;
         (r2.i1, r1.i2)
     } else {
         unreachable!()
     };
+    let total_length: Distance = grades1.iter().map(|(len, _)| *len).sum();
+    if total_length > Distance::ZERO {
+        new_road.percent_incline = grades1
+            .iter()
+            .map(|(len, grade)| (*len / total_length) * grade)
+            .sum();
+    }
+    new_road.grades = if had_grades { grades1 } else { Vec::new() };
     // Sanity check
     assert!(i != new_i1 && i != new_i2);
     // When we concatenate the points, the common point will be duplicated
@@ -232,6 +250,15 @@ pub fn collapse_intersection(raw: &mut RawMap, i: NodeID) {
     }
 }
 
+/// Flip a grade profile to describe the road in the opposite direction.
+fn reverse_grades(grades: Vec<(Distance, f64)>) -> Vec<(Distance, f64)> {
+    grades
+        .into_iter()
+        .rev()
+        .map(|(len, grade)| (len, -grade))
+        .collect()
+}
+
 const SHORT_THRESHOLD: Distance = Distance::const_meters(30.0);
 
 /// Some cycleways intersect footways with detailed curb mapping. The current rules for figuring
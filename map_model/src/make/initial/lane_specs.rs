@@ -1,20 +1,27 @@
 /// Purely from OSM tags, determine the lanes that a road segment has.
+use std::collections::BTreeSet;
 use std::iter;
 
 use abstutil::Tags;
 
-use crate::{osm, BufferType, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
+use crate::{
+    osm, BufferType, Direction, DrivingSide, LaneChange, LaneSpec, LaneType, MapConfig, TurnType,
+};
 
 pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
     let fwd = |lt: LaneType| LaneSpec {
         lt,
         dir: Direction::Fwd,
         width: LaneSpec::typical_lane_widths(lt, tags)[0].0,
+        allowed_turns: None,
+        lane_change: LaneChange::default(),
     };
     let back = |lt: LaneType| LaneSpec {
         lt,
         dir: Direction::Back,
         width: LaneSpec::typical_lane_widths(lt, tags)[0].0,
+        allowed_turns: None,
+        lane_change: LaneChange::default(),
     };
 
     // Easy special cases first.
@@ -178,6 +185,26 @@ pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
         }
     }
 
+    // Turn lane markings only apply at the end of the original OSM way, which may've been split
+    // into several road segments.
+    let fwd_turns = if tags.contains_key(osm::ENDPT_FWD) {
+        tags.get("turn:lanes:forward")
+            .or_else(|| tags.get("turn:lanes"))
+    } else {
+        None
+    };
+    let back_turns = if tags.contains_key(osm::ENDPT_BACK) {
+        tags.get("turn:lanes:backward")
+    } else {
+        None
+    };
+    let fwd_change = tags
+        .get("change:lanes:forward")
+        .or_else(|| tags.get("change:lanes"));
+    let back_change = tags.get("change:lanes:backward");
+    apply_turn_lanes(&mut fwd_side, fwd_turns, fwd_change, cfg.driving_side);
+    apply_turn_lanes(&mut back_side, back_turns, back_change, cfg.driving_side);
+
     if tags.is_any("cycleway", vec!["lane", "track"]) {
         fwd_side.push(fwd(LaneType::Biking));
         if !back_side.is_empty() {
@@ -358,6 +385,100 @@ fn assemble_ltr(
     }
 }
 
+/// Fill out allowed turns and lane-changing rules for the driving and bus lanes on one side of a
+/// road. The side is ordered from the road center outwards, but OSM lists lanes from left to right
+/// in the direction of travel.
+fn apply_turn_lanes(
+    side: &mut [LaneSpec],
+    turn_lanes: Option<&String>,
+    change_lanes: Option<&String>,
+    driving_side: DrivingSide,
+) {
+    let mut indices: Vec<usize> = side
+        .iter()
+        .enumerate()
+        .filter(|(_, spec)| spec.lt == LaneType::Driving || spec.lt == LaneType::Bus)
+        .map(|(idx, _)| idx)
+        .collect();
+    if driving_side == DrivingSide::Left {
+        indices.reverse();
+    }
+
+    if let Some(turn_lanes) = turn_lanes {
+        let parts: Vec<&str> = turn_lanes.split('|').collect();
+        if parts.len() == indices.len() {
+            // These both mean that physically, there's no marking saying what turn is valid. In
+            // practice, this seems to imply straight is always fine, and right/left are fine
+            // unless covered by an explicit turn lane.
+            //
+            // If a multi-lane road lacks markings, just listening to this will mean that the
+            // rightmost lanes could turn left, which probably isn't great for people in the middle
+            // lanes going straight. Further filtering (in remove_merging_turns) will prune this
+            // out.
+            let all_explicit_types: BTreeSet<TurnType> = parts
+                .iter()
+                .flat_map(|part| part.split(';').flat_map(parse_turn_type_from_osm))
+                .collect();
+            let mut implied = BTreeSet::new();
+            implied.insert(TurnType::Straight);
+            for tt in [TurnType::Left, TurnType::Right] {
+                if !all_explicit_types.contains(&tt) {
+                    implied.insert(tt);
+                }
+            }
+
+            for (idx, part) in indices.iter().zip(parts) {
+                side[*idx].allowed_turns = if part == "yes" || part == "psv" || part == "bus" {
+                    // TODO Probably the target lane should get marked as LaneType::Bus
+                    None
+                } else if part.is_empty() || part == "none" {
+                    Some(implied.clone())
+                } else {
+                    Some(part.split(';').flat_map(parse_turn_type_from_osm).collect())
+                };
+            }
+        } else {
+            warn!("turn:lanes = {} doesn't match the lanes", turn_lanes);
+        }
+    }
+
+    if let Some(change_lanes) = change_lanes {
+        let parts: Vec<&str> = change_lanes.split('|').collect();
+        if parts.len() == indices.len() {
+            for (idx, part) in indices.iter().zip(parts) {
+                if let Some(lane_change) = LaneChange::from_osm(part) {
+                    side[*idx].lane_change = lane_change;
+                } else {
+                    warn!("Unknown lane change restriction {}", part);
+                }
+            }
+        } else {
+            warn!("change:lanes = {} doesn't match the lanes", change_lanes);
+        }
+    }
+}
+
+// See https://wiki.openstreetmap.org/wiki/Key:turn
+fn parse_turn_type_from_osm(x: &str) -> Vec<TurnType> {
+    match x {
+        "left" => vec![TurnType::Left],
+        "right" => vec![TurnType::Right],
+        "through" => vec![TurnType::Straight],
+        "slight_right" | "slight right" | "merge_to_right" | "sharp_right" => {
+            vec![TurnType::Straight, TurnType::Right]
+        }
+        "slight_left" | "slight left" | "merge_to_left" | "sharp_left" => {
+            vec![TurnType::Straight, TurnType::Left]
+        }
+        "reverse" => vec![TurnType::UTurn],
+        "none" | "" => vec![],
+        _ => {
+            warn!("Unknown turn restriction {}", x);
+            vec![]
+        }
+    }
+}
+
 // See https://wiki.openstreetmap.org/wiki/Proposed_features/cycleway:separation#Typical_values.
 // Lots of these mappings are pretty wacky right now. We need more BufferTypes.
 #[allow(clippy::ptr_arg)] // Can't chain with `tags.get("foo").and_then` otherwise
@@ -574,4 +695,64 @@ mod tests {
         }
         assert!(ok);
     }
+
+    #[test]
+    fn test_turn_lanes() {
+        // OSM lists lanes from left to right in the direction of travel, but the specs go from
+        // left to right across the road, with the one-way's shoulder on the outside.
+        for (driving_side, expected_turns, expected_changes) in [
+            (
+                DrivingSide::Right,
+                vec![
+                    Some(vec![TurnType::Left]),
+                    Some(vec![TurnType::Straight]),
+                    Some(vec![TurnType::Straight, TurnType::Right]),
+                    None,
+                ],
+                vec![(false, false), (true, true), (true, false), (true, true)],
+            ),
+            (
+                DrivingSide::Left,
+                vec![
+                    None,
+                    Some(vec![TurnType::Left]),
+                    Some(vec![TurnType::Straight]),
+                    Some(vec![TurnType::Straight, TurnType::Right]),
+                ],
+                vec![(true, true), (false, false), (true, true), (true, false)],
+            ),
+        ] {
+            let cfg = MapConfig {
+                driving_side,
+                bikes_can_use_bus_lanes: true,
+                inferred_sidewalks: true,
+                street_parking_spot_length: geom::Distance::meters(8.0),
+            };
+            let specs = get_lane_specs_ltr(
+                &tags(vec![
+                    "lanes=3",
+                    "oneway=yes",
+                    "sidewalk=none",
+                    "turn:lanes=left|through|through;right",
+                    "change:lanes=no|yes|not_right",
+                    "abst:endpt_fwd=true",
+                ]),
+                &cfg,
+            );
+            let turns: Vec<Option<Vec<TurnType>>> = specs
+                .iter()
+                .map(|s| s.allowed_turns.clone().map(|set| set.into_iter().collect()))
+                .collect();
+            assert_eq!(turns, expected_turns, "driving on the {:?}", driving_side);
+            let changes: Vec<(bool, bool)> = specs
+                .iter()
+                .map(|s| (s.lane_change.left, s.lane_change.right))
+                .collect();
+            assert_eq!(
+                changes, expected_changes,
+                "driving on the {:?}",
+                driving_side
+            );
+        }
+    }
 }
@@ -1,13 +1,14 @@
 //! See <https://a-b-street.github.io/docs/tech/map/importing/index.html> for an overview. This module
 //! covers the RawMap->Map stage.
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use abstutil::{MultiMap, Tags, Timer};
 use geom::{Distance, FindClosest, HashablePt2D, Line, Polygon, Speed, EPSILON_DIST};
 
 pub use self::parking_lots::snap_driveway;
-use crate::pathfind::{CreateEngine, Pathfinder};
+pub use self::transit::{ScheduledRoute, ScheduledStop};
+use crate::pathfind::{CreateEngine, Pathfinder, TimeOfDayCosts, TransitRouterCache};
 use crate::raw::{OriginalRoad, RawMap};
 use crate::{
     connectivity, osm, AccessRestrictions, Area, AreaID, AreaType, ControlStopSign,
@@ -67,9 +68,11 @@ impl Map {
             buildings: Vec::new(),
             bus_stops: BTreeMap::new(),
             bus_routes: Vec::new(),
+            transit_stations: Vec::new(),
             areas: Vec::new(),
             parking_lots: Vec::new(),
             zones: Vec::new(),
+            time_of_day_costs: TimeOfDayCosts::default(),
             boundary_polygon: raw.boundary_polygon.clone(),
             stop_signs: BTreeMap::new(),
             traffic_signals: BTreeMap::new(),
@@ -78,7 +81,12 @@ impl Map {
             config: raw.config.clone(),
             pathfinder: Pathfinder::empty(),
             pathfinder_dirty: false,
+            pathfinder_structure_changed: false,
+            transit_router: TransitRouterCache::default(),
             routing_params: RoutingParams::default(),
+            observed_travel_times: None,
+            parking_regulations: BTreeMap::new(),
+            deleted_roads: BTreeSet::new(),
             name: raw.name.clone(),
             edits: MapEdits::new(),
             edits_generation: 0,
@@ -158,7 +166,9 @@ impl Map {
                 speed_limit: Speed::ZERO,
                 zorder: raw_road.get_zorder(),
                 access_restrictions: AccessRestrictions::new(),
+                toll: None,
                 percent_incline: raw_road.percent_incline,
+                grades: raw_road.grades.clone(),
             };
             road.speed_limit = road.speed_limit_from_osm();
             road.access_restrictions = road.access_restrictions_from_osm();
@@ -3,12 +3,14 @@ use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use anyhow::Result;
 
 use abstutil::Timer;
-use geom::{Distance, Duration, FindClosest, HashablePt2D, Time};
+use geom::{Angle, Distance, Duration, FindClosest, HashablePt2D, PolyLine, Pt2D, Time};
 
 use crate::make::match_points_to_lanes;
+use crate::pathfind::Pathfinder;
 use crate::raw::{RawBusRoute, RawBusStop};
 use crate::{
-    BusRoute, BusRouteID, BusStop, BusStopID, LaneID, LaneType, Map, PathConstraints, Position,
+    osm, BusRoute, BusRouteID, BusStop, BusStopID, IntersectionID, LaneID, LaneType, Map,
+    PathConstraints, PathRequest, Position, RoadID, Timetable, TransitStation, TransitStationID,
 };
 
 /// Construct the final model of bus/train stops and routes. This is quite broken currently, so not
@@ -36,6 +38,7 @@ pub fn make_stops_and_routes(map: &mut Map, raw_routes: &[RawBusRoute], timer: &
         map.bus_stops.remove(&id);
         map.mut_lane(id.sidewalk).bus_stops.remove(&id);
     }
+    make_stations(map);
 
     timer.stop("make transit stops and routes");
 }
@@ -141,6 +144,7 @@ fn make_route(
         end_border,
         spawn_times: default_spawn_times(),
         orig_spawn_times: default_spawn_times(),
+        timetable: None,
     };
 
     let mut debug_route = "All parts of the route:".to_string();
@@ -307,6 +311,78 @@ impl Matcher {
     }
 }
 
+/// Group train platforms into stations, and find where each station meets the sidewalk network.
+fn make_stations(map: &mut Map) {
+    // Platforms of one station are usually mapped separately for each direction, and maybe for
+    // different lines too.
+    let platform_threshold = Distance::meters(100.0);
+    let entrance_threshold = Distance::meters(50.0);
+
+    let mut stations: Vec<TransitStation> = Vec::new();
+    for stop in map.bus_stops.values() {
+        if !stop.is_train_stop {
+            continue;
+        }
+        let pt = stop.sidewalk_pos.pt(map);
+        if let Some(ts) = stations.iter_mut().find(|ts| {
+            ts.platforms.iter().any(|other| {
+                let other = &map.bus_stops[other];
+                other.name == stop.name
+                    || other.sidewalk_pos.pt(map).dist_to(pt) <= platform_threshold
+            })
+        }) {
+            ts.platforms.push(stop.id);
+            ts.entrances.push(stop.sidewalk_pos);
+        } else {
+            stations.push(TransitStation {
+                id: TransitStationID(stations.len()),
+                name: stop.name.clone(),
+                platforms: vec![stop.id],
+                entrances: vec![stop.sidewalk_pos],
+            });
+        }
+    }
+
+    // Platforms are often footways only loosely connected to the rest of the sidewalks, so also
+    // treat the closest point on every nearby sidewalk as an entrance.
+    let mut sidewalks = FindClosest::new(map.get_bounds());
+    for l in map.all_lanes() {
+        if l.is_walkable() {
+            sidewalks.add(l.id, l.lane_center_pts.points());
+        }
+    }
+    for ts in &mut stations {
+        let platform_roads: HashSet<RoadID> = ts
+            .entrances
+            .iter()
+            .map(|pos| map.get_l(pos.lane()).parent)
+            .collect();
+        let mut closest_per_road: BTreeMap<RoadID, (Distance, Position)> = BTreeMap::new();
+        for bs in &ts.platforms {
+            let pt = map.bus_stops[bs].sidewalk_pos.pt(map);
+            for (l, sidewalk_pt, dist) in sidewalks.all_close_pts(pt, entrance_threshold) {
+                let lane = map.get_l(l);
+                if platform_roads.contains(&lane.parent) {
+                    continue;
+                }
+                if let Some(dist_along) = lane.dist_along_of_point(sidewalk_pt) {
+                    if closest_per_road
+                        .get(&lane.parent)
+                        .map(|(d, _)| dist < *d)
+                        .unwrap_or(true)
+                    {
+                        closest_per_road.insert(lane.parent, (dist, Position::new(l, dist_along)));
+                    }
+                }
+            }
+        }
+        ts.entrances
+            .extend(closest_per_road.into_iter().map(|(_, (_, pos))| pos));
+    }
+
+    map.transit_stations = stations;
+}
+
 fn pick_start_lane(
     first_stop: Position,
     constraints: PathConstraints,
@@ -337,6 +413,376 @@ fn pick_start_lane(
     )
 }
 
+/// A transit route described by a published timetable (like GTFS), instead of an OSM relation.
+pub struct ScheduledRoute {
+    pub full_name: String,
+    pub short_name: String,
+    pub gtfs_trip_marker: Option<String>,
+    /// If not, light rail
+    pub is_bus: bool,
+    /// Only the stops inside the map, in order.
+    pub stops: Vec<ScheduledStop>,
+    /// The path vehicles physically follow, if known. Used to figure out which side of the road
+    /// each stop is on. May extend past the map boundary.
+    pub shape: Vec<Pt2D>,
+    /// Does the route begin before the first stop or continue after the last stop, outside the
+    /// map?
+    pub starts_off_map: bool,
+    pub ends_off_map: bool,
+    /// When each trip reaches the first stop. Sorted.
+    pub first_arrivals: Vec<Time>,
+    pub timetable: Timetable,
+}
+
+pub struct ScheduledStop {
+    pub name: String,
+    pub pt: Pt2D,
+}
+
+impl Map {
+    /// Throw away all transit stops and routes, replacing them with ones from a published
+    /// timetable. Routes that can't be matched to the map are skipped.
+    pub fn hack_replace_transit_routes(&mut self, routes: Vec<ScheduledRoute>, timer: &mut Timer) {
+        timer.start("replace transit with scheduled routes");
+        self.bus_routes.clear();
+        for id in self.bus_stops.keys().cloned().collect::<Vec<_>>() {
+            self.mut_lane(id.sidewalk).bus_stops.clear();
+        }
+        self.bus_stops.clear();
+
+        let matcher = ScheduleMatcher::new(self);
+        let mut pt_to_stop: BTreeMap<(Position, Position), BusStopID> = BTreeMap::new();
+        timer.start_iter("match scheduled routes", routes.len());
+        for r in routes {
+            timer.next();
+            if let Err(err) = make_scheduled_route(self, &r, &mut pt_to_stop, &matcher) {
+                warn!("Skipping scheduled route {}: {}", r.full_name, err);
+            }
+        }
+
+        make_stations(self);
+
+        // Walking with transit depends on the routes
+        let mut pathfinder = std::mem::replace(&mut self.pathfinder, Pathfinder::empty());
+        pathfinder.rebuild(self, timer);
+        self.pathfinder = pathfinder;
+        self.transit_router.clear();
+        timer.stop("replace transit with scheduled routes");
+    }
+}
+
+fn make_scheduled_route(
+    map: &mut Map,
+    r: &ScheduledRoute,
+    pt_to_stop: &mut BTreeMap<(Position, Position), BusStopID>,
+    matcher: &ScheduleMatcher,
+) -> Result<()> {
+    let route_type = if r.is_bus {
+        PathConstraints::Bus
+    } else {
+        PathConstraints::Train
+    };
+    if r.stops.len() < 2 {
+        bail!("only {} stops are inside the map", r.stops.len());
+    }
+    if r.timetable.arrival_offsets.len() != r.stops.len()
+        || r.timetable.dwell_times.len() != r.stops.len()
+    {
+        bail!("timetable doesn't cover every stop");
+    }
+    if r.first_arrivals.is_empty() {
+        bail!("no trips");
+    }
+    let shape = PolyLine::deduping_new(r.shape.clone()).ok();
+
+    let mut positions = Vec::new();
+    for (idx, stop) in r.stops.iter().enumerate() {
+        // Which way is the vehicle heading here? Prefer the shape, then guess from the neighboring
+        // stops.
+        let heading = if let Some((_, angle)) = shape
+            .as_ref()
+            .and_then(|pl| pl.dist_along_of_point(pl.project_pt(stop.pt)))
+        {
+            angle
+        } else if idx == r.stops.len() - 1 {
+            r.stops[idx - 1].pt.angle_to(stop.pt)
+        } else {
+            stop.pt.angle_to(r.stops[idx + 1].pt)
+        };
+        let pair = matcher
+            .lookup(route_type, stop.pt, heading, map)
+            .map_err(|err| anyhow!("couldn't match stop {}: {}", stop.name, err))?;
+        positions.push(pair);
+    }
+
+    // Where does the route enter and leave the map?
+    let boundary = map.get_boundary_polygon();
+    let entry_pt = r
+        .shape
+        .iter()
+        .find(|pt| boundary.contains_pt(**pt))
+        .cloned()
+        .unwrap_or(r.stops[0].pt);
+    let exit_pt = r
+        .shape
+        .iter()
+        .rev()
+        .find(|pt| boundary.contains_pt(**pt))
+        .cloned()
+        .unwrap_or(r.stops.last().unwrap().pt);
+
+    let first_stop = positions[0].1;
+    let last_stop = positions.last().unwrap().1;
+    let start = if r.starts_off_map {
+        closest_border(map, entry_pt, true, route_type).and_then(|i| {
+            map.get_i(i)
+                .get_outgoing_lanes(map, route_type)
+                .get(0)
+                .cloned()
+        })
+    } else {
+        None
+    };
+    let start = match start {
+        Some(l) => l,
+        None => pick_start_lane(first_stop, route_type, map)?,
+    };
+    let end_border = if r.ends_off_map {
+        closest_border(map, exit_pt, false, route_type).and_then(|i| {
+            // If the last stop is on a lane leading to the border, don't try to lane-change last
+            // minute
+            if map.get_l(last_stop.lane()).dst_i == i {
+                Some(last_stop.lane())
+            } else {
+                map.get_i(i)
+                    .get_incoming_lanes(map, route_type)
+                    .get(0)
+                    .cloned()
+            }
+        })
+    } else {
+        None
+    };
+
+    // Make sure the route is connected before creating anything.
+    let mut steps = vec![PathRequest::vehicle(
+        Position::start(start),
+        first_stop,
+        route_type,
+    )];
+    for pair in positions.windows(2) {
+        steps.push(PathRequest::vehicle(pair[0].1, pair[1].1, route_type));
+    }
+    if let Some(l) = end_border {
+        steps.push(PathRequest::vehicle(
+            last_stop,
+            Position::end(l, map),
+            route_type,
+        ));
+    }
+    let mut time_to_first_stop = Duration::ZERO;
+    for (idx, req) in steps.into_iter().enumerate() {
+        if req.start.lane() == req.end.lane() && req.start.dist_along() > req.end.dist_along() {
+            bail!(
+                "Two stops seemingly out of order somewhere on {}",
+                map.get_parent(req.start.lane()).orig_id
+            );
+        }
+        match map.pathfind(req.clone()) {
+            Ok(path) => {
+                if idx == 0 {
+                    time_to_first_stop = path.estimate_duration(map, None);
+                }
+            }
+            Err(err) => {
+                bail!(
+                    "No path between stop on {} and {}: {}",
+                    map.get_parent(req.start.lane()).orig_id,
+                    map.get_parent(req.end.lane()).orig_id,
+                    err
+                );
+            }
+        }
+    }
+
+    let mut stops = Vec::new();
+    for ((sidewalk_pos, driving_pos), stop) in positions.into_iter().zip(r.stops.iter()) {
+        let id = if let Some(id) = pt_to_stop.get(&(sidewalk_pos, driving_pos)) {
+            *id
+        } else {
+            let id = BusStopID {
+                sidewalk: sidewalk_pos.lane(),
+                idx: map.get_l(sidewalk_pos.lane()).bus_stops.len(),
+            };
+            pt_to_stop.insert((sidewalk_pos, driving_pos), id);
+            map.mut_lane(sidewalk_pos.lane()).bus_stops.insert(id);
+            map.bus_stops.insert(
+                id,
+                BusStop {
+                    id,
+                    name: stop.name.clone(),
+                    driving_pos,
+                    sidewalk_pos,
+                    is_train_stop: !r.is_bus,
+                },
+            );
+            id
+        };
+        stops.push(id);
+    }
+
+    // Vehicles have to leave the start early enough to reach the first stop on time.
+    let mut spawn_times: Vec<Time> = r
+        .first_arrivals
+        .iter()
+        .map(|t| {
+            if *t - Time::START_OF_DAY > time_to_first_stop {
+                *t - time_to_first_stop
+            } else {
+                Time::START_OF_DAY
+            }
+        })
+        .collect();
+    spawn_times.dedup();
+    map.bus_routes.push(BusRoute {
+        id: BusRouteID(map.bus_routes.len()),
+        full_name: r.full_name.clone(),
+        short_name: r.short_name.clone(),
+        gtfs_trip_marker: r.gtfs_trip_marker.clone(),
+        // Not from OSM
+        osm_rel_id: osm::RelationID(-1),
+        stops,
+        start,
+        end_border,
+        route_type,
+        spawn_times: spawn_times.clone(),
+        orig_spawn_times: spawn_times,
+        timetable: Some(r.timetable.clone()),
+    });
+    Ok(())
+}
+
+/// Finds the border intersection closest to a point where a route enters or leaves the map.
+fn closest_border(
+    map: &Map,
+    pt: Pt2D,
+    incoming: bool,
+    constraints: PathConstraints,
+) -> Option<IntersectionID> {
+    let borders = if incoming {
+        map.all_incoming_borders()
+    } else {
+        map.all_outgoing_borders()
+    };
+    borders
+        .into_iter()
+        .filter(|i| {
+            if incoming {
+                !i.get_outgoing_lanes(map, constraints).is_empty()
+            } else {
+                !i.get_incoming_lanes(map, constraints).is_empty()
+            }
+        })
+        .map(|i| (i.id, i.polygon.center().dist_to(pt)))
+        .filter(|(_, dist)| *dist <= Distance::meters(100.0))
+        .min_by_key(|(_, dist)| *dist)
+        .map(|(i, _)| i)
+}
+
+struct ScheduleMatcher {
+    bus_lanes: FindClosest<LaneID>,
+    rail_lanes: FindClosest<LaneID>,
+    sidewalks: FindClosest<LaneID>,
+}
+
+impl ScheduleMatcher {
+    fn new(map: &Map) -> ScheduleMatcher {
+        let mut bus_lanes = FindClosest::new(map.get_bounds());
+        let mut rail_lanes = FindClosest::new(map.get_bounds());
+        let mut sidewalks = FindClosest::new(map.get_bounds());
+        for l in map.all_lanes() {
+            if PathConstraints::Bus.can_use(l, map) {
+                bus_lanes.add(l.id, l.lane_center_pts.points());
+            } else if l.lane_type == LaneType::LightRail {
+                rail_lanes.add(l.id, l.lane_center_pts.points());
+            } else if l.is_walkable() {
+                sidewalks.add(l.id, l.lane_center_pts.points());
+            }
+        }
+        ScheduleMatcher {
+            bus_lanes,
+            rail_lanes,
+            sidewalks,
+        }
+    }
+
+    // returns (sidewalk, driving)
+    fn lookup(
+        &self,
+        route_type: PathConstraints,
+        pt: Pt2D,
+        heading: Angle,
+        map: &Map,
+    ) -> Result<(Position, Position)> {
+        let lanes = if route_type == PathConstraints::Bus {
+            &self.bus_lanes
+        } else {
+            &self.rail_lanes
+        };
+        // Only consider lanes pointing the way the vehicle travels. Stops are at the curb, so the
+        // closest remaining lane is usually the rightmost one.
+        let mut driving_pos = lanes
+            .all_close_pts(pt, Distance::meters(30.0))
+            .into_iter()
+            .filter_map(|(l, lane_pt, dist)| {
+                let (dist_along, angle) =
+                    map.get_l(l).lane_center_pts.dist_along_of_point(lane_pt)?;
+                if angle.approx_eq(heading, 60.0) {
+                    Some((dist, Position::new(l, dist_along)))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(dist, _)| *dist)
+            .map(|(_, pos)| pos)
+            .ok_or_else(|| anyhow!("no {:?} lane heading {} near {}", route_type, heading, pt))?;
+
+        let sidewalk_pos = if route_type == PathConstraints::Bus {
+            let sidewalk = map
+                .get_parent(driving_pos.lane())
+                .find_closest_lane(driving_pos.lane(), |l| {
+                    PathConstraints::Pedestrian.can_use(l, map)
+                })
+                .ok_or_else(|| anyhow!("driving {} to sidewalk failed", driving_pos.lane()))?;
+            driving_pos.equiv_pos(sidewalk, map)
+        } else {
+            // Platforms could be on a different road entirely
+            let (l, sidewalk_pt) = self
+                .sidewalks
+                .closest_pt(pt, Distance::meters(50.0))
+                .ok_or_else(|| anyhow!("no platform near {}", pt))?;
+            let dist_along = map
+                .get_l(l)
+                .dist_along_of_point(sidewalk_pt)
+                .ok_or_else(|| anyhow!("platform didn't match {}", l))?;
+            Position::new(l, dist_along)
+        };
+
+        // Same as for OSM routes, don't put a stop where the bus spawns at a border.
+        if map
+            .get_i(map.get_l(driving_pos.lane()).src_i)
+            .is_incoming_border()
+        {
+            if let Some(pos) = driving_pos.min_dist(Distance::meters(1.0), map) {
+                driving_pos = pos;
+            } else {
+                bail!("too close to start of a border {}", driving_pos.lane());
+            }
+        }
+        Ok((sidewalk_pos, driving_pos))
+    }
+}
+
 fn default_spawn_times() -> Vec<Time> {
     // Hourly spawning from midnight to 7, then every 30 minutes till 7, then hourly again
     let mut times = Vec::new();
@@ -33,7 +33,8 @@ pub fn make_all_turns(map: &Map, i: &Intersection) -> Vec<Turn> {
     // And remove merging left or right turns. If we wanted to remove the "lane-changing at
     // intersections" behavior, we could do this for TurnType::Straight too.
     let filtered_turns = remove_merging_turns(map, filtered_turns, TurnType::Right);
-    let mut filtered_turns = remove_merging_turns(map, filtered_turns, TurnType::Left);
+    let filtered_turns = remove_merging_turns(map, filtered_turns, TurnType::Left);
+    let mut filtered_turns = remove_forbidden_lane_changes(map, i, filtered_turns);
     if i.merged {
         filtered_turns.retain(|turn| {
             if turn.turn_type == TurnType::UTurn {
@@ -41,7 +42,7 @@ pub fn make_all_turns(map: &Map, i: &Intersection) -> Vec<Turn> {
                 // U-turns at divided highways are sometimes legal (and a common movement --
                 // https://www.openstreetmap.org/way/361443212), so let OSM turn:lanes override.
                 if src_lane
-                    .get_lane_level_turn_restrictions(map.get_r(src_lane.id.road), false)
+                    .get_lane_level_turn_restrictions(false)
                     .map(|set| !set.contains(&TurnType::UTurn))
                     .unwrap_or(true)
                 {
@@ -276,6 +277,55 @@ fn from_pt(pt: Point2d<f64>) -> Pt2D {
     Pt2D::new(pt.x, pt.y)
 }
 
+/// Where a road just continues through an intersection with two roads, moving to a different lane
+/// is really a lane change. Respect any restrictions on that from the source lane.
+fn remove_forbidden_lane_changes(map: &Map, i: &Intersection, input: Vec<Turn>) -> Vec<Turn> {
+    if i.roads.len() != 2 {
+        return input;
+    }
+    // Lanes are ordered from left to right in the direction of travel, no matter the driving side
+    let vehicle_lanes = |l: LaneID| -> Vec<LaneID> {
+        let lane = map.get_l(l);
+        map.get_parent(l)
+            .children(lane.dir)
+            .into_iter()
+            .filter(|(_, lt)| lt.is_for_moving_vehicles())
+            .map(|(id, _)| id)
+            .collect()
+    };
+
+    input
+        .into_iter()
+        .filter(|t| {
+            if t.turn_type != TurnType::Straight || t.between_sidewalks() {
+                return true;
+            }
+            let src_lanes = vehicle_lanes(t.id.src);
+            let dst_lanes = vehicle_lanes(t.id.dst);
+            if src_lanes.len() != dst_lanes.len() {
+                return true;
+            }
+            let src_idx = src_lanes.iter().position(|l| *l == t.id.src);
+            let dst_idx = dst_lanes.iter().position(|l| *l == t.id.dst);
+            let (src_idx, dst_idx) = match (src_idx, dst_idx) {
+                (Some(src_idx), Some(dst_idx)) => (src_idx, dst_idx),
+                _ => {
+                    return true;
+                }
+            };
+            if src_idx == dst_idx {
+                return true;
+            }
+            let lane_change = map.get_l(t.id.src).lane_change;
+            if dst_idx < src_idx {
+                lane_change.left
+            } else {
+                lane_change.right
+            }
+        })
+        .collect()
+}
+
 fn remove_merging_turns(map: &Map, input: Vec<Turn>, turn_type: TurnType) -> Vec<Turn> {
     let mut turns = Vec::new();
 
@@ -10,13 +10,17 @@ use abstio::{CityName, MapName};
 use abstutil::{prettyprint_usize, serialized_size_bytes, MultiMap, Tags, Timer};
 use geom::{Bounds, Distance, Duration, GPSBounds, Polygon, Pt2D, Ring, Time};
 
+use crate::pathfind::{zone_cost, zone_cost_at};
 use crate::raw::{OriginalRoad, RawMap};
 use crate::{
     osm, Area, AreaID, AreaType, Building, BuildingID, BuildingType, BusRoute, BusRouteID, BusStop,
     BusStopID, CompressedMovementID, ControlStopSign, ControlTrafficSignal, DirectedRoadID,
-    Direction, Intersection, IntersectionID, Lane, LaneID, LaneType, Map, MapEdits, Movement,
-    MovementID, OffstreetParking, ParkingLot, ParkingLotID, Path, PathConstraints, PathRequest,
-    PathV2, Pathfinder, Position, Road, RoadID, RoutingParams, Turn, TurnID, TurnType, Zone,
+    Direction, EditCmd, Intersection, IntersectionID, Itinerary, Lane, LaneID, LaneType, Map,
+    MapEdits, Movement, MovementID, ObservedTravelTimes, OffstreetParking, ParkingLot,
+    ParkingLotID, ParkingRegulation, ParkingRegulationTarget, Path, PathConstraints, PathRequest,
+    PathV2, Pathfinder, Position, Road, RoadID, RoutingParams, TimeOfDayCosts, TransitRide,
+    TransitRouterCache, TransitStation, TransitStationID, TravelTimeProfiles, Turn, TurnID,
+    TurnType, Zone,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -154,9 +158,11 @@ impl Map {
             buildings: Vec::new(),
             bus_stops: BTreeMap::new(),
             bus_routes: Vec::new(),
+            transit_stations: Vec::new(),
             areas: Vec::new(),
             parking_lots: Vec::new(),
             zones: Vec::new(),
+            time_of_day_costs: TimeOfDayCosts::default(),
             boundary_polygon: Ring::must_new(vec![
                 Pt2D::new(0.0, 0.0),
                 Pt2D::new(1.0, 0.0),
@@ -176,7 +182,12 @@ impl Map {
             },
             pathfinder: Pathfinder::empty(),
             pathfinder_dirty: false,
+            pathfinder_structure_changed: false,
+            transit_router: TransitRouterCache::default(),
             routing_params: RoutingParams::default(),
+            observed_travel_times: None,
+            parking_regulations: BTreeMap::new(),
+            deleted_roads: BTreeSet::new(),
             name: MapName::new("zz", "blank city", "blank"),
             edits: MapEdits::new(),
             edits_generation: 0,
@@ -184,12 +195,21 @@ impl Map {
         }
     }
 
-    pub fn all_roads(&self) -> &Vec<Road> {
+    /// Roads deleted by map edits are skipped.
+    pub fn all_roads(&self) -> impl Iterator<Item = &Road> {
+        self.roads
+            .iter()
+            .filter(move |r| !self.deleted_roads.contains(&r.id))
+    }
+
+    /// Includes roads deleted by map edits, so this can be indexed by `RoadID`.
+    pub fn all_roads_with_deleted(&self) -> &Vec<Road> {
         &self.roads
     }
 
+    /// Lanes belonging to roads deleted by map edits are skipped.
     pub fn all_lanes(&self) -> impl Iterator<Item = &Lane> {
-        self.roads.iter().flat_map(|r| r.lanes.iter())
+        self.all_roads().flat_map(|r| r.lanes.iter())
     }
 
     pub fn all_intersections(&self) -> &Vec<Intersection> {
@@ -220,6 +240,12 @@ impl Map {
         self.roads.get(id.0)
     }
 
+    /// Has this road been removed by map edits? Deleted roads keep their ID, but aren't connected
+    /// to anything.
+    pub fn is_road_deleted(&self, id: RoadID) -> bool {
+        self.deleted_roads.contains(&id)
+    }
+
     pub fn maybe_get_l(&self, id: LaneID) -> Option<&Lane> {
         self.maybe_get_r(id.road)?.lanes.get(id.offset)
     }
@@ -304,6 +330,25 @@ impl Map {
         &self.parking_lots[id.0]
     }
 
+    /// None means parking there is free, unlimited, and open to everybody.
+    pub fn get_parking_regulation(
+        &self,
+        target: ParkingRegulationTarget,
+    ) -> Option<&ParkingRegulation> {
+        self.parking_regulations.get(&target)
+    }
+
+    /// Residents of a building hold a permit for the zone regulating parking on its street, if
+    /// any.
+    pub fn get_permit_zone(&self, b: BuildingID) -> Option<&String> {
+        self.get_parking_regulation(ParkingRegulationTarget::Road(self.get_b(b).sidewalk().road))
+            .and_then(|reg| reg.permit_zone.as_ref())
+    }
+
+    pub fn all_parking_regulations(&self) -> &BTreeMap<ParkingRegulationTarget, ParkingRegulation> {
+        &self.parking_regulations
+    }
+
     pub fn get_stop_sign(&self, id: IntersectionID) -> &ControlStopSign {
         &self.stop_signs[&id]
     }
@@ -463,6 +508,21 @@ impl Map {
         &self.bus_routes
     }
 
+    pub fn get_ts(&self, id: TransitStationID) -> &TransitStation {
+        &self.transit_stations[id.0]
+    }
+
+    pub fn all_transit_stations(&self) -> &Vec<TransitStation> {
+        &self.transit_stations
+    }
+
+    /// Only train stops belong to a station.
+    pub fn get_station_for_stop(&self, stop: BusStopID) -> Option<&TransitStation> {
+        self.transit_stations
+            .iter()
+            .find(|ts| ts.platforms.contains(&stop))
+    }
+
     pub fn get_bus_route(&self, name: &str) -> Option<&BusRoute> {
         self.bus_routes.iter().find(|r| r.full_name == name)
     }
@@ -596,13 +656,35 @@ impl Map {
             .pathfind_with_params(req.clone(), params, cache_custom, self)
             .ok_or_else(|| anyhow!("can't fulfill {}", req))
     }
-    pub fn should_use_transit(
+    /// Finds a path for a vehicle that never enters some roads, unless it starts or ends there.
+    /// This is much slower than `pathfind`.
+    pub fn pathfind_avoiding_roads(
+        &self,
+        req: PathRequest,
+        avoid: &BTreeSet<RoadID>,
+    ) -> Result<Path> {
+        assert!(!self.pathfinder_dirty);
+        self.pathfinder
+            .pathfind_avoiding_roads(req.clone(), avoid, self)
+            .ok_or_else(|| anyhow!("can't fulfill {} avoiding {} roads", req, avoid.len()))?
+            .into_v1(self)
+    }
+    pub fn should_use_transit(&self, start: Position, end: Position) -> Option<Vec<TransitRide>> {
+        assert!(!self.pathfinder_dirty);
+        self.pathfinder.should_use_transit(self, start, end)
+    }
+
+    /// Find the fastest way to reach `end` from `start` using public transit, leaving at `depart`.
+    /// This accounts for when vehicles depart and transfers between routes. None if walking the
+    /// whole way is faster.
+    pub fn plan_transit_trip(
         &self,
         start: Position,
         end: Position,
-    ) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
+        depart: Time,
+    ) -> Option<Itinerary> {
         assert!(!self.pathfinder_dirty);
-        self.pathfinder.should_use_transit(self, start, end)
+        self.transit_router.get(self).plan(start, end, depart, self)
     }
 
     /// Return the cost of a single path, and also a mapping from every directed road to the cost
@@ -629,7 +711,8 @@ impl Map {
     }
 
     pub fn find_r_by_osm_id(&self, id: OriginalRoad) -> Result<RoadID> {
-        for r in self.all_roads() {
+        // Edits loaded over the current ones may refer to roads that the current edits deleted
+        for r in &self.roads {
             if r.orig_id == id {
                 return Ok(r.id);
             }
@@ -686,6 +769,7 @@ impl Map {
     pub fn hack_override_orig_spawn_times(&mut self, br: BusRouteID, times: Vec<Time>) {
         self.bus_routes[br.0].orig_spawn_times = times.clone();
         self.bus_routes[br.0].spawn_times = times;
+        self.transit_router.clear();
     }
 
     pub fn hack_add_area(&mut self, area_type: AreaType, polygon: Polygon, osm_tags: Tags) {
@@ -708,6 +792,185 @@ impl Map {
         self.recalculate_pathfinding_after_edits(timer);
     }
 
+    /// Route cars and buses using travel times measured from a previous simulation, instead of
+    /// free-flow estimates. Pass None to go back to free-flow costs. This rebuilds pathfinding, so
+    /// it's slow.
+    pub fn set_observed_travel_times(
+        &mut self,
+        times: Option<ObservedTravelTimes>,
+        timer: &mut Timer,
+    ) {
+        self.observed_travel_times = times;
+        self.pathfinder_dirty = true;
+        self.recalculate_pathfinding_after_edits(timer);
+    }
+
+    pub fn get_observed_travel_times(&self) -> Option<&ObservedTravelTimes> {
+        self.observed_travel_times.as_ref()
+    }
+
+    /// Find paths for vehicles as if the map used some observed travel times and routing params,
+    /// without changing the map's own pathfinding. Each search is slow, but nothing has to be re-prepared, so this is
+    /// much cheaper than `set_observed_travel_times` for a few thousand requests.
+    pub fn pathfind_with_observed_travel_times(
+        &mut self,
+        requests: Vec<PathRequest>,
+        times: ObservedTravelTimes,
+        params: &RoutingParams,
+        timer: &mut Timer,
+    ) -> Vec<Option<PathV2>> {
+        let orig_times = std::mem::replace(&mut self.observed_travel_times, Some(times));
+        let mut pathfinders: Vec<(PathConstraints, Pathfinder)> = Vec::new();
+        let mut results = Vec::new();
+        for req in requests {
+            let idx = match pathfinders
+                .iter()
+                .position(|(constraints, _)| *constraints == req.constraints)
+            {
+                Some(idx) => idx,
+                None => {
+                    let pathfinder = Pathfinder::new_for_one_mode(
+                        self,
+                        params.clone(),
+                        crate::pathfind::CreateEngine::Dijkstra,
+                        req.constraints,
+                        timer,
+                    );
+                    pathfinders.push((req.constraints, pathfinder));
+                    pathfinders.len() - 1
+                }
+            };
+            results.push(pathfinders[idx].1.pathfind(req, self));
+        }
+        self.observed_travel_times = orig_times;
+        results
+    }
+
+    /// Route cars and buses that have a departure time using travel times that vary through the
+    /// day. Pass None to stop. Nothing needs to be rebuilt, but each of these requests uses a much
+    /// slower search -- a time-dependent Dijkstra, not a contraction hierarchy.
+    pub fn set_travel_time_profiles(&mut self, profiles: Option<TravelTimeProfiles>) {
+        self.pathfinder.set_travel_time_profiles(profiles);
+    }
+
+    /// True if any zone restrictions or tolls change through the day.
+    pub fn has_time_of_day_costs(&self) -> bool {
+        !self.time_of_day_costs.roads.is_empty()
+    }
+
+    /// Find the roads with zone restrictions or tolls that change through the day, and the modes
+    /// for which they're ever cheaper than usual. Only edits set these, so the result is cached
+    /// when they're applied.
+    pub(crate) fn find_time_of_day_costs(&self) -> TimeOfDayCosts {
+        let mut costs = TimeOfDayCosts::default();
+        // Costs only change when some window starts or ends, so checking at each of those times
+        // covers every part of the day.
+        let mut breakpoints = Vec::new();
+        for r in self.edited_roads() {
+            let mut windows: Vec<(Time, Time)> = r
+                .access_restrictions
+                .schedule
+                .iter()
+                .map(|w| (w.start, w.end))
+                .collect();
+            for toll in r
+                .toll
+                .iter()
+                .chain(r.access_restrictions.cordon_toll.iter())
+            {
+                windows.extend(toll.schedule.iter().map(|w| (w.start, w.end)));
+            }
+            if !windows.is_empty() {
+                costs.roads.insert(r.id);
+                for (start, end) in windows {
+                    breakpoints.push(start);
+                    breakpoints.push(end);
+                }
+            }
+        }
+
+        for r in &costs.roads {
+            let road = self.get_r(*r);
+            for i in [road.src_i, road.dst_i] {
+                for mvmnt in self.get_i(i).movements.keys() {
+                    if mvmnt.crosswalk || (mvmnt.from.id != *r && mvmnt.to.id != *r) {
+                        continue;
+                    }
+                    let usual_toll = self.toll_between(mvmnt.from.id, mvmnt.to.id, None);
+                    let toll_discount = breakpoints.iter().any(|t| {
+                        self.toll_between(mvmnt.from.id, mvmnt.to.id, Some(*t)) < usual_toll
+                    });
+                    for constraints in [
+                        PathConstraints::Car,
+                        PathConstraints::Bike,
+                        PathConstraints::Bus,
+                    ] {
+                        // Only cars pay tolls
+                        if (toll_discount && constraints == PathConstraints::Car)
+                            || breakpoints.iter().any(|t| {
+                                zone_cost_at(*mvmnt, constraints, *t, self)
+                                    < zone_cost(*mvmnt, constraints, self)
+                            })
+                        {
+                            costs.discounted.insert(constraints);
+                        }
+                    }
+                }
+            }
+        }
+        costs
+    }
+
+    /// True if cars have to pay to use any road.
+    pub fn has_tolls(&self) -> bool {
+        self.edited_roads()
+            .any(|r| r.toll.is_some() || r.access_restrictions.cordon_toll.is_some())
+    }
+
+    // Tolls only come from edits, so there's no need to check every road
+    fn edited_roads(&self) -> impl Iterator<Item = &Road> {
+        let structural = self
+            .edits
+            .structural_commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                EditCmd::AddRoad { r, .. } => Some(*r),
+                EditCmd::SplitRoad { new_r, .. } => Some(*new_r),
+                _ => None,
+            });
+        self.edits
+            .changed_roads
+            .iter()
+            .cloned()
+            .chain(structural)
+            .filter_map(move |r| self.maybe_get_r(r))
+    }
+
+    /// How much a car pays, in dollars, to go from one road to the next. This covers tolls on the
+    /// next road and entering a priced zone. If the time isn't known, prices that vary through the
+    /// day are ignored.
+    pub fn toll_between(&self, from: RoadID, to: RoadID, time: Option<Time>) -> f64 {
+        let to = self.get_r(to);
+        let mut total = 0.0;
+        if let Some(ref toll) = to.toll {
+            total += toll.price(time, to.length());
+        }
+        if let Some(ref toll) = to.access_restrictions.cordon_toll {
+            let (flat, per_km) = toll.rates_at(time);
+            total += per_km * to.length().inner_meters() / 1000.0;
+            // Two adjacent zones might have identical restrictions when merge_zones is off, so
+            // compare the zones themselves.
+            let same_zone = self
+                .zones
+                .iter()
+                .any(|z| z.members.contains(&from) && z.members.contains(&to.id));
+            if !same_zone {
+                total += flat;
+            }
+        }
+        total
+    }
+
     pub fn get_languages(&self) -> BTreeSet<&str> {
         let mut languages = BTreeSet::new();
         for r in self.all_roads() {
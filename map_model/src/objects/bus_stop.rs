@@ -6,7 +6,7 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_usize, serialize_usize};
-use geom::Time;
+use geom::{Duration, Pt2D, Time};
 
 use crate::{osm, LaneID, Map, PathConstraints, PathRequest, Position};
 
@@ -39,6 +39,21 @@ impl fmt::Display for BusRouteID {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TransitStationID(
+    #[serde(
+        serialize_with = "serialize_usize",
+        deserialize_with = "deserialize_usize"
+    )]
+    pub usize,
+);
+
+impl fmt::Display for TransitStationID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TransitStation #{}", self.0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BusStop {
     pub id: BusStopID,
@@ -67,6 +82,41 @@ pub struct BusRoute {
     /// Explicitly store whatever the original was, since this can't be reconstructed without side
     /// input.
     pub orig_spawn_times: Vec<Time>,
+    /// Only filled out for routes imported from a published timetable.
+    pub timetable: Option<Timetable>,
+}
+
+/// How long vehicles on a route take between stops and how long they wait at each one, according
+/// to a published timetable. Individual trips vary; these are the median over all of them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Timetable {
+    /// For each stop, how long after reaching the first stop a vehicle is due to arrive.
+    pub arrival_offsets: Vec<Duration>,
+    /// For each stop, how long a vehicle waits before departing.
+    pub dwell_times: Vec<Duration>,
+}
+
+/// Groups nearby train platforms. Riders can walk between platforms without going through the
+/// sidewalk network, and reach the platforms from any of the entrances.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransitStation {
+    pub id: TransitStationID,
+    pub name: String,
+    /// Train stops served from this station
+    pub platforms: Vec<BusStopID>,
+    /// Where riders enter and exit from the sidewalk network. Includes the sidewalk position of
+    /// every platform.
+    pub entrances: Vec<Position>,
+}
+
+impl TransitStation {
+    pub fn closest_entrance(&self, pt: Pt2D, map: &Map) -> Position {
+        *self
+            .entrances
+            .iter()
+            .min_by_key(|pos| pos.pt(map).dist_to(pt))
+            .unwrap()
+    }
 }
 
 impl BusRoute {
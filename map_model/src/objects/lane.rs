@@ -7,8 +7,7 @@ use abstutil::{wraparound_get, Tags};
 use geom::{Distance, Line, PolyLine, Polygon, Pt2D, Ring};
 
 use crate::{
-    osm, BusStopID, DirectedRoadID, Direction, IntersectionID, Map, MapConfig, Road, RoadID,
-    TurnType,
+    osm, BusStopID, DirectedRoadID, Direction, IntersectionID, Map, MapConfig, RoadID, TurnType,
 };
 
 /// From some manually audited cases in Seattle, the length of parallel street parking spots is a
@@ -254,6 +253,11 @@ pub struct Lane {
     /// graph, because this is near a border.
     pub driving_blackhole: bool,
     pub biking_blackhole: bool,
+
+    /// Which turns vehicles may make from this lane at the end of the road. `None` means all turn
+    /// types are allowed.
+    pub allowed_turns: Option<BTreeSet<TurnType>>,
+    pub lane_change: LaneChange,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -261,6 +265,45 @@ pub struct LaneSpec {
     pub lt: LaneType,
     pub dir: Direction,
     pub width: Distance,
+    /// From OSM's turn:lanes. `None` means all turn types are allowed.
+    #[serde(default)]
+    pub allowed_turns: Option<BTreeSet<TurnType>>,
+    /// From OSM's change:lanes.
+    #[serde(default)]
+    pub lane_change: LaneChange,
+}
+
+/// Can vehicles change from this lane into the adjacent one on each side? Left and right are
+/// relative to the lane's direction of travel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaneChange {
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Default for LaneChange {
+    fn default() -> LaneChange {
+        LaneChange {
+            left: true,
+            right: true,
+        }
+    }
+}
+
+impl LaneChange {
+    /// See https://wiki.openstreetmap.org/wiki/Key:change
+    pub fn from_osm(x: &str) -> Option<LaneChange> {
+        let (left, right) = match x {
+            "yes" => (true, true),
+            "no" => (false, false),
+            "not_left" | "only_right" => (false, true),
+            "not_right" | "only_left" => (true, false),
+            _ => {
+                return None;
+            }
+        };
+        Some(LaneChange { left, right })
+    }
 }
 
 impl Lane {
@@ -372,67 +415,11 @@ impl Lane {
     /// This will return `None` for bus lanes, unless `force_bus` is true. OSM turn restrictions on
     /// bus lanes usually apply to regular vehicles, not the buses. When generating the turns for
     /// buses, we probably don't want to use the restrictions.
-    pub fn get_lane_level_turn_restrictions(
-        &self,
-        road: &Road,
-        force_bus: bool,
-    ) -> Option<BTreeSet<TurnType>> {
+    pub fn get_lane_level_turn_restrictions(&self, force_bus: bool) -> Option<BTreeSet<TurnType>> {
         if !self.is_driving() && (!force_bus || !self.is_bus()) {
             return None;
         }
-
-        let all = if self.dir == Direction::Fwd && road.osm_tags.contains_key(osm::ENDPT_FWD) {
-            road.osm_tags
-                .get("turn:lanes:forward")
-                .or_else(|| road.osm_tags.get("turn:lanes"))?
-        } else if self.dir == Direction::Back && road.osm_tags.contains_key(osm::ENDPT_BACK) {
-            road.osm_tags.get("turn:lanes:backward")?
-        } else {
-            return None;
-        };
-        let parts: Vec<&str> = all.split('|').collect();
-        // Verify the number of parts matches the road's lanes
-        let lanes: Vec<LaneID> = road
-            .children(self.dir)
-            .into_iter()
-            .filter(|(_, lt)| *lt == LaneType::Driving || *lt == LaneType::Bus)
-            .map(|(id, _)| id)
-            .collect();
-        if parts.len() != lanes.len() {
-            warn!("{}'s turn restrictions don't match the lanes", road.orig_id);
-            return None;
-        }
-        // TODO More warnings if this fails
-        let part = parts[lanes.iter().position(|l| *l == self.id)?];
-
-        // TODO Probably the target lane should get marked as LaneType::Bus
-        if part == "yes" || part == "psv" || part == "bus" {
-            return None;
-        }
-
-        // These both mean that physically, there's no marking saying what turn is valid. In
-        // practice, this seems to imply straight is always fine, and right/left are fine unless
-        // covered by an explicit turn lane.
-        //
-        // If a multi-lane road lacks markings, just listening to this function will mean that the
-        // rightmos lanes could turn left, which probably isn't great for people in the middle
-        // lanes going straight. Further filtering (in remove_merging_turns) will prune this out.
-        if part.is_empty() || part == "none" {
-            let all_explicit_types: BTreeSet<TurnType> = parts
-                .iter()
-                .flat_map(|part| part.split(';').flat_map(parse_turn_type_from_osm))
-                .collect();
-            let mut implied = BTreeSet::new();
-            implied.insert(TurnType::Straight);
-            for tt in [TurnType::Left, TurnType::Right] {
-                if !all_explicit_types.contains(&tt) {
-                    implied.insert(tt);
-                }
-            }
-            return Some(implied);
-        }
-
-        Some(part.split(';').flat_map(parse_turn_type_from_osm).collect())
+        self.allowed_turns.clone()
     }
 
     /// Starting from this lane, follow the lane's left edge to the intersection, continuing to
@@ -575,27 +562,6 @@ impl LaneSpec {
     }
 }
 
-// See https://wiki.openstreetmap.org/wiki/Key:turn
-fn parse_turn_type_from_osm(x: &str) -> Vec<TurnType> {
-    match x {
-        "left" => vec![TurnType::Left],
-        "right" => vec![TurnType::Right],
-        "through" => vec![TurnType::Straight],
-        "slight_right" | "slight right" | "merge_to_right" | "sharp_right" => {
-            vec![TurnType::Straight, TurnType::Right]
-        }
-        "slight_left" | "slight left" | "merge_to_left" | "sharp_left" => {
-            vec![TurnType::Straight, TurnType::Left]
-        }
-        "reverse" => vec![TurnType::UTurn],
-        "none" | "" => vec![],
-        _ => {
-            warn!("Unknown turn restriction {}", x);
-            vec![]
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
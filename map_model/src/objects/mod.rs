@@ -5,8 +5,10 @@ pub mod intersection;
 pub mod lane;
 pub mod movement;
 pub mod parking_lot;
+pub mod parking_regulation;
 pub mod road;
 pub mod stop_signs;
+pub mod toll;
 pub mod traffic_signals;
 pub mod turn;
 pub mod zone;
@@ -0,0 +1,152 @@
+//! Parking lanes, lots, and garages only know their capacity. ParkingRegulations add prices, time
+//! limits, and residential permit zones on top of that. The basemap doesn't have any; they're only
+//! set through map edits.
+
+use serde::{Deserialize, Serialize};
+
+use geom::Duration;
+
+use crate::{BuildingID, ParkingLotID, RoadID};
+
+/// Everything that can have its own parking rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ParkingRegulationTarget {
+    /// All on-street parking lanes on the road. Lanes get recreated when a road is edited, so
+    /// this is more stable than a LaneID.
+    Road(RoadID),
+    Lot(ParkingLotID),
+    /// Offstreet parking belonging to a building. Only useful for public garages; private spots
+    /// are only used by people going to that building anyway.
+    Garage(BuildingID),
+}
+
+/// The default is free parking with no time limit, open to everybody.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingRegulation {
+    /// The price to park for one hour, in dollars. Charged by the second.
+    pub hourly_price: f64,
+    /// Vehicles without a permit may not stay longer than this.
+    pub max_stay: Option<Duration>,
+    /// Vehicles with a permit for this zone park for free with no time limit. The simulation
+    /// grants a permit to vehicles that start the day parked in the zone.
+    pub permit_zone: Option<String>,
+    /// Only vehicles with a permit may park here at all.
+    pub permit_only: bool,
+    /// Reserved for delivery vehicles loading and unloading. Nobody else may park here, and
+    /// deliveries don't pay. Only meaningful for on-street parking.
+    pub loading_zone: bool,
+}
+
+impl Default for ParkingRegulation {
+    fn default() -> ParkingRegulation {
+        ParkingRegulation {
+            hourly_price: 0.0,
+            max_stay: None,
+            permit_zone: None,
+            permit_only: false,
+            loading_zone: false,
+        }
+    }
+}
+
+impl ParkingRegulation {
+    /// Can a vehicle holding a permit for the given zone (if any) park here?
+    pub fn allows(&self, permit: Option<&String>, delivery: bool) -> bool {
+        if self.loading_zone {
+            return delivery;
+        }
+        !self.permit_only || self.is_exempt(permit)
+    }
+
+    /// Does a vehicle holding a permit for the given zone (if any) skip the price and time limit?
+    pub fn is_exempt(&self, permit: Option<&String>) -> bool {
+        match (&self.permit_zone, permit) {
+            (Some(zone), Some(permit)) => zone == permit,
+            _ => false,
+        }
+    }
+
+    /// How much a vehicle owes after staying this long.
+    pub fn cost(&self, stay: Duration, permit: Option<&String>) -> f64 {
+        if self.is_exempt(permit) || self.loading_zone {
+            return 0.0;
+        }
+        self.hourly_price * stay.inner_seconds() / 3600.0
+    }
+
+    /// Did a vehicle stay longer than allowed?
+    pub fn is_overstay(&self, stay: Duration, permit: Option<&String>) -> bool {
+        if self.is_exempt(permit) || self.loading_zone {
+            return false;
+        }
+        self.max_stay.map(|max| stay > max).unwrap_or(false)
+    }
+
+    pub(crate) fn describe(&self) -> Vec<String> {
+        let mut details = Vec::new();
+        if self.loading_zone {
+            details.push("loading zone".to_string());
+            return details;
+        }
+        if self.hourly_price > 0.0 {
+            details.push(format!("${:.2}/hour", self.hourly_price));
+        }
+        if let Some(max) = self.max_stay {
+            details.push(format!("{} limit", max));
+        }
+        if let Some(ref zone) = self.permit_zone {
+            if self.permit_only {
+                details.push(format!("only permit zone {}", zone));
+            } else {
+                details.push(format!("permit zone {} exempt", zone));
+            }
+        }
+        if details.is_empty() {
+            details.push("unrestricted".to_string());
+        }
+        details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permit_exemption() {
+        let reg = ParkingRegulation {
+            hourly_price: 2.0,
+            max_stay: Some(Duration::hours(2)),
+            permit_zone: Some("A".to_string()),
+            permit_only: false,
+            loading_zone: false,
+        };
+        let resident = Some("A".to_string());
+        let visitor = Some("B".to_string());
+
+        assert_eq!(reg.cost(Duration::minutes(90), resident.as_ref()), 0.0);
+        assert_eq!(reg.cost(Duration::minutes(90), visitor.as_ref()), 3.0);
+        assert!(!reg.is_overstay(Duration::hours(3), resident.as_ref()));
+        assert!(reg.is_overstay(Duration::hours(3), None));
+        assert!(reg.allows(None, false));
+
+        let reg = ParkingRegulation {
+            permit_only: true,
+            ..reg
+        };
+        assert!(reg.allows(resident.as_ref(), false));
+        assert!(!reg.allows(visitor.as_ref(), false));
+    }
+
+    #[test]
+    fn test_loading_zone() {
+        let reg = ParkingRegulation {
+            hourly_price: 2.0,
+            loading_zone: true,
+            ..Default::default()
+        };
+        assert!(reg.allows(None, true));
+        assert!(!reg.allows(None, false));
+        assert_eq!(reg.cost(Duration::minutes(90), None), 0.0);
+    }
+}
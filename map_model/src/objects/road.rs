@@ -11,7 +11,7 @@ use geom::{Distance, PolyLine, Polygon, Speed};
 use crate::raw::{OriginalRoad, RestrictionType};
 use crate::{
     osm, AccessRestrictions, BusStopID, DrivingSide, IntersectionID, Lane, LaneID, LaneSpec,
-    LaneType, Map, PathConstraints, Zone,
+    LaneType, Map, PathConstraints, Toll, Zone,
 };
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -142,10 +142,16 @@ pub struct Road {
     pub orig_id: OriginalRoad,
     pub speed_limit: Speed,
     pub access_restrictions: AccessRestrictions,
+    /// Cars pay this every time they enter the road.
+    pub toll: Option<Toll>,
     pub zorder: isize,
     /// [-1.0, 1.0] theoretically, but in practice, about [-0.25, 0.25]. 0 is flat,
     /// positive is uphill from src_i -> dst_i, negative is downhill.
     pub percent_incline: f64,
+    /// The grade of each piece of the road, from src_i to dst_i, as (length, percent incline).
+    /// Sampled along `untrimmed_center_pts`. Empty if elevation is only known at the
+    /// intersections, in which case `percent_incline` applies everywhere.
+    pub grades: Vec<(Distance, f64)>,
 
     /// Invariant: A road must contain at least one child. These are ordered from the left side of
     /// the road to the right, with that orientation determined by the direction of `center_pts`.
@@ -169,10 +175,70 @@ impl Road {
                 lt: l.lane_type,
                 dir: l.dir,
                 width: l.width,
+                allowed_turns: l.allowed_turns.clone(),
+                lane_change: l.lane_change,
             })
             .collect()
     }
 
+    /// Describes the grade between two fractions of the road's length (measured from src_i),
+    /// when traveling in some direction. Returns (fraction of the road's length, percent incline)
+    /// for each piece. There's always at least one piece, even if the range is empty. The trimmed
+    /// road is assumed to follow the grades proportionally.
+    pub fn grades_between(&self, dir: Direction, pct1: f64, pct2: f64) -> Vec<(f64, f64)> {
+        let sign = if dir == Direction::Fwd { 1.0 } else { -1.0 };
+        let (lo, hi) = if pct1 <= pct2 {
+            (pct1, pct2)
+        } else {
+            (pct2, pct1)
+        };
+        let total: Distance = self.grades.iter().map(|(len, _)| *len).sum();
+        if total == Distance::ZERO {
+            return vec![(hi - lo, sign * self.percent_incline)];
+        }
+
+        let mut result = Vec::new();
+        let mut start = 0.0;
+        for (len, grade) in &self.grades {
+            let end = start + *len / total;
+            let overlap = end.min(hi) - start.max(lo);
+            if overlap > 0.0 {
+                result.push((overlap, sign * grade));
+            } else if result.is_empty() && lo == hi && start <= lo && lo <= end {
+                // The range is just one point
+                result.push((0.0, sign * grade));
+            }
+            start = end;
+        }
+        if result.is_empty() {
+            // Floating point trouble at the very end of the road
+            result.push((0.0, sign * self.grades.last().unwrap().1));
+        }
+        result
+    }
+
+    /// Splits `grades` at some fraction of the road's length.
+    pub(crate) fn split_grades(&self, pct: f64) -> (Vec<(Distance, f64)>, Vec<(Distance, f64)>) {
+        let total: Distance = self.grades.iter().map(|(len, _)| *len).sum();
+        let split = total * pct;
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        let mut start = Distance::ZERO;
+        for (len, grade) in &self.grades {
+            let end = start + *len;
+            if end <= split {
+                first.push((*len, *grade));
+            } else if start >= split {
+                second.push((*len, *grade));
+            } else {
+                first.push((split - start, *grade));
+                second.push((end - split, *grade));
+            }
+            start = end;
+        }
+        (first, second)
+    }
+
     /// Gets the left PolyLine of the road
     pub fn get_left_side(&self) -> PolyLine {
         self.center_pts.must_shift_left(self.get_half_width())
@@ -460,6 +526,7 @@ impl Road {
         };
         AccessRestrictions {
             allow_through_traffic,
+            ..AccessRestrictions::new()
         }
     }
 
@@ -557,6 +624,8 @@ impl Road {
                 bus_stops: BTreeSet::new(),
                 driving_blackhole: false,
                 biking_blackhole: false,
+                allowed_turns: lane.allowed_turns,
+                lane_change: lane.lane_change,
             });
         }
     }
@@ -0,0 +1,110 @@
+//! Tolls charge cars money to use a road or to enter a zone. The basemap doesn't have any; they're
+//! only set through map edits. Routing trades off the price against travel time using
+//! `RoutingParams::value_of_time`.
+
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Time};
+
+use crate::objects::zone::within_time_of_day;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Toll {
+    /// Dollars charged every time a car enters the road or zone.
+    pub flat: f64,
+    /// Dollars charged per kilometer driven.
+    pub per_km: f64,
+    /// During these times of day, different prices apply. If windows overlap, the first one wins.
+    #[serde(default)]
+    pub schedule: Vec<TollWindow>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TollWindow {
+    /// A time of day; the window is [start, end). If end is before start, the window wraps around
+    /// midnight.
+    pub start: Time,
+    pub end: Time,
+    pub flat: f64,
+    pub per_km: f64,
+}
+
+impl Toll {
+    pub fn flat(price: f64) -> Toll {
+        Toll {
+            flat: price,
+            per_km: 0.0,
+            schedule: Vec::new(),
+        }
+    }
+
+    pub fn per_km(price: f64) -> Toll {
+        Toll {
+            flat: 0.0,
+            per_km: price,
+            schedule: Vec::new(),
+        }
+    }
+
+    /// The (flat, per kilometer) price at some time of day. If the time isn't known, the schedule
+    /// is ignored.
+    pub fn rates_at(&self, time: Option<Time>) -> (f64, f64) {
+        if let Some(time) = time {
+            for window in &self.schedule {
+                if within_time_of_day(time, window.start, window.end) {
+                    return (window.flat, window.per_km);
+                }
+            }
+        }
+        (self.flat, self.per_km)
+    }
+
+    /// The price for entering and then driving some distance.
+    pub fn price(&self, time: Option<Time>, dist: Distance) -> f64 {
+        let (flat, per_km) = self.rates_at(time);
+        flat + per_km * dist.inner_meters() / 1000.0
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.flat > 0.0 {
+            parts.push(format!("${:.2} to enter", self.flat));
+        }
+        if self.per_km > 0.0 {
+            parts.push(format!("${:.2} per km", self.per_km));
+        }
+        if parts.is_empty() {
+            parts.push("free".to_string());
+        }
+        if !self.schedule.is_empty() {
+            parts.push(format!(
+                "different prices during {} times of day",
+                self.schedule.len()
+            ));
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_price() {
+        let hour = |h: usize| Time::START_OF_DAY + geom::Duration::hours(h);
+        let toll = Toll {
+            flat: 1.0,
+            per_km: 0.5,
+            schedule: vec![TollWindow {
+                start: hour(7),
+                end: hour(10),
+                flat: 4.0,
+                per_km: 0.0,
+            }],
+        };
+        assert_eq!(toll.price(None, Distance::meters(2000.0)), 2.0);
+        assert_eq!(toll.price(Some(hour(6)), Distance::meters(2000.0)), 2.0);
+        assert_eq!(toll.price(Some(hour(8)), Distance::meters(2000.0)), 4.0);
+    }
+}
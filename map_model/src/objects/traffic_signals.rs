@@ -24,6 +24,29 @@ pub struct ControlTrafficSignal {
     pub id: IntersectionID,
     pub stages: Vec<Stage>,
     pub offset: Duration,
+    /// If present, buses and trains approaching the signal can change its timing.
+    pub transit_priority: Option<TransitSignalPriority>,
+}
+
+/// Transit signal priority lets a bus or train approaching the intersection hold its green a
+/// little longer, or end a conflicting stage early so its turn comes up sooner. At most one of
+/// these happens per stage.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TransitSignalPriority {
+    /// The longest a green can be held for an approaching vehicle
+    pub max_green_extension: Duration,
+    /// A conflicting stage has to last at least this long before it can be ended early. The
+    /// minimum crosswalk time is always respected too.
+    pub min_stage_before_early_green: Duration,
+}
+
+impl TransitSignalPriority {
+    pub fn new() -> TransitSignalPriority {
+        TransitSignalPriority {
+            max_green_extension: Duration::seconds(10.0),
+            min_stage_before_early_green: Duration::seconds(10.0),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -422,6 +445,15 @@ impl ControlTrafficSignal {
                     .collect(),
                 offset_seconds: self.offset.inner_seconds() as usize,
             }],
+            transit_priority: self.transit_priority.as_ref().map(|tsp| {
+                traffic_signal_data::TransitPriority {
+                    max_green_extension_seconds: tsp.max_green_extension.inner_seconds() as usize,
+                    min_stage_before_early_green_seconds: tsp
+                        .min_stage_before_early_green
+                        .inner_seconds()
+                        as usize,
+                }
+            }),
         }
     }
 
@@ -482,6 +514,12 @@ impl ControlTrafficSignal {
             id,
             stages,
             offset: Duration::seconds(plan.offset_seconds as f64),
+            transit_priority: raw.transit_priority.map(|tsp| TransitSignalPriority {
+                max_green_extension: Duration::seconds(tsp.max_green_extension_seconds as f64),
+                min_stage_before_early_green: Duration::seconds(
+                    tsp.min_stage_before_early_green_seconds as f64,
+                ),
+            }),
         };
         ts.validate(map.get_i(id))?;
         Ok(ts)
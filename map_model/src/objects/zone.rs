@@ -3,17 +3,45 @@
 //!    use any of the private roads
 //! 2) Stay Healthy Streets, where most car traffic is banned, except for trips beginning/ending in
 //!    the zone
-//! 3) Congestion capping, where only so many cars per hour can enter the zone
+//! 3) School streets or peak-hour bus lanes, where the restrictions only apply at certain times of
+//!    day
+//! 4) Congestion capping, where only so many cars per hour can enter the zone
+//! 5) Cordon pricing, where cars pay to enter the zone
 
 use std::collections::BTreeSet;
 
 use enumset::EnumSet;
 use serde::{Deserialize, Serialize};
 
-use crate::{IntersectionID, Map, PathConstraints, RoadID};
+use geom::{Duration, Time};
+
+use crate::{IntersectionID, Map, PathConstraints, RoadID, Toll};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct AccessRestrictions {
+    /// Outside of any scheduled window, which modes may pass through without starting or ending a
+    /// trip inside the zone.
+    pub allow_through_traffic: EnumSet<PathConstraints>,
+    /// During these times of day, different modes may pass through. If windows overlap, the first
+    /// one wins.
+    #[serde(default)]
+    pub schedule: Vec<RestrictionWindow>,
+    /// How many cars may enter the zone during each hour. Once the cap is reached, only cars
+    /// heading to somewhere inside the zone may enter until the next hour starts.
+    #[serde(default)]
+    pub cap_vehicles_per_hour: Option<usize>,
+    /// Cars entering the zone pay this. The per-distance part is charged for driving on any road
+    /// in the zone.
+    #[serde(default)]
+    pub cordon_toll: Option<Toll>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RestrictionWindow {
+    /// A time of day; the window is [start, end). If end is before start, the window wraps around
+    /// midnight.
+    pub start: Time,
+    pub end: Time,
     pub allow_through_traffic: EnumSet<PathConstraints>,
 }
 
@@ -21,7 +49,35 @@ impl AccessRestrictions {
     pub fn new() -> AccessRestrictions {
         AccessRestrictions {
             allow_through_traffic: EnumSet::all(),
+            schedule: Vec::new(),
+            cap_vehicles_per_hour: None,
+            cordon_toll: None,
+        }
+    }
+
+    /// Which modes may pass through at some time, accounting for the schedule.
+    pub fn allow_through_traffic_at(&self, time: Time) -> EnumSet<PathConstraints> {
+        for window in &self.schedule {
+            if within_time_of_day(time, window.start, window.end) {
+                return window.allow_through_traffic;
+            }
         }
+        self.allow_through_traffic
+    }
+}
+
+/// Is the time of day within [start, end)? If end is before start, the window wraps around
+/// midnight. Simulations may last longer than a day, so only the time of day matters.
+pub(crate) fn within_time_of_day(time: Time, start: Time, end: Time) -> bool {
+    let time_of_day = Time::START_OF_DAY
+        + Duration::seconds(
+            time.inner_seconds()
+                .rem_euclid(Duration::hours(24).inner_seconds()),
+        );
+    if start <= end {
+        start <= time_of_day && time_of_day < end
+    } else {
+        start <= time_of_day || time_of_day < end
     }
 }
 
@@ -88,3 +144,45 @@ fn floodfill(map: &Map, start: RoadID) -> Zone {
         restrictions: match_constraints,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let hour = |h: usize| Time::START_OF_DAY + Duration::hours(h);
+        let restrictions = AccessRestrictions {
+            schedule: vec![
+                RestrictionWindow {
+                    start: hour(8),
+                    end: hour(9),
+                    allow_through_traffic: PathConstraints::Pedestrian | PathConstraints::Bike,
+                },
+                RestrictionWindow {
+                    start: hour(22),
+                    end: hour(6),
+                    allow_through_traffic: EnumSet::new(),
+                },
+            ],
+            ..AccessRestrictions::new()
+        };
+        assert_eq!(
+            restrictions.allow_through_traffic_at(hour(7)),
+            EnumSet::all()
+        );
+        assert!(!restrictions
+            .allow_through_traffic_at(hour(8))
+            .contains(PathConstraints::Car));
+        assert_eq!(
+            restrictions.allow_through_traffic_at(hour(9)),
+            EnumSet::all()
+        );
+        assert!(restrictions.allow_through_traffic_at(hour(23)).is_empty());
+        assert!(restrictions.allow_through_traffic_at(hour(3)).is_empty());
+        // The next day
+        assert!(!restrictions
+            .allow_through_traffic_at(hour(24 + 8))
+            .contains(PathConstraints::Car));
+    }
+}
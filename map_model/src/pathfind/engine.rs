@@ -1,15 +1,19 @@
 use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
 
 use fast_paths::{deserialize_32, serialize_32, FastGraph, InputGraph, PathCalculator};
 use petgraph::graph::{DiGraph, NodeIndex};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use thread_local::ThreadLocal;
 
 /// This operates on raw IDs and costs; no type safety. The thing containing this transforms
 /// to/from higher-level types.
 #[allow(clippy::large_enum_variant)]
-#[derive(Serialize, Deserialize)]
+#[derive(Deserialize)]
 pub enum PathfindEngine {
     Empty,
     Dijkstra {
@@ -21,6 +25,54 @@ pub enum PathfindEngine {
         #[serde(skip_serializing, skip_deserializing)]
         path_calc: ThreadLocal<RefCell<PathCalculator>>,
     },
+    /// After map edits, a contraction hierarchy may be prepared in a background thread. Until it's
+    /// ready, queries run Dijkstra's algorithm over the same graph, so results are correct, just
+    /// slower. This is saved as `Dijkstra`, so it still works after loading, but call
+    /// `finish_background_work` first to keep the contraction hierarchy.
+    #[serde(skip_deserializing)]
+    PendingCH {
+        graph: DiGraph<usize, usize>,
+        pending: Arc<BackgroundCH>,
+        path_calc: ThreadLocal<RefCell<PathCalculator>>,
+    },
+}
+
+/// A contraction hierarchy being prepared in a background thread
+pub struct BackgroundCH {
+    node_ordering: Vec<usize>,
+    result: RwLock<Option<FastGraph>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Mirrors `PathfindEngine` for serialization, without the variants that can't be saved.
+#[derive(Serialize)]
+#[serde(rename = "PathfindEngine")]
+enum SavedEngine<'a> {
+    Empty,
+    Dijkstra {
+        graph: &'a DiGraph<usize, usize>,
+    },
+    CH {
+        #[serde(serialize_with = "serialize_ch")]
+        graph: &'a FastGraph,
+    },
+}
+
+fn serialize_ch<S: Serializer>(graph: &&FastGraph, s: S) -> Result<S::Ok, S::Error> {
+    serialize_32(graph, s)
+}
+
+// Implemented manually, so that a pending contraction hierarchy falls back to Dijkstra when loaded
+impl Serialize for PathfindEngine {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            PathfindEngine::Empty => SavedEngine::Empty,
+            PathfindEngine::Dijkstra { ref graph }
+            | PathfindEngine::PendingCH { ref graph, .. } => SavedEngine::Dijkstra { graph },
+            PathfindEngine::CH { ref graph, .. } => SavedEngine::CH { graph },
+        }
+        .serialize(s)
+    }
 }
 
 // Implemented manually to deal with the ThreadLocal
@@ -35,6 +87,16 @@ impl Clone for PathfindEngine {
                 graph: graph.clone(),
                 path_calc: ThreadLocal::new(),
             },
+            // Share the background work
+            PathfindEngine::PendingCH {
+                ref graph,
+                ref pending,
+                ..
+            } => PathfindEngine::PendingCH {
+                graph: graph.clone(),
+                pending: pending.clone(),
+                path_calc: ThreadLocal::new(),
+            },
         }
     }
 }
@@ -53,48 +115,21 @@ impl PathfindEngine {
     ) -> Option<(usize, Vec<usize>)> {
         match self {
             PathfindEngine::Empty => unreachable!(),
-            PathfindEngine::Dijkstra { ref graph } => {
-                // If there are multiple starts and ends, calculate each individual path and take
-                // the lowest cost.
-                let mut best_pair: Option<(usize, Vec<NodeIndex>)> = None;
-                for (start_node, weight1) in starts {
-                    let start_node = NodeIndex::new(start_node);
-                    for (end_node, weight2) in &ends {
-                        let end_node = NodeIndex::new(*end_node);
-                        if let Some((raw_weight, raw_nodes)) = petgraph::algo::astar(
-                            graph,
-                            start_node,
-                            |node| node == end_node,
-                            |edge| *edge.weight(),
-                            |_| 0,
-                        ) {
-                            let total_weight = raw_weight + weight1 + weight2;
-                            if best_pair
-                                .as_ref()
-                                .map(|pair| total_weight < pair.0)
-                                .unwrap_or(true)
-                            {
-                                best_pair = Some((total_weight, raw_nodes));
-                            }
-                        }
-                    }
-                }
-                let (raw_weight, raw_nodes) = best_pair?;
-                Some((
-                    raw_weight,
-                    raw_nodes.into_iter().map(|n| n.index()).collect(),
-                ))
-            }
+            PathfindEngine::Dijkstra { ref graph } => dijkstra_path(graph, starts, ends),
             PathfindEngine::CH {
                 ref graph,
                 ref path_calc,
+            } => ch_path(graph, path_calc, starts, ends),
+            PathfindEngine::PendingCH {
+                ref graph,
+                ref pending,
+                ref path_calc,
             } => {
-                let mut calc = path_calc
-                    .get_or(|| RefCell::new(fast_paths::create_calculator(graph)))
-                    .borrow_mut();
-                let path = calc.calc_path_multiple_sources_and_targets(graph, starts, ends)?;
-                // TODO Add an into_nodes to avoid this clone
-                Some((path.get_weight(), path.get_nodes().to_vec()))
+                if let Some(ref ch) = *pending.result.read().unwrap() {
+                    ch_path(ch, path_calc, starts, ends)
+                } else {
+                    dijkstra_path(graph, starts, ends)
+                }
             }
         }
     }
@@ -104,7 +139,34 @@ impl PathfindEngine {
             PathfindEngine::Empty => unreachable!(),
             // Just don't reuse the ordering
             PathfindEngine::Dijkstra { .. } => CreateEngine::Dijkstra,
-            PathfindEngine::CH { ref graph, .. } => CreateEngine::CHSeedingNodeOrdering(graph),
+            PathfindEngine::CH { ref graph, .. } => {
+                CreateEngine::CHSeedingNodeOrdering(graph.get_node_ordering())
+            }
+            PathfindEngine::PendingCH { ref pending, .. } => {
+                CreateEngine::CHSeedingNodeOrdering(pending.node_ordering.clone())
+            }
+        }
+    }
+
+    /// If a contraction hierarchy is being prepared in the background, block until it's done and
+    /// start using it.
+    pub fn finish_background_work(&mut self) {
+        if let PathfindEngine::PendingCH { ref pending, .. } = self {
+            if let Some(thread) = pending.thread.lock().unwrap().take() {
+                thread
+                    .join()
+                    .expect("preparing a contraction hierarchy in the background failed");
+            }
+            let graph = pending
+                .result
+                .read()
+                .unwrap()
+                .clone()
+                .expect("preparing a contraction hierarchy in the background failed");
+            *self = PathfindEngine::CH {
+                graph,
+                path_calc: ThreadLocal::new(),
+            };
         }
     }
 
@@ -115,7 +177,8 @@ impl PathfindEngine {
     pub fn all_costs_from(&self, start: usize) -> HashMap<usize, usize> {
         match self {
             PathfindEngine::Empty => unreachable!(),
-            PathfindEngine::Dijkstra { ref graph } => {
+            PathfindEngine::Dijkstra { ref graph }
+            | PathfindEngine::PendingCH { ref graph, .. } => {
                 petgraph::algo::dijkstra(graph, NodeIndex::new(start), None, |edge| *edge.weight())
                     .into_iter()
                     .map(|(k, v)| (k.index(), v))
@@ -126,30 +189,18 @@ impl PathfindEngine {
     }
 }
 
-pub enum CreateEngine<'a> {
+pub enum CreateEngine {
     Dijkstra,
     CH,
-    CHSeedingNodeOrdering(&'a FastGraph),
+    CHSeedingNodeOrdering(Vec<usize>),
 }
 
-impl<'a> CreateEngine<'a> {
+impl CreateEngine {
     pub fn create(&self, input_graph: InputGraph) -> PathfindEngine {
         match self {
-            CreateEngine::Dijkstra => {
-                let mut graph = DiGraph::new();
-                let dummy_weight = 42;
-                for node in 0..input_graph.get_num_nodes() {
-                    assert_eq!(graph.add_node(dummy_weight).index(), node);
-                }
-                for edge in input_graph.get_edges() {
-                    graph.add_edge(
-                        NodeIndex::new(edge.from),
-                        NodeIndex::new(edge.to),
-                        edge.weight,
-                    );
-                }
-                PathfindEngine::Dijkstra { graph }
-            }
+            CreateEngine::Dijkstra => PathfindEngine::Dijkstra {
+                graph: to_dijkstra_graph(&input_graph),
+            },
             CreateEngine::CH => {
                 info!(
                     "Contraction hierarchy input graph has {} nodes",
@@ -165,19 +216,131 @@ impl<'a> CreateEngine<'a> {
                     path_calc: ThreadLocal::new(),
                 }
             }
-            CreateEngine::CHSeedingNodeOrdering(prev_graph) => {
-                let node_ordering = prev_graph.get_node_ordering();
-                let graph = fast_paths::prepare_with_order_with_params(
-                    &input_graph,
-                    &node_ordering,
-                    &fast_paths::ParamsWithOrder::new(100),
-                )
-                .unwrap();
-                PathfindEngine::CH {
+            CreateEngine::CHSeedingNodeOrdering(node_ordering) => PathfindEngine::CH {
+                graph: prepare_with_order(&input_graph, node_ordering),
+                path_calc: ThreadLocal::new(),
+            },
+        }
+    }
+
+    /// Like `create`, but when reusing a node ordering, the contraction hierarchy is prepared in a
+    /// background thread. The engine returned is immediately usable. There are no threads on web,
+    /// so there this is the same as `create`.
+    pub fn create_in_background(&self, input_graph: InputGraph) -> PathfindEngine {
+        if let CreateEngine::CHSeedingNodeOrdering(ref node_ordering) = self {
+            if cfg!(not(target_arch = "wasm32")) {
+                let graph = to_dijkstra_graph(&input_graph);
+                let pending = Arc::new(BackgroundCH {
+                    node_ordering: node_ordering.clone(),
+                    result: RwLock::new(None),
+                    thread: Mutex::new(None),
+                });
+                let job = pending.clone();
+                let thread = std::thread::spawn(move || {
+                    let ch = prepare_with_order(&input_graph, &job.node_ordering);
+                    *job.result.write().unwrap() = Some(ch);
+                });
+                *pending.thread.lock().unwrap() = Some(thread);
+                return PathfindEngine::PendingCH {
                     graph,
+                    pending,
                     path_calc: ThreadLocal::new(),
+                };
+            }
+        }
+        self.create(input_graph)
+    }
+}
+
+/// Summarizes all nodes and edges of an input graph, to cheaply detect when edits didn't change
+/// anything.
+pub fn hash_input_graph(input_graph: &InputGraph) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    input_graph.get_num_nodes().hash(&mut hasher);
+    for edge in input_graph.get_edges() {
+        (edge.from, edge.to, edge.weight).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn to_dijkstra_graph(input_graph: &InputGraph) -> DiGraph<usize, usize> {
+    let mut graph = DiGraph::new();
+    let dummy_weight = 42;
+    for node in 0..input_graph.get_num_nodes() {
+        assert_eq!(graph.add_node(dummy_weight).index(), node);
+    }
+    for edge in input_graph.get_edges() {
+        graph.add_edge(
+            NodeIndex::new(edge.from),
+            NodeIndex::new(edge.to),
+            edge.weight,
+        );
+    }
+    graph
+}
+
+/// This contracts the whole graph again, just skipping the search for a node ordering. fast_paths
+/// can't re-weight the edges of an existing hierarchy, so there's no cheaper update after edits.
+fn prepare_with_order(input_graph: &InputGraph, node_ordering: &[usize]) -> FastGraph {
+    // Structural map edits add nodes after the ordering was found; contract those last.
+    let mut node_ordering = node_ordering.to_vec();
+    let num_ordered = node_ordering.len();
+    node_ordering.extend(num_ordered..input_graph.get_num_nodes());
+    fast_paths::prepare_with_order_with_params(
+        input_graph,
+        &node_ordering,
+        &fast_paths::ParamsWithOrder::new(100),
+    )
+    .unwrap()
+}
+
+fn dijkstra_path(
+    graph: &DiGraph<usize, usize>,
+    starts: Vec<(usize, usize)>,
+    ends: Vec<(usize, usize)>,
+) -> Option<(usize, Vec<usize>)> {
+    // If there are multiple starts and ends, calculate each individual path and take the lowest
+    // cost.
+    let mut best_pair: Option<(usize, Vec<NodeIndex>)> = None;
+    for (start_node, weight1) in starts {
+        let start_node = NodeIndex::new(start_node);
+        for (end_node, weight2) in &ends {
+            let end_node = NodeIndex::new(*end_node);
+            if let Some((raw_weight, raw_nodes)) = petgraph::algo::astar(
+                graph,
+                start_node,
+                |node| node == end_node,
+                |edge| *edge.weight(),
+                |_| 0,
+            ) {
+                let total_weight = raw_weight + weight1 + weight2;
+                if best_pair
+                    .as_ref()
+                    .map(|pair| total_weight < pair.0)
+                    .unwrap_or(true)
+                {
+                    best_pair = Some((total_weight, raw_nodes));
                 }
             }
         }
     }
+    let (raw_weight, raw_nodes) = best_pair?;
+    Some((
+        raw_weight,
+        raw_nodes.into_iter().map(|n| n.index()).collect(),
+    ))
+}
+
+fn ch_path(
+    graph: &FastGraph,
+    path_calc: &ThreadLocal<RefCell<PathCalculator>>,
+    starts: Vec<(usize, usize)>,
+    ends: Vec<(usize, usize)>,
+) -> Option<(usize, Vec<usize>)> {
+    let mut calc = path_calc
+        .get_or(|| RefCell::new(fast_paths::create_calculator(graph)))
+        .borrow_mut();
+    let path = calc.calc_path_multiple_sources_and_targets(graph, starts, ends)?;
+    // TODO Add an into_nodes to avoid this clone
+    Some((path.get_weight(), path.get_nodes().to_vec()))
 }
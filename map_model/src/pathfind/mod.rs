@@ -1,21 +1,28 @@
 //! Everything related to pathfinding through a map for different types of agents.
 
-use enumset::EnumSetType;
+use std::collections::BTreeSet;
+
+use enumset::{EnumSet, EnumSetType};
 use serde::{Deserialize, Serialize};
 
-use geom::Duration;
+use geom::{Duration, Time};
 
 pub use self::engine::CreateEngine;
+pub use self::observed::{ObservedTravelTimes, TravelTimeProfiles};
 pub use self::pathfinder::Pathfinder;
+pub(crate) use self::transit::TransitRouterCache;
+pub use self::transit::{Itinerary, ItineraryLeg};
 pub use self::v1::{Path, PathRequest, PathStep};
 pub use self::v2::{PathStepV2, PathV2};
 pub use self::vehicles::vehicle_cost;
-pub use self::walking::WalkingNode;
-use crate::{osm, Lane, LaneID, LaneType, Map, MovementID, TurnType};
+pub use self::walking::{TransitRide, WalkingNode};
+use crate::{osm, Lane, LaneID, LaneType, Map, MovementID, RoadID, TurnType};
 
 mod engine;
 mod node_map;
+mod observed;
 mod pathfinder;
+mod transit;
 // TODO tmp
 pub mod uber_turns;
 mod v1;
@@ -102,9 +109,7 @@ impl PathConstraints {
         //    practice this isn't an issue; a bus lane often leads to another one, but the next bus
         //    lane won't also be an exclusive turn lane.
         if lane.is_bus() {
-            if let Some(types) =
-                lane.get_lane_level_turn_restrictions(map.get_r(lane.id.road), true)
-            {
+            if let Some(types) = lane.get_lane_level_turn_restrictions(true) {
                 if types.contains(&TurnType::Right) || types.contains(&TurnType::Left) {
                     return true;
                 }
@@ -144,15 +149,74 @@ pub fn zone_cost(mvmnt: MovementID, constraints: PathConstraints, map: &Map) ->
             .allow_through_traffic
             .contains(constraints)
     {
-        // This should be high enough to achieve the desired effect of somebody not entering
-        // the zone unless absolutely necessary. Someone would violate that and cut through anyway
-        // only when the alternative route would take more than 3 hours longer!
-        Duration::hours(3)
+        ZONE_PENALTY
     } else {
         Duration::ZERO
     }
 }
 
+/// Like `zone_cost`, but also accounting for restrictions that only apply at certain times of day.
+pub fn zone_cost_at(
+    mvmnt: MovementID,
+    constraints: PathConstraints,
+    time: Time,
+    map: &Map,
+) -> Duration {
+    if map
+        .get_r(mvmnt.from.id)
+        .access_restrictions
+        .allow_through_traffic_at(time)
+        .contains(constraints)
+        && !map
+            .get_r(mvmnt.to.id)
+            .access_restrictions
+            .allow_through_traffic_at(time)
+            .contains(constraints)
+    {
+        ZONE_PENALTY
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Convert any toll paid for a movement into a delay, using the value of time.
+pub fn toll_cost(
+    mvmnt: MovementID,
+    constraints: PathConstraints,
+    time: Option<Time>,
+    params: &RoutingParams,
+    map: &Map,
+) -> Duration {
+    if constraints != PathConstraints::Car {
+        return Duration::ZERO;
+    }
+    let price = map.toll_between(mvmnt.from.id, mvmnt.to.id, time);
+    if price == 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::hours(1) * (price / params.value_of_time)
+}
+
+/// Roads whose routing costs change through the day, because zone restrictions or tolls only apply
+/// at some times. Contraction hierarchies bake in the costs outside of any schedule, so this
+/// decides when they can still be trusted. Cached when edits are applied.
+#[derive(Clone, Default)]
+pub(crate) struct TimeOfDayCosts {
+    /// Movements to or from these roads may cost something different at some times of day.
+    pub roads: BTreeSet<RoadID>,
+    /// Modes for which some of those movements are ever cheaper than usual. For these, a path
+    /// that avoids `roads` might still not be the fastest.
+    pub discounted: EnumSet<PathConstraints>,
+}
+
+// This should be high enough to achieve the desired effect of somebody not entering the zone unless
+// absolutely necessary. Someone would violate that and cut through anyway only when the
+// alternative route would take more than 3 hours longer!
+const ZONE_PENALTY: Duration = Duration::const_seconds(3.0 * 3600.0);
+
+/// The uphill grade at which bikes pay the full `avoid_steep_incline_penalty`.
+pub(crate) const STEEP_INCLINE: f64 = 0.08;
+
 /// Tuneable parameters for all types of routing.
 // These will maybe become part of the PathRequest later, but that's an extremely invasive and
 // space-expensive change right now.
@@ -168,13 +232,17 @@ pub struct RoutingParams {
     pub driving_lane_penalty: f64,
 
     // For bike routing.
-    // "Steep" is a fixed threshold of 8% incline, uphill only. Multiply by the base cost. (Note
-    // that cost already includes a reduction of speed to account for the incline -- this is a
-    // further "delay" on top of that!)
-    // TODO But even steeper roads matter more!
+    // Multiply by the base cost for roads with an average uphill grade of `STEEP_INCLINE`. The
+    // penalty scales linearly with the grade, so flatter roads are penalized less and steeper
+    // ones more. Downhill parts don't count. (Note that cost already includes a reduction of
+    // speed to account for the incline -- this is a further "delay" on top of that!)
     pub avoid_steep_incline_penalty: f64,
     // If the road is `high_stress_for_bikes`, multiply by the base cost.
     pub avoid_high_stress: f64,
+
+    // For cars. How many dollars somebody would pay to save an hour of travel time. Tolls are
+    // converted to a delay using this.
+    pub value_of_time: f64,
 }
 
 impl Default for RoutingParams {
@@ -190,6 +258,9 @@ impl Default for RoutingParams {
 
             avoid_steep_incline_penalty: 1.0,
             avoid_high_stress: 1.0,
+
+            // Roughly half of a typical hourly wage, a common rule of thumb for personal trips
+            value_of_time: 20.0,
         }
     }
 }
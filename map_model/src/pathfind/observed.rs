@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Duration, Time};
+
+use crate::pathfind::vehicles::free_flow_time;
+use crate::{DirectedRoadID, Map, MovementID, PathConstraints};
+
+/// Travel times measured from a previous simulation. When a map has these, they replace the
+/// free-flow estimates used to route cars and buses, letting congestion feed back into route
+/// choice.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObservedTravelTimes {
+    /// How long it takes to cross the road, including any queueing before the intersection at the
+    /// end.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub roads: BTreeMap<DirectedRoadID, Duration>,
+    /// How long it takes to cross the intersection.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub movements: BTreeMap<MovementID, Duration>,
+}
+
+impl ObservedTravelTimes {
+    pub fn new() -> ObservedTravelTimes {
+        ObservedTravelTimes::default()
+    }
+
+    /// The time for a car to cross every road and movement without any congestion.
+    pub fn free_flow(map: &Map) -> ObservedTravelTimes {
+        let mut times = ObservedTravelTimes::new();
+        for i in map.all_intersections() {
+            for mvmnt in i.movements.keys() {
+                if mvmnt.crosswalk
+                    || mvmnt.from.lanes(PathConstraints::Car, map).is_empty()
+                    || mvmnt.to.lanes(PathConstraints::Car, map).is_empty()
+                {
+                    continue;
+                }
+                let (road, turn) = free_flow_time(mvmnt.from, *mvmnt, PathConstraints::Car, map);
+                times.roads.insert(mvmnt.from, road);
+                times.movements.insert(*mvmnt, turn);
+            }
+        }
+        times
+    }
+}
+
+/// Travel times measured from a previous simulation, broken down by time of day. Requests with a
+/// departure time use these to route cars and buses, so a trip at 3am and one at 5pm can take
+/// different routes.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TravelTimeProfiles {
+    /// The size of each bin, starting from midnight
+    pub bin_size: Duration,
+    /// Per directed road, (bin index -> how long it takes to cross the road when entering it during
+    /// that bin). Missing bins use the free-flow time.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub roads: BTreeMap<DirectedRoadID, BTreeMap<usize, Duration>>,
+    /// Per movement, (bin index -> how long it takes to cross the intersection)
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub movements: BTreeMap<MovementID, BTreeMap<usize, Duration>>,
+}
+
+impl TravelTimeProfiles {
+    pub fn new(bin_size: Duration) -> TravelTimeProfiles {
+        assert!(bin_size > Duration::ZERO);
+        TravelTimeProfiles {
+            bin_size,
+            roads: BTreeMap::new(),
+            movements: BTreeMap::new(),
+        }
+    }
+
+    pub fn bin(&self, time: Time) -> usize {
+        ((time - Time::START_OF_DAY) / self.bin_size) as usize
+    }
+
+    /// How long it takes to cross a road, entering it at some time
+    pub fn road(&self, dr: DirectedRoadID, time: Time) -> Option<Duration> {
+        self.roads.get(&dr)?.get(&self.bin(time)).cloned()
+    }
+
+    /// How long it takes to cross a movement, entering it at some time
+    pub fn movement(&self, mvmnt: MovementID, time: Time) -> Option<Duration> {
+        self.movements.get(&mvmnt)?.get(&self.bin(time)).cloned()
+    }
+}
@@ -1,18 +1,18 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 use thread_local::ThreadLocal;
 
 use abstutil::{Timer, VecMap};
-use geom::Duration;
+use geom::{Duration, Time};
 
 use crate::pathfind::engine::CreateEngine;
 use crate::pathfind::vehicles::VehiclePathfinder;
 use crate::pathfind::walking::SidewalkPathfinder;
 use crate::{
-    BusRouteID, BusStopID, DirectedRoadID, Map, PathConstraints, PathRequest, PathV2, Position,
-    RoutingParams,
+    DirectedRoadID, Map, PathConstraints, PathRequest, PathStepV2, PathV2, Position, RoadID,
+    RoutingParams, TransitRide, TravelTimeProfiles,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -27,6 +27,10 @@ pub struct Pathfinder {
     // These params cover the main graphs
     params: RoutingParams,
 
+    // Used for cars and buses with a departure time. Set from a previous simulation at runtime.
+    #[serde(skip_serializing, skip_deserializing)]
+    travel_time_profiles: Option<TravelTimeProfiles>,
+
     // Callers can opt into caching with pathfind_with_params
     // TODO VecMap is probably fast enough. RoutingParams is annoying to implement Hash.
     #[serde(skip_serializing, skip_deserializing)]
@@ -44,6 +48,7 @@ impl Clone for Pathfinder {
             walking_graph: self.walking_graph.clone(),
             walking_with_transit_graph: self.walking_with_transit_graph.clone(),
             params: self.params.clone(),
+            travel_time_profiles: self.travel_time_profiles.clone(),
             cached_alternatives: ThreadLocal::new(),
         }
     }
@@ -61,6 +66,7 @@ impl Pathfinder {
             walking_graph: SidewalkPathfinder::empty(),
             walking_with_transit_graph: SidewalkPathfinder::empty(),
             params: RoutingParams::default(),
+            travel_time_profiles: None,
             cached_alternatives: ThreadLocal::new(),
         }
     }
@@ -119,6 +125,7 @@ impl Pathfinder {
             walking_with_transit_graph,
 
             params,
+            travel_time_profiles: None,
             cached_alternatives: ThreadLocal::new(),
         }
     }
@@ -174,9 +181,9 @@ impl Pathfinder {
         if params == &self.params {
             return match constraints {
                 PathConstraints::Pedestrian => self.walking_graph.pathfind(req, map),
-                PathConstraints::Car => self.car_graph.pathfind(req, map),
-                PathConstraints::Bike => self.bike_graph.pathfind(req, map),
-                PathConstraints::Bus => self.bus_graph.pathfind(req, map),
+                PathConstraints::Car => self.pathfind_vehicle(&self.car_graph, req, map),
+                PathConstraints::Bike => self.pathfind_vehicle(&self.bike_graph, req, map),
+                PathConstraints::Bus => self.pathfind_vehicle(&self.bus_graph, req, map),
                 PathConstraints::Train => self.train_graph.pathfind(req, map),
             };
         }
@@ -211,6 +218,68 @@ impl Pathfinder {
         result
     }
 
+    fn pathfind_vehicle(
+        &self,
+        graph: &VehiclePathfinder,
+        req: PathRequest,
+        map: &Map,
+    ) -> Option<PathV2> {
+        if let Some(departure) = req.departure {
+            // TODO There's no time-dependent contraction hierarchy, so with profiles, every
+            // request with a departure time falls back to a plain Dijkstra over arrival times.
+            // Something like TCH or CATCHUp would make these fast enough to use for everybody.
+            if let Some(ref profiles) = self.travel_time_profiles {
+                return graph.pathfind_time_dependent(req, departure, profiles, map);
+            }
+            // Zones closed or priced differently during part of the day aren't baked into the
+            // contraction hierarchy. A path that never touches those roads costs the same at any
+            // time, so it's still the best one -- unless some of those roads are ever cheaper
+            // than usual.
+            let time_of_day = &map.time_of_day_costs;
+            if !time_of_day.roads.is_empty() {
+                if !time_of_day.discounted.contains(req.constraints) {
+                    let path = graph.pathfind(req.clone(), map)?;
+                    if !path.get_steps().iter().any(|step| match step {
+                        PathStepV2::Along(dr) | PathStepV2::Contraflow(dr) => {
+                            time_of_day.roads.contains(&dr.id)
+                        }
+                        PathStepV2::Movement(_) => false,
+                    }) {
+                        return Some(path);
+                    }
+                }
+                let profiles = TravelTimeProfiles::new(Duration::hours(1));
+                return graph.pathfind_time_dependent(req, departure, &profiles, map);
+            }
+        }
+        graph.pathfind(req, map)
+    }
+
+    /// Finds a path for a vehicle that never enters some roads, unless it starts or ends there.
+    /// Always uses a slow search.
+    pub fn pathfind_avoiding_roads(
+        &self,
+        req: PathRequest,
+        avoid: &BTreeSet<RoadID>,
+        map: &Map,
+    ) -> Option<PathV2> {
+        let graph = match req.constraints {
+            PathConstraints::Car => &self.car_graph,
+            PathConstraints::Bike => &self.bike_graph,
+            PathConstraints::Bus => &self.bus_graph,
+            PathConstraints::Train => &self.train_graph,
+            PathConstraints::Pedestrian => unreachable!(),
+        };
+        let departure = req.departure.unwrap_or(Time::START_OF_DAY);
+        let empty = TravelTimeProfiles::new(Duration::hours(1));
+        let profiles = self.travel_time_profiles.as_ref().unwrap_or(&empty);
+        graph.pathfind_avoiding_roads(req, departure, profiles, avoid, map)
+    }
+
+    pub fn set_travel_time_profiles(&mut self, profiles: Option<TravelTimeProfiles>) {
+        self.travel_time_profiles = profiles;
+    }
+
     pub fn all_costs_from(
         &self,
         req: PathRequest,
@@ -232,35 +301,124 @@ impl Pathfinder {
         map: &Map,
         start: Position,
         end: Position,
-    ) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
+    ) -> Option<Vec<TransitRide>> {
         self.walking_with_transit_graph
             .should_use_transit(map, start, end)
     }
 
-    pub fn apply_edits(&mut self, map: &Map, timer: &mut Timer) {
+    /// Recreate every graph from scratch, keeping the same engine and params.
+    pub fn rebuild(&mut self, map: &Map, timer: &mut Timer) {
+        let engine = if self.car_graph.engine.is_dijkstra() {
+            CreateEngine::Dijkstra
+        } else {
+            CreateEngine::CH
+        };
+        let mut pathfinder = Pathfinder::new(map, self.params.clone(), engine, timer);
+        pathfinder.travel_time_profiles = self.travel_time_profiles.take();
+        *self = pathfinder;
+    }
+
+    /// Update the graphs after map edits. Graphs whose edges didn't change are skipped; the others
+    /// are re-prepared from scratch, just reusing the previous node ordering. `structure_changed`
+    /// means structural edits added or removed roads; nodes for new roads are ordered last. Only
+    /// if the uber-turns changed is a graph prepared without its old ordering.
+    pub fn apply_edits(&mut self, map: &Map, structure_changed: bool, timer: &mut Timer) {
+        self.update_graphs(map, structure_changed, false, timer);
+    }
+
+    /// Like `apply_edits`, but any contraction hierarchies that need to be re-prepared are done in
+    /// background threads. Until they finish, pathfinding for those modes is slower, but still
+    /// correct. Because equally good paths may be chosen differently in the meantime, don't use
+    /// this when a simulation must be reproducible.
+    pub fn apply_edits_in_background(
+        &mut self,
+        map: &Map,
+        structure_changed: bool,
+        timer: &mut Timer,
+    ) {
+        self.update_graphs(map, structure_changed, true, timer);
+    }
+
+    /// Block until any contraction hierarchies being prepared in the background are done.
+    pub fn finish_background_work(&mut self) {
+        self.car_graph.engine.finish_background_work();
+        self.bike_graph.engine.finish_background_work();
+        self.bus_graph.engine.finish_background_work();
+        self.train_graph.engine.finish_background_work();
+        self.walking_graph.finish_background_work();
+        self.walking_with_transit_graph.finish_background_work();
+    }
+
+    fn update_graphs(
+        &mut self,
+        map: &Map,
+        structure_changed: bool,
+        in_background: bool,
+        timer: &mut Timer,
+    ) {
+        let mut unchanged = Vec::new();
+
         timer.start("apply edits to car pathfinding");
-        self.car_graph.apply_edits(map);
+        if !self
+            .car_graph
+            .apply_edits(map, structure_changed, in_background)
+        {
+            unchanged.push("cars");
+        }
         timer.stop("apply edits to car pathfinding");
 
         timer.start("apply edits to bike pathfinding");
-        self.bike_graph.apply_edits(map);
+        if !self
+            .bike_graph
+            .apply_edits(map, structure_changed, in_background)
+        {
+            unchanged.push("bikes");
+        }
         timer.stop("apply edits to bike pathfinding");
 
         timer.start("apply edits to bus pathfinding");
-        self.bus_graph.apply_edits(map);
+        if !self
+            .bus_graph
+            .apply_edits(map, structure_changed, in_background)
+        {
+            unchanged.push("buses");
+        }
         timer.stop("apply edits to bus pathfinding");
 
         timer.start("apply edits to train pathfinding");
-        self.train_graph.apply_edits(map);
+        if !self
+            .train_graph
+            .apply_edits(map, structure_changed, in_background)
+        {
+            unchanged.push("trains");
+        }
         timer.stop("apply edits to train pathfinding");
 
         timer.start("apply edits to pedestrian pathfinding");
-        self.walking_graph.apply_edits(map, None);
+        if !self
+            .walking_graph
+            .apply_edits(map, None, structure_changed, in_background)
+        {
+            unchanged.push("pedestrians");
+        }
         timer.stop("apply edits to pedestrian pathfinding");
 
         timer.start("apply edits to pedestrian using transit pathfinding");
-        self.walking_with_transit_graph
-            .apply_edits(map, Some((&self.bus_graph, &self.train_graph)));
+        if !self.walking_with_transit_graph.apply_edits(
+            map,
+            Some((&self.bus_graph, &self.train_graph)),
+            structure_changed,
+            in_background,
+        ) {
+            unchanged.push("pedestrians using transit");
+        }
         timer.stop("apply edits to pedestrian using transit pathfinding");
+
+        if !unchanged.is_empty() {
+            info!(
+                "Edits didn't affect pathfinding for {}",
+                unchanged.join(", ")
+            );
+        }
     }
 }
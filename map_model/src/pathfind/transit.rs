@@ -0,0 +1,441 @@
+//! Schedule-aware routing over public transit, using the Connection Scan Algorithm
+//! (https://arxiv.org/abs/1703.05997). Unlike the walking-with-transit graph, this knows when
+//! vehicles actually depart, so it can account for waiting and plan transfers between routes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use geom::{Distance, Duration, Pt2D, Time};
+
+use crate::{
+    BusRoute, BusRouteID, BusStopID, IntersectionID, Map, Position, TransitRide, MAX_WALKING_SPEED,
+};
+
+/// When a route has no published timetable, assume vehicles wait this long at each stop. This
+/// matches the simulation.
+const DEFAULT_DWELL_TIME: Duration = Duration::const_seconds(10.0);
+/// How far somebody is willing to walk to the first stop or from the last stop.
+const MAX_ACCESS_WALK: Distance = Distance::const_meters(1000.0);
+/// How far somebody is willing to walk between two stops to transfer.
+const MAX_TRANSFER_WALK: Distance = Distance::const_meters(400.0);
+/// Walking routes are longer than the straight line between two points.
+const WALK_DETOUR_FACTOR: f64 = 1.3;
+/// Leave some slack when getting off one vehicle and onto another.
+const MIN_TRANSFER_TIME: Duration = Duration::const_seconds(60.0);
+
+/// A fastest way to reach a destination using public transit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Itinerary {
+    pub legs: Vec<ItineraryLeg>,
+    pub arrival: Time,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItineraryLeg {
+    /// Walk from the start to the first stop, or from the last stop to the end.
+    Walk { duration: Duration },
+    /// Wait at a stop for the next vehicle.
+    Wait { stop: BusStopID, until: Time },
+    /// If there's no stop to alight at, ride off the map.
+    Ride {
+        route: BusRouteID,
+        board: BusStopID,
+        alight: Option<BusStopID>,
+        depart: Time,
+        arrive: Time,
+    },
+    /// Walk between two stops to switch routes.
+    Transfer {
+        from: BusStopID,
+        to: BusStopID,
+        duration: Duration,
+    },
+}
+
+impl Itinerary {
+    /// Convert to the rides that a pedestrian takes, walking to the station entrance closest to
+    /// wherever they're coming from.
+    pub fn rides(&self, start: Position, map: &Map) -> Vec<TransitRide> {
+        let mut rides = Vec::new();
+        let mut last_pt = start.pt(map);
+        for leg in &self.legs {
+            if let ItineraryLeg::Ride {
+                route,
+                board,
+                alight,
+                ..
+            } = leg
+            {
+                let walk_to = if let Some(ts) = map.get_station_for_stop(*board) {
+                    ts.closest_entrance(last_pt, map)
+                } else {
+                    map.get_bs(*board).sidewalk_pos
+                };
+                rides.push(TransitRide {
+                    route: *route,
+                    board: *board,
+                    alight: *alight,
+                    walk_to,
+                });
+                if let Some(stop) = alight {
+                    last_pt = map.get_bs(*stop).sidewalk_pos.pt(map);
+                }
+            }
+        }
+        rides
+    }
+}
+
+/// One vehicle traveling between two consecutive stops.
+struct Connection {
+    route: BusRouteID,
+    trip: usize,
+    from: BusStopID,
+    to: BusStopID,
+    depart: Time,
+    arrive: Time,
+    /// If this vehicle then leaves the map, when does it reach the border?
+    leave_map: Option<(IntersectionID, Time)>,
+}
+
+/// How somebody earliest reached a stop.
+#[derive(Clone, Copy)]
+enum Label {
+    Access(Duration),
+    Ride {
+        route: BusRouteID,
+        board: BusStopID,
+        depart: Time,
+    },
+    Transfer {
+        from: BusStopID,
+        duration: Duration,
+    },
+}
+
+enum Finish {
+    Egress(BusStopID, Duration),
+    RideOffMap {
+        route: BusRouteID,
+        board: BusStopID,
+        depart: Time,
+    },
+}
+
+pub struct TransitRouter {
+    /// Sorted by departure time
+    connections: Vec<Connection>,
+    stop_pts: BTreeMap<BusStopID, Pt2D>,
+    transfers: BTreeMap<BusStopID, Vec<(BusStopID, Duration)>>,
+}
+
+impl TransitRouter {
+    pub fn new(map: &Map) -> TransitRouter {
+        let mut connections = Vec::new();
+        for route in map.all_bus_routes() {
+            let (arrivals, dwells, to_border) = match route_schedule(route, map) {
+                Some(x) => x,
+                None => {
+                    warn!("Can't estimate the schedule for {}", route.full_name);
+                    continue;
+                }
+            };
+            for (trip, spawn) in route.spawn_times.iter().enumerate() {
+                for idx in 0..route.stops.len() - 1 {
+                    let leave_map = if idx == route.stops.len() - 2 {
+                        to_border
+                            .map(|(i, dt)| (i, *spawn + arrivals[idx + 1] + dwells[idx + 1] + dt))
+                    } else {
+                        None
+                    };
+                    connections.push(Connection {
+                        route: route.id,
+                        trip,
+                        from: route.stops[idx],
+                        to: route.stops[idx + 1],
+                        depart: *spawn + arrivals[idx] + dwells[idx],
+                        arrive: *spawn + arrivals[idx + 1],
+                        leave_map,
+                    });
+                }
+            }
+        }
+        connections.sort_by_key(|c| c.depart);
+
+        let stop_pts: BTreeMap<BusStopID, Pt2D> = map
+            .all_bus_stops()
+            .values()
+            .map(|bs| (bs.id, bs.sidewalk_pos.pt(map)))
+            .collect();
+        let mut transfers = BTreeMap::new();
+        for (stop1, pt1) in &stop_pts {
+            let mut nearby = Vec::new();
+            for (stop2, pt2) in &stop_pts {
+                if stop1 != stop2 && pt1.dist_to(*pt2) <= MAX_TRANSFER_WALK {
+                    nearby.push((*stop2, walking_time(*pt1, *pt2)));
+                }
+            }
+            transfers.insert(*stop1, nearby);
+        }
+
+        TransitRouter {
+            connections,
+            stop_pts,
+            transfers,
+        }
+    }
+
+    /// Find the earliest arrival at `end` when leaving `start` at `depart`. Returns None if
+    /// transit isn't useful, because walking the whole way is faster.
+    pub fn plan(
+        &self,
+        start: Position,
+        end: Position,
+        depart: Time,
+        map: &Map,
+    ) -> Option<Itinerary> {
+        let start_pt = start.pt(map);
+        let end_pt = end.pt(map);
+        let end_border = leaves_map_at(end, map);
+
+        let mut earliest: HashMap<BusStopID, Time> = HashMap::new();
+        let mut labels: HashMap<BusStopID, Label> = HashMap::new();
+        for (stop, pt) in &self.stop_pts {
+            if start_pt.dist_to(*pt) <= MAX_ACCESS_WALK {
+                let walk = walking_time(start_pt, *pt);
+                earliest.insert(*stop, depart + walk);
+                labels.insert(*stop, Label::Access(walk));
+            }
+        }
+        if earliest.is_empty() {
+            return None;
+        }
+
+        // For each vehicle somebody's on, where and when did they board?
+        let mut boarded: HashMap<(BusRouteID, usize), (BusStopID, Time)> = HashMap::new();
+        let mut best: Option<(Time, Finish)> = None;
+        for c in self.connections.iter().skip_while(|c| c.depart < depart) {
+            if let Some((arrival, _)) = &best {
+                if c.depart >= *arrival {
+                    break;
+                }
+            }
+
+            let key = (c.route, c.trip);
+            if !boarded.contains_key(&key) {
+                let ready = match (earliest.get(&c.from), labels.get(&c.from)) {
+                    (Some(t), Some(Label::Ride { .. })) => *t + MIN_TRANSFER_TIME <= c.depart,
+                    (Some(t), Some(_)) => *t <= c.depart,
+                    _ => false,
+                };
+                if !ready {
+                    continue;
+                }
+                boarded.insert(key, (c.from, c.depart));
+            }
+            let (board, board_time) = boarded[&key];
+
+            if let (Some(border), Some((i, t))) = (end_border, c.leave_map) {
+                if border == i
+                    && best
+                        .as_ref()
+                        .map(|(arrival, _)| t < *arrival)
+                        .unwrap_or(true)
+                {
+                    best = Some((
+                        t,
+                        Finish::RideOffMap {
+                            route: c.route,
+                            board,
+                            depart: board_time,
+                        },
+                    ));
+                }
+            }
+
+            if earliest.get(&c.to).map(|t| c.arrive >= *t).unwrap_or(false) {
+                continue;
+            }
+            earliest.insert(c.to, c.arrive);
+            labels.insert(
+                c.to,
+                Label::Ride {
+                    route: c.route,
+                    board,
+                    depart: board_time,
+                },
+            );
+
+            let pt = self.stop_pts[&c.to];
+            if pt.dist_to(end_pt) <= MAX_ACCESS_WALK {
+                let walk = walking_time(pt, end_pt);
+                let arrival = c.arrive + walk;
+                if best.as_ref().map(|(t, _)| arrival < *t).unwrap_or(true) {
+                    best = Some((arrival, Finish::Egress(c.to, walk)));
+                }
+            }
+
+            for (stop, duration) in &self.transfers[&c.to] {
+                let t = c.arrive + *duration;
+                if earliest.get(stop).map(|prev| t < *prev).unwrap_or(true) {
+                    earliest.insert(*stop, t);
+                    labels.insert(
+                        *stop,
+                        Label::Transfer {
+                            from: c.to,
+                            duration: *duration,
+                        },
+                    );
+                }
+            }
+        }
+
+        let (arrival, finish) = best?;
+        if depart + walking_time(start_pt, end_pt) <= arrival {
+            return None;
+        }
+
+        // Work backwards from the finish to reconstruct the legs
+        let mut legs = Vec::new();
+        let mut at = match finish {
+            Finish::Egress(stop, walk) => {
+                legs.push(ItineraryLeg::Walk { duration: walk });
+                stop
+            }
+            Finish::RideOffMap {
+                route,
+                board,
+                depart,
+            } => {
+                legs.push(ItineraryLeg::Ride {
+                    route,
+                    board,
+                    alight: None,
+                    depart,
+                    arrive: arrival,
+                });
+                legs.push(ItineraryLeg::Wait {
+                    stop: board,
+                    until: depart,
+                });
+                board
+            }
+        };
+        loop {
+            match labels[&at] {
+                Label::Access(walk) => {
+                    legs.push(ItineraryLeg::Walk { duration: walk });
+                    break;
+                }
+                Label::Ride {
+                    route,
+                    board,
+                    depart,
+                } => {
+                    legs.push(ItineraryLeg::Ride {
+                        route,
+                        board,
+                        alight: Some(at),
+                        depart,
+                        arrive: earliest[&at],
+                    });
+                    legs.push(ItineraryLeg::Wait {
+                        stop: board,
+                        until: depart,
+                    });
+                    at = board;
+                }
+                Label::Transfer { from, duration } => {
+                    legs.push(ItineraryLeg::Transfer {
+                        from,
+                        to: at,
+                        duration,
+                    });
+                    at = from;
+                }
+            }
+        }
+        legs.reverse();
+        Some(Itinerary { legs, arrival })
+    }
+}
+
+/// The router depends on the map's transit schedules and roads, but it's only needed for
+/// simulations using transit, so build it lazily and drop it when the map changes.
+#[derive(Default)]
+pub struct TransitRouterCache(Mutex<Option<Arc<TransitRouter>>>);
+
+impl Clone for TransitRouterCache {
+    fn clone(&self) -> Self {
+        TransitRouterCache::default()
+    }
+}
+
+impl TransitRouterCache {
+    pub fn get(&self, map: &Map) -> Arc<TransitRouter> {
+        let mut cached = self.0.lock().unwrap();
+        if cached.is_none() {
+            *cached = Some(Arc::new(TransitRouter::new(map)));
+        }
+        cached.clone().unwrap()
+    }
+
+    pub fn clear(&mut self) {
+        *self.0.get_mut().unwrap() = None;
+    }
+}
+
+/// For each stop along a route, how long after spawning does a vehicle arrive and how long does
+/// it wait? Also, if the route ends at a border, how long after leaving the last stop does the
+/// vehicle reach it?
+fn route_schedule(
+    route: &BusRoute,
+    map: &Map,
+) -> Option<(
+    Vec<Duration>,
+    Vec<Duration>,
+    Option<(IntersectionID, Duration)>,
+)> {
+    let mut steps = Vec::new();
+    for req in route.all_steps(map) {
+        steps.push(map.pathfind(req).ok()?.estimate_duration(map, None));
+    }
+    let to_border = route
+        .end_border
+        .map(|l| (map.get_l(l).dst_i, *steps.last().unwrap()));
+
+    let (arrivals, dwells) = if let Some(ref timetable) = route.timetable {
+        (
+            timetable
+                .arrival_offsets
+                .iter()
+                .map(|offset| steps[0] + *offset)
+                .collect(),
+            timetable.dwell_times.clone(),
+        )
+    } else {
+        let mut arrivals = vec![steps[0]];
+        for dt in &steps[1..route.stops.len()] {
+            let prev = *arrivals.last().unwrap();
+            arrivals.push(prev + DEFAULT_DWELL_TIME + *dt);
+        }
+        (arrivals, vec![DEFAULT_DWELL_TIME; route.stops.len()])
+    };
+    Some((arrivals, dwells, to_border))
+}
+
+/// If a trip ends by leaving the map through a border, which one?
+fn leaves_map_at(pos: Position, map: &Map) -> Option<IntersectionID> {
+    let l = map.get_l(pos.lane());
+    if map.get_i(l.src_i).is_outgoing_border() && pos.dist_along() == Distance::ZERO {
+        return Some(l.src_i);
+    }
+    if map.get_i(l.dst_i).is_outgoing_border() && pos.dist_along() == l.length() {
+        return Some(l.dst_i);
+    }
+    None
+}
+
+fn walking_time(pt1: Pt2D, pt2: Pt2D) -> Duration {
+    WALK_DETOUR_FACTOR * pt1.dist_to(pt2) / MAX_WALKING_SPEED
+}
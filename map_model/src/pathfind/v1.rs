@@ -4,9 +4,11 @@ use std::fmt;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use geom::{Distance, Duration, PolyLine, Speed, EPSILON_DIST};
+use geom::{Distance, Duration, PolyLine, Speed, Time, EPSILON_DIST};
 
-use crate::{BuildingID, LaneID, Map, PathConstraints, Position, Traversable, TurnID, UberTurn};
+use crate::{
+    BuildingID, Direction, LaneID, Map, PathConstraints, Position, Traversable, TurnID, UberTurn,
+};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum PathStep {
@@ -99,24 +101,56 @@ impl PathStep {
         max_speed_on_flat_ground: Option<Speed>,
         constraints: PathConstraints,
         map: &Map,
+    ) -> (Speed, f64) {
+        self.max_speed_and_incline_between(
+            Distance::ZERO,
+            self.as_traversable().get_polyline(map).length(),
+            max_speed_on_flat_ground,
+            constraints,
+            map,
+        )
+    }
+
+    /// Like `max_speed_and_incline_along`, but only covering part of the step, since the grade can
+    /// change along a road. The distances are measured along the lane or turn, in either order.
+    /// The incline returned is the steepest uphill part.
+    pub fn max_speed_and_incline_between(
+        &self,
+        dist1: Distance,
+        dist2: Distance,
+        max_speed_on_flat_ground: Option<Speed>,
+        constraints: PathConstraints,
+        map: &Map,
     ) -> (Speed, f64) {
         match self {
-            PathStep::Lane(l) => Traversable::max_speed_along_road(
-                map.get_l(*l).get_directed_parent(),
-                max_speed_on_flat_ground,
-                constraints,
-                map,
-            ),
-            PathStep::ContraflowLane(l) => Traversable::max_speed_along_road(
-                {
-                    let mut dr = map.get_l(*l).get_directed_parent();
+            PathStep::Lane(l) | PathStep::ContraflowLane(l) => {
+                let lane = map.get_l(*l);
+                let mut dr = lane.get_directed_parent();
+                if let PathStep::ContraflowLane(_) = self {
                     dr.dir = dr.dir.opposite();
-                    dr
-                },
-                max_speed_on_flat_ground,
-                constraints,
-                map,
-            ),
+                }
+                // Express the distances as fractions of the road's length from src_i
+                let to_pct = |dist: Distance| {
+                    let pct = if lane.length() == Distance::ZERO {
+                        0.0
+                    } else {
+                        (dist / lane.length()).max(0.0).min(1.0)
+                    };
+                    if lane.dir == Direction::Fwd {
+                        pct
+                    } else {
+                        1.0 - pct
+                    }
+                };
+                Traversable::max_speed_along_part_of_road(
+                    dr,
+                    to_pct(dist1),
+                    to_pct(dist2),
+                    max_speed_on_flat_ground,
+                    constraints,
+                    map,
+                )
+            }
             PathStep::Turn(t) => (
                 Traversable::max_speed_along_movement(
                     t.to_movement(map),
@@ -308,6 +342,20 @@ impl Path {
         // TODO Maybe need to amend uber_turns?
     }
 
+    /// Replace everything after the current step with a different route, keeping track of
+    /// progress along the original path. The new path must start on the current lane and end in
+    /// the same place as the original request.
+    pub fn reroute(&mut self, new_path: Path, map: &Map) {
+        assert!(self.currently_inside_ut.is_none());
+        assert_eq!(self.steps[0], new_path.steps[0]);
+        assert_eq!(self.orig_req.end, new_path.orig_req.end);
+        self.total_length = self.crossed_so_far
+            + self.dist_crossed_from_step(map, &self.steps[0])
+            + new_path.total_length;
+        self.steps = new_path.steps;
+        self.uber_turns = new_path.uber_turns;
+    }
+
     pub fn is_upcoming_uber_turn_component(&self, t: TurnID) -> bool {
         self.uber_turns
             .front()
@@ -472,6 +520,18 @@ impl Path {
         self.blocked_starts.clone()
     }
 
+    /// Returns the total tolls, in dollars, a car would pay over the rest of the path. The price
+    /// is taken at one time for the whole path.
+    pub fn get_total_toll(&self, map: &Map, time: Time) -> f64 {
+        let mut total = 0.0;
+        for step in &self.steps {
+            if let PathStep::Turn(t) = step {
+                total += map.toll_between(t.src.road, t.dst.road, Some(time));
+            }
+        }
+        total
+    }
+
     /// Returns the total elevation (gain, loss) experienced over the path.
     pub fn get_total_elevation_change(&self, map: &Map) -> (Distance, Distance) {
         let mut gain = Distance::ZERO;
@@ -530,6 +590,11 @@ pub struct PathRequest {
     // TODO It's assumed this lane is on the same directed road as `start`, but this isn't
     // enforced!
     pub(crate) alt_start: Option<(Position, Duration)>,
+    // If present, vehicles are routed using the travel times expected when they depart, if the
+    // pathfinder has TravelTimeProfiles, and respecting zones that are only closed at some times.
+    // Not serialized, so requests saved elsewhere keep their old encoding.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub departure: Option<Time>,
 }
 
 impl fmt::Display for PathRequest {
@@ -583,6 +648,7 @@ impl PathRequest {
                 end,
                 constraints,
                 alt_start: None,
+                departure: None,
             })
         }
     }
@@ -594,6 +660,7 @@ impl PathRequest {
             end,
             constraints: PathConstraints::Pedestrian,
             alt_start: None,
+            departure: None,
         }
     }
 
@@ -605,6 +672,7 @@ impl PathRequest {
             end,
             constraints,
             alt_start: None,
+            departure: None,
         }
     }
 
@@ -641,8 +709,15 @@ impl PathRequest {
             end,
             constraints,
             alt_start,
+            departure: None,
         }
     }
+
+    /// Route using the travel times expected at this time of day, if available.
+    pub fn with_departure(mut self, time: Time) -> PathRequest {
+        self.departure = Some(time);
+        self
+    }
 }
 
 fn validate_continuity(map: &Map, steps: &[PathStep]) {
@@ -142,7 +142,7 @@ impl PathV2 {
         // from a single node and since we want to prefer the originally requested lane anyway,
         // create a virtual start node and connect it to all possible starting lanes.
         let virtual_start_node = LaneID {
-            road: RoadID(map.all_roads().len()),
+            road: RoadID(map.all_roads_with_deleted().len()),
             offset: 0,
         };
         let start_lane = self.req.start.lane();
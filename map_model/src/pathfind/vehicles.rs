@@ -1,21 +1,22 @@
 //! Pathfinding for cars, bikes, buses, and trains using contraction hierarchies
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
 
 use fast_paths::InputGraph;
 use serde::{Deserialize, Serialize};
 
 use abstutil::MultiMap;
-use geom::Duration;
+use geom::{Duration, Time};
 
-use crate::pathfind::engine::{CreateEngine, PathfindEngine};
+use crate::pathfind::engine::{hash_input_graph, CreateEngine, PathfindEngine};
 use crate::pathfind::node_map::{deserialize_nodemap, NodeMap};
 use crate::pathfind::uber_turns::{IntersectionCluster, UberTurnV2};
-use crate::pathfind::zone_cost;
 use crate::pathfind::{round, unround};
+use crate::pathfind::{toll_cost, zone_cost, zone_cost_at, STEEP_INCLINE};
 use crate::{
-    DirectedRoadID, Direction, LaneType, Map, MovementID, PathConstraints, PathRequest, PathV2,
-    Position, RoutingParams, Traversable,
+    DirectedRoadID, LaneType, Map, MovementID, PathConstraints, PathRequest, PathV2, Position,
+    RoadID, RoutingParams, TravelTimeProfiles, Traversable,
 };
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -26,6 +27,8 @@ pub struct VehiclePathfinder {
     constraints: PathConstraints,
     params: RoutingParams,
     pub engine: PathfindEngine,
+    /// Used to detect when edits don't affect this graph at all
+    graph_hash: u64,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
@@ -42,6 +45,7 @@ impl VehiclePathfinder {
             constraints: PathConstraints::Car,
             params: RoutingParams::default(),
             engine: PathfindEngine::Empty,
+            graph_hash: 0,
         }
     }
 
@@ -53,29 +57,16 @@ impl VehiclePathfinder {
     ) -> VehiclePathfinder {
         // Insert every road as a node.
         let mut nodes = NodeMap::new();
-        for r in map.all_roads() {
-            // Regardless of current lane types or even directions, add both. These could change
-            // later, and we want the node IDs to match up.
-            nodes.get_or_insert(Node::Road(DirectedRoadID {
-                id: r.id,
-                dir: Direction::Fwd,
-            }));
-            nodes.get_or_insert(Node::Road(DirectedRoadID {
-                id: r.id,
-                dir: Direction::Back,
-            }));
-        }
+        add_road_nodes(&mut nodes, map);
 
         // Find all uber-turns and make a node for them too.
-        let mut uber_turns = Vec::new();
-        for ic in IntersectionCluster::find_all(map) {
-            for ut in ic.into_v2(map) {
-                nodes.get_or_insert(Node::UberTurn(uber_turns.len()));
-                uber_turns.push(ut);
-            }
+        let uber_turns = find_uber_turns(map);
+        for idx in 0..uber_turns.len() {
+            nodes.get_or_insert(Node::UberTurn(idx));
         }
 
         let input_graph = make_input_graph(constraints, &nodes, &uber_turns, params, map);
+        let graph_hash = hash_input_graph(&input_graph);
         let engine = engine.create(input_graph);
 
         VehiclePathfinder {
@@ -84,6 +75,7 @@ impl VehiclePathfinder {
             constraints,
             params: params.clone(),
             engine,
+            graph_hash,
         }
     }
 
@@ -110,10 +102,151 @@ impl VehiclePathfinder {
                 0,
             )],
         )?;
+        let nodes = raw_nodes
+            .into_iter()
+            .map(|id| self.nodes.translate_id(id))
+            .collect();
+        Some(self.nodes_to_path(nodes, req, unround(raw_weight), map))
+    }
+
+    /// Like `pathfind`, but the cost of each road and movement depends on when the vehicle is
+    /// expected to reach it. The contraction hierarchy can't handle this, so this is a plain
+    /// Dijkstra search over arrival times, which is much slower.
+    pub fn pathfind_time_dependent(
+        &self,
+        req: PathRequest,
+        departure: Time,
+        profiles: &TravelTimeProfiles,
+        map: &Map,
+    ) -> Option<PathV2> {
+        self.pathfind_dijkstra(req, departure, profiles, &BTreeSet::new(), map)
+    }
+
+    /// Like `pathfind_time_dependent`, but never entering some roads, unless the path starts or
+    /// ends there.
+    pub fn pathfind_avoiding_roads(
+        &self,
+        req: PathRequest,
+        departure: Time,
+        profiles: &TravelTimeProfiles,
+        avoid: &BTreeSet<RoadID>,
+        map: &Map,
+    ) -> Option<PathV2> {
+        self.pathfind_dijkstra(req, departure, profiles, avoid, map)
+    }
+
+    fn pathfind_dijkstra(
+        &self,
+        req: PathRequest,
+        departure: Time,
+        profiles: &TravelTimeProfiles,
+        avoid: &BTreeSet<RoadID>,
+        map: &Map,
+    ) -> Option<PathV2> {
+        assert!(!map.get_l(req.start.lane()).is_walkable());
+        let end = Node::Road(map.get_l(req.end.lane()).get_directed_parent());
+        let uber_turn_entrances = find_uber_turn_entrances(self.constraints, &self.uber_turns, map);
+        let allowed = |dr: DirectedRoadID| Node::Road(dr) == end || !avoid.contains(&dr.id);
+
+        // (arrival time, node, previous node)
+        let mut queue: BinaryHeap<Reverse<(Time, Node, Option<Node>)>> = BinaryHeap::new();
+        queue.push(Reverse((
+            departure,
+            Node::Road(map.get_l(req.start.lane()).get_directed_parent()),
+            None,
+        )));
+        if let Some((pos, cost)) = req.alt_start {
+            queue.push(Reverse((
+                departure + cost,
+                Node::Road(map.get_l(pos.lane()).get_directed_parent()),
+                None,
+            )));
+        }
+
+        let mut backrefs: HashMap<Node, Option<Node>> = HashMap::new();
+        while let Some(Reverse((time, node, prev))) = queue.pop() {
+            if backrefs.contains_key(&node) {
+                continue;
+            }
+            backrefs.insert(node, prev);
+
+            if node == end {
+                let mut nodes = vec![end];
+                let mut current = end;
+                while let Some(Some(prev)) = backrefs.get(&current) {
+                    nodes.push(*prev);
+                    current = *prev;
+                }
+                nodes.reverse();
+                return Some(self.nodes_to_path(nodes, req, time - departure, map));
+            }
+
+            match node {
+                Node::Road(dr) => {
+                    if dr.lanes(self.constraints, map).is_empty() {
+                        continue;
+                    }
+                    let indices = uber_turn_entrances.get(dr);
+                    if indices.is_empty() {
+                        for mvmnt in map.get_movements_for(dr, self.constraints) {
+                            if !allowed(mvmnt.to) {
+                                continue;
+                            }
+                            let cost = time_dependent_cost(
+                                dr,
+                                mvmnt,
+                                self.constraints,
+                                &self.params,
+                                profiles,
+                                time,
+                                map,
+                            );
+                            queue.push(Reverse((time + cost, Node::Road(mvmnt.to), Some(node))));
+                        }
+                    } else {
+                        for idx in indices {
+                            if !self.uber_turns[*idx].path.iter().all(|m| allowed(m.to)) {
+                                continue;
+                            }
+                            let mut arrival = time;
+                            for mvmnt in &self.uber_turns[*idx].path {
+                                arrival += time_dependent_cost(
+                                    mvmnt.from,
+                                    *mvmnt,
+                                    self.constraints,
+                                    &self.params,
+                                    profiles,
+                                    arrival,
+                                    map,
+                                );
+                            }
+                            queue.push(Reverse((arrival, Node::UberTurn(*idx), Some(node))));
+                        }
+                    }
+                }
+                Node::UberTurn(idx) => {
+                    // The cost is already captured for entering the uber-turn
+                    queue.push(Reverse((
+                        time,
+                        Node::Road(self.uber_turns[idx].exit()),
+                        Some(node),
+                    )));
+                }
+            }
+        }
+        None
+    }
 
+    fn nodes_to_path(
+        &self,
+        nodes: Vec<Node>,
+        req: PathRequest,
+        cost: Duration,
+        map: &Map,
+    ) -> PathV2 {
         let mut road_steps = Vec::new();
         let mut uber_turns = Vec::new();
-        for node in raw_nodes.into_iter().map(|id| self.nodes.translate_id(id)) {
+        for node in nodes {
             match node {
                 Node::Road(dr) => {
                     road_steps.push(dr);
@@ -129,17 +262,37 @@ impl VehiclePathfinder {
                 }
             }
         }
-        let cost = unround(raw_weight);
-        Some(PathV2::from_roads(road_steps, req, cost, uber_turns, map))
+        PathV2::from_roads(road_steps, req, cost, uber_turns, map)
     }
 
-    pub fn apply_edits(&mut self, map: &Map) {
+    /// Returns false if the edits didn't change this graph at all. If `in_background` is set,
+    /// the new contraction hierarchy (if any) is prepared in a background thread.
+    /// `structure_changed` means roads may have been added or removed.
+    pub fn apply_edits(&mut self, map: &Map, structure_changed: bool, in_background: bool) -> bool {
         if matches!(self.engine, PathfindEngine::Empty) {
-            return;
+            return false;
+        }
+
+        if structure_changed {
+            // Renumbering the uber-turns would scramble the node ordering, so start over
+            let uber_turns = find_uber_turns(map);
+            if uber_turns != self.uber_turns {
+                let engine = if self.engine.is_dijkstra() {
+                    CreateEngine::Dijkstra
+                } else {
+                    CreateEngine::CH
+                };
+                let params = self.params.clone();
+                *self = VehiclePathfinder::new(map, self.constraints, &params, &engine);
+                return true;
+            }
+            // New roads get nodes after all existing ones, and nodes of removed roads just lose
+            // their edges.
+            add_road_nodes(&mut self.nodes, map);
         }
 
-        // The NodeMap is just all roads and uber-turns -- it won't change. So we can also reuse
-        // the node ordering.
+        // Otherwise the NodeMap is just all roads and uber-turns -- it won't change. So we can
+        // also reuse the node ordering.
         // TODO Make sure the result of this is deterministic and equivalent to computing from
         // scratch.
         let input_graph = make_input_graph(
@@ -149,8 +302,21 @@ impl VehiclePathfinder {
             &self.params,
             map,
         );
-        let engine = self.engine.reuse_ordering().create(input_graph);
-        self.engine = engine;
+        let graph_hash = hash_input_graph(&input_graph);
+        if graph_hash == self.graph_hash {
+            if !in_background {
+                self.engine.finish_background_work();
+            }
+            return false;
+        }
+        let create = self.engine.reuse_ordering();
+        self.engine = if in_background {
+            create.create_in_background(input_graph)
+        } else {
+            create.create(input_graph)
+        };
+        self.graph_hash = graph_hash;
+        true
     }
 
     pub fn all_costs_from(&self, start: Position, map: &Map) -> HashMap<DirectedRoadID, Duration> {
@@ -185,6 +351,24 @@ impl VehiclePathfinder {
     }
 }
 
+fn add_road_nodes(nodes: &mut NodeMap<Node>, map: &Map) {
+    for r in map.all_roads_with_deleted() {
+        // Regardless of current lane types or even directions, add both. These could change
+        // later, and we want the node IDs to match up.
+        for dr in r.id.both_directions() {
+            nodes.get_or_insert(Node::Road(dr));
+        }
+    }
+}
+
+fn find_uber_turns(map: &Map) -> Vec<UberTurnV2> {
+    let mut uber_turns = Vec::new();
+    for ic in IntersectionCluster::find_all(map) {
+        uber_turns.extend(ic.into_v2(map));
+    }
+    uber_turns
+}
+
 fn make_input_graph(
     constraints: PathConstraints,
     nodes: &NodeMap<Node>,
@@ -194,25 +378,11 @@ fn make_input_graph(
 ) -> InputGraph {
     let mut input_graph = InputGraph::new();
 
-    // From some roads, instead of adding edges to movements, add edges to these (indexed)
-    // uber-turns.
-    let mut uber_turn_entrances: MultiMap<DirectedRoadID, usize> = MultiMap::new();
-    for (idx, ut) in uber_turns.iter().enumerate() {
-        // Force the nodes to always match up in the graph for different vehicle types.
+    // Force the nodes to always match up in the graph for different vehicle types.
+    for idx in 0..uber_turns.len() {
         nodes.get(Node::UberTurn(idx));
-
-        // But actually, make sure this uber-turn only contains roads that can be used by this
-        // vehicle.
-        // TODO Need to test editing lanes inside an IntersectionCluster very carefully. See Mercer
-        // and Dexter.
-        if ut
-            .path
-            .iter()
-            .all(|mvmnt| !mvmnt.to.lanes(constraints, map).is_empty())
-        {
-            uber_turn_entrances.insert(ut.entry(), idx);
-        }
     }
+    let uber_turn_entrances = find_uber_turn_entrances(constraints, uber_turns, map);
 
     for r in map.all_roads() {
         for dr in r.id.both_directions() {
@@ -226,7 +396,8 @@ fn make_input_graph(
                             nodes.get(Node::Road(mvmnt.to)),
                             round(
                                 vehicle_cost(mvmnt.from, mvmnt, constraints, params, map)
-                                    + zone_cost(mvmnt, constraints, map),
+                                    + zone_cost(mvmnt, constraints, map)
+                                    + toll_cost(mvmnt, constraints, None, params, map),
                             ),
                         );
                     }
@@ -237,7 +408,8 @@ fn make_input_graph(
                         let mut sum_cost = Duration::ZERO;
                         for mvmnt in &ut.path {
                             sum_cost += vehicle_cost(mvmnt.from, *mvmnt, constraints, params, map)
-                                + zone_cost(*mvmnt, constraints, map);
+                                + zone_cost(*mvmnt, constraints, map)
+                                + toll_cost(*mvmnt, constraints, None, params, map);
                         }
                         input_graph.add_edge(
                             from,
@@ -261,16 +433,36 @@ fn make_input_graph(
     input_graph
 }
 
-/// This returns the pathfinding cost of crossing one road and turn. This is also expressed in
-/// units of time. It factors in the ideal time to cross the space, along with penalties for
-/// entering an access-restricted zone, taking an unprotected turn, and so on.
-pub fn vehicle_cost(
+/// From some roads, instead of adding edges to movements, add edges to these (indexed) uber-turns.
+fn find_uber_turn_entrances(
+    constraints: PathConstraints,
+    uber_turns: &[UberTurnV2],
+    map: &Map,
+) -> MultiMap<DirectedRoadID, usize> {
+    let mut uber_turn_entrances = MultiMap::new();
+    for (idx, ut) in uber_turns.iter().enumerate() {
+        // Make sure this uber-turn only contains roads that can be used by this vehicle.
+        // TODO Need to test editing lanes inside an IntersectionCluster very carefully. See Mercer
+        // and Dexter.
+        if ut
+            .path
+            .iter()
+            .all(|mvmnt| !mvmnt.to.lanes(constraints, map).is_empty())
+        {
+            uber_turn_entrances.insert(ut.entry(), idx);
+        }
+    }
+    uber_turn_entrances
+}
+
+/// The time to cross one road and then a movement at the maximum allowed speed, ignoring any
+/// congestion or penalties.
+pub(crate) fn free_flow_time(
     dr: DirectedRoadID,
     mvmnt: MovementID,
     constraints: PathConstraints,
-    params: &RoutingParams,
     map: &Map,
-) -> Duration {
+) -> (Duration, Duration) {
     let movement = &map.get_i(mvmnt.parent).movements[&mvmnt];
     let max_speed = match constraints {
         PathConstraints::Car | PathConstraints::Bus | PathConstraints::Train => None,
@@ -281,6 +473,81 @@ pub fn vehicle_cost(
         / Traversable::max_speed_along_road(dr, max_speed, constraints, map).0;
     let t2 = movement.geom.length()
         / Traversable::max_speed_along_movement(mvmnt, max_speed, constraints, map);
+    (t1, t2)
+}
+
+/// This returns the pathfinding cost of crossing one road and turn. This is also expressed in
+/// units of time. It factors in the ideal time to cross the space, along with penalties for
+/// entering an access-restricted zone, taking an unprotected turn, and so on.
+pub fn vehicle_cost(
+    dr: DirectedRoadID,
+    mvmnt: MovementID,
+    constraints: PathConstraints,
+    params: &RoutingParams,
+    map: &Map,
+) -> Duration {
+    let (mut t1, mut t2) = free_flow_time(dr, mvmnt, constraints, map);
+
+    // If we've measured congestion from a previous simulation, use that instead. Never go below
+    // the free-flow time, in case a measurement is based on very few vehicles.
+    let mut observed_road = false;
+    if constraints == PathConstraints::Car || constraints == PathConstraints::Bus {
+        if let Some(observed) = map.get_observed_travel_times() {
+            if let Some(dt) = observed.roads.get(&dr) {
+                t1 = t1.max(*dt);
+                observed_road = true;
+            }
+            if let Some(dt) = observed.movements.get(&mvmnt) {
+                t2 = t2.max(*dt);
+            }
+        }
+    }
+
+    cost_from_times(dr, mvmnt, constraints, params, t1, t2, observed_road, map)
+}
+
+/// Like `vehicle_cost`, but using the travel times expected when entering the road at a certain
+/// time. Also includes the zone cost.
+fn time_dependent_cost(
+    dr: DirectedRoadID,
+    mvmnt: MovementID,
+    constraints: PathConstraints,
+    params: &RoutingParams,
+    profiles: &TravelTimeProfiles,
+    time: Time,
+    map: &Map,
+) -> Duration {
+    let (mut t1, mut t2) = free_flow_time(dr, mvmnt, constraints, map);
+    let observed_road = if let Some(dt) = profiles.road(dr, time) {
+        t1 = t1.max(dt);
+        true
+    } else {
+        false
+    };
+    // The movement is reached after crossing the road
+    if let Some(dt) = profiles.movement(mvmnt, time + t1) {
+        t2 = t2.max(dt);
+    }
+
+    cost_from_times(dr, mvmnt, constraints, params, t1, t2, observed_road, map)
+        + zone_cost_at(mvmnt, constraints, time + t1, map)
+        + toll_cost(mvmnt, constraints, Some(time + t1), params, map)
+}
+
+/// Given the time to cross a road (t1) and a movement (t2), apply the penalties from the routing
+/// params.
+#[allow(clippy::too_many_arguments)]
+fn cost_from_times(
+    dr: DirectedRoadID,
+    mvmnt: MovementID,
+    constraints: PathConstraints,
+    params: &RoutingParams,
+    t1: Duration,
+    t2: Duration,
+    observed_road: bool,
+    map: &Map,
+) -> Duration {
+    let movement = &map.get_i(mvmnt.parent).movements[&mvmnt];
 
     let base = match constraints {
         PathConstraints::Car | PathConstraints::Train => t1 + t2,
@@ -316,15 +583,15 @@ pub fn vehicle_cost(
     if constraints == PathConstraints::Bike
         && (params.avoid_steep_incline_penalty - 1.0).abs() > f64::EPSILON
     {
-        let road = map.get_r(dr.id);
-        let percent_incline = if dr.dir == Direction::Fwd {
-            road.percent_incline
-        } else {
-            -road.percent_incline
-        };
-        if percent_incline >= 0.08 {
-            multiplier *= params.avoid_steep_incline_penalty;
-        }
+        // Only the uphill parts count, scaled by how steep they are
+        let uphill: f64 = map
+            .get_r(dr.id)
+            .grades_between(dr.dir, 0.0, 1.0)
+            .into_iter()
+            .map(|(pct, grade)| pct * grade.max(0.0))
+            .sum();
+        multiplier *=
+            (1.0 + (params.avoid_steep_incline_penalty - 1.0) * uphill / STEEP_INCLINE).max(0.0);
     }
 
     if constraints == PathConstraints::Bike && (params.avoid_high_stress - 1.0).abs() > f64::EPSILON
@@ -336,8 +603,9 @@ pub fn vehicle_cost(
     }
 
     let mut extra = Duration::ZERO;
-    // Penalize unprotected turns at a stop sign from smaller to larger roads.
-    if map.is_unprotected_turn(dr.id, mvmnt.to.id, movement.turn_type) {
+    // Penalize unprotected turns at a stop sign from smaller to larger roads. Observed times
+    // already include this delay.
+    if !observed_road && map.is_unprotected_turn(dr.id, mvmnt.to.id, movement.turn_type) {
         extra += params.unprotected_turn_penalty
     }
 
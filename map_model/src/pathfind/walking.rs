@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 
 use geom::{Distance, Duration};
 
-use crate::pathfind::engine::{CreateEngine, PathfindEngine};
+use crate::pathfind::engine::{hash_input_graph, CreateEngine, PathfindEngine};
 use crate::pathfind::node_map::{deserialize_nodemap, NodeMap};
 use crate::pathfind::vehicles::VehiclePathfinder;
 use crate::pathfind::zone_cost;
@@ -23,6 +23,8 @@ pub struct SidewalkPathfinder {
     nodes: NodeMap<WalkingNode>,
     use_transit: bool,
     engine: PathfindEngine,
+    /// Used to detect when edits don't affect this graph at all
+    graph_hash: u64,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize)]
@@ -35,6 +37,18 @@ pub enum WalkingNode {
     LeaveMap(IntersectionID),
 }
 
+/// One ride on public transit, as part of a longer trip.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitRide {
+    pub route: BusRouteID,
+    pub board: BusStopID,
+    /// If there's no stop, ride off the map.
+    pub alight: Option<BusStopID>,
+    /// Where to walk before boarding. This is the stop's sidewalk position or an entrance to its
+    /// station.
+    pub walk_to: Position,
+}
+
 impl WalkingNode {
     pub fn closest(pos: Position, map: &Map) -> WalkingNode {
         let lane = map.get_l(pos.lane());
@@ -60,6 +74,7 @@ impl SidewalkPathfinder {
             nodes: NodeMap::new(),
             use_transit: false,
             engine: PathfindEngine::Empty,
+            graph_hash: 0,
         }
     }
 
@@ -69,15 +84,7 @@ impl SidewalkPathfinder {
         engine: &CreateEngine,
     ) -> SidewalkPathfinder {
         let mut nodes = NodeMap::new();
-        for r in map.all_roads() {
-            // Regardless of whether the road has sidewalks/shoulders on one or both sides, add
-            // both. These could change later, and we want the node IDs to match up.
-            for dr in r.id.both_directions() {
-                for endpt in [true, false] {
-                    nodes.get_or_insert(WalkingNode::SidewalkEndpoint(dr, endpt));
-                }
-            }
-        }
+        add_road_nodes(&mut nodes, map);
         if use_transit.is_some() {
             // Add a node for each bus stop.
             for bs in map.all_bus_stops().keys() {
@@ -90,27 +97,55 @@ impl SidewalkPathfinder {
         }
 
         let input_graph = make_input_graph(&nodes, use_transit, map);
+        let graph_hash = hash_input_graph(&input_graph);
         let engine = engine.create(input_graph);
 
         SidewalkPathfinder {
             nodes,
             use_transit: use_transit.is_some(),
             engine,
+            graph_hash,
         }
     }
 
+    /// Returns false if the edits didn't change this graph at all. If `in_background` is set,
+    /// the new contraction hierarchy (if any) is prepared in a background thread.
+    /// `structure_changed` means roads may have been added or removed.
     pub fn apply_edits(
         &mut self,
         map: &Map,
         use_transit: Option<(&VehiclePathfinder, &VehiclePathfinder)>,
-    ) {
+        structure_changed: bool,
+        in_background: bool,
+    ) -> bool {
         if matches!(self.engine, PathfindEngine::Empty) {
-            return;
+            return false;
+        }
+        if structure_changed {
+            // New roads get nodes after all existing ones, so the node ordering can be reused.
+            add_road_nodes(&mut self.nodes, map);
         }
 
         let input_graph = make_input_graph(&self.nodes, use_transit, map);
-        let engine = self.engine.reuse_ordering().create(input_graph);
-        self.engine = engine;
+        let graph_hash = hash_input_graph(&input_graph);
+        if graph_hash == self.graph_hash {
+            if !in_background {
+                self.engine.finish_background_work();
+            }
+            return false;
+        }
+        let create = self.engine.reuse_ordering();
+        self.engine = if in_background {
+            create.create_in_background(input_graph)
+        } else {
+            create.create(input_graph)
+        };
+        self.graph_hash = graph_hash;
+        true
+    }
+
+    pub fn finish_background_work(&mut self) {
+        self.engine.finish_background_work();
     }
 
     pub fn pathfind(&self, req: PathRequest, map: &Map) -> Option<PathV2> {
@@ -130,14 +165,15 @@ impl SidewalkPathfinder {
         Some(PathV2::new(steps, req, cost, Vec::new()))
     }
 
-    /// Attempt the pathfinding and see if we should ride a bus. If so, says (stop1, optional stop
-    /// 2, route). If there's no stop 2, then ride the bus off the border.
+    /// Attempt the pathfinding and see if we should ride public transit. If so, returns each ride
+    /// in order, possibly with transfers between them. If the last ride has no stop to alight at,
+    /// then ride off the border.
     pub fn should_use_transit(
         &self,
         map: &Map,
         start: Position,
         end: Position,
-    ) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
+    ) -> Option<Vec<TransitRide>> {
         assert!(self.use_transit);
 
         let (_, raw_nodes) = self.engine.calculate_path(
@@ -156,71 +192,122 @@ impl SidewalkPathfinder {
             }
         }
 
-        let mut first_stop = None;
-        let mut last_stop = None;
-        let mut possible_routes: Vec<&BusRoute> = Vec::new();
-        for n in &nodes {
+        let mut rides = Vec::new();
+        // The ride in progress: (boarding stop, where to walk before boarding, the last stop
+        // reached, routes that serve all of the stops so far)
+        let mut current: Option<(BusStopID, Position, Option<BusStopID>, Vec<&BusRoute>)> = None;
+        for (idx, n) in nodes.iter().enumerate() {
             match n {
                 WalkingNode::RideBus(stop2) => {
-                    if let Some(stop1) = first_stop {
+                    if let Some((stop1, walk_to, last_stop, possible_routes)) = current.take() {
                         // Keep riding the same route?
                         // We need to do this check, because some transfers might be instantaneous
                         // at the same stop and involve no walking.
                         // Also need to make sure the stops are in the proper order. We might have
                         // a transfer, then try to hop on the first route again, but starting from
                         // a different point.
-                        let mut filtered = possible_routes.clone();
-                        filtered.retain(|r| {
-                            let idx1 = r.stops.iter().position(|s| *s == stop1).unwrap();
-                            let idx2 = r.stops.iter().position(|s| s == stop2);
-                            idx2.map(|idx2| idx1 < idx2).unwrap_or(false)
-                        });
-                        if filtered.is_empty() {
-                            // Aha, a transfer!
-                            return Some((
-                                first_stop.unwrap(),
-                                // TODO I thought this should be impossible, but huge_seattle hits
-                                // it. Workaround for now by just walking.
-                                Some(last_stop?),
-                                possible_routes[0].id,
-                            ));
+                        let filtered = routes_between(&possible_routes, stop1, *stop2);
+                        if !filtered.is_empty() {
+                            current = Some((stop1, walk_to, Some(*stop2), filtered));
+                            continue;
+                        }
+
+                        // Aha, a transfer!
+                        if let Some(last_stop) = last_stop {
+                            rides.push(TransitRide {
+                                route: possible_routes[0].id,
+                                board: stop1,
+                                alight: Some(last_stop),
+                                walk_to,
+                            });
+                            // Switch routes at the same stop?
+                            let next_routes = routes_between(
+                                &map.get_routes_serving_stop(last_stop),
+                                last_stop,
+                                *stop2,
+                            );
+                            if !next_routes.is_empty() {
+                                current = Some((
+                                    last_stop,
+                                    map.get_bs(last_stop).sidewalk_pos,
+                                    Some(*stop2),
+                                    next_routes,
+                                ));
+                                continue;
+                            }
                         }
-                        last_stop = Some(*stop2);
-                        possible_routes = filtered;
+                        // Otherwise we walked to another platform in the same station.
+                        current = Some((
+                            *stop2,
+                            map.get_bs(*stop2).sidewalk_pos,
+                            None,
+                            map.get_routes_serving_stop(*stop2),
+                        ));
                     } else {
-                        first_stop = Some(*stop2);
-                        possible_routes = map.get_routes_serving_stop(*stop2);
+                        let walk_to =
+                            if let (Some(ts), Some(WalkingNode::SidewalkEndpoint(dr, _))) = (
+                                map.get_station_for_stop(*stop2),
+                                idx.checked_sub(1).map(|i| nodes[i]),
+                            ) {
+                                // Which entrance did we use?
+                                ts.entrances
+                                    .iter()
+                                    .find(|pos| map.get_l(pos.lane()).get_directed_parent() == dr)
+                                    .cloned()
+                                    .unwrap_or(map.get_bs(*stop2).sidewalk_pos)
+                            } else {
+                                map.get_bs(*stop2).sidewalk_pos
+                            };
+                        let possible_routes = map.get_routes_serving_stop(*stop2);
                         assert!(!possible_routes.is_empty());
+                        current = Some((*stop2, walk_to, None, possible_routes));
                     }
                 }
                 WalkingNode::LeaveMap(i) => {
+                    let (stop1, walk_to, last_stop, possible_routes) = current.take()?;
                     // Make sure the route actually leaves via the correct border!
                     if let Some(r) = possible_routes.iter().find(|r| {
                         r.end_border
                             .map(|l| map.get_l(l).dst_i == *i)
                             .unwrap_or(false)
                     }) {
-                        return Some((first_stop.unwrap(), None, r.id));
+                        rides.push(TransitRide {
+                            route: r.id,
+                            board: stop1,
+                            alight: None,
+                            walk_to,
+                        });
+                    } else {
+                        // We can get close to the border, but should hop off at some stop.
+                        rides.push(TransitRide {
+                            route: possible_routes[0].id,
+                            board: stop1,
+                            alight: Some(last_stop.expect("impossible transit transfer")),
+                            walk_to,
+                        });
                     }
-                    // We can get close to the border, but should hop off at some stop.
-                    return Some((
-                        first_stop.unwrap(),
-                        Some(last_stop.expect("impossible transit transfer")),
-                        possible_routes[0].id,
-                    ));
+                    break;
                 }
                 WalkingNode::SidewalkEndpoint(_, _) => {
-                    if let Some(stop1) = first_stop {
-                        return Some((
-                            stop1,
-                            Some(last_stop.expect("impossible transit transfer")),
-                            possible_routes[0].id,
-                        ));
+                    if let Some((stop1, walk_to, last_stop, possible_routes)) = current.take() {
+                        // If we never rode anywhere, we just walked through a station.
+                        if let Some(last_stop) = last_stop {
+                            rides.push(TransitRide {
+                                route: possible_routes[0].id,
+                                board: stop1,
+                                alight: Some(last_stop),
+                                walk_to,
+                            });
+                        }
                     }
                 }
             }
         }
-        None
+        if rides.is_empty() {
+            None
+        } else {
+            Some(rides)
+        }
     }
 
     pub fn all_costs_from(&self, start: Position, map: &Map) -> HashMap<DirectedRoadID, Duration> {
@@ -249,6 +336,38 @@ impl SidewalkPathfinder {
     }
 }
 
+/// Routes that visit stop1 and later stop2
+fn routes_between<'a>(
+    routes: &[&'a BusRoute],
+    stop1: BusStopID,
+    stop2: BusStopID,
+) -> Vec<&'a BusRoute> {
+    routes
+        .iter()
+        .filter(|r| {
+            let idx1 = r.stops.iter().position(|s| *s == stop1);
+            let idx2 = r.stops.iter().position(|s| *s == stop2);
+            match (idx1, idx2) {
+                (Some(idx1), Some(idx2)) => idx1 < idx2,
+                _ => false,
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+fn add_road_nodes(nodes: &mut NodeMap<WalkingNode>, map: &Map) {
+    for r in map.all_roads_with_deleted() {
+        // Regardless of whether the road has sidewalks/shoulders on one or both sides, add
+        // both. These could change later, and we want the node IDs to match up.
+        for dr in r.id.both_directions() {
+            for endpt in [true, false] {
+                nodes.get_or_insert(WalkingNode::SidewalkEndpoint(dr, endpt));
+            }
+        }
+    }
+}
+
 fn make_input_graph(
     nodes: &NodeMap<WalkingNode>,
     use_transit: Option<(&VehiclePathfinder, &VehiclePathfinder)>,
@@ -317,18 +436,17 @@ fn transit_input_graph(
     train_graph: &VehiclePathfinder,
 ) {
     let max_speed = Some(crate::MAX_WALKING_SPEED);
-    // Connect bus stops with both sidewalk endpoints, using the appropriate distance.
-    for stop in map.all_bus_stops().values() {
-        let ride_bus = nodes.get(WalkingNode::RideBus(stop.id));
-        let lane = map.get_l(stop.sidewalk_pos.lane());
+    // Connect a stop with both endpoints of a sidewalk, using the appropriate distance.
+    let mut connect = |ride_bus, pos: Position, extra_cost: Duration| {
+        let lane = map.get_l(pos.lane());
         for (endpt, step) in [
             (false, PathStep::Lane(lane.id)),
             (true, PathStep::ContraflowLane(lane.id)),
         ] {
             let dist = if endpt {
-                lane.length() - stop.sidewalk_pos.dist_along()
+                lane.length() - pos.dist_along()
             } else {
-                stop.sidewalk_pos.dist_along()
+                pos.dist_along()
             };
             let cost = dist / step.max_speed_along(max_speed, PathConstraints::Pedestrian, map);
             // Add some extra penalty to using a bus stop. Otherwise a path might try to pass
@@ -338,8 +456,52 @@ fn transit_input_graph(
                 lane.get_directed_parent(),
                 endpt,
             ));
-            input_graph.add_edge(sidewalk, ride_bus, round(cost + penalty));
-            input_graph.add_edge(ride_bus, sidewalk, round(cost + penalty));
+            input_graph.add_edge(sidewalk, ride_bus, round(cost + extra_cost + penalty));
+            input_graph.add_edge(ride_bus, sidewalk, round(cost + extra_cost + penalty));
+        }
+    };
+    for stop in map.all_bus_stops().values() {
+        connect(
+            nodes.get(WalkingNode::RideBus(stop.id)),
+            stop.sidewalk_pos,
+            Duration::ZERO,
+        );
+    }
+    // Station entrances lead to every platform, and riders can walk between platforms without
+    // leaving the station.
+    for ts in map.all_transit_stations() {
+        for platform in &ts.platforms {
+            let ride_bus = nodes.get(WalkingNode::RideBus(*platform));
+            let platform_pt = map.get_bs(*platform).sidewalk_pos.pt(map);
+            for entrance in &ts.entrances {
+                if *entrance != map.get_bs(*platform).sidewalk_pos {
+                    connect(
+                        ride_bus,
+                        *entrance,
+                        entrance.pt(map).dist_to(platform_pt) / crate::MAX_WALKING_SPEED,
+                    );
+                }
+            }
+        }
+    }
+    for ts in map.all_transit_stations() {
+        for platform1 in &ts.platforms {
+            for platform2 in &ts.platforms {
+                if platform1 != platform2 {
+                    let dist = map
+                        .get_bs(*platform1)
+                        .sidewalk_pos
+                        .pt(map)
+                        .dist_to(map.get_bs(*platform2).sidewalk_pos.pt(map));
+                    // Changing platforms takes longer than just the walk
+                    let penalty = Duration::seconds(30.0);
+                    input_graph.add_edge(
+                        nodes.get(WalkingNode::RideBus(*platform1)),
+                        nodes.get(WalkingNode::RideBus(*platform2)),
+                        round(dist / crate::MAX_WALKING_SPEED + penalty),
+                    );
+                }
+            }
         }
     }
 
@@ -559,6 +559,9 @@ pub struct RawRoad {
     /// (via, to). For turn restrictions where 'via' is an entire road. Only BanTurns.
     pub complicated_turn_restrictions: Vec<(OriginalRoad, OriginalRoad)>,
     pub percent_incline: f64,
+    /// The grade of each piece of the road, from i1 to i2, as (length, percent incline). Empty if
+    /// elevation was only known at the intersections.
+    pub grades: Vec<(Distance, f64)>,
 }
 
 impl RawRoad {
@@ -635,6 +638,15 @@ impl RawRoad {
         PolyLine::unchecked_new(self.center_points.clone()).length()
     }
 
+    /// Like `grades`, but if there's no detailed profile, the whole road has `percent_incline`.
+    pub fn grade_profile(&self) -> Vec<(Distance, f64)> {
+        if self.grades.is_empty() {
+            vec![(self.length(), self.percent_incline)]
+        } else {
+            self.grades.clone()
+        }
+    }
+
     pub fn get_zorder(&self) -> isize {
         if let Some(layer) = self.osm_tags.get("layer") {
             match layer.parse::<f64>() {
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use geom::{Angle, Distance, PolyLine, Pt2D, Speed};
 
-use crate::{DirectedRoadID, Direction, LaneID, Map, MovementID, PathConstraints, TurnID};
+use crate::{DirectedRoadID, LaneID, Map, MovementID, PathConstraints, TurnID};
 
 /// Represents a specific point some distance along a lane.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -202,31 +202,66 @@ impl Traversable {
         constraints: PathConstraints,
         map: &Map,
     ) -> (Speed, f64) {
-        let road = map.get_r(dr.id);
-        let percent_incline = if dr.dir == Direction::Fwd {
-            road.percent_incline
-        } else {
-            -road.percent_incline
-        };
+        Traversable::max_speed_along_part_of_road(
+            dr,
+            0.0,
+            1.0,
+            max_speed_on_flat_ground,
+            constraints,
+            map,
+        )
+    }
 
-        let base = if constraints == PathConstraints::Bike {
-            // We assume every bike has a max_speed defined.
-            bike_speed_on_incline(max_speed_on_flat_ground.unwrap(), percent_incline)
+    /// Like `max_speed_along_road`, but only between two fractions of the road's length, measured
+    /// from src_i. The grade can vary along a road, so the speed is the average that takes the
+    /// right amount of time to cover the whole range, and the incline is the steepest uphill part.
+    pub(crate) fn max_speed_along_part_of_road(
+        dr: DirectedRoadID,
+        pct1: f64,
+        pct2: f64,
+        max_speed_on_flat_ground: Option<Speed>,
+        constraints: PathConstraints,
+        map: &Map,
+    ) -> (Speed, f64) {
+        let road = map.get_r(dr.id);
+        let grades = road.grades_between(dr.dir, pct1, pct2);
+        let percent_incline = grades
+            .iter()
+            .map(|(_, grade)| *grade)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let speed_on_incline: fn(Speed, f64) -> Speed = if constraints == PathConstraints::Bike {
+            bike_speed_on_incline
         } else if constraints == PathConstraints::Pedestrian {
-            // We assume every pedestrian has a max_speed defined.
-            walking_speed_on_incline(max_speed_on_flat_ground.unwrap(), percent_incline)
+            walking_speed_on_incline
         } else {
-            debug_assert!(max_speed_on_flat_ground.is_none());
-            // Incline doesn't affect cars, buses, or trains
-            road.speed_limit
+            // Incline doesn't affect cars, buses, or trains, but some vehicles can't reach the
+            // speed limit
+            let speed = if let Some(s) = max_speed_on_flat_ground {
+                road.speed_limit.min(s)
+            } else {
+                road.speed_limit
+            };
+            return (speed, percent_incline);
         };
-
-        let speed = if let Some(s) = max_speed_on_flat_ground {
-            base.min(s)
+        // We assume every bike and pedestrian has a max_speed defined.
+        let max_speed = max_speed_on_flat_ground.unwrap();
+
+        // Add up the time needed per meter on each piece
+        let total: f64 = grades.iter().map(|(pct, _)| *pct).sum();
+        let base = if total > 0.0 {
+            let seconds_per_meter: f64 = grades
+                .iter()
+                .map(|(pct, grade)| {
+                    (pct / total) / speed_on_incline(max_speed, *grade).inner_meters_per_second()
+                })
+                .sum();
+            Speed::meters_per_second(1.0 / seconds_per_meter)
         } else {
-            base
+            speed_on_incline(max_speed, grades[0].1)
         };
-        (speed, percent_incline)
+        // Going downhill can be faster than the speed on flat ground.
+        (base, percent_incline)
     }
 
     /// The single definitive place to determine how fast somebody could go along a single
@@ -270,9 +305,10 @@ fn bike_speed_on_incline(max_speed: Speed, percent_incline: f64) -> Speed {
     // https://github.com/valhalla/valhalla/blob/f899a940ccbd0bc986769197dec5bb9383014afb/src/sif/bicyclecost.cc#L139.
     // Valhalla is MIT licensed: https://github.com/valhalla/valhalla/blob/master/COPYING.
 
-    // TODO Could binary search or do something a bit faster here, but doesn't matter much
+    // Interpolate between the entries, so the speed changes smoothly with the grade. Anything
+    // steeper than the ends of the table is clamped.
     let pct = percent_incline * 100.0;
-    for (grade, factor) in vec![
+    let table = [
         (-10.0, 2.2),
         (-8.0, 2.0),
         (-6.5, 1.9),
@@ -288,14 +324,19 @@ fn bike_speed_on_incline(max_speed: Speed, percent_incline: f64) -> Speed {
         (10.0, 0.5),
         (11.5, 0.45),
         (13.0, 0.4),
-    ] {
-        if pct <= grade {
+        (15.0, 0.3),
+    ];
+    if pct <= table[0].0 {
+        return table[0].1 * max_speed;
+    }
+    for pair in table.windows(2) {
+        let ((grade1, factor1), (grade2, factor2)) = (pair[0], pair[1]);
+        if pct <= grade2 {
+            let factor = factor1 + (factor2 - factor1) * (pct - grade1) / (grade2 - grade1);
             return factor * max_speed;
         }
     }
-    // The last pair is a factor of 0.3 for grades of 15%, but we'll use it for anything steeper
-    // than 15%
-    0.3 * max_speed
+    table[table.len() - 1].1 * max_speed
 }
 
 fn walking_speed_on_incline(max_speed: Speed, percent_incline: f64) -> Speed {
@@ -335,6 +376,11 @@ mod tests {
             Speed::miles_per_hour(3.0),
             bike_speed_on_incline(base_speed, 0.15),
         );
+        // Halfway between the 3% and 5% entries
+        assert_approx_eq(
+            Speed::miles_per_hour(8.0),
+            bike_speed_on_incline(base_speed, 0.04),
+        );
     }
 
     #[test]
@@ -6,14 +6,16 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use abstutil::Counter;
-use geom::{Duration, Time};
+use geom::{Distance, Duration, Time};
 use map_model::{
-    BusRouteID, BusStopID, CompressedMovementID, IntersectionID, LaneID, Map, MovementID,
-    ParkingLotID, Path, PathRequest, RoadID, Traversable, TurnID,
+    BuildingID, BusRouteID, BusStopID, CompressedMovementID, DirectedRoadID, IntersectionID,
+    LaneID, LaneType, Map, MovementID, ObservedTravelTimes, OffstreetParking, ParkingLotID,
+    ParkingRegulationTarget, Path, PathRequest, RoadID, TravelTimeProfiles, Traversable, TurnID,
 };
 
 use crate::{
     AgentID, AgentType, AlertLocation, CarID, Event, ParkingSpot, TripID, TripMode, TripPhaseType,
+    VehicleType,
 };
 
 /// As a simulation runs, different pieces emit Events. The Analytics object listens to these,
@@ -39,6 +41,11 @@ pub struct Analytics {
 
     // TODO Reconsider this one
     pub bus_arrivals: Vec<(Time, CarID, BusRouteID, BusStopID)>,
+    /// For every bus arrival at a stop, how late it was compared to its schedule
+    pub bus_schedule_adherence: Vec<(Time, CarID, BusRouteID, BusStopID, Duration)>,
+    /// Every time a traffic signal changed its timing for a bus or train. A positive duration
+    /// held a green longer; a negative one ended a conflicting stage early.
+    pub transit_signal_priority: Vec<(Time, IntersectionID, CarID, Duration)>,
     /// For each passenger boarding, how long did they wait at the stop?
     pub passengers_boarding: BTreeMap<BusStopID, Vec<(Time, BusRouteID, Duration)>>,
     pub passengers_alighting: BTreeMap<BusStopID, Vec<(Time, BusRouteID)>>,
@@ -60,6 +67,67 @@ pub struct Analytics {
     /// Per parking lane or lot, when does a spot become filled (true) or free (false)
     pub parking_lane_changes: BTreeMap<LaneID, Vec<(Time, bool)>>,
     pub parking_lot_changes: BTreeMap<ParkingLotID, Vec<(Time, bool)>>,
+    /// Only for public garages
+    pub parking_garage_changes: BTreeMap<BuildingID, Vec<(Time, bool)>>,
+
+    /// For every parking search that ended: when, the trip, how long and how far the driver cruised
+    /// looking for a spot, and if they found one
+    pub parking_searches: Vec<(Time, TripID, Duration, Distance, bool)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    parking_search_started: BTreeMap<TripID, Time>,
+
+    /// For every car leaving a spot with a ParkingRegulation: when, where, how long it stayed,
+    /// what it paid, and if it stayed longer than the time limit. Cars still parked haven't paid
+    /// yet.
+    pub parking_sessions: Vec<(Time, ParkingRegulationTarget, Duration, f64, bool)>,
+    // When each car entered a regulated spot and which permit it holds, to measure the above.
+    // Cars seeded by the scenario emit CarReachedParkingSpot when the trips are spawned, so they're
+    // billed from the start of the simulation too.
+    #[serde(skip_serializing, skip_deserializing)]
+    parked_since: BTreeMap<CarID, (Time, ParkingRegulationTarget)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    parking_permits: BTreeMap<CarID, String>,
+
+    /// For every toll paid: when, the car, its trip, the road, and how many dollars
+    pub toll_payments: Vec<(Time, CarID, Option<TripID>, RoadID, f64)>,
+    /// For every driving trip that picked a different route to avoid tolls: when it started, the
+    /// trip, and how many dollars it saved
+    pub toll_diversions: Vec<(Time, TripID, f64)>,
+
+    /// For every ride-hail pickup: when, the trip, the vehicle, how long the passenger waited since
+    /// requesting, and how far the vehicle drove empty to get there
+    pub ride_hail_pickups: Vec<(Time, TripID, CarID, Duration, Distance)>,
+    /// For every ride-hail dropoff: when, the trip, the vehicle, and how far the passenger rode
+    pub ride_hail_dropoffs: Vec<(Time, TripID, CarID, Distance)>,
+    /// When a ride-hail vehicle is dispatched (true) or drops somebody off (false)
+    pub ride_hail_busy_changes: Vec<(Time, bool)>,
+
+    /// For every delivery stop: when, the vehicle, the building, how long it stayed, and the lane
+    /// it double-parked on (or None if it used a loading zone)
+    pub delivery_stops: Vec<(Time, CarID, BuildingID, Duration, Option<LaneID>)>,
+    /// For every finished delivery tour: when, the vehicle, and how long the tour took
+    pub delivery_tours: Vec<(Time, CarID, Duration)>,
+
+    /// For every shared bike or scooter pickup: when, the trip, the vehicle, and the building
+    /// where it was docked or left
+    pub micromobility_pickups: Vec<(Time, TripID, CarID, BuildingID)>,
+    /// For every shared bike or scooter dropoff: when, the trip, the vehicle, and the building
+    /// where it was left
+    pub micromobility_dropoffs: Vec<(Time, TripID, CarID, BuildingID)>,
+    /// When somebody wanted a shared vehicle and none was available near the building
+    pub micromobility_unavailable: Vec<(Time, TripID, BuildingID)>,
+    /// When shared vehicles were rebalanced, and how many moved
+    pub micromobility_rebalanced: Vec<(Time, usize)>,
+
+    /// Only for cars. How long does it take to cross each road (including waiting at the end) and
+    /// movement? Not serialized, so prebaked results and savestates don't carry these.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub road_travel_times: TravelTimeStats<DirectedRoadID>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub movement_travel_times: TravelTimeStats<MovementID>,
+    // Where and when each car most recently entered a lane or turn, to measure the above
+    #[serde(skip_serializing, skip_deserializing)]
+    cars_entered: BTreeMap<CarID, (Traversable, Time)>,
 
     pub(crate) alerts: Vec<(Time, AlertLocation, String)>,
 
@@ -77,6 +145,8 @@ pub enum Problem {
     ArterialIntersectionCrossing(TurnID),
     /// Another vehicle wanted to over-take this cyclist somewhere on this lane or turn.
     OvertakeDesired(Traversable),
+    /// A vehicle was stuck behind a delivery vehicle double-parked on this lane.
+    BlockedByDoubleParking(LaneID),
 }
 
 impl Analytics {
@@ -87,6 +157,8 @@ impl Analytics {
             traffic_signal_thruput: TimeSeriesCount::new(),
             demand: BTreeMap::new(),
             bus_arrivals: Vec::new(),
+            bus_schedule_adherence: Vec::new(),
+            transit_signal_priority: Vec::new(),
             passengers_boarding: BTreeMap::new(),
             passengers_alighting: BTreeMap::new(),
             started_trips: BTreeMap::new(),
@@ -96,6 +168,26 @@ impl Analytics {
             intersection_delays: BTreeMap::new(),
             parking_lane_changes: BTreeMap::new(),
             parking_lot_changes: BTreeMap::new(),
+            parking_garage_changes: BTreeMap::new(),
+            parking_searches: Vec::new(),
+            parking_search_started: BTreeMap::new(),
+            parking_sessions: Vec::new(),
+            parked_since: BTreeMap::new(),
+            parking_permits: BTreeMap::new(),
+            toll_payments: Vec::new(),
+            toll_diversions: Vec::new(),
+            ride_hail_pickups: Vec::new(),
+            ride_hail_dropoffs: Vec::new(),
+            ride_hail_busy_changes: Vec::new(),
+            delivery_stops: Vec::new(),
+            delivery_tours: Vec::new(),
+            micromobility_pickups: Vec::new(),
+            micromobility_dropoffs: Vec::new(),
+            micromobility_unavailable: Vec::new(),
+            micromobility_rebalanced: Vec::new(),
+            road_travel_times: TravelTimeStats::new(),
+            movement_travel_times: TravelTimeStats::new(),
+            cars_entered: BTreeMap::new(),
             alerts: Vec::new(),
             record_anything,
         }
@@ -159,6 +251,13 @@ impl Analytics {
         if let Event::BusArrivedAtStop(bus, route, stop) = ev {
             self.bus_arrivals.push((time, bus, route, stop));
         }
+        if let Event::BusScheduleAdherence(bus, route, stop, late) = ev {
+            self.bus_schedule_adherence
+                .push((time, bus, route, stop, late));
+        }
+        if let Event::TransitSignalPriority(i, bus, dt) = ev {
+            self.transit_signal_priority.push((time, i, bus, dt));
+        }
 
         // Passengers boarding/alighting
         if let Event::PassengerBoardsTransit(_, _, route, stop, waiting) = ev {
@@ -231,6 +330,13 @@ impl Analytics {
                     .entry(pl)
                     .or_insert_with(Vec::new)
                     .push((time, true));
+            } else if let ParkingSpot::Offstreet(b, _) = spot {
+                if let OffstreetParking::PublicGarage(_, _) = map.get_b(b).parking {
+                    self.parking_garage_changes
+                        .entry(b)
+                        .or_insert_with(Vec::new)
+                        .push((time, true));
+                }
             }
         }
         if let Event::CarLeftParkingSpot(_, spot) = ev {
@@ -244,9 +350,143 @@ impl Analytics {
                     .entry(pl)
                     .or_insert_with(Vec::new)
                     .push((time, false));
+            } else if let ParkingSpot::Offstreet(b, _) = spot {
+                if let OffstreetParking::PublicGarage(_, _) = map.get_b(b).parking {
+                    self.parking_garage_changes
+                        .entry(b)
+                        .or_insert_with(Vec::new)
+                        .push((time, false));
+                }
             }
         }
 
+        // Parking search
+        match ev {
+            Event::ParkingSearchStarted(trip, _) => {
+                self.parking_search_started.insert(trip, time);
+            }
+            Event::ParkingSearchEnded(trip, _, spot, dist) => {
+                if let Some(started) = self.parking_search_started.remove(&trip) {
+                    self.parking_searches
+                        .push((time, trip, time - started, dist, spot.is_some()));
+                }
+            }
+            _ => {}
+        }
+
+        // Ride-hail
+        match ev {
+            Event::RideHailDispatched(_, _) => {
+                self.ride_hail_busy_changes.push((time, true));
+            }
+            Event::RideHailPickup(trip, car, wait, deadhead) => {
+                self.ride_hail_pickups
+                    .push((time, trip, car, wait, deadhead));
+            }
+            Event::RideHailDropoff(trip, car, dist) => {
+                self.ride_hail_dropoffs.push((time, trip, car, dist));
+                self.ride_hail_busy_changes.push((time, false));
+            }
+            Event::RideHailAbandoned(_, _) => {
+                self.ride_hail_busy_changes.push((time, false));
+            }
+            _ => {}
+        }
+
+        // Freight
+        match ev {
+            Event::DeliveryStop(car, b, dwell, double_parked) => {
+                self.delivery_stops
+                    .push((time, car, b, dwell, double_parked));
+            }
+            Event::DeliveryTourFinished(car, duration) => {
+                self.delivery_tours.push((time, car, duration));
+            }
+            Event::MicromobilityPickup(trip, car, b) => {
+                self.micromobility_pickups.push((time, trip, car, b));
+            }
+            Event::MicromobilityDropoff(trip, car, b) => {
+                self.micromobility_dropoffs.push((time, trip, car, b));
+            }
+            Event::MicromobilityUnavailable(trip, b) => {
+                self.micromobility_unavailable.push((time, trip, b));
+            }
+            Event::MicromobilityRebalanced(count) => {
+                self.micromobility_rebalanced.push((time, count));
+            }
+            _ => {}
+        }
+
+        // Parking regulations
+        match ev {
+            Event::ParkingPermitGranted(car, ref zone) => {
+                self.parking_permits.insert(car, zone.clone());
+            }
+            Event::CarReachedParkingSpot(car, spot) => {
+                let target = spot.regulation_target();
+                if map.get_parking_regulation(target).is_some() {
+                    self.parked_since.insert(car, (time, target));
+                }
+            }
+            Event::CarLeftParkingSpot(car, _) => {
+                if let Some((since, target)) = self.parked_since.remove(&car) {
+                    // Use the regulation in effect when the car leaves
+                    if let Some(reg) = map.get_parking_regulation(target) {
+                        let stay = time - since;
+                        let permit = self.parking_permits.get(&car);
+                        self.parking_sessions.push((
+                            time,
+                            target,
+                            stay,
+                            reg.cost(stay, permit),
+                            reg.is_overstay(stay, permit),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Tolls
+        match ev {
+            Event::TollPaid(car, trip, r, price) => {
+                self.toll_payments.push((time, car, trip, r, price));
+            }
+            Event::TripDivertedByToll(trip, saved) => {
+                self.toll_diversions.push((time, trip, saved));
+            }
+            _ => {}
+        }
+
+        // Travel times
+        if let Event::AgentEntersTraversable(AgentID::Car(car), _, to, _) = ev {
+            if matches!(car.vehicle_type, VehicleType::Car | VehicleType::RideHail) {
+                if let Some((from, entered)) = self.cars_entered.insert(car, (to, time)) {
+                    // Only measure when the car continues directly from one to the other
+                    match (from, to) {
+                        (Traversable::Lane(l), Traversable::Turn(t)) if t.src == l => {
+                            self.road_travel_times.record(
+                                entered,
+                                map.get_l(l).get_directed_parent(),
+                                time - entered,
+                            );
+                        }
+                        (Traversable::Turn(t), Traversable::Lane(l)) if t.dst == l => {
+                            self.movement_travel_times.record(
+                                entered,
+                                t.to_movement(map),
+                                time - entered,
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if let Event::VehicleRemoved(car) = ev {
+            self.cars_entered.remove(&car);
+        }
+
         // Safety metrics
         if let Event::AgentEntersTraversable(a, Some(trip), Traversable::Turn(t), _) = ev {
             if a.to_type() == AgentType::Bike && map.get_i(t.parent).roads.len() > 4 {
@@ -309,6 +549,234 @@ impl Analytics {
         }
     }
 
+    /// Per trip, the total time and distance spent cruising for parking.
+    pub fn parking_cruising_per_trip(&self) -> BTreeMap<TripID, (Duration, Distance)> {
+        let mut results: BTreeMap<TripID, (Duration, Distance)> = BTreeMap::new();
+        for (_, trip, dt, dist, _) in &self.parking_searches {
+            let entry = results
+                .entry(*trip)
+                .or_insert((Duration::ZERO, Distance::ZERO));
+            entry.0 += *dt;
+            entry.1 += *dist;
+        }
+        results
+    }
+
+    /// The total time and distance all drivers spent cruising for parking, and how many searches
+    /// ended without finding a spot.
+    pub fn total_parking_cruising(&self) -> (Duration, Distance, usize) {
+        let mut total_time = Duration::ZERO;
+        let mut total_dist = Distance::ZERO;
+        let mut failed = 0;
+        for (_, _, dt, dist, found) in &self.parking_searches {
+            total_time += *dt;
+            total_dist += *dist;
+            if !found {
+                failed += 1;
+            }
+        }
+        (total_time, total_dist, failed)
+    }
+
+    /// Per regulated road, lot, or garage, the total amount paid for parking.
+    pub fn parking_revenue(&self) -> BTreeMap<ParkingRegulationTarget, f64> {
+        let mut results = BTreeMap::new();
+        for (_, target, _, cost, _) in &self.parking_sessions {
+            *results.entry(*target).or_insert(0.0) += *cost;
+        }
+        results
+    }
+
+    /// The total amount paid for parking, how many cars paid anything, and how many stayed longer
+    /// than the time limit.
+    pub fn total_parking_revenue(&self) -> (f64, usize, usize) {
+        let mut total = 0.0;
+        let mut paid = 0;
+        let mut overstays = 0;
+        for (_, _, _, cost, overstay) in &self.parking_sessions {
+            total += *cost;
+            if *cost > 0.0 {
+                paid += 1;
+            }
+            if *overstay {
+                overstays += 1;
+            }
+        }
+        (total, paid, overstays)
+    }
+
+    /// Per road, the total amount paid in tolls.
+    pub fn toll_revenue(&self) -> BTreeMap<RoadID, f64> {
+        let mut results = BTreeMap::new();
+        for (_, _, _, r, price) in &self.toll_payments {
+            *results.entry(*r).or_insert(0.0) += *price;
+        }
+        results
+    }
+
+    /// The total amount paid in tolls, how many distinct cars paid anything, and how many trips
+    /// took a different route to avoid tolls.
+    pub fn total_toll_revenue(&self) -> (f64, usize, usize) {
+        let mut total = 0.0;
+        let mut cars = BTreeSet::new();
+        for (_, car, _, _, price) in &self.toll_payments {
+            total += *price;
+            cars.insert(*car);
+        }
+        (total, cars.len(), self.toll_diversions.len())
+    }
+
+    /// How long somebody waited for their ride-hail vehicle to arrive, if it has yet.
+    pub fn ride_hail_wait_time(&self, trip: TripID) -> Option<Duration> {
+        self.ride_hail_pickups
+            .iter()
+            .find(|(_, t, _, _, _)| *t == trip)
+            .map(|(_, _, _, wait, _)| *wait)
+    }
+
+    /// Per ride-hail vehicle, the number of rides completed, the distance driven empty, and the
+    /// distance driven carrying somebody.
+    pub fn ride_hail_vehicle_summary(&self) -> BTreeMap<CarID, (usize, Distance, Distance)> {
+        let mut results: BTreeMap<CarID, (usize, Distance, Distance)> = BTreeMap::new();
+        for (_, _, car, _, deadhead) in &self.ride_hail_pickups {
+            results
+                .entry(*car)
+                .or_insert((0, Distance::ZERO, Distance::ZERO))
+                .1 += *deadhead;
+        }
+        for (_, _, car, dist) in &self.ride_hail_dropoffs {
+            let entry = results
+                .entry(*car)
+                .or_insert((0, Distance::ZERO, Distance::ZERO));
+            entry.0 += 1;
+            entry.2 += *dist;
+        }
+        results
+    }
+
+    /// The number of ride-hail vehicles busy with a request over time
+    pub fn ride_hail_utilization(&self, now: Time) -> Vec<(Time, usize)> {
+        let mut pts = Vec::new();
+        let mut cnt: usize = 0;
+        let mut last_t = Time::START_OF_DAY;
+        for (t, busy) in &self.ride_hail_busy_changes {
+            if *t > now {
+                break;
+            }
+            if *t != last_t {
+                // Step functions. Don't interpolate.
+                pts.push((last_t, cnt));
+            }
+            last_t = *t;
+            if *busy {
+                cnt += 1;
+            } else {
+                cnt = cnt.saturating_sub(1);
+            }
+        }
+        pts.push((last_t, cnt));
+        if last_t != now {
+            pts.push((now, cnt));
+        }
+        pts
+    }
+
+    /// Per route, the number of stop arrivals, how late buses were on average, and the fraction of
+    /// arrivals no more than `on_time` late.
+    pub fn bus_schedule_adherence_per_route(
+        &self,
+        on_time: Duration,
+    ) -> BTreeMap<BusRouteID, (usize, Duration, f64)> {
+        let mut per_route: BTreeMap<BusRouteID, Vec<Duration>> = BTreeMap::new();
+        for (_, _, route, _, late) in &self.bus_schedule_adherence {
+            per_route.entry(*route).or_insert_with(Vec::new).push(*late);
+        }
+        per_route
+            .into_iter()
+            .map(|(route, delays)| {
+                let n = delays.len();
+                let total: Duration = delays.iter().fold(Duration::ZERO, |sum, x| sum + *x);
+                let num_on_time = delays.iter().filter(|x| **x <= on_time).count();
+                (
+                    route,
+                    (n, total / (n as f64), num_on_time as f64 / n as f64),
+                )
+            })
+            .collect()
+    }
+
+    /// Per intersection, how many times the signal held a green for transit and how many times it
+    /// ended a stage early.
+    pub fn transit_signal_priority_counts(&self) -> BTreeMap<IntersectionID, (usize, usize)> {
+        let mut results: BTreeMap<IntersectionID, (usize, usize)> = BTreeMap::new();
+        for (_, i, _, dt) in &self.transit_signal_priority {
+            let entry = results.entry(*i).or_insert((0, 0));
+            if *dt >= Duration::ZERO {
+                entry.0 += 1;
+            } else {
+                entry.1 += 1;
+            }
+        }
+        results
+    }
+
+    /// Returns the number of delivery stops made in a loading zone and double-parked.
+    pub fn delivery_stop_counts(&self) -> (usize, usize) {
+        let double_parked = self
+            .delivery_stops
+            .iter()
+            .filter(|(_, _, _, _, lane)| lane.is_some())
+            .count();
+        (self.delivery_stops.len() - double_parked, double_parked)
+    }
+
+    /// Per lane, how many times a delivery vehicle double-parked there and the total time the lane
+    /// was blocked.
+    pub fn double_parking_per_lane(&self) -> BTreeMap<LaneID, (usize, Duration)> {
+        let mut results: BTreeMap<LaneID, (usize, Duration)> = BTreeMap::new();
+        for (_, _, _, dwell, lane) in &self.delivery_stops {
+            if let Some(l) = lane {
+                let entry = results.entry(*l).or_insert((0, Duration::ZERO));
+                entry.0 += 1;
+                entry.1 += *dwell;
+            }
+        }
+        results
+    }
+
+    /// Per building, how many times a shared vehicle was picked up there, dropped off there, and
+    /// how many people found nothing available nearby.
+    pub fn micromobility_usage_per_building(&self) -> BTreeMap<BuildingID, (usize, usize, usize)> {
+        let mut results: BTreeMap<BuildingID, (usize, usize, usize)> = BTreeMap::new();
+        for (_, _, _, b) in &self.micromobility_pickups {
+            results.entry(*b).or_insert((0, 0, 0)).0 += 1;
+        }
+        for (_, _, _, b) in &self.micromobility_dropoffs {
+            results.entry(*b).or_insert((0, 0, 0)).1 += 1;
+        }
+        for (_, _, b) in &self.micromobility_unavailable {
+            results.entry(*b).or_insert((0, 0, 0)).2 += 1;
+        }
+        results
+    }
+
+    /// Summarize how long cars took to cross every road and movement over the entire simulation.
+    pub fn observed_travel_times(&self) -> ObservedTravelTimes {
+        ObservedTravelTimes {
+            roads: self.road_travel_times.overall_means(),
+            movements: self.movement_travel_times.overall_means(),
+        }
+    }
+
+    /// Summarize how long cars took to cross every road and movement, broken down by time of
+    /// day.
+    pub fn travel_time_profiles(&self) -> TravelTimeProfiles {
+        let mut profiles = TravelTimeProfiles::new(TravelTimeStats::<DirectedRoadID>::WINDOW_SIZE);
+        profiles.roads = self.road_travel_times.window_means();
+        profiles.movements = self.movement_travel_times.window_means();
+        profiles
+    }
+
     // TODO If these ever need to be speeded up, just cache the histogram and index in the events
     // list.
 
@@ -473,6 +941,64 @@ impl Analytics {
         }
     }
 
+    /// Returns the filled spots over time, for all parking covered by one regulation. Must be
+    /// called with the map used during the simulation.
+    pub fn parking_occupancy(
+        &self,
+        now: Time,
+        target: ParkingRegulationTarget,
+        map: &Map,
+    ) -> Vec<(Time, usize)> {
+        let mut changes: Vec<(Time, bool)> = Vec::new();
+        match target {
+            ParkingRegulationTarget::Road(r) => {
+                for l in &map.get_r(r).lanes {
+                    if l.lane_type == LaneType::Parking {
+                        if let Some(list) = self.parking_lane_changes.get(&l.id) {
+                            changes.extend(list.iter().cloned());
+                        }
+                    }
+                }
+                // Stable, so each lane's changes stay in order
+                changes.sort_by_key(|(t, _)| *t);
+            }
+            ParkingRegulationTarget::Lot(pl) => {
+                if let Some(list) = self.parking_lot_changes.get(&pl) {
+                    changes = list.clone();
+                }
+            }
+            ParkingRegulationTarget::Garage(b) => {
+                if let Some(list) = self.parking_garage_changes.get(&b) {
+                    changes = list.clone();
+                }
+            }
+        }
+
+        let mut pts = Vec::new();
+        let mut cnt = 0;
+        let mut last_t = Time::START_OF_DAY;
+        for (t, filled) in changes {
+            if t > now {
+                break;
+            }
+            if t != last_t {
+                // Step functions. Don't interpolate.
+                pts.push((last_t, cnt));
+            }
+            last_t = t;
+            if filled {
+                cnt += 1;
+            } else {
+                cnt -= 1;
+            }
+        }
+        pts.push((last_t, cnt));
+        if last_t != now {
+            pts.push((now, cnt));
+        }
+        pts
+    }
+
     fn parking_spot_availability(
         now: Time,
         changes: &[(Time, bool)],
@@ -661,6 +1187,62 @@ impl<X: Ord + Clone> TimeSeriesCount<X> {
     }
 }
 
+/// Sums up how long agents take to cross something, grouped into 15 minute windows by when they
+/// started crossing.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TravelTimeStats<X: Ord + Clone> {
+    /// (Road or movement, window index) -> (total seconds, number of agents)
+    pub totals: BTreeMap<(X, usize), (f64, usize)>,
+}
+
+impl<X: Ord + Clone> Default for TravelTimeStats<X> {
+    fn default() -> TravelTimeStats<X> {
+        TravelTimeStats::new()
+    }
+}
+
+impl<X: Ord + Clone> TravelTimeStats<X> {
+    pub const WINDOW_SIZE: Duration = Duration::const_seconds(900.0);
+
+    fn new() -> TravelTimeStats<X> {
+        TravelTimeStats {
+            totals: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, started: Time, id: X, dt: Duration) {
+        let window = ((started - Time::START_OF_DAY) / Self::WINDOW_SIZE) as usize;
+        let entry = self.totals.entry((id, window)).or_insert((0.0, 0));
+        entry.0 += dt.inner_seconds();
+        entry.1 += 1;
+    }
+
+    /// The mean time per window
+    pub fn window_means(&self) -> BTreeMap<X, BTreeMap<usize, Duration>> {
+        let mut results: BTreeMap<X, BTreeMap<usize, Duration>> = BTreeMap::new();
+        for ((id, window), (total, count)) in &self.totals {
+            results
+                .entry(id.clone())
+                .or_insert_with(BTreeMap::new)
+                .insert(*window, Duration::seconds(total / (*count as f64)));
+        }
+        results
+    }
+
+    /// The mean time over the entire simulation
+    pub fn overall_means(&self) -> BTreeMap<X, Duration> {
+        let mut sums: BTreeMap<X, (f64, usize)> = BTreeMap::new();
+        for ((id, _), (total, count)) in &self.totals {
+            let entry = sums.entry(id.clone()).or_insert((0.0, 0));
+            entry.0 += total;
+            entry.1 += count;
+        }
+        sums.into_iter()
+            .map(|(id, (total, count))| (id, Duration::seconds(total / (count as f64))))
+            .collect()
+    }
+}
+
 /// A sliding window, used to count something over time
 pub struct SlidingWindow {
     times: VecDeque<Time>,
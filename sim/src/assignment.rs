@@ -0,0 +1,280 @@
+//! Routes are calculated once when a trip starts, using free-flow costs, so congestion never
+//! influences route choice. This module runs an iterative dynamic traffic assignment, similar to
+//! MATSim's plan/score/replan loop: simulate a day, measure how long it takes to cross each road
+//! and movement, let some drivers pick a new route using those times while everyone else keeps
+//! their previous route, and repeat until route choice stabilizes.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::{Duration, Time};
+use map_model::{
+    DirectedRoadID, Map, MovementID, ObservedTravelTimes, Path, PathStep, PathStepV2, PathV2,
+    RoutingParams, TravelTimeProfiles,
+};
+
+use crate::{fork_rng, AlertHandler, Analytics, Scenario, Sim, SimFlags, TripID};
+
+/// Configures the assignment loop.
+pub struct TrafficAssignment {
+    /// `load` must point to a scenario. The same RNG seed is used for every iteration, so only
+    /// route choice changes between them.
+    pub flags: SimFlags,
+    pub max_iterations: usize,
+    /// After each iteration, this fraction of driving trips, picked randomly, switches to the best
+    /// route given the latest observed travel times. Everybody else keeps the route they took
+    /// last time. Keeping this small damps oscillation between routes.
+    pub replan_fraction: f64,
+    /// Stop early once the relative gap falls below this.
+    pub gap_threshold: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssignmentIteration {
+    pub iteration: usize,
+    /// How much longer the routes drivers took are than the best routes, both measured using the
+    /// travel times observed during the iteration, relative to the best routes. 0 means nobody
+    /// could've done better by switching routes. None if nobody drove.
+    pub relative_gap: Option<f64>,
+    pub finished_trips: usize,
+    pub cancelled_trips: usize,
+    /// Use f64 seconds, since a serialized Duration has a low cap.
+    pub total_trip_time_seconds: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssignmentResults {
+    pub scenario_name: String,
+    pub iterations: Vec<AssignmentIteration>,
+    pub converged: bool,
+    /// The travel times observed during the last iteration. Apply with
+    /// `Map::set_observed_travel_times`.
+    pub travel_times: ObservedTravelTimes,
+    /// Travel times by time of day from the last iteration. Apply with
+    /// `Map::set_travel_time_profiles`, or pass to `SimFlags` as `--travel_time_profiles`.
+    pub travel_time_profiles: Option<TravelTimeProfiles>,
+}
+
+impl TrafficAssignment {
+    pub fn run(&self, timer: &mut Timer) -> Result<AssignmentResults> {
+        if !self.flags.load.contains("/scenarios/") {
+            bail!(
+                "Traffic assignment must be run on a scenario, not {}",
+                self.flags.load
+            );
+        }
+
+        let mut scenario: Scenario = abstio::read_object(self.flags.load.clone(), timer)?;
+        let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
+        for m in &self.flags.modifiers {
+            scenario = m.apply(&map, scenario);
+        }
+        self.assign(&mut map, &scenario, timer)
+    }
+
+    /// Runs the assignment on a scenario that's already loaded, ignoring `flags.load` and
+    /// `flags.modifiers`. Afterwards, the map routes using the last observed travel times.
+    pub fn assign(
+        &self,
+        map: &mut Map,
+        scenario: &Scenario,
+        timer: &mut Timer,
+    ) -> Result<AssignmentResults> {
+        if !(self.replan_fraction > 0.0 && self.replan_fraction <= 1.0) {
+            bail!(
+                "replan_fraction must be in (0, 1], not {}",
+                self.replan_fraction
+            );
+        }
+
+        let free_flow = ObservedTravelTimes::free_flow(map);
+        let mut replan_rng = fork_rng(&mut self.flags.make_rng());
+        let mut previous: BTreeMap<TripID, Path> = BTreeMap::new();
+        let mut iterations = Vec::new();
+        let mut converged = false;
+        let mut observed = ObservedTravelTimes::new();
+        let mut travel_time_profiles = None;
+        for iteration in 0..self.max_iterations {
+            timer.start(format!("traffic assignment iteration {}", iteration));
+            // Every trip starts on the route it took last time, except for the ones picked to
+            // replan. The first time through, everybody routes from scratch.
+            let fixed: BTreeMap<TripID, Path> = std::mem::take(&mut previous)
+                .into_iter()
+                .filter(|_| !replan_rng.gen_bool(self.replan_fraction))
+                .collect();
+            let (analytics, chosen) = self.run_day(map, scenario, fixed, timer);
+            observed = analytics.observed_travel_times();
+            travel_time_profiles = Some(analytics.travel_time_profiles());
+
+            let measure = TravelTimeMeasure {
+                free_flow: &free_flow,
+                observed: &observed,
+            };
+            let result =
+                AssignmentIteration::new(iteration, &analytics, &chosen, &measure, map, timer);
+            info!(
+                "Iteration {}: relative gap {:?}, {} trips finished, {} cancelled",
+                iteration,
+                result.relative_gap,
+                prettyprint_usize(result.finished_trips),
+                prettyprint_usize(result.cancelled_trips)
+            );
+            let gap = result.relative_gap;
+            iterations.push(result);
+            previous = chosen;
+
+            // Whoever replans next time uses what was just observed
+            map.set_observed_travel_times(Some(observed.clone()), timer);
+            timer.stop(format!("traffic assignment iteration {}", iteration));
+
+            if gap.map(|x| x < self.gap_threshold).unwrap_or(false) {
+                converged = true;
+                break;
+            }
+        }
+
+        Ok(AssignmentResults {
+            scenario_name: scenario.scenario_name.clone(),
+            iterations,
+            converged,
+            travel_times: observed,
+            travel_time_profiles,
+        })
+    }
+
+    /// Simulates the whole day, with some driving trips fixed to a route. Returns the route every
+    /// car trip took.
+    fn run_day(
+        &self,
+        map: &Map,
+        scenario: &Scenario,
+        fixed: BTreeMap<TripID, Path>,
+        timer: &mut Timer,
+    ) -> (Analytics, BTreeMap<TripID, Path>) {
+        let mut opts = self.flags.opts.clone();
+        opts.alerts = AlertHandler::Silence;
+        let mut sim = Sim::new(map, opts);
+        sim.set_fixed_routes(fixed);
+        let mut rng = self.flags.make_rng();
+        scenario.instantiate(&mut sim, map, &mut rng, timer);
+        sim.timed_step(
+            map,
+            sim.get_end_of_day() - Time::START_OF_DAY + Duration::hours(3),
+            &mut None,
+            timer,
+        );
+        let chosen = sim.take_chosen_routes();
+        (sim.get_analytics().clone(), chosen)
+    }
+}
+
+impl AssignmentIteration {
+    fn new(
+        iteration: usize,
+        analytics: &Analytics,
+        chosen: &BTreeMap<TripID, Path>,
+        measure: &TravelTimeMeasure,
+        map: &mut Map,
+        timer: &mut Timer,
+    ) -> AssignmentIteration {
+        let mut result = AssignmentIteration {
+            iteration,
+            relative_gap: None,
+            finished_trips: 0,
+            cancelled_trips: 0,
+            total_trip_time_seconds: 0.0,
+        };
+        for (_, _, _, maybe_dt) in &analytics.finished_trips {
+            if let Some(dt) = maybe_dt {
+                result.finished_trips += 1;
+                result.total_trip_time_seconds += dt.inner_seconds();
+            } else {
+                result.cancelled_trips += 1;
+            }
+        }
+
+        // Find the best routes purely by travel time, so they're comparable to the routes taken
+        let params = RoutingParams {
+            unprotected_turn_penalty: Duration::ZERO,
+            value_of_time: f64::INFINITY,
+            ..map.routing_params().clone()
+        };
+        let requests = chosen.values().map(|path| path.get_req().clone()).collect();
+        let best_paths = map.pathfind_with_observed_travel_times(
+            requests,
+            measure.observed.clone(),
+            &params,
+            timer,
+        );
+        let mut experienced = 0.0;
+        let mut best = 0.0;
+        for (path, maybe_best) in chosen.values().zip(best_paths) {
+            if let Some(best_path) = maybe_best {
+                experienced += measure.path(path, map).inner_seconds();
+                best += measure.path_v2(&best_path, map).inner_seconds();
+            }
+        }
+        if best > 0.0 {
+            result.relative_gap = Some((experienced - best) / best);
+        }
+        result
+    }
+}
+
+/// Measures routes using the travel times observed during one iteration. Anything nobody crossed
+/// takes its free-flow time, and nothing is faster than free-flow, matching how the map routes
+/// with observed times.
+struct TravelTimeMeasure<'a> {
+    free_flow: &'a ObservedTravelTimes,
+    observed: &'a ObservedTravelTimes,
+}
+
+impl<'a> TravelTimeMeasure<'a> {
+    fn road(&self, dr: DirectedRoadID) -> Duration {
+        measured_time(&self.free_flow.roads, &self.observed.roads, dr)
+    }
+
+    fn movement(&self, mvmnt: MovementID) -> Duration {
+        measured_time(&self.free_flow.movements, &self.observed.movements, mvmnt)
+    }
+
+    fn path(&self, path: &Path, map: &Map) -> Duration {
+        let mut total = Duration::ZERO;
+        for step in path.get_steps() {
+            total += match step {
+                PathStep::Lane(l) | PathStep::ContraflowLane(l) => {
+                    self.road(map.get_l(*l).get_directed_parent())
+                }
+                PathStep::Turn(t) => self.movement(t.to_movement(map)),
+            };
+        }
+        total
+    }
+
+    fn path_v2(&self, path: &PathV2, map: &Map) -> Duration {
+        let mut total = Duration::ZERO;
+        for step in path.get_steps() {
+            total += match step {
+                PathStepV2::Along(dr) | PathStepV2::Contraflow(dr) => self.road(*dr),
+                PathStepV2::Movement(mvmnt) => self.movement(*mvmnt),
+            };
+        }
+        total
+    }
+}
+
+fn measured_time<K: Ord>(
+    free_flow: &BTreeMap<K, Duration>,
+    observed: &BTreeMap<K, Duration>,
+    key: K,
+) -> Duration {
+    let free_flow = free_flow.get(&key).cloned().unwrap_or(Duration::ZERO);
+    observed
+        .get(&key)
+        .map(|dt| dt.max(free_flow))
+        .unwrap_or(free_flow)
+}
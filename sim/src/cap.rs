@@ -0,0 +1,153 @@
+//! Enforces the access restrictions and congestion caps of zones as vehicles cross into them. The
+//! pathfinder already avoids zones that don't allow through-traffic, but it has no idea how many
+//! vehicles have entered a capped zone recently, and paths calculated before a restriction kicks in
+//! might still cut through.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::Time;
+use map_model::{Map, PathConstraints, Position, RoadID, TurnID, Zone};
+
+use crate::CarID;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CapSimState {
+    /// Keyed by the first member of each zone, which is stable as long as the map isn't edited.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    entries: BTreeMap<RoadID, HourlyEntries>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct HourlyEntries {
+    hour: usize,
+    cars: BTreeSet<CarID>,
+}
+
+impl CapSimState {
+    pub fn new() -> CapSimState {
+        CapSimState {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Can a vehicle about to perform this turn enter the zone on the other side, if there is one?
+    /// Vehicles headed somewhere inside the zone are always allowed. If the answer is yes and the
+    /// zone is capped, the vehicle is counted. Asking again for the same vehicle is harmless.
+    pub fn allow_entry(
+        &mut self,
+        now: Time,
+        car: CarID,
+        turn: TurnID,
+        end: Position,
+        map: &Map,
+    ) -> bool {
+        let zone = match entering_zone(turn, map) {
+            Some(z) => z,
+            None => {
+                return true;
+            }
+        };
+        if zone.members.contains(&end.lane().road) {
+            return true;
+        }
+        let constraints = car.vehicle_type.to_constraints();
+        if !zone
+            .restrictions
+            .allow_through_traffic_at(now)
+            .contains(constraints)
+        {
+            return false;
+        }
+        if constraints != PathConstraints::Car {
+            return true;
+        }
+        if let Some(cap) = zone.restrictions.cap_vehicles_per_hour {
+            let entries = self.entries_now(now, zone);
+            if entries.cars.contains(&car) {
+                return true;
+            }
+            if entries.cars.len() >= cap {
+                return false;
+            }
+            entries.cars.insert(car);
+        }
+        true
+    }
+
+    /// When rerouting fails, a vehicle enters a zone anyway. It still counts towards the cap.
+    pub fn force_entry(&mut self, now: Time, car: CarID, turn: TurnID, map: &Map) {
+        if let Some(zone) = entering_zone(turn, map) {
+            if zone.restrictions.cap_vehicles_per_hour.is_some() {
+                self.entries_now(now, zone).cars.insert(car);
+            }
+        }
+    }
+
+    /// All roads belonging to zones that this vehicle can't enter right now. If the vehicle is
+    /// already inside a zone, it's free to keep using it.
+    pub fn roads_to_avoid(
+        &self,
+        now: Time,
+        car: CarID,
+        current: RoadID,
+        map: &Map,
+    ) -> BTreeSet<RoadID> {
+        let constraints = car.vehicle_type.to_constraints();
+        let hour = now.get_hours();
+        let mut avoid = BTreeSet::new();
+        for zone in map.all_zones() {
+            if zone.members.contains(&current) {
+                continue;
+            }
+            let mut closed = !zone
+                .restrictions
+                .allow_through_traffic_at(now)
+                .contains(constraints);
+            if let (Some(cap), PathConstraints::Car) =
+                (zone.restrictions.cap_vehicles_per_hour, constraints)
+            {
+                if let Some(entries) = self.entries.get(zone.members.iter().next().unwrap()) {
+                    closed |= entries.hour == hour
+                        && entries.cars.len() >= cap
+                        && !entries.cars.contains(&car);
+                }
+            }
+            if closed {
+                avoid.extend(zone.members.iter().cloned());
+            }
+        }
+        avoid
+    }
+
+    fn entries_now(&mut self, now: Time, zone: &Zone) -> &mut HourlyEntries {
+        let hour = now.get_hours();
+        let entries = self
+            .entries
+            .entry(*zone.members.iter().next().unwrap())
+            .or_insert_with(|| HourlyEntries {
+                hour,
+                cars: BTreeSet::new(),
+            });
+        if entries.hour != hour {
+            entries.hour = hour;
+            entries.cars.clear();
+        }
+        entries
+    }
+}
+
+/// If this turn leads into a different zone, return it.
+fn entering_zone(turn: TurnID, map: &Map) -> Option<&Zone> {
+    let dst = map.get_parent(turn.dst);
+    let zone = dst.get_zone(map)?;
+    if zone.members.contains(&turn.src.road) {
+        return None;
+    }
+    Some(zone)
+}
@@ -0,0 +1,396 @@
+//! The simulation is deterministic for a given RNG seed, so comparing a single run with edits to a
+//! single run without them can't distinguish a real change from noise. An Ensemble runs the same
+//! scenario with many seeds, both before and after applying some edits, and reports travel time
+//! differences with confidence intervals.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::{Duration, Time};
+use map_model::{Map, MapEdits};
+
+use crate::{AlertHandler, Analytics, Scenario, Sim, SimFlags, TripID, TripMode};
+
+/// Describes a batch of simulations to run.
+pub struct Ensemble {
+    /// `load` must point to a scenario. The modifiers and options apply to every run, and
+    /// `rng_seed` is the first seed used.
+    pub flags: SimFlags,
+    /// How many different seeds to run. Seeds are consecutive, starting from `flags.rng_seed`.
+    pub num_seeds: usize,
+    /// A path to map edits. If present, every seed is also run with these edits applied, and
+    /// results are paired by seed. If not, only the baseline runs.
+    pub edits: Option<String>,
+}
+
+/// A 95% confidence interval around the mean of some samples, using Student's t-distribution.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfidenceInterval {
+    pub mean: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub num_samples: usize,
+}
+
+impl ConfidenceInterval {
+    /// Returns None if there are fewer than 2 samples.
+    pub fn from_samples(samples: &[f64]) -> Option<ConfidenceInterval> {
+        let n = samples.len();
+        if n < 2 {
+            return None;
+        }
+        let mean = samples.iter().sum::<f64>() / (n as f64);
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / ((n - 1) as f64);
+        let half_width = t_critical_95(n - 1) * (variance / (n as f64)).sqrt();
+        Some(ConfidenceInterval {
+            mean,
+            lower: mean - half_width,
+            upper: mean + half_width,
+            num_samples: n,
+        })
+    }
+
+    /// True if the interval doesn't contain 0, meaning the difference is unlikely to be noise.
+    pub fn is_significant(&self) -> bool {
+        self.lower > 0.0 || self.upper < 0.0
+    }
+
+    pub fn describe_duration(&self) -> String {
+        format!(
+            "{} (95% CI {} to {}, {} samples)",
+            Duration::seconds(self.mean),
+            Duration::seconds(self.lower),
+            Duration::seconds(self.upper),
+            self.num_samples
+        )
+    }
+}
+
+/// Two-tailed critical values of Student's t-distribution for 95% confidence, indexed by degrees
+/// of freedom.
+fn t_critical_95(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    if df == 0 {
+        panic!("t_critical_95 needs at least 1 degree of freedom");
+    }
+    if df <= TABLE.len() {
+        TABLE[df - 1]
+    } else if df <= 60 {
+        2.000
+    } else if df <= 120 {
+        1.980
+    } else {
+        1.960
+    }
+}
+
+/// The trip times from one run. Only finished trips from Analytics are kept; the rest is
+/// discarded to keep memory reasonable over many runs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnsembleRun {
+    pub rng_seed: u64,
+    pub edited: bool,
+    /// Trip duration in seconds for every finished trip. Use f64 seconds, since a serialized
+    /// Duration has a low cap.
+    pub trip_times: BTreeMap<TripID, f64>,
+    pub trip_modes: BTreeMap<TripID, TripMode>,
+    pub num_cancelled: usize,
+}
+
+impl EnsembleRun {
+    fn from_analytics(rng_seed: u64, edited: bool, analytics: &Analytics) -> EnsembleRun {
+        let mut run = EnsembleRun {
+            rng_seed,
+            edited,
+            trip_times: BTreeMap::new(),
+            trip_modes: BTreeMap::new(),
+            num_cancelled: 0,
+        };
+        for (_, id, mode, maybe_dt) in &analytics.finished_trips {
+            if let Some(dt) = maybe_dt {
+                run.trip_times.insert(*id, dt.inner_seconds());
+                run.trip_modes.insert(*id, *mode);
+            } else {
+                run.num_cancelled += 1;
+            }
+        }
+        run
+    }
+}
+
+/// How one trip's travel time changes with the edits, paired across seeds.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TripDelta {
+    pub trip: TripID,
+    pub mode: TripMode,
+    /// Mean seconds, over seeds where the trip finished in both worlds
+    pub mean_before: f64,
+    pub mean_after: f64,
+    /// None if the trip finished in both worlds for fewer than 2 seeds
+    pub delta: Option<ConfidenceInterval>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnsembleResults {
+    pub scenario_name: String,
+    pub runs: Vec<EnsembleRun>,
+    /// The mean travel time of all finished trips, over baseline runs.
+    pub baseline_mean_trip_time: Option<ConfidenceInterval>,
+    /// Per seed, the change in total travel time for trips that finished in both worlds. Only
+    /// filled out when edits are used.
+    pub total_time_delta: Option<ConfidenceInterval>,
+    /// Per seed, the change in mean travel time for trips that finished in both worlds.
+    pub mean_trip_time_delta: Option<ConfidenceInterval>,
+    pub trips: Vec<TripDelta>,
+}
+
+impl Ensemble {
+    pub fn run(&self, timer: &mut Timer) -> Result<EnsembleResults> {
+        if !self.flags.load.contains("/scenarios/") {
+            bail!(
+                "An ensemble must be run on a scenario, not {}",
+                self.flags.load
+            );
+        }
+        if self.num_seeds == 0 {
+            bail!("An ensemble needs at least one seed");
+        }
+
+        let mut scenario: Scenario = abstio::read_object(self.flags.load.clone(), timer)?;
+        let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
+        for m in &self.flags.modifiers {
+            scenario = m.apply(&map, scenario);
+        }
+
+        let seeds: Vec<u64> = (0..self.num_seeds as u64)
+            .map(|i| self.flags.rng_seed + i)
+            .collect();
+        let mut runs = Vec::new();
+        for seed in &seeds {
+            runs.push(self.run_once(&map, &scenario, *seed, false, timer));
+        }
+
+        if let Some(ref path) = self.edits {
+            let edits = MapEdits::load_from_file(&map, path.clone(), timer)?;
+            map.must_apply_edits(edits, timer);
+            map.recalculate_pathfinding_after_edits(timer);
+            for seed in &seeds {
+                runs.push(self.run_once(&map, &scenario, *seed, true, timer));
+            }
+        }
+
+        Ok(EnsembleResults::new(scenario.scenario_name, runs))
+    }
+
+    fn run_once(
+        &self,
+        map: &Map,
+        scenario: &Scenario,
+        rng_seed: u64,
+        edited: bool,
+        timer: &mut Timer,
+    ) -> EnsembleRun {
+        let name = format!(
+            "{} with seed {}{}",
+            scenario.scenario_name,
+            rng_seed,
+            if edited { " (edited)" } else { "" }
+        );
+        timer.start(name.clone());
+
+        let mut opts = self.flags.opts.clone();
+        opts.alerts = AlertHandler::Silence;
+        let mut sim = Sim::new(map, opts);
+        let mut rng = XorShiftRng::seed_from_u64(rng_seed);
+        scenario.instantiate(&mut sim, map, &mut rng, timer);
+        // Like prebaking, run a few hours past the end of the day, so trips starting close to
+        // midnight can finish.
+        sim.timed_step(
+            map,
+            sim.get_end_of_day() - Time::START_OF_DAY + Duration::hours(3),
+            &mut None,
+            timer,
+        );
+        let run = EnsembleRun::from_analytics(rng_seed, edited, sim.get_analytics());
+
+        timer.stop(name);
+        run
+    }
+}
+
+impl EnsembleResults {
+    pub fn new(scenario_name: String, runs: Vec<EnsembleRun>) -> EnsembleResults {
+        let baseline_means: Vec<f64> = runs
+            .iter()
+            .filter(|r| !r.edited && !r.trip_times.is_empty())
+            .map(|r| r.trip_times.values().sum::<f64>() / (r.trip_times.len() as f64))
+            .collect();
+
+        let mut total_deltas = Vec::new();
+        let mut mean_deltas = Vec::new();
+        // TripID -> (before, after) for every seed
+        let mut per_trip: BTreeMap<TripID, Vec<(f64, f64)>> = BTreeMap::new();
+        let mut modes: BTreeMap<TripID, TripMode> = BTreeMap::new();
+        for before in runs.iter().filter(|r| !r.edited) {
+            let after = match runs
+                .iter()
+                .find(|r| r.edited && r.rng_seed == before.rng_seed)
+            {
+                Some(r) => r,
+                None => continue,
+            };
+
+            let mut total_before = 0.0;
+            let mut total_after = 0.0;
+            let mut count = 0;
+            for (id, dt0) in &before.trip_times {
+                if let Some(dt1) = after.trip_times.get(id) {
+                    total_before += dt0;
+                    total_after += dt1;
+                    count += 1;
+                    per_trip
+                        .entry(*id)
+                        .or_insert_with(Vec::new)
+                        .push((*dt0, *dt1));
+                    modes.insert(*id, before.trip_modes[id]);
+                }
+            }
+            if count > 0 {
+                total_deltas.push(total_after - total_before);
+                mean_deltas.push((total_after - total_before) / (count as f64));
+            }
+        }
+
+        let trips = per_trip
+            .into_iter()
+            .map(|(trip, pairs)| {
+                let n = pairs.len() as f64;
+                let deltas: Vec<f64> = pairs.iter().map(|(a, b)| b - a).collect();
+                TripDelta {
+                    trip,
+                    mode: modes[&trip],
+                    mean_before: pairs.iter().map(|(a, _)| a).sum::<f64>() / n,
+                    mean_after: pairs.iter().map(|(_, b)| b).sum::<f64>() / n,
+                    delta: ConfidenceInterval::from_samples(&deltas),
+                }
+            })
+            .collect();
+
+        EnsembleResults {
+            scenario_name,
+            runs,
+            baseline_mean_trip_time: ConfidenceInterval::from_samples(&baseline_means),
+            total_time_delta: ConfidenceInterval::from_samples(&total_deltas),
+            mean_trip_time_delta: ConfidenceInterval::from_samples(&mean_deltas),
+            trips,
+        }
+    }
+
+    /// A short human-readable summary.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{}: {} runs",
+            self.scenario_name,
+            prettyprint_usize(self.runs.len())
+        )];
+        if let Some(ci) = self.baseline_mean_trip_time {
+            lines.push(format!(
+                "Baseline mean trip time: {}",
+                ci.describe_duration()
+            ));
+        }
+        if let Some(ci) = self.mean_trip_time_delta {
+            lines.push(format!(
+                "Change in mean trip time: {}",
+                ci.describe_duration()
+            ));
+        }
+        if let Some(ci) = self.total_time_delta {
+            lines.push(format!(
+                "Change in total trip time: {}",
+                ci.describe_duration()
+            ));
+        }
+        if !self.trips.is_empty() {
+            let faster = self
+                .trips
+                .iter()
+                .filter(|t| t.delta.map(|ci| ci.upper < 0.0).unwrap_or(false))
+                .count();
+            let slower = self
+                .trips
+                .iter()
+                .filter(|t| t.delta.map(|ci| ci.lower > 0.0).unwrap_or(false))
+                .count();
+            lines.push(format!(
+                "{} trips significantly faster, {} significantly slower, {} compared",
+                prettyprint_usize(faster),
+                prettyprint_usize(slower),
+                prettyprint_usize(self.trips.len())
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confidence_interval() {
+        assert!(ConfidenceInterval::from_samples(&[]).is_none());
+        assert!(ConfidenceInterval::from_samples(&[1.0]).is_none());
+
+        let ci = ConfidenceInterval::from_samples(&[5.0, 5.0, 5.0]).unwrap();
+        assert_eq!(ci.mean, 5.0);
+        assert_eq!(ci.lower, 5.0);
+        assert_eq!(ci.upper, 5.0);
+        assert!(ci.is_significant());
+
+        // mean 2, sample sd 1, n = 3, so the half width is 4.303 / sqrt(3)
+        let ci = ConfidenceInterval::from_samples(&[1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(ci.mean, 2.0);
+        assert!((ci.upper - ci.mean - 4.303 / 3.0_f64.sqrt()).abs() < 1e-9);
+        assert!(!ci.is_significant());
+    }
+
+    #[test]
+    fn test_paired_deltas() {
+        let mut runs = Vec::new();
+        for (seed, edited, times) in vec![
+            (1, false, vec![100.0, 200.0]),
+            (2, false, vec![110.0, 190.0]),
+            (1, true, vec![90.0, 200.0]),
+            (2, true, vec![101.0, 190.0]),
+        ] {
+            let mut run = EnsembleRun {
+                rng_seed: seed,
+                edited,
+                trip_times: BTreeMap::new(),
+                trip_modes: BTreeMap::new(),
+                num_cancelled: 0,
+            };
+            for (idx, dt) in times.into_iter().enumerate() {
+                run.trip_times.insert(TripID(idx), dt);
+                run.trip_modes.insert(TripID(idx), TripMode::Drive);
+            }
+            runs.push(run);
+        }
+
+        let results = EnsembleResults::new("test".to_string(), runs);
+        assert_eq!(results.trips.len(), 2);
+        assert_eq!(results.trips[0].delta.unwrap().mean, -9.5);
+        assert_eq!(results.trips[1].delta.unwrap().mean, 0.0);
+        assert_eq!(results.total_time_delta.unwrap().mean, -9.5);
+    }
+}
@@ -0,0 +1,232 @@
+//! Optionally stream every Event emitted by the simulation to a file, so questions can be answered
+//! offline without re-running the simulation.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use anyhow::Result;
+
+use geom::Time;
+
+use crate::Event;
+
+/// How events are encoded in the log. Picked from the file extension, like `abstio::read_object`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventLogFormat {
+    /// One JSON object per line, for any file not ending in `.bin`
+    JsonLines,
+    /// A sequence of bincode-encoded records, for files ending in `.bin`
+    Binary,
+}
+
+impl EventLogFormat {
+    pub fn from_path(path: &str) -> EventLogFormat {
+        if path.ends_with(".bin") {
+            EventLogFormat::Binary
+        } else {
+            EventLogFormat::JsonLines
+        }
+    }
+}
+
+/// Writes events to a file as they occur.
+pub(crate) struct EventLogWriter {
+    path: String,
+    format: EventLogFormat,
+    // None after a write error
+    out: Option<BufWriter<File>>,
+}
+
+impl EventLogWriter {
+    pub fn new(path: String) -> Result<EventLogWriter> {
+        if let Some(parent) = std::path::Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let out = BufWriter::new(File::create(&path)?);
+        Ok(EventLogWriter {
+            format: EventLogFormat::from_path(&path),
+            path,
+            out: Some(out),
+        })
+    }
+
+    pub fn handle_event(&mut self, time: Time, ev: &Event) {
+        if let Some(ref mut out) = self.out {
+            if let Err(err) = write_event(out, self.format, time, ev) {
+                error!(
+                    "Couldn't write to event log {}, no longer recording: {}",
+                    self.path, err
+                );
+                self.out = None;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(ref mut out) = self.out {
+            if let Err(err) = out.flush() {
+                error!("Couldn't flush event log {}: {}", self.path, err);
+            }
+        }
+    }
+}
+
+fn write_event(
+    out: &mut BufWriter<File>,
+    format: EventLogFormat,
+    time: Time,
+    ev: &Event,
+) -> Result<()> {
+    match format {
+        EventLogFormat::JsonLines => {
+            serde_json::to_writer(&mut *out, &(time, ev))?;
+            writeln!(out)?;
+        }
+        EventLogFormat::Binary => {
+            bincode::serialize_into(&mut *out, &(time, ev))?;
+        }
+    }
+    Ok(())
+}
+
+impl Clone for EventLogWriter {
+    /// A cloned simulation won't write to the same file; its events would interleave with the
+    /// original's.
+    fn clone(&self) -> EventLogWriter {
+        EventLogWriter {
+            path: self.path.clone(),
+            format: self.format,
+            out: None,
+        }
+    }
+}
+
+/// Reads an event log written by the simulation, yielding events in the order they occurred.
+pub struct EventLogReader {
+    format: EventLogFormat,
+    input: BufReader<File>,
+    line: String,
+}
+
+impl EventLogReader {
+    pub fn open(path: &str) -> Result<EventLogReader> {
+        Ok(EventLogReader {
+            format: EventLogFormat::from_path(path),
+            input: BufReader::new(File::open(path)?),
+            line: String::new(),
+        })
+    }
+
+    fn read_next(&mut self) -> Result<Option<(Time, Event)>> {
+        match self.format {
+            EventLogFormat::JsonLines => loop {
+                self.line.clear();
+                if self.input.read_line(&mut self.line)? == 0 {
+                    return Ok(None);
+                }
+                if !self.line.trim().is_empty() {
+                    return Ok(Some(serde_json::from_str(&self.line)?));
+                }
+            },
+            EventLogFormat::Binary => {
+                // Distinguish a clean end of file from a truncated record
+                if self.input.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(bincode::deserialize_from(&mut self.input)?))
+            }
+        }
+    }
+}
+
+impl Iterator for EventLogReader {
+    type Item = Result<(Time, Event)>;
+
+    fn next(&mut self) -> Option<Result<(Time, Event)>> {
+        self.read_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geom::Duration;
+
+    use super::*;
+    use crate::{AlertLocation, TripID, TripMode};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}", std::process::id(), name))
+            .display()
+            .to_string()
+    }
+
+    fn write_log(path: &str, events: &[(Time, Event)]) {
+        let mut writer = EventLogWriter::new(path.to_string()).unwrap();
+        for (time, ev) in events {
+            writer.handle_event(*time, ev);
+        }
+        writer.flush();
+    }
+
+    fn sample_events() -> Vec<(Time, Event)> {
+        vec![
+            (
+                Time::START_OF_DAY,
+                Event::TripCancelled(TripID(3), TripMode::Drive),
+            ),
+            (
+                Time::START_OF_DAY + Duration::seconds(5.5),
+                Event::TripFinished {
+                    trip: TripID(1),
+                    mode: TripMode::Walk,
+                    total_time: Duration::minutes(3),
+                    blocked_time: Duration::ZERO,
+                },
+            ),
+            (
+                Time::START_OF_DAY + Duration::minutes(1),
+                Event::Alert(AlertLocation::Nil, "spans\ntwo lines".to_string()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let events = sample_events();
+        for (name, format) in [
+            ("events.json", EventLogFormat::JsonLines),
+            ("events.bin", EventLogFormat::Binary),
+        ] {
+            let path = temp_path(name);
+            assert_eq!(EventLogFormat::from_path(&path), format);
+            write_log(&path, &events);
+            let read: Vec<(Time, Event)> = EventLogReader::open(&path)
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(read, events);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_truncated_binary_log() {
+        let path = temp_path("truncated_events.bin");
+        write_log(&path, &sample_events());
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        // The complete records still come through
+        let mut reader = EventLogReader::open(&path).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,9 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use geom::Duration;
+use geom::{Distance, Duration};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, Path, PathRequest, Traversable,
-    TurnID,
+    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, Path, PathRequest, RoadID,
+    Traversable, TurnID,
 };
 
 use crate::{AgentID, CarID, ParkingSpot, PedestrianID, PersonID, Problem, TripID, TripMode};
@@ -20,13 +20,55 @@ use crate::{AgentID, CarID, ParkingSpot, PedestrianID, PersonID, Problem, TripID
 pub enum Event {
     CarReachedParkingSpot(CarID, ParkingSpot),
     CarLeftParkingSpot(CarID, ParkingSpot),
+    /// A driver reached the end of their path and started looking for a parking spot.
+    ParkingSearchStarted(TripID, CarID),
+    /// A driver stopped looking for parking, either finding a spot or giving up. Includes how far
+    /// they drove while searching.
+    ParkingSearchEnded(TripID, CarID, Option<ParkingSpot>, Distance),
+    /// A car starting the day parked in a residential permit zone gets a permit for it.
+    ParkingPermitGranted(CarID, String),
 
     BusArrivedAtStop(CarID, BusRouteID, BusStopID),
+    /// How late a bus or train reached a stop, compared to its schedule. Negative if it's early.
+    BusScheduleAdherence(CarID, BusRouteID, BusStopID, Duration),
     BusDepartedFromStop(CarID, BusRouteID, BusStopID),
     /// How long waiting at the stop?
     PassengerBoardsTransit(PersonID, CarID, BusRouteID, BusStopID, Duration),
     PassengerAlightsTransit(PersonID, CarID, BusRouteID, BusStopID),
 
+    /// A ride-hail vehicle was assigned to a trip and starts driving to the pickup.
+    RideHailDispatched(TripID, CarID),
+    /// How long the passenger waited since requesting a ride, and how far the vehicle drove empty
+    /// to reach them
+    RideHailPickup(TripID, CarID, Duration, Distance),
+    /// How far the vehicle drove with the passenger
+    RideHailDropoff(TripID, CarID, Distance),
+
+    /// A delivery vehicle stopped to load or unload at a building for some duration. The lane is
+    /// where it double-parked, or None if it pulled into a loading zone.
+    DeliveryStop(CarID, BuildingID, Duration, Option<LaneID>),
+    /// A delivery vehicle made it back to its depot. Includes the total time spent on the tour.
+    DeliveryTourFinished(CarID, Duration),
+
+    /// Somebody picked up a shared bike or scooter at a building's dock or curb
+    MicromobilityPickup(TripID, CarID, BuildingID),
+    /// Somebody left a shared bike or scooter at a building's dock or curb
+    MicromobilityDropoff(TripID, CarID, BuildingID),
+    /// Nothing was available within walking distance of the building, so the trip was cancelled
+    MicromobilityUnavailable(TripID, BuildingID),
+    /// Shared vehicles were moved between docks. Includes how many.
+    MicromobilityRebalanced(usize),
+
+    /// A car paid a toll, in dollars, to use a road or enter a priced zone.
+    TollPaid(CarID, Option<TripID>, RoadID, f64),
+    /// A driver paying some toll took a different route than they would have if roads were free,
+    /// saving this many dollars.
+    TripDivertedByToll(TripID, f64),
+
+    /// A traffic signal changed its timing for an approaching bus or train. A positive duration
+    /// held the green longer; a negative one ended a conflicting stage early.
+    TransitSignalPriority(IntersectionID, CarID, Duration),
+
     PersonEntersBuilding(PersonID, BuildingID),
     PersonLeavesBuilding(PersonID, BuildingID),
     /// None if cancelled
@@ -59,6 +101,82 @@ pub enum Event {
     PathAmended(Path),
 
     Alert(AlertLocation, String),
+    /// A car, bike, or bus left the simulation for any reason -- parking, vanishing at a border,
+    /// finishing a shift, or being deleted.
+    VehicleRemoved(CarID),
+    /// A ride-hail vehicle stopped serving a trip before dropping anybody off, because the trip
+    /// was cancelled.
+    RideHailAbandoned(TripID, CarID),
+}
+
+impl Event {
+    /// The name of the variant, for filtering event logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::CarReachedParkingSpot(_, _) => "CarReachedParkingSpot",
+            Event::CarLeftParkingSpot(_, _) => "CarLeftParkingSpot",
+            Event::ParkingSearchStarted(_, _) => "ParkingSearchStarted",
+            Event::ParkingSearchEnded(_, _, _, _) => "ParkingSearchEnded",
+            Event::ParkingPermitGranted(_, _) => "ParkingPermitGranted",
+            Event::BusArrivedAtStop(_, _, _) => "BusArrivedAtStop",
+            Event::BusScheduleAdherence(_, _, _, _) => "BusScheduleAdherence",
+            Event::BusDepartedFromStop(_, _, _) => "BusDepartedFromStop",
+            Event::PassengerBoardsTransit(_, _, _, _, _) => "PassengerBoardsTransit",
+            Event::PassengerAlightsTransit(_, _, _, _) => "PassengerAlightsTransit",
+            Event::RideHailDispatched(_, _) => "RideHailDispatched",
+            Event::RideHailPickup(_, _, _, _) => "RideHailPickup",
+            Event::RideHailDropoff(_, _, _) => "RideHailDropoff",
+            Event::DeliveryStop(_, _, _, _) => "DeliveryStop",
+            Event::DeliveryTourFinished(_, _) => "DeliveryTourFinished",
+            Event::MicromobilityPickup(_, _, _) => "MicromobilityPickup",
+            Event::MicromobilityDropoff(_, _, _) => "MicromobilityDropoff",
+            Event::MicromobilityUnavailable(_, _) => "MicromobilityUnavailable",
+            Event::MicromobilityRebalanced(_) => "MicromobilityRebalanced",
+            Event::TollPaid(_, _, _, _) => "TollPaid",
+            Event::TripDivertedByToll(_, _) => "TripDivertedByToll",
+            Event::TransitSignalPriority(_, _, _) => "TransitSignalPriority",
+            Event::PersonEntersBuilding(_, _) => "PersonEntersBuilding",
+            Event::PersonLeavesBuilding(_, _) => "PersonLeavesBuilding",
+            Event::PersonLeavesMap(_, _, _) => "PersonLeavesMap",
+            Event::PersonEntersMap(_, _, _) => "PersonEntersMap",
+            Event::PedReachedParkingSpot(_, _) => "PedReachedParkingSpot",
+            Event::BikeStoppedAtSidewalk(_, _) => "BikeStoppedAtSidewalk",
+            Event::ProblemEncountered(_, _) => "ProblemEncountered",
+            Event::AgentEntersTraversable(_, _, _, _) => "AgentEntersTraversable",
+            Event::IntersectionDelayMeasured(_, _, _, _) => "IntersectionDelayMeasured",
+            Event::TripFinished { .. } => "TripFinished",
+            Event::TripCancelled(_, _) => "TripCancelled",
+            Event::TripPhaseStarting(_, _, _, _) => "TripPhaseStarting",
+            Event::PathAmended(_) => "PathAmended",
+            Event::Alert(_, _) => "Alert",
+            Event::VehicleRemoved(_) => "VehicleRemoved",
+            Event::RideHailAbandoned(_, _) => "RideHailAbandoned",
+        }
+    }
+
+    /// The trip this event is about, if it's directly associated with one.
+    pub fn trip(&self) -> Option<TripID> {
+        match self {
+            Event::ParkingSearchStarted(trip, _)
+            | Event::ParkingSearchEnded(trip, _, _, _)
+            | Event::RideHailDispatched(trip, _)
+            | Event::RideHailPickup(trip, _, _, _)
+            | Event::RideHailDropoff(trip, _, _)
+            | Event::RideHailAbandoned(trip, _)
+            | Event::MicromobilityPickup(trip, _, _)
+            | Event::MicromobilityDropoff(trip, _, _)
+            | Event::MicromobilityUnavailable(trip, _)
+            | Event::TripDivertedByToll(trip, _)
+            | Event::ProblemEncountered(trip, _)
+            | Event::IntersectionDelayMeasured(trip, _, _, _)
+            | Event::TripFinished { trip, .. }
+            | Event::TripCancelled(trip, _)
+            | Event::TripPhaseStarting(trip, _, _, _) => Some(*trip),
+            Event::AgentEntersTraversable(_, maybe_trip, _, _)
+            | Event::TollPaid(_, maybe_trip, _, _) => *maybe_trip,
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -78,6 +196,8 @@ pub enum TripPhaseType {
     WaitingForBus(BusRouteID, BusStopID),
     /// What stop did they board at?
     RidingBus(BusRouteID, BusStopID, CarID),
+    WaitingForRideHail,
+    RidingRideHail(CarID),
     Cancelled,
     Finished,
     DelayedStart,
@@ -94,6 +214,8 @@ impl TripPhaseType {
                 format!("Waiting for bus {}", map.get_br(r).full_name)
             }
             TripPhaseType::RidingBus(r, _, _) => format!("Riding bus {}", map.get_br(r).full_name),
+            TripPhaseType::WaitingForRideHail => "Waiting for a ride-hail pickup".to_string(),
+            TripPhaseType::RidingRideHail(car) => format!("Riding in {}", car),
             TripPhaseType::Cancelled => "Trip was cancelled due to some bug".to_string(),
             TripPhaseType::Finished => "Trip finished".to_string(),
             TripPhaseType::DelayedStart => "Delayed by a previous trip taking too long".to_string(),
@@ -0,0 +1,255 @@
+//! Delivery vans and trucks make tours, leaving a depot, stopping at a series of buildings to load
+//! or unload, then returning to the depot. At each stop, the vehicle looks for a free loading zone
+//! on the last lane before the building. If there isn't one, it double-parks in the driving lane,
+//! blocking everybody behind it until it's done.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Duration, Time};
+use map_model::{BuildingID, LaneID, Map, PathConstraints, PathRequest, Position};
+
+use crate::router::path_to_curb;
+use crate::sim::Ctx;
+use crate::{
+    CarID, Command, CreateCar, DeliveryStop, DeliveryTour, Event, ParkingSpot, Router, Scheduler,
+    TripEndpoint, TripMode, Vehicle,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Tour {
+    vehicle: Vehicle,
+    depot: TripEndpoint,
+    /// The first stop is where the vehicle is currently headed
+    stops: VecDeque<DeliveryStop>,
+    total_stops: usize,
+    /// None until the vehicle leaves the depot
+    started: Option<Time>,
+}
+
+/// Manages the delivery tours that haven't finished yet.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct FreightSimState {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    tours: BTreeMap<CarID, Tour>,
+
+    events: Vec<Event>,
+}
+
+impl FreightSimState {
+    pub fn new() -> FreightSimState {
+        FreightSimState {
+            tours: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn add_tour(&mut self, vehicle: Vehicle, tour: &DeliveryTour, scheduler: &mut Scheduler) {
+        let id = vehicle.id;
+        self.tours.insert(
+            id,
+            Tour {
+                vehicle,
+                depot: tour.depot,
+                stops: tour.stops.iter().cloned().collect(),
+                total_stops: tour.stops.len(),
+                started: None,
+            },
+        );
+        scheduler.push(tour.depart, Command::StartDeliveryTour(id));
+    }
+
+    pub fn start_tour(&mut self, now: Time, car: CarID, ctx: &mut Ctx) {
+        let tour = self.tours.get_mut(&car).unwrap();
+        tour.started = Some(now);
+        let start = match tour.depot.pos(TripMode::Drive, true, ctx.map) {
+            // Make sure the whole vehicle fits on the lane
+            Some(pos) => pos.buffer_dist(tour.vehicle.length, ctx.map).unwrap_or(pos),
+            None => {
+                let reason = format!("can't leave from {:?}", tour.depot);
+                self.abandon_tour(car, reason);
+                return;
+            }
+        };
+        match next_leg(now, tour, start, ctx.map) {
+            Ok(router) => {
+                ctx.scheduler.push(
+                    now,
+                    Command::SpawnCar(
+                        CreateCar {
+                            vehicle: tour.vehicle.clone(),
+                            router,
+                            maybe_parked_car: None,
+                            trip_and_person: None,
+                            maybe_route: None,
+                        },
+                        true,
+                    ),
+                );
+            }
+            Err(err) => {
+                self.abandon_tour(car, err.to_string());
+            }
+        }
+    }
+
+    /// The vehicle just finished parking in a loading zone. Once it's done there, it'll leave for
+    /// the next stop.
+    pub fn vehicle_parked(&mut self, now: Time, car: CarID, spot: ParkingSpot, ctx: &mut Ctx) {
+        let tour = self.tours.get_mut(&car).unwrap();
+        let stop = tour.stops.pop_front().unwrap();
+        self.events
+            .push(Event::DeliveryStop(car, stop.building, stop.dwell, None));
+
+        let leave_at = now + stop.dwell;
+        let start = ctx
+            .parking
+            .spot_to_driving_pos(spot, &tour.vehicle, ctx.map);
+        match next_leg(leave_at, tour, start, ctx.map) {
+            Ok(router) => {
+                ctx.scheduler.push(
+                    leave_at,
+                    Command::SpawnCar(
+                        CreateCar {
+                            vehicle: tour.vehicle.clone(),
+                            router,
+                            maybe_parked_car: ctx.parking.lookup_parked_car(car).cloned(),
+                            trip_and_person: None,
+                            maybe_route: None,
+                        },
+                        true,
+                    ),
+                );
+            }
+            Err(err) => {
+                let parked_car = ctx.parking.lookup_parked_car(car).unwrap().clone();
+                ctx.parking.remove_parked_car(parked_car);
+                self.abandon_tour(car, err.to_string());
+            }
+        }
+    }
+
+    /// The vehicle just double-parked outside a stop, or came back to a depot at a building.
+    /// Returns how long to stay stopped, or None if the tour is over and the vehicle should
+    /// vanish.
+    pub fn vehicle_reached_curb(
+        &mut self,
+        now: Time,
+        car: CarID,
+        lane: LaneID,
+    ) -> Option<Duration> {
+        let tour = self.tours.get_mut(&car).unwrap();
+        if let Some(stop) = tour.stops.pop_front() {
+            self.events.push(Event::DeliveryStop(
+                car,
+                stop.building,
+                stop.dwell,
+                Some(lane),
+            ));
+            return Some(stop.dwell);
+        }
+        self.finish_tour(now, car);
+        None
+    }
+
+    /// Returns the route to the next stop, or None if the vehicle should vanish.
+    pub fn vehicle_leaving_curb(
+        &mut self,
+        now: Time,
+        car: CarID,
+        pos: Position,
+        ctx: &mut Ctx,
+    ) -> Option<Router> {
+        let tour = self.tours.get_mut(&car).unwrap();
+        match next_leg(now, tour, pos, ctx.map) {
+            Ok(router) => Some(router),
+            Err(err) => {
+                self.abandon_tour(car, err.to_string());
+                None
+            }
+        }
+    }
+
+    /// The vehicle returned to a depot at a border.
+    pub fn vehicle_left_map(&mut self, now: Time, car: CarID) {
+        self.finish_tour(now, car);
+    }
+
+    /// Something went wrong; the vehicle has already been removed from the map.
+    pub fn abandon_tour(&mut self, car: CarID, reason: String) {
+        warn!("Abandoning the delivery tour for {}: {}", car, reason);
+        self.tours.remove(&car);
+    }
+
+    fn finish_tour(&mut self, now: Time, car: CarID) {
+        let tour = self.tours.remove(&car).unwrap();
+        self.events.push(Event::DeliveryTourFinished(
+            car,
+            now - tour.started.unwrap(),
+        ));
+    }
+
+    pub fn collect_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// Queries
+impl FreightSimState {
+    /// Tours that have started, but not finished yet
+    pub fn num_active_tours(&self) -> usize {
+        self.tours.values().filter(|t| t.started.is_some()).count()
+    }
+
+    /// Returns the next building the vehicle is headed to (or None when returning to the depot),
+    /// and how many stops it's made so far out of the total.
+    pub fn tour_progress(&self, car: CarID) -> Option<(Option<BuildingID>, usize, usize)> {
+        let tour = self.tours.get(&car)?;
+        Some((
+            tour.stops.front().map(|stop| stop.building),
+            tour.total_stops - tour.stops.len(),
+            tour.total_stops,
+        ))
+    }
+}
+
+/// Route to the next stop, skipping any that can't be reached, or back to the depot if there are
+/// no more.
+fn next_leg(now: Time, tour: &mut Tour, from: Position, map: &Map) -> Result<Router> {
+    let id = tour.vehicle.id;
+    while let Some(stop) = tour.stops.front() {
+        match path_to_curb(now, from, tour.vehicle.length, stop.building, map) {
+            Ok((path, end_dist)) => {
+                return Ok(Router::deliver(id, path, end_dist, stop.building));
+            }
+            Err(err) => {
+                warn!("{} skipping a delivery: {}", id, err);
+                tour.stops.pop_front();
+            }
+        }
+    }
+
+    match tour.depot {
+        TripEndpoint::Bldg(b) => {
+            let (path, end_dist) = path_to_curb(now, from, tour.vehicle.length, b, map)?;
+            Ok(Router::stop_at_curb(id, path, end_dist))
+        }
+        TripEndpoint::Border(i) => {
+            let end = tour
+                .depot
+                .pos(TripMode::Drive, false, map)
+                .ok_or_else(|| anyhow!("can't end at {}", i))?;
+            let path = map.pathfind(
+                PathRequest::vehicle(from, end, PathConstraints::Car).with_departure(now),
+            )?;
+            Ok(Router::end_at_border(id, path, end.dist_along(), i))
+        }
+        TripEndpoint::SuddenlyAppear(_) => unreachable!(),
+    }
+}
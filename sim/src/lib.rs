@@ -24,8 +24,8 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_usize, serialize_usize};
 use geom::{Distance, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, ParkingLotID, Path,
-    PathConstraints, Position,
+    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, ParkingLotID,
+    ParkingRegulationTarget, Path, PathConstraints, Position,
 };
 
 pub use crate::render::{
@@ -33,20 +33,30 @@ pub use crate::render::{
     UnzoomedAgent,
 };
 
-pub use self::analytics::{Analytics, Problem, SlidingWindow, TripPhase};
-pub(crate) use self::events::Event;
-pub use self::events::{AlertLocation, TripPhaseType};
+pub use self::analytics::{Analytics, Problem, SlidingWindow, TravelTimeStats, TripPhase};
+pub use self::assignment::{AssignmentIteration, AssignmentResults, TrafficAssignment};
+pub(crate) use self::cap::CapSimState;
+pub use self::ensemble::{ConfidenceInterval, Ensemble, EnsembleResults, EnsembleRun, TripDelta};
+pub(crate) use self::event_log::EventLogWriter;
+pub use self::event_log::{EventLogFormat, EventLogReader};
+pub use self::events::{AlertLocation, Event, TripPhaseType};
+pub(crate) use self::freight::FreightSimState;
 pub use self::make::{
-    fork_rng, BorderSpawnOverTime, ExternalPerson, ExternalTrip, ExternalTripEndpoint, IndividTrip,
-    MapBorders, PersonSpec, Scenario, ScenarioGenerator, ScenarioModifier, SimFlags, SpawnOverTime,
-    TripEndpoint, TripPurpose,
+    fork_rng, BorderSpawnOverTime, DeliveryStop, DeliveryTour, DeliveryVehicle, DockSpec,
+    ExternalPerson, ExternalTrip, ExternalTripEndpoint, IndividTrip, MapBorders,
+    MicromobilityFleet, MicromobilityVehicle, PersonSpec, Scenario, ScenarioGenerator,
+    ScenarioModifier, SimFlags, SpawnOverTime, TripEndpoint, TripPurpose,
 };
 pub(crate) use self::make::{StartTripArgs, TripSpec};
 pub(crate) use self::mechanics::{
     DrivingSimState, IntersectionSimState, ParkingSim, ParkingSimState, WalkingSimState,
 };
+pub(crate) use self::micromobility::MicromobilitySimState;
 pub(crate) use self::pandemic::PandemicModel;
 pub(crate) use self::recorder::TrafficRecorder;
+pub use self::ridehail::RideHailParams;
+pub(crate) use self::ridehail::{RideHailRequest, RideHailSimState};
+pub use self::router::ParkingSearchParams;
 pub(crate) use self::router::{ActionAtEnd, Router};
 pub(crate) use self::scheduler::{Command, Scheduler};
 pub use self::sim::{AgentProperties, AlertHandler, DelayCause, Sim, SimCallback, SimOptions};
@@ -56,12 +66,19 @@ pub use self::trips::{CommutersVehiclesCounts, Person, PersonState, TripInfo, Tr
 pub(crate) use self::trips::{TripLeg, TripManager};
 
 mod analytics;
+mod assignment;
+mod cap;
+mod ensemble;
+mod event_log;
 mod events;
+mod freight;
 mod make;
 mod mechanics;
+mod micromobility;
 mod pandemic;
 mod recorder;
 mod render;
+mod ridehail;
 mod router;
 mod scheduler;
 mod sim;
@@ -75,6 +92,9 @@ pub(crate) const MAX_CAR_LENGTH: Distance = Distance::const_meters(6.5);
 // Note this is more than MAX_CAR_LENGTH
 pub(crate) const BUS_LENGTH: Distance = Distance::const_meters(12.5);
 pub(crate) const LIGHT_RAIL_LENGTH: Distance = Distance::const_meters(60.0);
+pub(crate) const VAN_LENGTH: Distance = Distance::const_meters(6.0);
+// Note this is more than MAX_CAR_LENGTH, so these overhang a regular parking spot
+pub(crate) const TRUCK_LENGTH: Distance = Distance::const_meters(10.0);
 
 /// At all speeds (including at rest), cars must be at least this far apart, measured from front of
 /// one car to the back of the other.
@@ -104,6 +124,8 @@ impl fmt::Display for CarID {
             VehicleType::Bus => write!(f, "Bus #{}", self.id),
             VehicleType::Train => write!(f, "Train #{}", self.id),
             VehicleType::Bike => write!(f, "Bike #{}", self.id),
+            VehicleType::RideHail => write!(f, "Ride-hail #{}", self.id),
+            VehicleType::Truck => write!(f, "Truck #{}", self.id),
         }
     }
 }
@@ -142,7 +164,7 @@ impl AgentID {
     pub fn to_type(self) -> AgentType {
         match self {
             AgentID::Car(c) => match c.vehicle_type {
-                VehicleType::Car => AgentType::Car,
+                VehicleType::Car | VehicleType::RideHail | VehicleType::Truck => AgentType::Car,
                 VehicleType::Bike => AgentType::Bike,
                 VehicleType::Bus => AgentType::Bus,
                 VehicleType::Train => AgentType::Train,
@@ -287,6 +309,10 @@ pub enum VehicleType {
     Bus,
     Train,
     Bike,
+    /// Part of a fleet of taxis or on-demand vehicles, not owned by anybody
+    RideHail,
+    /// A delivery van or truck making a tour of stops, not owned by anybody
+    Truck,
 }
 
 impl fmt::Display for VehicleType {
@@ -296,6 +322,8 @@ impl fmt::Display for VehicleType {
             VehicleType::Bus => write!(f, "bus"),
             VehicleType::Train => write!(f, "train"),
             VehicleType::Bike => write!(f, "bike"),
+            VehicleType::RideHail => write!(f, "ride-hail"),
+            VehicleType::Truck => write!(f, "truck"),
         }
     }
 }
@@ -307,6 +335,8 @@ impl VehicleType {
             VehicleType::Bus => PathConstraints::Bus,
             VehicleType::Train => PathConstraints::Train,
             VehicleType::Bike => PathConstraints::Bike,
+            VehicleType::RideHail => PathConstraints::Car,
+            VehicleType::Truck => PathConstraints::Car,
         }
     }
 
@@ -316,6 +346,8 @@ impl VehicleType {
             VehicleType::Bus => true,
             VehicleType::Train => true,
             VehicleType::Bike => false,
+            VehicleType::RideHail => false,
+            VehicleType::Truck => false,
         }
     }
 }
@@ -358,6 +390,17 @@ pub enum ParkingSpot {
     Lot(ParkingLotID, usize),
 }
 
+impl ParkingSpot {
+    /// Which ParkingRegulation in the map applies to this spot
+    pub fn regulation_target(&self) -> ParkingRegulationTarget {
+        match self {
+            ParkingSpot::Onstreet(l, _) => ParkingRegulationTarget::Road(l.road),
+            ParkingSpot::Offstreet(b, _) => ParkingRegulationTarget::Garage(*b),
+            ParkingSpot::Lot(pl, _) => ParkingRegulationTarget::Lot(*pl),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ParkedCar {
     pub vehicle: Vehicle,
@@ -474,6 +517,14 @@ impl SidewalkSpot {
         }
     }
 
+    /// Like `bus_stop`, but entering or exiting from somewhere else, like a station entrance.
+    pub fn station_entrance(stop: BusStopID, sidewalk_pos: Position) -> SidewalkSpot {
+        SidewalkSpot {
+            sidewalk_pos,
+            connection: SidewalkPOI::BusStop(stop),
+        }
+    }
+
     // Recall sidewalks are bidirectional.
     pub fn start_at_border(i: IntersectionID, map: &Map) -> Option<SidewalkSpot> {
         let lanes = map
@@ -3,7 +3,7 @@ use rand_xorshift::XorShiftRng;
 
 use abstio::MapName;
 use abstutil::CmdArgs;
-use map_model::{Map, MapEdits};
+use map_model::{Map, MapEdits, TravelTimeProfiles};
 
 use crate::{Scenario, ScenarioModifier, Sim, SimOptions};
 
@@ -18,6 +18,9 @@ pub struct SimFlags {
     pub modifiers: Vec<ScenarioModifier>,
     pub rng_seed: u64,
     pub opts: SimOptions,
+    /// A path to travel times by time of day, from `Analytics::travel_time_profiles`. If set,
+    /// trips route using these.
+    pub travel_time_profiles: Option<String>,
 }
 
 impl SimFlags {
@@ -39,6 +42,7 @@ impl SimFlags {
             modifiers,
             rng_seed,
             opts: SimOptions::from_args(args, rng_seed),
+            travel_time_profiles: args.optional("--travel_time_profiles"),
         }
     }
 
@@ -49,6 +53,7 @@ impl SimFlags {
             modifiers: Vec::new(),
             rng_seed: SimFlags::RNG_SEED,
             opts: SimOptions::new(run_name),
+            travel_time_profiles: None,
         }
     }
 
@@ -81,6 +86,7 @@ impl SimFlags {
                     panic!("Couldn't load edits \"{}\": {}", sim.edits_name, err);
                 }
             }
+            self.load_travel_time_profiles(&mut map, timer);
 
             (map, sim, rng)
         } else if self.load.contains("/scenarios/") {
@@ -88,7 +94,8 @@ impl SimFlags {
 
             let mut scenario: Scenario = abstio::must_read_object(self.load.clone(), timer);
 
-            let map = Map::load_synchronously(scenario.map_name.path(), timer);
+            let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
+            self.load_travel_time_profiles(&mut map, timer);
 
             for m in &self.modifiers {
                 scenario = m.apply(&map, scenario);
@@ -104,7 +111,8 @@ impl SimFlags {
         } else if self.load.contains("/raw_maps/") || self.load.contains("/maps/") {
             info!("Loading map {}", self.load);
 
-            let map = Map::load_synchronously(self.load.clone(), timer);
+            let mut map = Map::load_synchronously(self.load.clone(), timer);
+            self.load_travel_time_profiles(&mut map, timer);
 
             timer.start("create sim");
             let sim = Sim::new(&map, opts);
@@ -115,4 +123,12 @@ impl SimFlags {
             panic!("Don't know how to load {}", self.load);
         }
     }
+
+    fn load_travel_time_profiles(&self, map: &mut Map, timer: &mut abstutil::Timer) {
+        if let Some(ref path) = self.travel_time_profiles {
+            info!("Routing with travel times from {}", path);
+            let profiles: TravelTimeProfiles = abstio::must_read_object(path.clone(), timer);
+            map.set_travel_time_profiles(Some(profiles));
+        }
+    }
 }
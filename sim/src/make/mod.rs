@@ -8,7 +8,10 @@ pub use self::external::{ExternalPerson, ExternalTrip, ExternalTripEndpoint, Map
 pub use self::generator::{BorderSpawnOverTime, ScenarioGenerator, SpawnOverTime};
 pub use self::load::SimFlags;
 pub use self::modifier::ScenarioModifier;
-pub use self::scenario::{IndividTrip, PersonSpec, Scenario, TripPurpose};
+pub use self::scenario::{
+    DeliveryStop, DeliveryTour, DeliveryVehicle, DockSpec, IndividTrip, MicromobilityFleet,
+    MicromobilityVehicle, PersonSpec, Scenario, TripPurpose,
+};
 pub use self::spawner::TripEndpoint;
 pub(crate) use self::spawner::{StartTripArgs, TripSpec};
 
@@ -6,7 +6,7 @@ use abstutil::Timer;
 use geom::{Duration, Time};
 use map_model::Map;
 
-use crate::{Scenario, TripMode};
+use crate::{Scenario, TripEndpoint, TripMode};
 
 /// Transforms an existing Scenario before instantiating it.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
@@ -21,6 +21,12 @@ pub enum ScenarioModifier {
     },
     /// Scenario name
     AddExtraTrips(String),
+    /// Some people who'd pay tolls to drive switch all of their driving trips to another mode.
+    /// For every dollar of the most expensive trip, this percent of those people switch.
+    ShiftModeForTolls {
+        pct_per_dollar: usize,
+        to_mode: TripMode,
+    },
 }
 
 impl ScenarioModifier {
@@ -84,6 +90,43 @@ impl ScenarioModifier {
                 }
                 s
             }
+            ScenarioModifier::ShiftModeForTolls {
+                pct_per_dollar,
+                to_mode,
+            } => {
+                if !map.has_tolls() {
+                    return s;
+                }
+                for (idx, person) in s.people.iter_mut().enumerate() {
+                    let mut max_toll: f64 = 0.0;
+                    for trip in &person.trips {
+                        if trip.cancelled || trip.mode != TripMode::Drive {
+                            continue;
+                        }
+                        if let Some(path) = TripEndpoint::path_req(
+                            trip.origin,
+                            trip.destination,
+                            TripMode::Drive,
+                            map,
+                        )
+                        .and_then(|req| map.pathfind(req.with_departure(trip.depart)).ok())
+                        {
+                            max_toll = max_toll.max(path.get_total_toll(map, trip.depart));
+                        }
+                    }
+                    // Stable as the percentage increases, like ChangeMode
+                    if ((idx % 100) as f64) >= max_toll * (*pct_per_dollar as f64) {
+                        continue;
+                    }
+                    for trip in &mut person.trips {
+                        if !trip.cancelled && trip.mode == TripMode::Drive {
+                            trip.mode = *to_mode;
+                            trip.modified = true;
+                        }
+                    }
+                }
+                s
+            }
         }
     }
 
@@ -105,6 +148,14 @@ impl ScenarioModifier {
                 to_mode.map(|m| m.verb())
             ),
             ScenarioModifier::AddExtraTrips(name) => format!("Add extra trips from {}", name),
+            ScenarioModifier::ShiftModeForTolls {
+                pct_per_dollar,
+                to_mode,
+            } => format!(
+                "{}% of drivers per dollar of tolls switch to {}",
+                pct_per_dollar,
+                to_mode.verb()
+            ),
         }
     }
 }
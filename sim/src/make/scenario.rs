@@ -5,17 +5,18 @@ use anyhow::Result;
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use abstio::MapName;
 use abstutil::{prettyprint_usize, Counter, Timer};
-use geom::{Distance, Speed, Time};
+use geom::{Distance, Duration, Speed, Time};
 use map_model::{BuildingID, Map, OffstreetParking, RoadID};
 
 use crate::make::fork_rng;
 use crate::{
     OrigPersonID, ParkingSpot, Sim, StartTripArgs, TripEndpoint, TripInfo, TripMode, Vehicle,
-    VehicleSpec, VehicleType, BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+    VehicleSpec, VehicleType, BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH, TRUCK_LENGTH,
+    VAN_LENGTH,
 };
 
 /// A Scenario describes all the input to a simulation. Usually a scenario covers one day.
@@ -27,6 +28,21 @@ pub struct Scenario {
     pub people: Vec<PersonSpec>,
     /// None means seed all buses. Otherwise the route name must be present here.
     pub only_seed_buses: Option<BTreeSet<String>>,
+    #[serde(default, deserialize_with = "default_if_missing")]
+    pub deliveries: Vec<DeliveryTour>,
+    /// Shared bikes or scooters for micromobility trips. If there's no fleet, those trips are
+    /// cancelled.
+    #[serde(default, deserialize_with = "default_if_missing")]
+    pub micromobility: Option<MicromobilityFleet>,
+}
+
+/// Binary scenarios written before a trailing field existed just end early, and bincode can't skip
+/// missing fields like JSON can, so running out of input has to be caught here. Any other problem
+/// reading these fields is also treated as missing.
+fn default_if_missing<'de, D: Deserializer<'de>, T: Deserialize<'de> + Default>(
+    d: D,
+) -> Result<T, D::Error> {
+    Ok(T::deserialize(d).unwrap_or_default())
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -71,6 +87,91 @@ impl IndividTrip {
     }
 }
 
+/// A delivery vehicle leaves a depot, stops at some buildings in order to load or unload, then
+/// returns to the depot.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DeliveryTour {
+    pub depart: Time,
+    pub vehicle: DeliveryVehicle,
+    /// Where the tour starts and ends. Can't be SuddenlyAppear.
+    pub depot: TripEndpoint,
+    pub stops: Vec<DeliveryStop>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DeliveryStop {
+    pub building: BuildingID,
+    /// How long it takes to load or unload, once the vehicle is stopped
+    pub dwell: Duration,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum DeliveryVehicle {
+    Van,
+    Truck,
+}
+
+impl DeliveryVehicle {
+    pub(crate) fn spec(self) -> VehicleSpec {
+        // There's no acceleration in the simulation, so heavier vehicles just cruise more slowly.
+        match self {
+            DeliveryVehicle::Van => VehicleSpec {
+                vehicle_type: VehicleType::Truck,
+                length: VAN_LENGTH,
+                max_speed: Some(Speed::miles_per_hour(55.0)),
+            },
+            DeliveryVehicle::Truck => VehicleSpec {
+                vehicle_type: VehicleType::Truck,
+                length: TRUCK_LENGTH,
+                max_speed: Some(Speed::miles_per_hour(45.0)),
+            },
+        }
+    }
+}
+
+/// Shared bikes or scooters, picked up and left either at docks or anywhere.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MicromobilityFleet {
+    pub vehicle: MicromobilityVehicle,
+    /// Stations where vehicles must be picked up and returned
+    pub docks: Vec<DockSpec>,
+    /// Dockless vehicles, each initially left outside one of these buildings
+    pub free_floating: Vec<BuildingID>,
+    /// How far somebody will walk to pick up a vehicle, or from the dock where they return it
+    pub max_walk: Distance,
+    /// Periodically move vehicles from the fullest docks to the emptiest ones. None means docks
+    /// are never rebalanced.
+    pub rebalance_every: Option<Duration>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DockSpec {
+    /// The dock is on the sidewalk in front of this building
+    pub building: BuildingID,
+    pub capacity: usize,
+    /// How many vehicles are here at the start of the simulation
+    pub initial_vehicles: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum MicromobilityVehicle {
+    Bike,
+    Scooter,
+}
+
+impl MicromobilityVehicle {
+    pub(crate) fn spec(self) -> VehicleSpec {
+        VehicleSpec {
+            vehicle_type: VehicleType::Bike,
+            length: BIKE_LENGTH,
+            max_speed: Some(match self {
+                MicromobilityVehicle::Bike => map_model::MAX_BIKE_SPEED,
+                MicromobilityVehicle::Scooter => Speed::miles_per_hour(15.0),
+            }),
+        }
+    }
+}
+
 /// Lifted from Seattle's Soundcast model, but seems general enough to use anyhere.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum TripPurpose {
@@ -188,6 +289,10 @@ impl Scenario {
         seed_parked_cars(parked_cars, sim, map, rng, timer);
 
         sim.spawn_trips(schedule_trips, map, timer);
+        sim.spawn_delivery_tours(&self.deliveries);
+        if let Some(ref fleet) = self.micromobility {
+            sim.spawn_micromobility_fleet(fleet, map);
+        }
         timer.stop(format!("Instantiating {}", self.scenario_name));
     }
 
@@ -204,6 +309,8 @@ impl Scenario {
             map_name: map.get_name().clone(),
             people: Vec::new(),
             only_seed_buses: Some(BTreeSet::new()),
+            deliveries: Vec::new(),
+            micromobility: None,
         }
     }
 
@@ -297,7 +404,7 @@ fn seed_parked_cars(
         for (vehicle, b) in parked_cars {
             timer.next();
             if let Some(spot) = sim.get_free_offstreet_spots(b).pop() {
-                sim.seed_parked_car(vehicle, spot);
+                sim.seed_parked_car(vehicle, spot, b, map);
             } else {
                 blackholed += 1;
             }
@@ -349,7 +456,7 @@ fn seed_parked_cars(
         }
         if let Some(spot) = find_spot_near_building(b, &mut open_spots_per_road, map) {
             seeded += 1;
-            sim.seed_parked_car(vehicle, spot);
+            sim.seed_parked_car(vehicle, spot, b, map);
         } else {
             warn!(
                 "Not enough room to seed parked cars. Only found spots for {} of {}",
@@ -470,7 +577,10 @@ impl PersonSpec {
         // TODO If the trip is cancelled, this should be affected...
         for trip in &self.trips {
             let use_for_trip = match trip.mode {
-                TripMode::Walk | TripMode::Transit => None,
+                TripMode::Walk
+                | TripMode::Transit
+                | TripMode::RideHail
+                | TripMode::Micromobility => None,
                 TripMode::Bike => {
                     if bike_idx.is_none() {
                         bike_idx = Some(vehicle_specs.len());
@@ -536,3 +646,45 @@ impl PersonSpec {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_scenario_without_trailing_fields() {
+        // The layout before deliveries and micromobility were added
+        #[derive(Serialize)]
+        struct OldScenario {
+            scenario_name: String,
+            map_name: MapName,
+            people: Vec<PersonSpec>,
+            only_seed_buses: Option<BTreeSet<String>>,
+        }
+        let old = OldScenario {
+            scenario_name: "old".to_string(),
+            map_name: MapName::seattle("montlake"),
+            people: Vec::new(),
+            only_seed_buses: None,
+        };
+        let scenario: Scenario = bincode::deserialize(&bincode::serialize(&old).unwrap()).unwrap();
+        assert_eq!(scenario.scenario_name, "old");
+        assert!(scenario.deliveries.is_empty());
+        assert!(scenario.micromobility.is_none());
+
+        // Newer scenarios keep the fields
+        let mut scenario = scenario;
+        scenario.micromobility = Some(MicromobilityFleet {
+            vehicle: MicromobilityVehicle::Scooter,
+            docks: Vec::new(),
+            free_floating: vec![BuildingID(3)],
+            max_walk: Distance::meters(100.0),
+            rebalance_every: None,
+        });
+        let copy: Scenario = bincode::deserialize(&bincode::serialize(&scenario).unwrap()).unwrap();
+        assert_eq!(
+            copy.micromobility.unwrap().free_floating,
+            vec![BuildingID(3)]
+        );
+    }
+}
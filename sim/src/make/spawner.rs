@@ -4,9 +4,10 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use geom::Pt2D;
+use geom::{Pt2D, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, PathConstraints, PathRequest, Position,
+    BuildingID, BusRouteID, IntersectionID, Map, PathConstraints, PathRequest, Position,
+    TransitRide,
 };
 
 use crate::{CarID, DrivingGoal, SidewalkSpot, TripLeg, TripMode, VehicleType, SPAWN_DIST};
@@ -53,10 +54,15 @@ pub(crate) enum TripSpec {
     UsingTransit {
         start: SidewalkSpot,
         goal: SidewalkSpot,
-        route: BusRouteID,
-        stop1: BusStopID,
-        maybe_stop2: Option<BusStopID>,
+        /// In order, with transfers between them
+        rides: Vec<TransitRide>,
     },
+    /// Wait inside the start building for a ride-hail vehicle, then ride to the curb outside the
+    /// goal building.
+    UsingRideHail { start: BuildingID, goal: BuildingID },
+    /// Walk to a shared bike or scooter, ride it, then walk to the goal building. Which vehicle
+    /// gets used isn't known until the trip starts.
+    UsingSharedVehicle { start: BuildingID, goal: BuildingID },
 }
 
 impl TripSpec {
@@ -185,23 +191,36 @@ impl TripSpec {
                     .into_plan(map);
                 }
             }
-            TripSpec::UsingTransit {
-                route,
-                stop1,
-                maybe_stop2,
-                goal,
-                ..
-            } => {
-                let walk_to = SidewalkSpot::bus_stop(*stop1, map);
-                if let Some(stop2) = maybe_stop2 {
-                    legs = vec![
-                        TripLeg::Walk(walk_to),
-                        TripLeg::RideBus(*route, Some(*stop2)),
-                        TripLeg::Walk(goal.clone()),
-                    ];
-                } else {
-                    legs = vec![TripLeg::Walk(walk_to), TripLeg::RideBus(*route, None)];
+            TripSpec::UsingTransit { rides, goal, .. } => {
+                for ride in rides {
+                    legs.push(TripLeg::Walk(SidewalkSpot::station_entrance(
+                        ride.board,
+                        ride.walk_to,
+                    )));
+                    legs.push(TripLeg::RideBus(ride.route, ride.alight));
+                }
+                // Otherwise, ride off the map
+                if rides.last().unwrap().alight.is_some() {
+                    legs.push(TripLeg::Walk(goal.clone()));
+                }
+            }
+            TripSpec::UsingRideHail { start, goal } => {
+                if start == goal {
+                    panic!(
+                        "A ride-hail trip from {} to itself doesn't make sense",
+                        start
+                    );
                 }
+                legs.push(TripLeg::RideHail(*goal, None));
+            }
+            TripSpec::UsingSharedVehicle { start, goal } => {
+                if start == goal {
+                    panic!(
+                        "A micromobility trip from {} to itself doesn't make sense",
+                        start
+                    );
+                }
+                // The legs are filled out once a vehicle is reserved
             }
         };
 
@@ -209,13 +228,14 @@ impl TripSpec {
     }
 
     /// Turn an origin/destination pair and mode into a specific plan for instantiating a trip.
-    /// Decisions like how to use public transit happen here.
+    /// Decisions like how to use public transit happen here, based on the schedules at `now`.
     pub fn maybe_new(
         from: TripEndpoint,
         to: TripEndpoint,
         mode: TripMode,
         use_vehicle: Option<CarID>,
         retry_if_no_room: bool,
+        now: Time,
         map: &Map,
     ) -> Result<TripSpec> {
         Ok(match mode {
@@ -274,22 +294,29 @@ impl TripSpec {
             TripMode::Transit => {
                 let start = from.start_sidewalk_spot(map)?;
                 let goal = to.end_sidewalk_spot(map)?;
-                if let Some((stop1, maybe_stop2, route)) =
-                    map.should_use_transit(start.sidewalk_pos, goal.sidewalk_pos)
+                if let Some(itinerary) =
+                    map.plan_transit_trip(start.sidewalk_pos, goal.sidewalk_pos, now)
                 {
-                    TripSpec::UsingTransit {
-                        start,
-                        goal,
-                        route,
-                        stop1,
-                        maybe_stop2,
-                    }
+                    let rides = itinerary.rides(start.sidewalk_pos, map);
+                    TripSpec::UsingTransit { start, goal, rides }
                 } else {
                     //warn!("{:?} not actually using transit, because pathfinding didn't find any
                     // useful route", trip);
                     TripSpec::JustWalking { start, goal }
                 }
             }
+            TripMode::RideHail => match (from, to) {
+                (TripEndpoint::Bldg(start), TripEndpoint::Bldg(goal)) => {
+                    TripSpec::UsingRideHail { start, goal }
+                }
+                _ => bail!("ride-hail trips must start and end at a building"),
+            },
+            TripMode::Micromobility => match (from, to) {
+                (TripEndpoint::Bldg(start), TripEndpoint::Bldg(goal)) => {
+                    TripSpec::UsingSharedVehicle { start, goal }
+                }
+                _ => bail!("micromobility trips must start and end at a building"),
+            },
         })
     }
 }
@@ -318,7 +345,11 @@ impl TripEndpoint {
         let end = to.pos(mode, false, map)?;
         Some(match mode {
             TripMode::Walk | TripMode::Transit => PathRequest::walking(start, end),
-            TripMode::Bike => PathRequest::vehicle(start, end, PathConstraints::Bike),
+            TripMode::Bike | TripMode::Micromobility => {
+                PathRequest::vehicle(start, end, PathConstraints::Bike)
+            }
+            // Ride-hail vehicles are already on the road when they pick somebody up
+            TripMode::RideHail => PathRequest::vehicle(start, end, PathConstraints::Car),
             // Only cars leaving from a building might turn out from the driveway in a special way
             TripMode::Drive => {
                 if matches!(from, TripEndpoint::Bldg(_)) {
@@ -368,7 +399,7 @@ impl TripEndpoint {
         }
     }
 
-    fn pos(self, mode: TripMode, from: bool, map: &Map) -> Option<Position> {
+    pub(crate) fn pos(self, mode: TripMode, from: bool, map: &Map) -> Option<Position> {
         match mode {
             TripMode::Walk | TripMode::Transit => (if from {
                 self.start_sidewalk_spot(map)
@@ -377,7 +408,7 @@ impl TripEndpoint {
             })
             .ok()
             .map(|spot| spot.sidewalk_pos),
-            TripMode::Drive | TripMode::Bike => {
+            TripMode::Drive | TripMode::Bike | TripMode::RideHail | TripMode::Micromobility => {
                 if from {
                     match self {
                         // Fall through and use DrivingGoal also to start.
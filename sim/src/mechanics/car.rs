@@ -55,7 +55,9 @@ impl Car {
             .router
             .get_path()
             .current_step()
-            .max_speed_and_incline_along(
+            .max_speed_and_incline_between(
+                dist_int.start,
+                dist_int.end,
                 self.vehicle.max_speed,
                 self.vehicle.vehicle_type.to_constraints(),
                 map,
@@ -4,19 +4,23 @@ use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_hashmap, serialize_hashmap, FixedMap, IndexableKey};
 use geom::{Distance, Duration, PolyLine, Time};
-use map_model::{DrivingSide, IntersectionID, LaneID, Map, Path, PathStep, Position, Traversable};
+use map_model::{
+    DrivingSide, IntersectionID, LaneID, Map, Path, PathConstraints, PathStep, Position,
+    Traversable,
+};
 
 use crate::mechanics::car::{Car, CarState};
 use crate::mechanics::queue::{Queue, QueueEntry, Queued};
 use crate::sim::Ctx;
 use crate::{
-    ActionAtEnd, AgentID, AgentProperties, CarID, CarStatus, Command, CreateCar, DelayCause,
-    DistanceInterval, DrawCarInput, Event, IntersectionSimState, ParkedCar, ParkingSim,
-    ParkingSpot, PersonID, Problem, SimOptions, TimeInterval, TransitSimState, TripID, TripManager,
-    UnzoomedAgent, Vehicle, VehicleType, WalkingSimState, FOLLOWING_DISTANCE, MAX_CAR_LENGTH,
+    ActionAtEnd, AgentID, AgentProperties, CapSimState, CarID, CarStatus, Command, CreateCar,
+    DelayCause, DistanceInterval, DrawCarInput, Event, FreightSimState, IntersectionSimState,
+    ParkedCar, ParkingSearchParams, ParkingSim, ParkingSpot, PersonID, Problem, RideHailSimState,
+    SimOptions, TimeInterval, TransitSimState, TripID, TripManager, UnzoomedAgent, Vehicle,
+    VehicleType, WalkingSimState, FOLLOWING_DISTANCE, MAX_CAR_LENGTH,
 };
 
-const TIME_TO_WAIT_AT_BUS_STOP: Duration = Duration::const_seconds(10.0);
+pub(crate) const TIME_TO_WAIT_AT_BUS_STOP: Duration = Duration::const_seconds(10.0);
 const TIME_TO_CHANGE_LANES: Duration = Duration::const_seconds(1.0);
 
 // TODO Do something else.
@@ -45,6 +49,8 @@ pub(crate) struct DrivingSimState {
 
     recalc_lanechanging: bool,
     handle_uber_turns: bool,
+    parking_search: ParkingSearchParams,
+    zone_caps: CapSimState,
 
     time_to_unpark_onstreet: Duration,
     time_to_park_onstreet: Duration,
@@ -61,6 +67,8 @@ impl DrivingSimState {
             events: Vec::new(),
             recalc_lanechanging: opts.recalc_lanechanging,
             handle_uber_turns: opts.handle_uber_turns,
+            parking_search: opts.parking_search.clone(),
+            zone_caps: CapSimState::new(),
             waiting_to_spawn: BTreeMap::new(),
 
             time_to_unpark_onstreet: Duration::seconds(10.0),
@@ -232,11 +240,13 @@ impl DrivingSimState {
                 // Have to do this early
                 if car.router.last_step() {
                     match car.router.maybe_handle_end(
+                        now,
                         start_dist,
                         &car.vehicle,
                         ctx.parking,
                         ctx.map,
                         car.trip_and_person,
+                        &self.parking_search,
                         &mut self.events,
                     ) {
                         None | Some(ActionAtEnd::GotoLaneEnd) => {}
@@ -314,6 +324,8 @@ impl DrivingSimState {
         trips: &mut TripManager,
         transit: &mut TransitSimState,
         walking: &mut WalkingSimState,
+        ridehail: &mut RideHailSimState,
+        freight: &mut FreightSimState,
     ) {
         let mut need_distances = {
             let car = &self.cars[&id];
@@ -350,9 +362,9 @@ impl DrivingSimState {
             // checker, temporarily move one of them out of the map.
             let mut car = self.cars.remove(&id).unwrap();
             // Responsibility of update_car_with_distances to manage scheduling stuff!
-            if self
-                .update_car_with_distances(&mut car, &dists, idx, now, ctx, trips, transit, walking)
-            {
+            if self.update_car_with_distances(
+                &mut car, &dists, idx, now, ctx, trips, transit, walking, ridehail, freight,
+            ) {
                 self.cars.insert(id, car);
             } else {
                 self.delete_car_internal(&mut car, dists, idx, now, ctx);
@@ -450,11 +462,13 @@ impl DrivingSimState {
                     // the next loop will pick that up. Just trigger the side effect of choosing an
                     // end_dist.
                     car.router.maybe_handle_end(
+                        now,
                         front,
                         &car.vehicle,
                         ctx.parking,
                         ctx.map,
                         car.trip_and_person,
+                        &self.parking_search,
                         &mut self.events,
                     );
                 }
@@ -465,6 +479,26 @@ impl DrivingSimState {
             CarState::WaitingToAdvance { blocked_since } => {
                 // 'car' is the leader.
                 let from = car.router.head();
+                if let Traversable::Turn(t) = car.router.next() {
+                    let end = car.router.get_path().get_req().end;
+                    if !self
+                        .zone_caps
+                        .allow_entry(now, car.vehicle.id, t, end, ctx.map)
+                    {
+                        let avoid =
+                            self.zone_caps
+                                .roads_to_avoid(now, car.vehicle.id, t.src.road, ctx.map);
+                        if car.router.reroute_avoiding(now, &avoid, ctx.map) {
+                            ctx.intersections
+                                .cancel_request(AgentID::Car(car.vehicle.id), t);
+                            self.events
+                                .push(Event::PathAmended(car.router.get_path().clone()));
+                        } else {
+                            // There's no other way, so go through anyway
+                            self.zone_caps.force_entry(now, car.vehicle.id, t, ctx.map);
+                        }
+                    }
+                }
                 let goto = car.router.next();
                 assert!(from != goto);
 
@@ -493,6 +527,17 @@ impl DrivingSimState {
                             now - blocked_since,
                         ));
                     }
+                    if car.vehicle.vehicle_type.to_constraints() == PathConstraints::Car {
+                        let price = ctx.map.toll_between(t.src.road, t.dst.road, Some(now));
+                        if price > 0.0 {
+                            self.events.push(Event::TollPaid(
+                                car.vehicle.id,
+                                car.trip_and_person.map(|(trip, _)| trip),
+                                t.dst.road,
+                                price,
+                            ));
+                        }
+                    }
                 }
 
                 {
@@ -504,16 +549,33 @@ impl DrivingSimState {
                 // way, until laggy_head is None.
 
                 let last_step = car.router.advance(
+                    now,
                     &car.vehicle,
                     ctx.parking,
                     ctx.map,
                     car.trip_and_person,
+                    &self.parking_search,
                     &mut self.events,
                 );
                 car.total_blocked_time += now - blocked_since;
                 car.state = car.crossing_state(Distance::ZERO, now, ctx.map);
                 ctx.scheduler
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                // Buses and trains check in with the next traffic signal, which might give them
+                // priority
+                if let (Traversable::Lane(_), true) = (goto, car.vehicle.vehicle_type.is_transit())
+                {
+                    if let Some(Traversable::Turn(t)) = car.router.maybe_next() {
+                        ctx.intersections.transit_approaching(
+                            now,
+                            car.vehicle.id,
+                            t,
+                            car.state.get_end_time(),
+                            ctx.scheduler,
+                            ctx.map,
+                        );
+                    }
+                }
                 self.events.push(Event::AgentEntersTraversable(
                     AgentID::Car(car.vehicle.id),
                     car.trip_and_person.map(|(t, _)| t),
@@ -598,6 +660,8 @@ impl DrivingSimState {
         trips: &mut TripManager,
         transit: &mut TransitSimState,
         walking: &mut WalkingSimState,
+        ridehail: &mut RideHailSimState,
+        freight: &mut FreightSimState,
     ) -> bool {
         let our_dist = dists[idx].front;
 
@@ -618,15 +682,20 @@ impl DrivingSimState {
                 }
 
                 match car.router.maybe_handle_end(
+                    now,
                     our_dist,
                     &car.vehicle,
                     ctx.parking,
                     ctx.map,
                     car.trip_and_person,
+                    &self.parking_search,
                     &mut self.events,
                 ) {
                     Some(ActionAtEnd::VanishAtBorder(i)) => {
                         car.total_blocked_time += now - blocked_since;
+                        if car.vehicle.vehicle_type == VehicleType::Truck {
+                            freight.vehicle_left_map(now, car.vehicle.id);
+                        }
                         // Don't do this for buses
                         if car.trip_and_person.is_some() {
                             trips.car_or_bike_reached_border(
@@ -692,10 +761,12 @@ impl DrivingSimState {
                     }
                     Some(ActionAtEnd::BusAtStop) => {
                         car.total_blocked_time += now - blocked_since;
-                        if transit.bus_arrived_at_stop(now, car.vehicle.id, trips, walking, ctx) {
+                        if let Some(dwell) =
+                            transit.bus_arrived_at_stop(now, car.vehicle.id, trips, walking, ctx)
+                        {
                             car.state = CarState::IdlingAtStop(
                                 our_dist,
-                                TimeInterval::new(now, now + TIME_TO_WAIT_AT_BUS_STOP),
+                                TimeInterval::new(now, now + dwell),
                             );
                             ctx.scheduler
                                 .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
@@ -705,6 +776,46 @@ impl DrivingSimState {
                             false
                         }
                     }
+                    Some(ActionAtEnd::StopAtCurb) => {
+                        car.total_blocked_time += now - blocked_since;
+                        if car.vehicle.vehicle_type == VehicleType::Truck {
+                            let lane = car.router.head().as_lane();
+                            return match freight.vehicle_reached_curb(now, car.vehicle.id, lane) {
+                                Some(dwell) => {
+                                    // Double-park, blocking the lane
+                                    car.state = CarState::IdlingAtStop(
+                                        our_dist,
+                                        TimeInterval::new(now, now + dwell),
+                                    );
+                                    ctx.scheduler.push(
+                                        car.state.get_end_time(),
+                                        Command::UpdateCar(car.vehicle.id),
+                                    );
+                                    true
+                                }
+                                // Back at the depot
+                                None => false,
+                            };
+                        }
+                        // Only count delay for the current passenger, not the whole shift
+                        let blocked_time =
+                            std::mem::replace(&mut car.total_blocked_time, Duration::ZERO);
+                        car.trip_and_person = ridehail.vehicle_reached_curb(
+                            now,
+                            car.vehicle.id,
+                            blocked_time,
+                            car.router.get_path().total_length(),
+                            trips,
+                            ctx,
+                        );
+                        car.state = CarState::IdlingAtStop(
+                            our_dist,
+                            TimeInterval::new(now, now + ridehail.curb_dwell_time()),
+                        );
+                        ctx.scheduler
+                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                        true
+                    }
                     None => {
                         ctx.scheduler.push(
                             now + BLIND_RETRY_TO_REACH_END_DIST,
@@ -740,18 +851,62 @@ impl DrivingSimState {
                     spot,
                     parked_since: now,
                 });
-                trips.car_reached_parking_spot(
-                    now,
-                    car.vehicle.id,
-                    spot,
-                    car.total_blocked_time,
-                    car.router.get_path().total_length(),
-                    ctx,
-                );
+                if car.vehicle.vehicle_type == VehicleType::Truck {
+                    freight.vehicle_parked(now, car.vehicle.id, spot, ctx);
+                } else {
+                    trips.car_reached_parking_spot(
+                        now,
+                        car.vehicle.id,
+                        spot,
+                        car.total_blocked_time,
+                        car.router.get_path().total_length(),
+                        ctx,
+                    );
+                }
                 false
             }
             CarState::IdlingAtStop(dist, _) => {
-                car.router = transit.bus_departed_from_stop(car.vehicle.id, ctx.map);
+                if car.vehicle.vehicle_type == VehicleType::RideHail {
+                    let pos = Position::new(car.router.head().as_lane(), dist);
+                    match ridehail.vehicle_leaving_curb(now, car.vehicle.id, pos, trips, ctx) {
+                        Some((router, passenger)) => {
+                            car.router = router;
+                            car.trip_and_person = passenger;
+                        }
+                        None => {
+                            // Nothing to do, so get out of the way until the next request
+                            return false;
+                        }
+                    }
+                } else if car.vehicle.vehicle_type == VehicleType::Truck {
+                    // Everybody stuck behind the double-parked vehicle finds out now
+                    let lane = car.router.head().as_lane();
+                    for entry in &dists[idx + 1..] {
+                        if let Queued::Vehicle(follower) = entry.member {
+                            let follower = &self.cars[&follower];
+                            if let (CarState::Queued { .. }, Some((trip, _))) =
+                                (&follower.state, follower.trip_and_person)
+                            {
+                                self.events.push(Event::ProblemEncountered(
+                                    trip,
+                                    Problem::BlockedByDoubleParking(lane),
+                                ));
+                            }
+                        }
+                    }
+
+                    let pos = Position::new(lane, dist);
+                    match freight.vehicle_leaving_curb(now, car.vehicle.id, pos, ctx) {
+                        Some(router) => {
+                            car.router = router;
+                        }
+                        None => {
+                            return false;
+                        }
+                    }
+                } else {
+                    car.router = transit.bus_departed_from_stop(car.vehicle.id, ctx.map);
+                }
                 self.events
                     .push(Event::PathAmended(car.router.get_path().clone()));
                 car.state = car.crossing_state(dist, now, ctx.map);
@@ -831,6 +986,7 @@ impl DrivingSimState {
         }
 
         ctx.intersections.vehicle_gone(car.vehicle.id);
+        self.events.push(Event::VehicleRemoved(car.vehicle.id));
 
         // We might be vanishing while partly clipping into other stuff.
         self.trim_last_steps(car, now, car.last_steps.len(), ctx);
@@ -1541,7 +1697,11 @@ impl DrivingSimState {
                 }
             }) {
                 // TODO Buses aren't handled yet! Mostly not a big deal, because they're pretty
-                // much never created anyway.
+                // much never created anyway. Ride-hail vehicles aren't either; the fleet would
+                // lose track of them.
+                if car.vehicle.vehicle_type == VehicleType::RideHail {
+                    continue;
+                }
                 if let Some((trip, _)) = car.trip_and_person {
                     affected.push((AgentID::Car(car.vehicle.id), trip));
                 }
@@ -13,10 +13,14 @@ use crate::mechanics::car::{Car, CarState};
 use crate::mechanics::Queue;
 use crate::{
     AgentID, AlertLocation, CarID, Command, DelayCause, Event, Scheduler, SimOptions, Speed,
+    VehicleType,
 };
 
 const WAIT_AT_STOP_SIGN: Duration = Duration::const_seconds(0.5);
 const WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL: Duration = Duration::const_seconds(0.2);
+/// When holding a green for an approaching transit vehicle, leave a little slack in case it
+/// arrives later than expected.
+const TRANSIT_PRIORITY_BUFFER: Duration = Duration::const_seconds(2.0);
 
 /// Manages conflicts at intersections. When an agent has reached the end of a lane, they call
 /// maybe_start_turn to make a Request. Based on the intersection type (stop sign, traffic signal,
@@ -78,6 +82,19 @@ struct SignalState {
     stage_ends_at: Time,
     // The number of times a variable signal has been extended during the current stage.
     extensions_count: usize,
+    // When the current stage started
+    stage_started_at: Time,
+    // Transit signal priority can only change each stage once
+    priority_used: bool,
+    // An external controller decides when to advance the stage, instead of the signal's timing
+    external_control: bool,
+    // Buses and trains that checked in on their way here, the turn they'll want, and when they
+    // expect to arrive
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    approaching_transit: BTreeMap<CarID, (TurnID, Time)>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Debug)]
@@ -184,6 +201,71 @@ impl IntersectionSimState {
     /// turn.
     pub fn vehicle_gone(&mut self, car: CarID) {
         self.blocked_by.retain(|(c1, c2)| *c1 != car && *c2 != car);
+        if car.vehicle_type.is_transit() {
+            for signal_state in self.state.values_mut().filter_map(|s| s.signal.as_mut()) {
+                signal_state.approaching_transit.remove(&car);
+            }
+        }
+    }
+
+    /// A bus or train just started the lane leading to this turn, and expects to reach the
+    /// intersection at `eta`. If the signal gives transit priority and the turn is red right now,
+    /// maybe end the current stage early.
+    pub fn transit_approaching(
+        &mut self,
+        now: Time,
+        car: CarID,
+        turn: TurnID,
+        eta: Time,
+        scheduler: &mut Scheduler,
+        map: &Map,
+    ) {
+        let signal = match map.maybe_get_traffic_signal(turn.parent) {
+            Some(signal) => signal,
+            None => return,
+        };
+        let priority = match signal.transit_priority {
+            Some(ref priority) => priority,
+            None => return,
+        };
+        if self.use_freeform_policy_everywhere {
+            return;
+        }
+        let i = map.get_i(turn.parent);
+        let signal_state = self
+            .state
+            .get_mut(&turn.parent)
+            .unwrap()
+            .signal
+            .as_mut()
+            .unwrap();
+        signal_state.approaching_transit.insert(car, (turn, eta));
+        if signal_state.external_control {
+            return;
+        }
+
+        // Only end the current stage early if it's blocking the vehicle, and the next stage lets
+        // it go.
+        let current = signal_state.current_stage;
+        let next = (current + 1) % signal.stages.len();
+        if signal.stages[current].get_priority_of_turn(turn, i) != TurnPriority::Banned
+            || signal.stages[next].get_priority_of_turn(turn, i) == TurnPriority::Banned
+        {
+            return;
+        }
+        let min_duration = priority
+            .min_stage_before_early_green
+            .max(signal.get_min_crossing_time(current, i));
+        if let Some(end) = signal_state.early_green(now, min_duration) {
+            self.events.push(Event::TransitSignalPriority(
+                turn.parent,
+                car,
+                end - signal_state.stage_ends_at,
+            ));
+            signal_state.priority_used = true;
+            signal_state.stage_ends_at = end;
+            scheduler.update(end, Command::UpdateIntersection(turn.parent));
+        }
     }
 
     pub fn agent_deleted_mid_turn(&mut self, agent: AgentID, turn: TurnID) {
@@ -282,28 +364,6 @@ impl IntersectionSimState {
     ) {
         let i = map.get_i(id);
 
-        // trivial function that advances the signal stage and returns duration
-        fn advance(
-            signal_state: &mut SignalState,
-            signal: &ControlTrafficSignal,
-            i: &Intersection,
-            allow_crosswalk_skip: bool,
-        ) -> Duration {
-            signal_state.current_stage = (signal_state.current_stage + 1) % signal.stages.len();
-            let stage = &signal.stages[signal_state.current_stage];
-            // only skip for variable all-walk crosswalk
-            if let StageType::Variable(_, _, _) = stage.stage_type {
-                if allow_crosswalk_skip && stage.max_crosswalk_time(i).is_some() {
-                    // we can skip this stage, as its all walk and we're allowed to skip (no
-                    // pedestrian waiting).
-                    signal_state.current_stage =
-                        (signal_state.current_stage + 1) % signal.stages.len();
-                }
-            }
-            signal.stages[signal_state.current_stage]
-                .stage_type
-                .simple_duration()
-        }
         let state = self.state.get_mut(&id).unwrap();
         let signal_state = state.signal.as_mut().unwrap();
         let signal = map.get_traffic_signal(id);
@@ -317,9 +377,29 @@ impl IntersectionSimState {
         // Switch to a new stage?
         assert_eq!(now, signal_state.stage_ends_at);
         let old_stage = &signal.stages[signal_state.current_stage];
+
+        // Hold the green for a bus or train that's almost here?
+        signal_state
+            .approaching_transit
+            .retain(|_, (_, eta)| *eta >= now);
+        if let Some(ref priority) = signal.transit_priority {
+            if let Some((car, extension)) =
+                signal_state.green_extension(now, priority.max_green_extension, |turn| {
+                    old_stage.get_priority_of_turn(turn, i) != TurnPriority::Banned
+                })
+            {
+                self.events
+                    .push(Event::TransitSignalPriority(id, car, extension));
+                signal_state.priority_used = true;
+                signal_state.stage_ends_at = now + extension;
+                scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+                return;
+            }
+        }
+
         match old_stage.stage_type {
             StageType::Fixed(_) => {
-                duration = advance(signal_state, signal, i, !ped_waiting);
+                duration = advance(now, signal_state, signal, i, !ped_waiting);
             }
             StageType::Variable(min, delay, additional) => {
                 // test if anyone is waiting in current stage, and if so, extend the signal cycle.
@@ -337,7 +417,7 @@ impl IntersectionSimState {
                             min, delay, additional, signal_state.extensions_count
                         ),
                     ));
-                    duration = advance(signal_state, signal, i, !ped_waiting);
+                    duration = advance(now, signal_state, signal, i, !ped_waiting);
                     signal_state.extensions_count = 0;
                 } else if state.waiting.keys().all(|req| {
                     if let AgentID::Pedestrian(_) = req.agent {
@@ -348,7 +428,7 @@ impl IntersectionSimState {
                     old_stage.get_priority_of_turn(req.turn, i) != TurnPriority::Protected
                 }) {
                     signal_state.extensions_count = 0;
-                    duration = advance(signal_state, signal, i, !ped_waiting);
+                    duration = advance(now, signal_state, signal, i, !ped_waiting);
                 } else {
                     signal_state.extensions_count += 1;
                     duration = delay;
@@ -368,6 +448,56 @@ impl IntersectionSimState {
         self.wakeup_waiting(now, id, scheduler, map);
     }
 
+    /// Stop a traffic signal from changing stages by itself, or return it to its normal timing.
+    /// While controlled externally, the current stage lasts until `advance_signal` is called.
+    pub fn set_external_signal_control(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        enabled: bool,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal_state = self.state.get_mut(&id).unwrap().signal.as_mut().unwrap();
+        if signal_state.external_control == enabled {
+            return;
+        }
+        signal_state.external_control = enabled;
+        signal_state.extensions_count = 0;
+        if enabled {
+            scheduler.cancel(Command::UpdateIntersection(id));
+        } else {
+            // Let the current stage finish, counting from when it started
+            let duration = map.get_traffic_signal(id).stages[signal_state.current_stage]
+                .stage_type
+                .simple_duration();
+            signal_state.stage_ends_at = now.max(signal_state.stage_started_at + duration);
+            scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+        }
+    }
+
+    /// Immediately switch an externally controlled traffic signal to its next stage.
+    pub fn advance_signal(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal_state = self.state.get_mut(&id).unwrap().signal.as_mut().unwrap();
+        assert!(signal_state.external_control);
+        // The controller asked for the next stage, so don't skip any
+        advance(
+            now,
+            signal_state,
+            map.get_traffic_signal(id),
+            map.get_i(id),
+            false,
+        );
+        signal_state.stage_ends_at = now;
+        self.wakeup_waiting(now, id, scheduler, map);
+    }
+
     /// For cars: The head car calls this when they're at the end of the lane WaitingToAdvance. If
     /// this returns true, then the head car MUST actually start this turn.
     /// For peds: Likewise -- only called when the ped is at the start of the turn. They must
@@ -445,6 +575,13 @@ impl IntersectionSimState {
             }
 
             true
+        } else if agent.to_vehicle_type() == Some(VehicleType::Train) {
+            // Trains ignore road traffic control and follow block signals instead. Each lane of
+            // track is a block, and a train can only enter one once nobody else is in it. This
+            // keeps trains apart by at least one block.
+            readonly_pair
+                .map(|(_, queues)| queues[&Traversable::Lane(turn.dst)].is_empty())
+                .unwrap_or(true)
         } else if self.use_freeform_policy_everywhere {
             // If we made it this far, we don't conflict with an accepted turn
             true
@@ -561,6 +698,9 @@ impl IntersectionSimState {
         let state = self.state.get_mut(&turn.parent).unwrap();
         state.waiting.remove(&req).unwrap();
         state.accepted.insert(req);
+        if let (AgentID::Car(car), Some(signal_state)) = (agent, state.signal.as_mut()) {
+            signal_state.approaching_transit.remove(&car);
+        }
         if self.break_turn_conflict_cycles {
             if let AgentID::Car(car) = agent {
                 self.blocked_by.retain(|(c, _)| *c != car);
@@ -707,6 +847,10 @@ impl IntersectionSimState {
         i: IntersectionID,
     ) -> (usize, Duration) {
         let state = &self.state[&i].signal.as_ref().unwrap();
+        // The stage lasts until the controller says otherwise
+        if state.external_control {
+            return (state.current_stage, Duration::ZERO);
+        }
         if now > state.stage_ends_at {
             panic!(
                 "At {}, but {} should have advanced its stage at {}",
@@ -716,6 +860,18 @@ impl IntersectionSimState {
         (state.current_stage, state.stage_ends_at - now)
     }
 
+    pub fn time_in_current_stage(&self, now: Time, i: IntersectionID) -> Duration {
+        now - self.state[&i].signal.as_ref().unwrap().stage_started_at
+    }
+
+    pub fn is_signal_externally_controlled(&self, i: IntersectionID) -> bool {
+        self.state[&i]
+            .signal
+            .as_ref()
+            .map(|signal| signal.external_control)
+            .unwrap_or(false)
+    }
+
     pub fn describe_stats(&self) -> Vec<String> {
         vec![
             "intersection stats".to_string(),
@@ -859,7 +1015,13 @@ impl IntersectionSimState {
         let signal_state = state.signal.as_ref().unwrap();
         let stage = &signal.stages[signal_state.current_stage];
         let full_stage_duration = stage.stage_type.simple_duration();
-        let remaining_stage_time = signal_state.stage_ends_at - now;
+        // There's no telling when an external controller will change the stage, so don't hold
+        // anybody back.
+        let remaining_stage_time = if signal_state.external_control {
+            full_stage_duration
+        } else {
+            signal_state.stage_ends_at - now
+        };
         let (our_time, _) = state.waiting[req];
 
         // Can't go at all this stage.
@@ -1033,11 +1195,50 @@ impl IntersectionSimState {
 }
 
 impl SignalState {
+    /// If transit priority hasn't been used yet this stage, returns the approaching vehicle to
+    /// hold the current green for, and how long to extend it. Only vehicles whose turn is green
+    /// and that'll arrive within `max_extension` count.
+    fn green_extension<F: Fn(TurnID) -> bool>(
+        &self,
+        now: Time,
+        max_extension: Duration,
+        is_green: F,
+    ) -> Option<(CarID, Duration)> {
+        if self.priority_used {
+            return None;
+        }
+        self.approaching_transit
+            .iter()
+            .filter(|(_, (turn, eta))| {
+                is_green(*turn) && *eta + TRANSIT_PRIORITY_BUFFER - now <= max_extension
+            })
+            .map(|(car, (_, eta))| (*car, *eta))
+            .max_by_key(|(_, eta)| *eta)
+            .map(|(car, eta)| (car, eta + TRANSIT_PRIORITY_BUFFER - now))
+    }
+
+    /// If transit priority hasn't been used yet this stage, returns when to end the current stage
+    /// early, keeping it for at least `min_duration`. None if that's no earlier than planned.
+    fn early_green(&self, now: Time, min_duration: Duration) -> Option<Time> {
+        if self.priority_used {
+            return None;
+        }
+        let end = now.max(self.stage_started_at + min_duration);
+        if end >= self.stage_ends_at {
+            return None;
+        }
+        Some(end)
+    }
+
     fn new(id: IntersectionID, now: Time, map: &Map, scheduler: &mut Scheduler) -> SignalState {
         let mut state = SignalState {
             current_stage: 0,
             stage_ends_at: now,
             extensions_count: 0,
+            stage_started_at: now,
+            priority_used: false,
+            external_control: false,
+            approaching_transit: BTreeMap::new(),
         };
 
         let signal = map.get_traffic_signal(id);
@@ -1054,6 +1255,9 @@ impl SignalState {
                     state.current_stage = 0;
                 }
             } else {
+                // The stage may have started before the simulation did
+                state.stage_started_at =
+                    Time::START_OF_DAY + ((now - Time::START_OF_DAY) - offset).max(Duration::ZERO);
                 state.stage_ends_at = now + dt - offset;
                 break;
             }
@@ -1063,6 +1267,31 @@ impl SignalState {
     }
 }
 
+/// Advances the signal to the next stage and returns how long it should last.
+fn advance(
+    now: Time,
+    signal_state: &mut SignalState,
+    signal: &ControlTrafficSignal,
+    i: &Intersection,
+    allow_crosswalk_skip: bool,
+) -> Duration {
+    signal_state.stage_started_at = now;
+    signal_state.priority_used = false;
+    signal_state.current_stage = (signal_state.current_stage + 1) % signal.stages.len();
+    let stage = &signal.stages[signal_state.current_stage];
+    // only skip for variable all-walk crosswalk
+    if let StageType::Variable(_, _, _) = stage.stage_type {
+        if allow_crosswalk_skip && stage.max_crosswalk_time(i).is_some() {
+            // we can skip this stage, as its all walk and we're allowed to skip (no pedestrian
+            // waiting).
+            signal_state.current_stage = (signal_state.current_stage + 1) % signal.stages.len();
+        }
+    }
+    signal.stages[signal_state.current_stage]
+        .stage_type
+        .simple_duration()
+}
+
 fn allow_block_the_box(i: &Intersection) -> bool {
     // Degenerate intersections are often just artifacts of how roads are split up in OSM. Allow
     // vehicles to get stuck in them, since the only possible thing they could block is pedestrians
@@ -1112,3 +1341,105 @@ fn allow_block_the_box(i: &Intersection) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use map_model::RoadID;
+
+    use super::*;
+
+    fn turn(idx: usize) -> TurnID {
+        TurnID {
+            parent: IntersectionID(0),
+            src: LaneID {
+                road: RoadID(idx),
+                offset: 0,
+            },
+            dst: LaneID {
+                road: RoadID(idx + 1),
+                offset: 0,
+            },
+        }
+    }
+
+    fn bus(id: usize) -> CarID {
+        CarID {
+            id,
+            vehicle_type: VehicleType::Bus,
+        }
+    }
+
+    /// The current stage started at 0s and is planned to end at 30s.
+    fn signal_state() -> SignalState {
+        SignalState {
+            current_stage: 0,
+            stage_ends_at: Time::START_OF_DAY + Duration::seconds(30.0),
+            extensions_count: 0,
+            stage_started_at: Time::START_OF_DAY,
+            priority_used: false,
+            external_control: false,
+            approaching_transit: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_green_extension() {
+        let t = |secs| Time::START_OF_DAY + Duration::seconds(secs);
+        let now = t(30.0);
+        let max = Duration::seconds(10.0);
+        let mut state = signal_state();
+        // Too far away to wait for
+        state.approaching_transit.insert(bus(1), (turn(1), t(45.0)));
+        assert_eq!(state.green_extension(now, max, |_| true), None);
+
+        // Hold the green for the last vehicle that'll make it, plus a buffer
+        state.approaching_transit.insert(bus(2), (turn(1), t(33.0)));
+        state.approaching_transit.insert(bus(3), (turn(1), t(36.0)));
+        assert_eq!(
+            state.green_extension(now, max, |_| true),
+            Some((bus(3), Duration::seconds(8.0)))
+        );
+        // Vehicles facing a red don't count
+        assert_eq!(
+            state.green_extension(now, max, |turn| turn.src.road == RoadID(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_early_green() {
+        let t = |secs| Time::START_OF_DAY + Duration::seconds(secs);
+        let state = signal_state();
+        // The stage has to last for the minimum first
+        assert_eq!(
+            state.early_green(t(2.0), Duration::seconds(10.0)),
+            Some(t(10.0))
+        );
+        // After that, end it right away
+        assert_eq!(
+            state.early_green(t(15.0), Duration::seconds(10.0)),
+            Some(t(15.0))
+        );
+        // Never extend a stage by "ending it early"
+        assert_eq!(state.early_green(t(2.0), Duration::seconds(40.0)), None);
+    }
+
+    #[test]
+    fn test_priority_once_per_stage() {
+        let t = |secs| Time::START_OF_DAY + Duration::seconds(secs);
+        let mut state = signal_state();
+        state.approaching_transit.insert(bus(1), (turn(1), t(33.0)));
+        state.priority_used = true;
+        assert_eq!(
+            state.green_extension(t(30.0), Duration::seconds(10.0), |_| true),
+            None
+        );
+        assert_eq!(state.early_green(t(15.0), Duration::seconds(10.0)), None);
+
+        // The next stage can use it again
+        state.priority_used = false;
+        assert!(state
+            .green_extension(t(30.0), Duration::seconds(10.0), |_| true)
+            .is_some());
+    }
+}
@@ -1,4 +1,4 @@
-pub(crate) use self::driving::DrivingSimState;
+pub(crate) use self::driving::{DrivingSimState, TIME_TO_WAIT_AT_BUS_STOP};
 pub(crate) use self::intersection::IntersectionSimState;
 pub(crate) use self::parking::{ParkingSim, ParkingSimState};
 pub(crate) use self::queue::Queue;
@@ -9,18 +9,20 @@ use abstutil::{
     deserialize_btreemap, deserialize_multimap, serialize_btreemap, serialize_multimap, MultiMap,
     Timer,
 };
-use geom::{Distance, PolyLine, Pt2D};
+use geom::{Distance, Duration, PolyLine, Pt2D, Time};
 use map_model::{
     BuildingID, Lane, LaneID, LaneType, Map, OffstreetParking, ParkingLotID, PathConstraints,
     PathStep, Position, Traversable, TurnID,
 };
 
-use crate::{CarID, CarStatus, DrawCarInput, Event, ParkedCar, ParkingSpot, PersonID, Vehicle};
+use crate::{
+    CarID, CarStatus, DrawCarInput, Event, ParkedCar, ParkingSpot, PersonID, Vehicle, VehicleType,
+};
 
 /// Manages the state of parked cars. There are two implementations:
 /// - NormalParkingSimState allows only one vehicle per ParkingSpot defined in the map
 /// - InfiniteParkingSimState pretends every building has infinite capacity, and onstreet parking is
-///   ignored
+///   ignored. Permit-only parking regulations are ignored too.
 #[enum_dispatch(ParkingSimState)]
 pub trait ParkingSim {
     /// Returns any cars that got very abruptly evicted from existence, and also cars actively
@@ -34,6 +36,13 @@ pub trait ParkingSim {
     fn unreserve_spot(&mut self, car: CarID);
     fn remove_parked_car(&mut self, p: ParkedCar);
     fn add_parked_car(&mut self, p: ParkedCar);
+    /// The car may use parking restricted to this residential permit zone, and doesn't pay there.
+    fn grant_permit(&mut self, car: CarID, zone: String);
+    /// When the car will next be needed, or None if it stays parked for the rest of the
+    /// simulation. Spots with a shorter time limit are excluded when the car looks for parking.
+    fn plan_departure(&mut self, car: CarID, departure: Option<Time>);
+    /// What this car would pay to park at the spot for an hour, accounting for any permit it holds.
+    fn hourly_price(&self, spot: ParkingSpot, car: CarID, map: &Map) -> f64;
     fn get_draw_cars(&self, id: LaneID, map: &Map) -> Vec<DrawCarInput>;
     fn get_draw_cars_in_lots(&self, id: LaneID, map: &Map) -> Vec<DrawCarInput>;
     fn get_draw_car(&self, id: CarID, map: &Map) -> Option<DrawCarInput>;
@@ -43,7 +52,8 @@ pub trait ParkingSim {
     fn is_free(&self, spot: ParkingSpot) -> bool;
     fn get_car_at_spot(&self, spot: ParkingSpot) -> Option<&ParkedCar>;
     /// The vehicle's front is currently at the given driving_pos. Returns all valid spots and their
-    /// driving position.
+    /// driving position. Spots reserved for a permit zone the vehicle doesn't belong to are
+    /// excluded, as are spots whose time limit is shorter than the car's planned stay.
     fn get_all_free_spots(
         &self,
         driving_pos: Position,
@@ -51,6 +61,7 @@ pub trait ParkingSim {
         // Either the building where a seeded car starts or the target of a trip. For filtering
         // private spots.
         target: BuildingID,
+        now: Time,
         map: &Map,
     ) -> Vec<(ParkingSpot, Position)>;
     fn spot_to_driving_pos(&self, spot: ParkingSpot, vehicle: &Vehicle, map: &Map) -> Position;
@@ -65,11 +76,15 @@ pub trait ParkingSim {
     /// the implementation has some internal jitter between different vehicles, to discourage
     /// everybody near one spot from all competing for it.
     /// Note the first PathStep is the turn after start, NOT PathStep::Lane(start).
+    /// If `max_walking_distance` is specified, only consider spots within that straight-line
+    /// distance of the target.
     fn path_to_free_parking_spot(
         &self,
         start: LaneID,
         vehicle: &Vehicle,
         target: BuildingID,
+        max_walking_distance: Option<Distance>,
+        now: Time,
         map: &Map,
     ) -> Option<(Vec<PathStep>, ParkingSpot, Position)>;
     fn collect_events(&mut self) -> Vec<Event>;
@@ -147,6 +162,17 @@ pub struct NormalParkingSimState {
     )]
     driving_to_lots: MultiMap<LaneID, ParkingLotID>,
 
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    permits: BTreeMap<CarID, String>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    planned_departures: BTreeMap<CarID, Time>,
+
     events: Vec<Event>,
 }
 
@@ -164,6 +190,9 @@ impl NormalParkingSimState {
             num_spots_per_lot: BTreeMap::new(),
             driving_to_lots: MultiMap::new(),
 
+            permits: BTreeMap::new(),
+            planned_departures: BTreeMap::new(),
+
             events: Vec::new(),
         };
         for l in map.all_lanes() {
@@ -334,6 +363,24 @@ impl ParkingSim for NormalParkingSimState {
         self.parked_cars.insert(p.vehicle.id, p);
     }
 
+    fn grant_permit(&mut self, car: CarID, zone: String) {
+        self.events
+            .push(Event::ParkingPermitGranted(car, zone.clone()));
+        self.permits.insert(car, zone);
+    }
+
+    fn plan_departure(&mut self, car: CarID, departure: Option<Time>) {
+        if let Some(t) = departure {
+            self.planned_departures.insert(car, t);
+        } else {
+            self.planned_departures.remove(&car);
+        }
+    }
+
+    fn hourly_price(&self, spot: ParkingSpot, car: CarID, map: &Map) -> f64 {
+        hourly_price(&self.permits, spot, car, map)
+    }
+
     fn get_draw_cars(&self, id: LaneID, map: &Map) -> Vec<DrawCarInput> {
         let mut cars = Vec::new();
         if let Some(lane) = self.onstreet_lanes.get(&id) {
@@ -446,6 +493,7 @@ impl ParkingSim for NormalParkingSimState {
         // Either the building where a seeded car starts or the target of a trip. For filtering
         // private spots.
         target: BuildingID,
+        now: Time,
         map: &Map,
     ) -> Vec<(ParkingSpot, Position)> {
         let mut candidates = Vec::new();
@@ -489,8 +537,21 @@ impl ParkingSim for NormalParkingSimState {
             }
         }
 
+        let permit = self.permits.get(&vehicle.id);
+        // Cars without a later trip don't plan to leave, so they aren't held to time limits.
+        let stay = self.planned_departures.get(&vehicle.id).map(|t| *t - now);
         candidates
             .into_iter()
+            .filter(|spot| {
+                map.get_parking_regulation(spot.regulation_target())
+                    .map(|reg| {
+                        reg.allows(permit, vehicle.vehicle_type == VehicleType::Truck)
+                            && !stay
+                                .map(|stay| reg.is_overstay(stay, permit))
+                                .unwrap_or(false)
+                    })
+                    .unwrap_or(true)
+            })
             .map(|spot| (spot, self.spot_to_driving_pos(spot, vehicle, map)))
             .collect()
     }
@@ -563,8 +624,11 @@ impl ParkingSim for NormalParkingSimState {
         start: LaneID,
         vehicle: &Vehicle,
         target: BuildingID,
+        max_walking_distance: Option<Distance>,
+        now: Time,
         map: &Map,
     ) -> Option<(Vec<PathStep>, ParkingSpot, Position)> {
+        let target_pt = map.get_b(target).sidewalk_pos.pt(map);
         let mut backrefs: HashMap<LaneID, TurnID> = HashMap::new();
         // Don't travel far.
         // This is a max-heap, so negate all distances. Tie breaker is lane ID, arbitrary but
@@ -584,12 +648,25 @@ impl ParkingSim for NormalParkingSimState {
             // If the current lane has a spot open, we wouldn't be asking. This can happen if a spot
             // opens up on the 'start' lane, but behind the car.
             if current != start {
-                // Pick the closest to the start of the lane, since that's closest to where we came
-                // from
+                // Pick the cheapest, then the closest to the start of the lane, since that's
+                // closest to where we came from
                 if let Some((spot, pos)) = self
-                    .get_all_free_spots(Position::start(current), vehicle, target, map)
+                    .get_all_free_spots(Position::start(current), vehicle, target, now, map)
                     .into_iter()
-                    .min_by_key(|(_, pos)| pos.dist_along())
+                    .filter(|(spot, _)| {
+                        max_walking_distance
+                            .map(|max| {
+                                self.spot_to_sidewalk_pos(*spot, map)
+                                    .pt(map)
+                                    .dist_to(target_pt)
+                                    <= max
+                            })
+                            .unwrap_or(true)
+                    })
+                    .min_by_key(|(spot, pos)| {
+                        let price = self.hourly_price(*spot, vehicle.id, map);
+                        ((price * 100.0).round() as usize, pos.dist_along())
+                    })
                 {
                     let mut steps = vec![PathStep::Lane(current)];
                     let mut current = current;
@@ -656,6 +733,17 @@ impl ParkingSim for NormalParkingSimState {
     }
 }
 
+fn hourly_price(
+    permits: &BTreeMap<CarID, String>,
+    spot: ParkingSpot,
+    car: CarID,
+    map: &Map,
+) -> f64 {
+    map.get_parking_regulation(spot.regulation_target())
+        .map(|reg| reg.cost(Duration::hours(1), permits.get(&car)))
+        .unwrap_or(0.0)
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct ParkingLane {
     parking_lane: LaneID,
@@ -753,6 +841,12 @@ pub struct InfiniteParkingSimState {
     // An optimization for get_free_bldg_spot
     num_occupants_per_offstreet: BTreeMap<BuildingID, usize>,
 
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    permits: BTreeMap<CarID, String>,
+
     events: Vec<Event>,
 }
 
@@ -768,6 +862,8 @@ impl InfiniteParkingSimState {
 
             num_occupants_per_offstreet: BTreeMap::new(),
 
+            permits: BTreeMap::new(),
+
             events: Vec::new(),
         };
         let mut blackholes = Vec::new();
@@ -896,6 +992,19 @@ impl ParkingSim for InfiniteParkingSimState {
         self.parked_cars.insert(p.vehicle.id, p);
     }
 
+    fn grant_permit(&mut self, car: CarID, zone: String) {
+        self.events
+            .push(Event::ParkingPermitGranted(car, zone.clone()));
+        self.permits.insert(car, zone);
+    }
+
+    // Infinite parking only uses offstreet spots, which never have time limits.
+    fn plan_departure(&mut self, _: CarID, _: Option<Time>) {}
+
+    fn hourly_price(&self, spot: ParkingSpot, car: CarID, map: &Map) -> f64 {
+        hourly_price(&self.permits, spot, car, map)
+    }
+
     fn get_draw_cars(&self, _: LaneID, _: &Map) -> Vec<DrawCarInput> {
         Vec::new()
     }
@@ -934,6 +1043,7 @@ impl ParkingSim for InfiniteParkingSimState {
         driving_pos: Position,
         vehicle: &Vehicle,
         target: BuildingID,
+        _: Time,
         map: &Map,
     ) -> Vec<(ParkingSpot, Position)> {
         // The target building may be blackholed, so fallback to a building on one of the
@@ -989,6 +1099,9 @@ impl ParkingSim for InfiniteParkingSimState {
         start: LaneID,
         vehicle: &Vehicle,
         target: BuildingID,
+        // There's always room at the target
+        _: Option<Distance>,
+        now: Time,
         map: &Map,
     ) -> Option<(Vec<PathStep>, ParkingSpot, Position)> {
         // TODO This impl is copied from NormalParkingSimState. Instead, we already know the
@@ -1008,7 +1121,7 @@ impl ParkingSim for InfiniteParkingSimState {
                 // Pick the closest to the start of the lane, since that's closest to where we came
                 // from
                 if let Some((spot, pos)) = self
-                    .get_all_free_spots(Position::start(current), vehicle, target, map)
+                    .get_all_free_spots(Position::start(current), vehicle, target, now, map)
                     .into_iter()
                     .min_by_key(|(_, pos)| pos.dist_along())
                 {
@@ -397,6 +397,13 @@ impl Queue {
         self.reserved_length >= self.geom_len
     }
 
+    /// Nobody is in the queue or headed towards it.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+            && self.laggy_head.is_none()
+            && self.reserved_length == Distance::ZERO
+    }
+
     /// Can a car start a turn for this queue?
     pub fn room_for_car(&self, car: &Car) -> bool {
         self.reserved_length == Distance::ZERO
@@ -631,7 +631,9 @@ impl Pedestrian {
             }
         };
         let dist_int = DistanceInterval::new_walking(start_dist, end_dist);
-        let (speed, percent_incline) = self.path.current_step().max_speed_and_incline_along(
+        let (speed, percent_incline) = self.path.current_step().max_speed_and_incline_between(
+            dist_int.start,
+            dist_int.end,
             Some(self.speed),
             PathConstraints::Pedestrian,
             map,
@@ -0,0 +1,412 @@
+//! Shared bikes and scooters. Anybody making a micromobility trip walks to the closest available
+//! vehicle, rides it, then leaves it and walks the rest of the way. Docked vehicles have to be
+//! picked up from and returned to a dock with room; free-floating vehicles can be left anywhere.
+//!
+//! Vehicles not being ridden aren't physically simulated; they just sit at a building's bike
+//! connection. While somebody rides one, it's a regular bike in the driving simulation.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Time};
+use map_model::{BuildingID, Map};
+
+use crate::{CarID, Command, Event, MicromobilityFleet, Scheduler, SidewalkSpot, TripID, Vehicle};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Dock {
+    capacity: usize,
+    vehicles: BTreeSet<CarID>,
+    /// Slots promised to people currently riding here
+    reserved_slots: usize,
+}
+
+impl Dock {
+    fn has_room(&self) -> bool {
+        self.vehicles.len() + self.reserved_slots < self.capacity
+    }
+}
+
+/// Where a vehicle is left
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Location {
+    Dock(BuildingID),
+    /// Free-floating vehicles sit at the bike connection of some building
+    Curb(BuildingID),
+}
+
+impl Location {
+    fn building(self) -> BuildingID {
+        match self {
+            Location::Dock(b) | Location::Curb(b) => b,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum VehicleState {
+    Available(Location),
+    /// Somebody is walking to pick it up
+    Reserved {
+        trip: TripID,
+        pickup: Location,
+        dropoff: Location,
+    },
+    Riding {
+        trip: TripID,
+        dropoff: Location,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SharedVehicle {
+    vehicle: Vehicle,
+    state: VehicleState,
+}
+
+/// Manages a fleet of shared bikes or scooters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct MicromobilitySimState {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    docks: BTreeMap<BuildingID, Dock>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    vehicles: BTreeMap<CarID, SharedVehicle>,
+    max_walk: Distance,
+    rebalance_every: Option<Duration>,
+
+    events: Vec<Event>,
+}
+
+impl MicromobilitySimState {
+    pub fn new() -> MicromobilitySimState {
+        MicromobilitySimState {
+            docks: BTreeMap::new(),
+            vehicles: BTreeMap::new(),
+            max_walk: Distance::ZERO,
+            rebalance_every: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Place the fleet on the map. `new_car_id` hands out IDs for the vehicles.
+    pub fn add_fleet<F: FnMut() -> usize>(
+        &mut self,
+        fleet: &MicromobilityFleet,
+        mut new_car_id: F,
+        scheduler: &mut Scheduler,
+        map: &Map,
+    ) {
+        self.max_walk = fleet.max_walk;
+        self.rebalance_every = fleet.rebalance_every;
+        let spec = fleet.vehicle.spec();
+        let mut make_vehicle = || {
+            spec.clone().make(
+                CarID {
+                    id: new_car_id(),
+                    vehicle_type: spec.vehicle_type,
+                },
+                None,
+            )
+        };
+
+        for dock in &fleet.docks {
+            if SidewalkSpot::bike_rack(dock.building, map).is_none() {
+                warn!(
+                    "Skipping dock at {}; it has no bike connection",
+                    dock.building
+                );
+                continue;
+            }
+            if self.docks.contains_key(&dock.building) {
+                warn!("Skipping duplicate dock at {}", dock.building);
+                continue;
+            }
+            let mut vehicles = BTreeSet::new();
+            for _ in 0..dock.initial_vehicles.min(dock.capacity) {
+                let vehicle = make_vehicle();
+                vehicles.insert(vehicle.id);
+                self.vehicles.insert(
+                    vehicle.id,
+                    SharedVehicle {
+                        vehicle,
+                        state: VehicleState::Available(Location::Dock(dock.building)),
+                    },
+                );
+            }
+            self.docks.insert(
+                dock.building,
+                Dock {
+                    capacity: dock.capacity,
+                    vehicles,
+                    reserved_slots: 0,
+                },
+            );
+        }
+
+        for b in &fleet.free_floating {
+            if SidewalkSpot::bike_rack(*b, map).is_none() {
+                warn!(
+                    "Skipping free-floating vehicle at {}; it has no bike connection",
+                    b
+                );
+                continue;
+            }
+            let vehicle = make_vehicle();
+            self.vehicles.insert(
+                vehicle.id,
+                SharedVehicle {
+                    vehicle,
+                    state: VehicleState::Available(Location::Curb(*b)),
+                },
+            );
+        }
+
+        if let Some(dt) = self.rebalance_every {
+            scheduler.push(Time::START_OF_DAY + dt, Command::RebalanceMicromobility);
+        }
+    }
+
+    pub fn is_shared(&self, id: CarID) -> bool {
+        self.vehicles.contains_key(&id)
+    }
+
+    /// Reserve the closest available vehicle within walking distance of the start, and a place to
+    /// leave it near the goal. Returns the vehicle, where to pick it up, and the building where
+    /// it'll be left.
+    pub fn reserve(
+        &mut self,
+        trip: TripID,
+        start: BuildingID,
+        goal: BuildingID,
+        map: &Map,
+    ) -> Result<(CarID, SidewalkSpot, BuildingID)> {
+        match self.find_vehicle(start, goal, map) {
+            Some((car, pickup, dropoff)) => {
+                let spot = match SidewalkSpot::bike_rack(pickup.building(), map) {
+                    Some(spot) => spot,
+                    None => bail!(
+                        "{} can't be picked up; {} has no bike rack",
+                        car,
+                        pickup.building()
+                    ),
+                };
+                if let Location::Dock(b) = pickup {
+                    self.docks.get_mut(&b).unwrap().vehicles.remove(&car);
+                }
+                if let Location::Dock(b) = dropoff {
+                    self.docks.get_mut(&b).unwrap().reserved_slots += 1;
+                }
+                self.vehicles.get_mut(&car).unwrap().state = VehicleState::Reserved {
+                    trip,
+                    pickup,
+                    dropoff,
+                };
+                Ok((car, spot, dropoff.building()))
+            }
+            None => {
+                self.events
+                    .push(Event::MicromobilityUnavailable(trip, start));
+                bail!(
+                    "no shared vehicle available within {} of {}",
+                    self.max_walk,
+                    start
+                )
+            }
+        }
+    }
+
+    fn find_vehicle(
+        &self,
+        start: BuildingID,
+        goal: BuildingID,
+        map: &Map,
+    ) -> Option<(CarID, Location, Location)> {
+        let start_pt = map.get_b(start).polygon.center();
+        let goal_pt = map.get_b(goal).polygon.center();
+        // The closest dock with room near the goal, if there is one
+        let dock_near_goal = self
+            .docks
+            .iter()
+            .filter(|(_, dock)| dock.has_room())
+            .map(|(b, _)| (*b, map.get_b(*b).polygon.center().dist_to(goal_pt)))
+            .filter(|(_, dist)| *dist <= self.max_walk)
+            .min_by_key(|(_, dist)| *dist)
+            .map(|(b, _)| Location::Dock(b));
+
+        self.vehicles
+            .iter()
+            .filter_map(|(id, v)| match v.state {
+                VehicleState::Available(loc) => {
+                    let dist = map.get_b(loc.building()).polygon.center().dist_to(start_pt);
+                    if dist > self.max_walk {
+                        return None;
+                    }
+                    let dropoff = match loc {
+                        Location::Dock(_) => dock_near_goal?,
+                        Location::Curb(_) => Location::Curb(goal),
+                    };
+                    Some((dist, *id, loc, dropoff))
+                }
+                _ => None,
+            })
+            .min_by_key(|(dist, id, _, _)| (*dist, *id))
+            .map(|(_, id, pickup, dropoff)| (id, pickup, dropoff))
+    }
+
+    /// Somebody reached the vehicle they reserved and is about to ride it.
+    pub fn pick_up(&mut self, car: CarID) -> Vehicle {
+        let v = self.vehicles.get_mut(&car).unwrap();
+        match v.state {
+            VehicleState::Reserved {
+                trip,
+                pickup,
+                dropoff,
+            } => {
+                self.events
+                    .push(Event::MicromobilityPickup(trip, car, pickup.building()));
+                v.state = VehicleState::Riding { trip, dropoff };
+            }
+            ref state => unreachable!("{} picked up while {:?}", car, state),
+        }
+        v.vehicle.clone()
+    }
+
+    /// Somebody finished riding the vehicle and left it.
+    pub fn drop_off(&mut self, car: CarID) {
+        let v = self.vehicles.get_mut(&car).unwrap();
+        let (trip, dropoff) = match v.state {
+            VehicleState::Riding { trip, dropoff } => (trip, dropoff),
+            ref state => unreachable!("{} dropped off while {:?}", car, state),
+        };
+        v.state = VehicleState::Available(dropoff);
+        if let Location::Dock(b) = dropoff {
+            let dock = self.docks.get_mut(&b).unwrap();
+            dock.reserved_slots -= 1;
+            dock.vehicles.insert(car);
+        }
+        self.events
+            .push(Event::MicromobilityDropoff(trip, car, dropoff.building()));
+    }
+
+    /// The trip using a vehicle was cancelled. If it was still waiting to be picked up, it's
+    /// available again where it was. If somebody was riding it, it warps to where they were going
+    /// to leave it, just like the person.
+    pub fn trip_cancelled(&mut self, trip: TripID) {
+        let car = if let Some(car) = self.vehicles.iter().find_map(|(id, v)| match v.state {
+            VehicleState::Reserved { trip: t, .. } | VehicleState::Riding { trip: t, .. }
+                if t == trip =>
+            {
+                Some(*id)
+            }
+            _ => None,
+        }) {
+            car
+        } else {
+            return;
+        };
+
+        let v = self.vehicles.get_mut(&car).unwrap();
+        let (location, dropoff) = match v.state {
+            VehicleState::Reserved {
+                pickup, dropoff, ..
+            } => (pickup, dropoff),
+            VehicleState::Riding { dropoff, .. } => (dropoff, dropoff),
+            VehicleState::Available(_) => unreachable!(),
+        };
+        v.state = VehicleState::Available(location);
+        if let Location::Dock(b) = dropoff {
+            self.docks.get_mut(&b).unwrap().reserved_slots -= 1;
+        }
+        if let Location::Dock(b) = location {
+            self.docks.get_mut(&b).unwrap().vehicles.insert(car);
+        }
+    }
+
+    /// Move vehicles from the fullest docks to the emptiest ones, until every dock is as close to
+    /// half full as possible. The vehicles are moved instantly; the trucks doing this aren't
+    /// simulated.
+    pub fn rebalance(&mut self, now: Time, scheduler: &mut Scheduler) {
+        let fill = |dock: &Dock, delta: isize| {
+            ((dock.vehicles.len() + dock.reserved_slots) as isize + delta) as f64
+                / dock.capacity.max(1) as f64
+        };
+        let mut moved = 0;
+        // Each vehicle moves at most once
+        for _ in 0..self.vehicles.len() {
+            let fullest = self
+                .docks
+                .iter()
+                .filter(|(_, dock)| !dock.vehicles.is_empty() && fill(dock, 0) > 0.5)
+                .max_by(|(_, d1), (_, d2)| fill(d1, 0).partial_cmp(&fill(d2, 0)).unwrap());
+            let emptiest = self
+                .docks
+                .iter()
+                .filter(|(_, dock)| dock.has_room() && fill(dock, 0) < 0.5)
+                .min_by(|(_, d1), (_, d2)| fill(d1, 0).partial_cmp(&fill(d2, 0)).unwrap());
+            let (from, to) = match (fullest, emptiest) {
+                // Don't overshoot, or the vehicle would just get moved back
+                (Some((from, d1)), Some((to, d2))) if fill(d2, 1) <= fill(d1, -1) => (*from, *to),
+                _ => break,
+            };
+
+            let car = *self.docks[&from].vehicles.iter().next().unwrap();
+            self.docks.get_mut(&from).unwrap().vehicles.remove(&car);
+            self.docks.get_mut(&to).unwrap().vehicles.insert(car);
+            self.vehicles.get_mut(&car).unwrap().state =
+                VehicleState::Available(Location::Dock(to));
+            moved += 1;
+        }
+        if moved > 0 {
+            self.events.push(Event::MicromobilityRebalanced(moved));
+        }
+
+        if let Some(dt) = self.rebalance_every {
+            scheduler.push(now + dt, Command::RebalanceMicromobility);
+        }
+    }
+
+    pub fn collect_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// Queries
+impl MicromobilitySimState {
+    /// For every dock, the number of vehicles there now and the capacity
+    pub fn dock_status(&self) -> BTreeMap<BuildingID, (usize, usize)> {
+        self.docks
+            .iter()
+            .map(|(b, dock)| (*b, (dock.vehicles.len(), dock.capacity)))
+            .collect()
+    }
+
+    /// The number of vehicles in the fleet and how many are being ridden or are about to be
+    pub fn fleet_status(&self) -> (usize, usize) {
+        let busy = self
+            .vehicles
+            .values()
+            .filter(|v| !matches!(v.state, VehicleState::Available(_)))
+            .count();
+        (self.vehicles.len(), busy)
+    }
+
+    /// Where the free-floating vehicles not in use are currently left
+    pub fn free_floating_vehicles(&self) -> Vec<(CarID, BuildingID)> {
+        self.vehicles
+            .iter()
+            .filter_map(|(id, v)| match v.state {
+                VehicleState::Available(Location::Curb(b)) => Some((*id, b)),
+                _ => None,
+            })
+            .collect()
+    }
+}
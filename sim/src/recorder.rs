@@ -78,6 +78,8 @@ impl TrafficRecorder {
             map_name: map.get_name().clone(),
             people,
             only_seed_buses: None,
+            deliveries: Vec::new(),
+            micromobility: None,
         }
         .save();
     }
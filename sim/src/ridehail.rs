@@ -0,0 +1,341 @@
+//! A fleet of ride-hail vehicles (taxis, TNCs, and the like) carries people from the curb outside
+//! one building to the curb outside another. A simple dispatcher handles requests first come,
+//! first served, assigning the closest idle vehicle.
+//!
+//! Idle vehicles wait somewhere off to the side of the road, so they aren't physically simulated.
+//! When they're dispatched, they appear where they last stopped and drive empty ("deadhead") to
+//! the pickup. After a dropoff, a vehicle heads straight to the oldest waiting request, or vanishes
+//! again if there's nothing to do.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Time};
+use map_model::{BuildingID, Map, PathConstraints, Position};
+
+use crate::router::path_to_curb;
+use crate::sim::Ctx;
+use crate::{
+    AgentID, CarID, Command, CreateCar, Event, PersonID, Router, TripID, TripManager,
+    TripPhaseType, Vehicle, VehicleSpec, VehicleType, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+};
+
+/// Configures the ride-hail fleet.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RideHailParams {
+    /// How many vehicles are in the fleet. If there are none, every ride-hail trip is cancelled.
+    pub fleet_size: usize,
+    /// How long a vehicle waits at the curb for somebody to get in or out
+    pub curb_dwell_time: Duration,
+}
+
+impl Default for RideHailParams {
+    fn default() -> RideHailParams {
+        RideHailParams {
+            fleet_size: 0,
+            curb_dwell_time: Duration::seconds(30.0),
+        }
+    }
+}
+
+/// Somebody waiting inside a building for a ride
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RideHailRequest {
+    pub trip: TripID,
+    pub person: PersonID,
+    pub pickup: BuildingID,
+    pub dropoff: BuildingID,
+    pub requested: Time,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum VehicleState {
+    /// Off the road, last stopped here
+    Idle(Position),
+    /// Driving empty to a pickup
+    Deadheading(RideHailRequest),
+    /// Waiting at the curb for the passenger to get in
+    Boarding(RideHailRequest),
+    Carrying(RideHailRequest),
+    /// Waiting at the curb for the passenger to get out, or after finding the trip was cancelled
+    Alighting,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FleetVehicle {
+    vehicle: Vehicle,
+    state: VehicleState,
+}
+
+/// Manages the ride-hail fleet and requests for rides.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RideHailSimState {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    fleet: BTreeMap<CarID, FleetVehicle>,
+    /// Requests not assigned to any vehicle yet, oldest first
+    waiting: VecDeque<RideHailRequest>,
+    curb_dwell_time: Duration,
+
+    events: Vec<Event>,
+}
+
+impl RideHailSimState {
+    /// The fleet starts the day spread out evenly over the map.
+    pub fn new(map: &Map, params: &RideHailParams, trips: &mut TripManager) -> RideHailSimState {
+        let mut state = RideHailSimState {
+            fleet: BTreeMap::new(),
+            waiting: VecDeque::new(),
+            curb_dwell_time: params.curb_dwell_time,
+            events: Vec::new(),
+        };
+        if params.fleet_size == 0 {
+            return state;
+        }
+
+        let lanes: Vec<Position> = map
+            .all_lanes()
+            .filter(|l| {
+                PathConstraints::Car.can_use(l, map)
+                    && !l.driving_blackhole
+                    && l.length() > 2.0 * MAX_CAR_LENGTH
+            })
+            .map(|l| Position::new(l.id, l.length() / 2.0))
+            .collect();
+        if lanes.is_empty() {
+            warn!("No lanes to start the ride-hail fleet on");
+            return state;
+        }
+        for idx in 0..params.fleet_size {
+            let id = CarID {
+                id: trips.new_car_id(),
+                vehicle_type: VehicleType::RideHail,
+            };
+            let vehicle = VehicleSpec {
+                vehicle_type: VehicleType::RideHail,
+                length: MIN_CAR_LENGTH,
+                max_speed: None,
+            }
+            .make(id, None);
+            let pos = lanes[idx * lanes.len() / params.fleet_size];
+            state.fleet.insert(
+                id,
+                FleetVehicle {
+                    vehicle,
+                    state: VehicleState::Idle(pos),
+                },
+            );
+        }
+        state
+    }
+
+    pub fn request(
+        &mut self,
+        now: Time,
+        req: RideHailRequest,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) {
+        if self.fleet.is_empty() {
+            trips.cancel_trip(
+                now,
+                req.trip,
+                "there are no ride-hail vehicles".to_string(),
+                None,
+                ctx,
+            );
+            return;
+        }
+        self.waiting.push_back(req);
+        self.dispatch_idle(now, trips, ctx);
+    }
+
+    /// Assign idle vehicles to the oldest waiting requests.
+    fn dispatch_idle(&mut self, now: Time, trips: &mut TripManager, ctx: &mut Ctx) {
+        while let Some(req) = self.waiting.pop_front() {
+            if !trips.waiting_for_ride_hail(req.trip) {
+                continue;
+            }
+            let pickup_pt = ctx.map.get_b(req.pickup).polygon.center();
+            let closest = self
+                .fleet
+                .iter()
+                .filter_map(|(id, v)| match v.state {
+                    VehicleState::Idle(pos) => Some((*id, pos)),
+                    _ => None,
+                })
+                .min_by_key(|(_, pos)| pos.pt(ctx.map).dist_to(pickup_pt));
+            let (car, pos) = if let Some(pair) = closest {
+                pair
+            } else {
+                // Everybody's busy
+                self.waiting.push_front(req);
+                return;
+            };
+
+            let length = self.fleet[&car].vehicle.length;
+            match path_to_curb(now, pos, length, req.pickup, ctx.map) {
+                Ok((path, end_dist)) => {
+                    self.events.push(Event::RideHailDispatched(req.trip, car));
+                    let v = self.fleet.get_mut(&car).unwrap();
+                    ctx.scheduler.push(
+                        now,
+                        Command::SpawnCar(
+                            CreateCar {
+                                vehicle: v.vehicle.clone(),
+                                router: Router::stop_at_curb(car, path, end_dist),
+                                maybe_parked_car: None,
+                                trip_and_person: None,
+                                maybe_route: None,
+                            },
+                            true,
+                        ),
+                    );
+                    v.state = VehicleState::Deadheading(req);
+                }
+                Err(err) => {
+                    trips.cancel_trip(now, req.trip, err.to_string(), None, ctx);
+                }
+            }
+        }
+    }
+
+    /// Returns the trip and person now riding, if the vehicle just picked somebody up.
+    pub fn vehicle_reached_curb(
+        &mut self,
+        now: Time,
+        car: CarID,
+        blocked_time: Duration,
+        distance_crossed: Distance,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) -> Option<(TripID, PersonID)> {
+        let v = self.fleet.get_mut(&car).unwrap();
+        match std::mem::replace(&mut v.state, VehicleState::Alighting) {
+            VehicleState::Deadheading(req) if !trips.waiting_for_ride_hail(req.trip) => {
+                // The trip was cancelled on the way. Nobody gets in, and the vehicle moves on to
+                // the next request after the usual wait at the curb.
+                self.events.push(Event::RideHailAbandoned(req.trip, car));
+                None
+            }
+            VehicleState::Deadheading(req) => {
+                self.events.push(Event::RideHailPickup(
+                    req.trip,
+                    car,
+                    now - req.requested,
+                    distance_crossed,
+                ));
+                trips.ride_hail_pickup(car, req.trip);
+                let result = Some((req.trip, req.person));
+                v.state = VehicleState::Boarding(req);
+                result
+            }
+            VehicleState::Carrying(req) => {
+                self.events
+                    .push(Event::RideHailDropoff(req.trip, car, distance_crossed));
+                trips.ride_hail_dropoff(now, car, blocked_time, distance_crossed, ctx);
+                None
+            }
+            state => unreachable!("{} reached the curb while {:?}", car, state),
+        }
+    }
+
+    /// Returns the route to follow next and anybody riding along it, or None if the vehicle has
+    /// nothing to do and should vanish.
+    pub fn vehicle_leaving_curb(
+        &mut self,
+        now: Time,
+        car: CarID,
+        pos: Position,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) -> Option<(Router, Option<(TripID, PersonID)>)> {
+        let v = self.fleet.get_mut(&car).unwrap();
+        let length = v.vehicle.length;
+        match std::mem::replace(&mut v.state, VehicleState::Idle(pos)) {
+            VehicleState::Boarding(req) => {
+                match path_to_curb(now, pos, length, req.dropoff, ctx.map) {
+                    Ok((path, end_dist)) => {
+                        self.events.push(Event::TripPhaseStarting(
+                            req.trip,
+                            req.person,
+                            Some(path.get_req().clone()),
+                            TripPhaseType::RidingRideHail(car),
+                        ));
+                        let passenger = Some((req.trip, req.person));
+                        v.state = VehicleState::Carrying(req);
+                        return Some((Router::stop_at_curb(car, path, end_dist), passenger));
+                    }
+                    Err(err) => {
+                        // The passenger already got in, so they're no longer waiting
+                        trips.trip_abruptly_cancelled(req.trip, AgentID::Car(car));
+                        trips.cancel_trip(now, req.trip, err.to_string(), None, ctx);
+                        self.events.push(Event::RideHailAbandoned(req.trip, car));
+                    }
+                }
+            }
+            VehicleState::Alighting => {}
+            state => unreachable!("{} left the curb while {:?}", car, state),
+        }
+
+        // Head straight to the oldest waiting request
+        while let Some(req) = self.waiting.pop_front() {
+            if !trips.waiting_for_ride_hail(req.trip) {
+                continue;
+            }
+            match path_to_curb(now, pos, length, req.pickup, ctx.map) {
+                Ok((path, end_dist)) => {
+                    self.events.push(Event::RideHailDispatched(req.trip, car));
+                    self.fleet.get_mut(&car).unwrap().state = VehicleState::Deadheading(req);
+                    return Some((Router::stop_at_curb(car, path, end_dist), None));
+                }
+                Err(err) => {
+                    trips.cancel_trip(now, req.trip, err.to_string(), None, ctx);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn curb_dwell_time(&self) -> Duration {
+        self.curb_dwell_time
+    }
+
+    pub fn collect_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// Queries
+impl RideHailSimState {
+    pub fn fleet_size(&self) -> usize {
+        self.fleet.len()
+    }
+
+    /// Vehicles driving to a pickup, waiting at the curb, or carrying somebody
+    pub fn num_busy_vehicles(&self) -> usize {
+        self.fleet
+            .values()
+            .filter(|v| !matches!(v.state, VehicleState::Idle(_)))
+            .count()
+    }
+
+    pub fn num_waiting_requests(&self) -> usize {
+        self.waiting.len()
+    }
+
+    /// The trip a vehicle is currently serving, either on the way to pick somebody up or carrying
+    /// them.
+    pub fn serving_trip(&self, car: CarID) -> Option<TripID> {
+        match self.fleet.get(&car)?.state {
+            VehicleState::Deadheading(ref req)
+            | VehicleState::Boarding(ref req)
+            | VehicleState::Carrying(ref req) => Some(req.trip),
+            VehicleState::Idle(_) | VehicleState::Alighting => None,
+        }
+    }
+}
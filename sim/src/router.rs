@@ -1,20 +1,23 @@
 //! For vehicles only, not pedestrians. Follows a Path from map_model, but can opportunistically
 //! lane-change to avoid a slow lane, can can handle re-planning to look for available parking.
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
+use anyhow::Result;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
 use serde::{Deserialize, Serialize};
 
-use geom::Distance;
+use geom::{Distance, Duration, Pt2D, Time};
 use map_model::{
     BuildingID, IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
-    Position, Traversable, Turn, TurnID,
+    Position, RoadID, Traversable, Turn, TurnID,
 };
 
 use crate::mechanics::Queue;
 use crate::{
-    AlertLocation, CarID, Event, ParkingSim, ParkingSimState, ParkingSpot, PersonID, SidewalkSpot,
-    TripID, TripPhaseType, Vehicle, VehicleType,
+    AlertLocation, CarID, DrivingGoal, Event, ParkingSim, ParkingSimState, ParkingSpot, PersonID,
+    SidewalkSpot, TripID, TripPhaseType, Vehicle, VehicleType,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -32,9 +35,31 @@ pub(crate) enum ActionAtEnd {
     GotoLaneEnd,
     StopBiking(SidewalkSpot),
     BusAtStop,
+    /// Ride-hail vehicles picking up or dropping off, and delivery vehicles double-parking
+    StopAtCurb,
     GiveUpOnParking,
 }
 
+/// How drivers look for parking once they reach their destination.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingSearchParams {
+    /// Drivers won't take a spot farther than this (in a straight line) from their destination.
+    /// If nothing that close is free, they circle nearby blocks until something opens up. If None,
+    /// drivers take the first free spot they can find, no matter how far away.
+    pub max_walking_distance: Option<Distance>,
+    /// After searching for this long, drivers give up on staying close and take any free spot.
+    pub time_budget: Duration,
+}
+
+impl Default for ParkingSearchParams {
+    fn default() -> ParkingSearchParams {
+        ParkingSearchParams {
+            max_walking_distance: None,
+            time_budget: Duration::minutes(10),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 enum Goal {
     /// Spot and cached distance along the last driving lane
@@ -43,7 +68,8 @@ enum Goal {
         spot: Option<(ParkingSpot, Distance)>,
         /// No parking available at all!
         stuck_end_dist: Option<Distance>,
-        started_looking: bool,
+        /// Set once the driver reaches the last lane of their original path
+        search: Option<ParkingSearch>,
     },
     EndAtBorder {
         end_dist: Distance,
@@ -55,6 +81,33 @@ enum Goal {
     FollowBusRoute {
         end_dist: Distance,
     },
+    /// A ride-hail vehicle picking up or dropping off somebody
+    StopAtCurb {
+        end_dist: Distance,
+    },
+    /// A delivery vehicle stopping at a building. Once it reaches the last lane, it looks for a
+    /// free loading zone. If there isn't one, it double-parks at end_dist.
+    Deliver {
+        target: BuildingID,
+        end_dist: Distance,
+        loading_zone: Option<(ParkingSpot, Distance)>,
+        looked: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ParkingSearch {
+    started: Time,
+    /// How far along the lane the driver was when they started looking
+    start_dist: Distance,
+    /// The length of every lane and turn finished since starting to look
+    crossed: Distance,
+}
+
+impl ParkingSearch {
+    fn distance(&self, front: Distance) -> Distance {
+        self.crossed + front - self.start_dist
+    }
 }
 
 impl Router {
@@ -92,7 +145,7 @@ impl Router {
                 target: bldg,
                 spot: None,
                 stuck_end_dist: None,
-                started_looking: false,
+                search: None,
             },
             owner,
         }
@@ -116,6 +169,28 @@ impl Router {
         }
     }
 
+    /// The path may loop around the block, so its end isn't necessarily where to stop.
+    pub fn stop_at_curb(owner: CarID, path: Path, end_dist: Distance) -> Router {
+        Router {
+            goal: Goal::StopAtCurb { end_dist },
+            path,
+            owner,
+        }
+    }
+
+    pub fn deliver(owner: CarID, path: Path, end_dist: Distance, target: BuildingID) -> Router {
+        Router {
+            goal: Goal::Deliver {
+                target,
+                end_dist,
+                loading_zone: None,
+                looked: false,
+            },
+            path,
+            owner,
+        }
+    }
+
     pub fn head(&self) -> Traversable {
         self.path.current_step().as_traversable()
     }
@@ -144,6 +219,12 @@ impl Router {
             } => stuck_end_dist.unwrap_or_else(|| spot.unwrap().1),
             Goal::BikeThenStop { ref goal } => goal.sidewalk_pos.dist_along(),
             Goal::FollowBusRoute { end_dist } => end_dist,
+            Goal::StopAtCurb { end_dist } => end_dist,
+            Goal::Deliver {
+                end_dist,
+                loading_zone,
+                ..
+            } => loading_zone.map(|(_, dist)| dist).unwrap_or(end_dist),
         }
     }
 
@@ -154,21 +235,32 @@ impl Router {
     /// Returns the step just finished
     pub fn advance(
         &mut self,
+        now: Time,
         vehicle: &Vehicle,
         parking: &ParkingSimState,
         map: &Map,
         trip_and_person: Option<(TripID, PersonID)>,
+        search_params: &ParkingSearchParams,
         events: &mut Vec<Event>,
     ) -> Traversable {
         let prev = self.path.shift(map).as_traversable();
+        if let Goal::ParkNearBuilding {
+            search: Some(ref mut search),
+            ..
+        } = self.goal
+        {
+            search.crossed += prev.get_polyline(map).length();
+        }
         if self.last_step() {
             // Do this to trigger the side-effect of looking for parking.
             self.maybe_handle_end(
+                now,
                 Distance::ZERO,
                 vehicle,
                 parking,
                 map,
                 trip_and_person,
+                search_params,
                 events,
             );
         }
@@ -191,12 +283,14 @@ impl Router {
     /// step.
     pub fn maybe_handle_end(
         &mut self,
+        now: Time,
         front: Distance,
         vehicle: &Vehicle,
         parking: &ParkingSimState,
         map: &Map,
         // TODO Not so nice to plumb all of this here
         trip_and_person: Option<(TripID, PersonID)>,
+        search_params: &ParkingSearchParams,
         events: &mut Vec<Event>,
     ) -> Option<ActionAtEnd> {
         assert!(self.path.is_last_step());
@@ -213,10 +307,18 @@ impl Router {
                 ref mut spot,
                 ref mut stuck_end_dist,
                 target,
-                ref mut started_looking,
+                ref mut search,
             } => {
                 if let Some(d) = stuck_end_dist {
                     if *d == front {
+                        if let (Some((t, _)), Some(search)) = (trip_and_person, search) {
+                            events.push(Event::ParkingSearchEnded(
+                                t,
+                                vehicle.id,
+                                None,
+                                search.distance(front),
+                            ));
+                        }
                         return Some(ActionAtEnd::GiveUpOnParking);
                     } else {
                         return None;
@@ -228,34 +330,71 @@ impl Router {
                     None => true,
                 };
                 if need_new_spot {
-                    *started_looking = true;
+                    if search.is_none() {
+                        *search = Some(ParkingSearch {
+                            started: now,
+                            start_dist: front,
+                            crossed: Distance::ZERO,
+                        });
+                        if let Some((t, _)) = trip_and_person {
+                            events.push(Event::ParkingSearchStarted(t, vehicle.id));
+                        }
+                    }
+                    // Once the time budget runs out, drivers will take anything.
+                    let max_walk =
+                        if now - search.as_ref().unwrap().started < search_params.time_budget {
+                            search_params.max_walking_distance
+                        } else {
+                            None
+                        };
+                    let target_pt = map.get_b(target).sidewalk_pos.pt(map);
+
                     let current_lane = self.path.current_step().as_lane();
                     let candidates = parking.get_all_free_spots(
                         Position::new(current_lane, front),
                         vehicle,
                         target,
+                        now,
                         map,
                     );
-                    let best =
-                        if let Some((driving_pos, _)) = map.get_b(target).driving_connection(map) {
-                            if driving_pos.lane() == current_lane {
-                                let target_dist = driving_pos.dist_along();
-                                // Closest to the building
-                                candidates
-                                    .into_iter()
-                                    .min_by_key(|(_, pos)| (pos.dist_along() - target_dist).abs())
-                            } else {
-                                // Closest to the road endpoint, I guess
-                                candidates
-                                    .into_iter()
-                                    .min_by_key(|(_, pos)| pos.dist_along())
-                            }
+                    // Drivers always prefer cheaper spots. Compare in cents.
+                    let price = |spot: ParkingSpot| {
+                        (parking.hourly_price(spot, vehicle.id, map) * 100.0).round() as usize
+                    };
+                    let best = if let Some(max) = max_walk {
+                        // Prefer the spot closest to the destination
+                        candidates
+                            .into_iter()
+                            .map(|(spot, pos)| {
+                                let walk = parking
+                                    .spot_to_sidewalk_pos(spot, map)
+                                    .pt(map)
+                                    .dist_to(target_pt);
+                                (walk, spot, pos)
+                            })
+                            .filter(|(walk, _, _)| *walk <= max)
+                            .min_by_key(|(walk, spot, _)| (price(*spot), *walk))
+                            .map(|(_, spot, pos)| (spot, pos))
+                    } else if let Some((driving_pos, _)) = map.get_b(target).driving_connection(map)
+                    {
+                        if driving_pos.lane() == current_lane {
+                            let target_dist = driving_pos.dist_along();
+                            // Closest to the building
+                            candidates.into_iter().min_by_key(|(spot, pos)| {
+                                (price(*spot), (pos.dist_along() - target_dist).abs())
+                            })
                         } else {
                             // Closest to the road endpoint, I guess
                             candidates
                                 .into_iter()
-                                .min_by_key(|(_, pos)| pos.dist_along())
-                        };
+                                .min_by_key(|(spot, pos)| (price(*spot), pos.dist_along()))
+                        }
+                    } else {
+                        // Closest to the road endpoint, I guess
+                        candidates
+                            .into_iter()
+                            .min_by_key(|(spot, pos)| (price(*spot), pos.dist_along()))
+                    };
                     if let Some((new_spot, new_pos)) = best {
                         if let Some((t, p)) = trip_and_person {
                             events.push(Event::TripPhaseStarting(
@@ -273,8 +412,15 @@ impl Router {
                         assert!(new_pos.dist_along() >= front);
                         *spot = Some((new_spot, new_pos.dist_along()));
                     } else {
-                        if let Some((new_path_steps, new_spot, new_pos)) =
-                            parking.path_to_free_parking_spot(current_lane, vehicle, target, map)
+                        if let Some((new_path_steps, new_spot, new_pos)) = parking
+                            .path_to_free_parking_spot(
+                                current_lane,
+                                vehicle,
+                                target,
+                                max_walk,
+                                now,
+                                map,
+                            )
                         {
                             assert!(!new_path_steps.is_empty());
                             for step in new_path_steps {
@@ -295,6 +441,15 @@ impl Router {
                                     TripPhaseType::Parking,
                                 ));
                             }
+                        } else if let Some(turn) = max_walk
+                            .and_then(|_| circle_nearby(current_lane, target_pt, vehicle, map))
+                        {
+                            // Nothing close enough is free right now. Keep driving around nearby
+                            // and hope something opens up.
+                            *spot = None;
+                            self.path.add(PathStep::Turn(turn), map);
+                            self.path.add(PathStep::Lane(turn.dst), map);
+                            events.push(Event::PathAmended(self.path.clone()));
                         } else {
                             if let Some((_, p)) = trip_and_person {
                                 events.push(Event::Alert(
@@ -312,8 +467,17 @@ impl Router {
                     }
                 }
 
-                if spot.unwrap().1 == front {
-                    Some(ActionAtEnd::StartParking(spot.unwrap().0))
+                let (spot, dist) = spot.unwrap();
+                if dist == front {
+                    if let (Some((t, _)), Some(search)) = (trip_and_person, search) {
+                        events.push(Event::ParkingSearchEnded(
+                            t,
+                            vehicle.id,
+                            Some(spot),
+                            search.distance(front),
+                        ));
+                    }
+                    Some(ActionAtEnd::StartParking(spot))
                 } else {
                     None
                 }
@@ -332,6 +496,64 @@ impl Router {
                     None
                 }
             }
+            Goal::StopAtCurb { end_dist } => {
+                if end_dist == front {
+                    Some(ActionAtEnd::StopAtCurb)
+                } else {
+                    None
+                }
+            }
+            Goal::Deliver {
+                target,
+                ref mut end_dist,
+                ref mut loading_zone,
+                ref mut looked,
+            } => {
+                let current_lane = self.path.current_step().as_lane();
+                if !*looked {
+                    *looked = true;
+                    let curb = *end_dist;
+                    *loading_zone = parking
+                        .get_all_free_spots(
+                            Position::new(current_lane, front),
+                            vehicle,
+                            target,
+                            now,
+                            map,
+                        )
+                        .into_iter()
+                        .filter(|(spot, _)| {
+                            // Vehicles longer than a spot would overhang the neighbors, so they
+                            // double-park instead
+                            matches!(spot, ParkingSpot::Onstreet(_, _))
+                                && vehicle.length <= map.get_config().street_parking_spot_length
+                                && map
+                                    .get_parking_regulation(spot.regulation_target())
+                                    .map(|reg| reg.loading_zone)
+                                    .unwrap_or(false)
+                        })
+                        .min_by_key(|(_, pos)| (pos.dist_along() - curb).abs())
+                        .map(|(spot, pos)| (spot, pos.dist_along()));
+                }
+
+                if let Some((spot, dist)) = *loading_zone {
+                    if parking.is_free(spot) {
+                        return if dist == front {
+                            Some(ActionAtEnd::StartParking(spot))
+                        } else {
+                            None
+                        };
+                    }
+                    // Somebody else took it. Double-park instead, as soon as possible.
+                    *loading_zone = None;
+                    *end_dist = end_dist.max(front);
+                }
+                if *end_dist == front {
+                    Some(ActionAtEnd::StopAtCurb)
+                } else {
+                    None
+                }
+            }
         }
     }
 
@@ -466,6 +688,34 @@ impl Router {
         }
     }
 
+    /// Replace the rest of the path with one that doesn't enter any of these roads, unless the
+    /// destination is there. Returns false if there's no such path, or the vehicle is already
+    /// committed to its current route.
+    pub fn reroute_avoiding(&mut self, now: Time, avoid: &BTreeSet<RoadID>, map: &Map) -> bool {
+        if self.path.currently_inside_ut().is_some() {
+            return false;
+        }
+        let current_lane = self.head().as_lane();
+        let end = self.path.get_req().end;
+        // Parking searches may have extended the path past the original destination
+        if self.path.last_step() != PathStep::Lane(end.lane()) || current_lane == end.lane() {
+            return false;
+        }
+        let req = PathRequest::vehicle(
+            Position::end(current_lane, map),
+            end,
+            self.owner.vehicle_type.to_constraints(),
+        )
+        .with_departure(now);
+        match map.pathfind_avoiding_roads(req, avoid) {
+            Ok(path) if path.current_step() == self.path.current_step() => {
+                self.path.reroute(path, map);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn can_lanechange(&self, from: LaneID, to: LaneID, map: &Map) -> bool {
         let steps = self.path.get_steps();
         if steps.len() < 3 {
@@ -499,9 +749,7 @@ impl Router {
 
     pub fn is_parking(&self) -> bool {
         match self.goal {
-            Goal::ParkNearBuilding {
-                started_looking, ..
-            } => started_looking,
+            Goal::ParkNearBuilding { ref search, .. } => search.is_some(),
             _ => false,
         }
     }
@@ -513,3 +761,76 @@ impl Router {
         }
     }
 }
+
+/// Pick the next lane to drive to while circling around a destination, looking for parking. Prefer
+/// lanes closer to the destination and avoid turning around, but vary the choice a bit between
+/// vehicles.
+fn circle_nearby(
+    current_lane: LaneID,
+    target_pt: Pt2D,
+    vehicle: &Vehicle,
+    map: &Map,
+) -> Option<TurnID> {
+    // Deterministic across runs of the same simulation, but different between vehicles. See
+    // path_to_free_parking_spot.
+    let mut rng =
+        XorShiftRng::seed_from_u64((vehicle.id.id + current_lane.encode_u32() as usize) as u64);
+    let current_road = current_lane.road;
+    map.get_turns_for(current_lane, vehicle.vehicle_type.to_constraints())
+        .into_iter()
+        .map(|turn| {
+            let dist = map
+                .get_l(turn.id.dst)
+                .lane_center_pts
+                .middle()
+                .dist_to(target_pt);
+            let turning_around = turn.id.dst.road == current_road;
+            ((turning_around, dist * rng.gen_range(1.0..1.5)), turn.id)
+        })
+        .min_by_key(|(key, _)| *key)
+        .map(|(_, t)| t)
+}
+
+/// Returns a path from a vehicle's current position to where it stops outside a building, and the
+/// distance along the last lane to stop. The stop leaves room for the vehicle's full length.
+pub(crate) fn path_to_curb(
+    now: Time,
+    from: Position,
+    vehicle_length: Distance,
+    b: BuildingID,
+    map: &Map,
+) -> Result<(Path, Distance)> {
+    let curb = DrivingGoal::ParkNear(b)
+        .goal_pos(PathConstraints::Car, map)
+        .and_then(|pos| pos.buffer_dist(vehicle_length, map))
+        .ok_or_else(|| anyhow!("no curb to stop at near {}", b))?;
+
+    let same_road = map.get_l(from.lane()).get_directed_parent()
+        == map.get_l(curb.lane()).get_directed_parent();
+    if !same_road || from.dist_along() <= curb.dist_along() {
+        let path = map
+            .pathfind(PathRequest::vehicle(from, curb, PathConstraints::Car).with_departure(now))?;
+        return Ok((path, curb.dist_along()));
+    }
+
+    // The curb is just behind the vehicle. Leave the road and come back around, preferably without
+    // turning around.
+    let turn = map
+        .get_turns_for(from.lane(), PathConstraints::Car)
+        .into_iter()
+        .min_by_key(|t| t.id.dst.road == from.lane().road)
+        .ok_or_else(|| anyhow!("can't circle back to {} from {}", b, from))?;
+    let back = map.pathfind(
+        PathRequest::vehicle(Position::start(turn.id.dst), curb, PathConstraints::Car)
+            .with_departure(now),
+    )?;
+    let mut path = Path::one_step(
+        PathRequest::vehicle(from, Position::end(from.lane(), map), PathConstraints::Car),
+        map,
+    );
+    path.add(PathStep::Turn(turn.id), map);
+    for step in back.get_steps() {
+        path.add(*step, map);
+    }
+    Ok((path, curb.dist_along()))
+}
@@ -9,7 +9,8 @@ use geom::{Duration, Histogram, Time};
 use map_model::{BusRouteID, IntersectionID};
 
 use crate::{
-    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, StartTripArgs, TripID,
+    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, RideHailRequest,
+    StartTripArgs, TripID,
 };
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -27,6 +28,9 @@ pub(crate) enum Command {
     Pandemic(pandemic::Cmd),
     /// The Time is redundant, just used to dedupe commands
     StartBus(BusRouteID, Time),
+    RequestRideHail(RideHailRequest),
+    StartDeliveryTour(CarID),
+    RebalanceMicromobility,
 }
 
 impl Command {
@@ -50,6 +54,9 @@ impl Command {
             Command::Callback(_) => CommandType::Callback,
             Command::Pandemic(ref p) => CommandType::Pandemic(p.clone()),
             Command::StartBus(r, t) => CommandType::StartBus(*r, *t),
+            Command::RequestRideHail(ref req) => CommandType::RideHail(req.trip),
+            Command::StartDeliveryTour(id) => CommandType::DeliveryTour(*id),
+            Command::RebalanceMicromobility => CommandType::RebalanceMicromobility,
         }
     }
 
@@ -65,6 +72,9 @@ impl Command {
             Command::Callback(_) => SimpleCommandType::Callback,
             Command::Pandemic(_) => SimpleCommandType::Pandemic,
             Command::StartBus(_, _) => SimpleCommandType::StartBus,
+            Command::RequestRideHail(_) => SimpleCommandType::RideHail,
+            Command::StartDeliveryTour(_) => SimpleCommandType::DeliveryTour,
+            Command::RebalanceMicromobility => SimpleCommandType::RebalanceMicromobility,
         }
     }
 }
@@ -81,6 +91,9 @@ enum CommandType {
     Callback,
     Pandemic(pandemic::Cmd),
     StartBus(BusRouteID, Time),
+    RideHail(TripID),
+    DeliveryTour(CarID),
+    RebalanceMicromobility,
 }
 
 /// A more compressed form of CommandType, just used for keeping stats on event processing.
@@ -94,6 +107,9 @@ enum SimpleCommandType {
     Callback,
     Pandemic,
     StartBus,
+    RideHail,
+    DeliveryTour,
+    RebalanceMicromobility,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
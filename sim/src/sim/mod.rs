@@ -1,6 +1,6 @@
 // This file has a jumbled mess of queries, setup, and mutating methods.
 
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::panic;
 
 use anyhow::Result;
@@ -19,11 +19,13 @@ use map_model::{
 
 pub use self::queries::{AgentProperties, DelayCause};
 use crate::{
-    AgentID, AlertLocation, Analytics, CarID, Command, CreateCar, DrivingSimState, Event,
-    IntersectionSimState, OrigPersonID, PandemicModel, ParkedCar, ParkingSim, ParkingSimState,
-    ParkingSpot, Person, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot, StartTripArgs,
-    TrafficRecorder, TransitSimState, TripID, TripInfo, TripManager, TripPhaseType, Vehicle,
-    VehicleSpec, VehicleType, WalkingSimState, BUS_LENGTH, LIGHT_RAIL_LENGTH, MIN_CAR_LENGTH,
+    AgentID, AlertLocation, Analytics, CarID, Command, CreateCar, DeliveryTour, DrivingSimState,
+    Event, EventLogWriter, FreightSimState, IntersectionSimState, MicromobilityFleet, OrigPersonID,
+    PandemicModel, ParkedCar, ParkingSearchParams, ParkingSim, ParkingSimState, ParkingSpot,
+    Person, PersonID, RideHailParams, RideHailSimState, Router, Scheduler, SidewalkPOI,
+    SidewalkSpot, StartTripArgs, TrafficRecorder, TransitSimState, TripEndpoint, TripID, TripInfo,
+    TripManager, TripPhaseType, Vehicle, VehicleSpec, VehicleType, WalkingSimState, BUS_LENGTH,
+    LIGHT_RAIL_LENGTH, MIN_CAR_LENGTH,
 };
 
 mod queries;
@@ -39,6 +41,8 @@ pub struct Sim {
     walking: WalkingSimState,
     intersections: IntersectionSimState,
     transit: TransitSimState,
+    ridehail: RideHailSimState,
+    freight: FreightSimState,
     trips: TripManager,
     #[serde(skip_serializing, skip_deserializing)]
     pandemic: Option<PandemicModel>,
@@ -58,6 +62,11 @@ pub struct Sim {
     // This is created interactively, and there's no reason to preserve one for savestates.
     #[serde(skip_serializing, skip_deserializing)]
     recorder: Option<TrafficRecorder>,
+    #[serde(skip_serializing, skip_deserializing)]
+    event_log: Option<EventLogWriter>,
+    // Something outside the simulation wants to receive events directly
+    #[serde(skip_serializing, skip_deserializing)]
+    captured_events: Option<Vec<(Time, Event)>>,
 
     #[serde(skip_serializing, skip_deserializing)]
     alerts: AlertHandler,
@@ -111,6 +120,14 @@ pub struct SimOptions {
     /// Don't collect any analytics. Only useful for benchmarking and debugging gridlock more
     /// quickly.
     pub skip_analytics: bool,
+    /// If present, write every event to this file as it happens. Files ending in `.bin` use a
+    /// compact binary encoding; anything else is written as newline-delimited JSON. Read the log
+    /// with `EventLogReader`.
+    pub event_log: Option<String>,
+    /// How drivers look for parking near their destination.
+    pub parking_search: ParkingSearchParams,
+    /// The fleet of ride-hail vehicles serving ride-hail trips.
+    pub ride_hail: RideHailParams,
 }
 
 impl Default for SimOptions {
@@ -147,6 +164,23 @@ impl SimOptions {
             infinite_parking: args.enabled("--infinite_parking"),
             disable_turn_conflicts: args.enabled("--disable_turn_conflicts"),
             skip_analytics: args.enabled("--skip_analytics"),
+            event_log: args.optional("--event_log"),
+            parking_search: ParkingSearchParams {
+                max_walking_distance: args.optional_parse("--max_walk_from_parking", |s| {
+                    s.parse::<f64>().map(Distance::meters)
+                }),
+                time_budget: args
+                    .optional_parse("--parking_search_budget", |s| Duration::parse(s))
+                    .unwrap_or_else(|| ParkingSearchParams::default().time_budget),
+            },
+            ride_hail: RideHailParams {
+                fleet_size: args
+                    .optional_parse("--ride_hail_fleet", |s| s.parse::<usize>())
+                    .unwrap_or(0),
+                curb_dwell_time: args
+                    .optional_parse("--ride_hail_dwell", |s| Duration::parse(s))
+                    .unwrap_or_else(|| RideHailParams::default().curb_dwell_time),
+            },
         }
     }
 }
@@ -181,6 +215,9 @@ impl SimOptions {
             infinite_parking: false,
             disable_turn_conflicts: false,
             skip_analytics: false,
+            event_log: None,
+            parking_search: ParkingSearchParams::default(),
+            ride_hail: RideHailParams::default(),
         }
     }
 }
@@ -210,13 +247,18 @@ impl Sim {
             opts.dont_block_the_box = false;
         }
 
+        let mut trips = TripManager::new();
+        let ridehail = RideHailSimState::new(map, &opts.ride_hail, &mut trips);
+
         Sim {
             driving: DrivingSimState::new(map, &opts),
             parking: ParkingSimState::new(map, opts.infinite_parking, &mut timer),
             walking: WalkingSimState::new(),
             intersections: IntersectionSimState::new(map, &mut scheduler, &opts),
             transit: TransitSimState::new(map),
-            trips: TripManager::new(),
+            ridehail,
+            freight: FreightSimState::new(),
+            trips,
             pandemic: opts.enable_pandemic_model.map(PandemicModel::new),
             scheduler,
             time: Time::START_OF_DAY,
@@ -230,9 +272,42 @@ impl Sim {
 
             analytics: Analytics::new(!opts.skip_analytics),
             recorder: None,
+            event_log: opts
+                .event_log
+                .and_then(|path| match EventLogWriter::new(path.clone()) {
+                    Ok(writer) => Some(writer),
+                    Err(err) => {
+                        error!("Couldn't create event log {}: {}", path, err);
+                        None
+                    }
+                }),
+            captured_events: None,
+        }
+    }
+
+    pub(crate) fn spawn_delivery_tours(&mut self, tours: &[DeliveryTour]) {
+        for tour in tours {
+            if let TripEndpoint::SuddenlyAppear(_) = tour.depot {
+                warn!("Skipping a delivery tour without a depot: {:?}", tour);
+                continue;
+            }
+            let spec = tour.vehicle.spec();
+            let vehicle = spec.make(
+                CarID {
+                    id: self.trips.new_car_id(),
+                    vehicle_type: spec.vehicle_type,
+                },
+                None,
+            );
+            self.freight.add_tour(vehicle, tour, &mut self.scheduler);
         }
     }
 
+    pub(crate) fn spawn_micromobility_fleet(&mut self, fleet: &MicromobilityFleet, map: &Map) {
+        self.trips
+            .add_micromobility_fleet(fleet, &mut self.scheduler, map);
+    }
+
     pub(crate) fn spawn_trips(
         &mut self,
         input: Vec<(PersonID, TripInfo, StartTripArgs)>,
@@ -299,14 +374,19 @@ impl Sim {
         // TODO Refactor the logic in router
         let spot = if let Some((spot, _)) = self
             .parking
-            .get_all_free_spots(Position::start(driving_lane), &vehicle, b, map)
+            .get_all_free_spots(Position::start(driving_lane), &vehicle, b, self.time, map)
             .get(0)
         {
             *spot
         } else {
-            let (_, spot, _) =
-                self.parking
-                    .path_to_free_parking_spot(driving_lane, &vehicle, b, map)?;
+            let (_, spot, _) = self.parking.path_to_free_parking_spot(
+                driving_lane,
+                &vehicle,
+                b,
+                None,
+                self.time,
+                map,
+            )?;
             spot
         };
 
@@ -323,7 +403,18 @@ impl Sim {
     ) -> &Person {
         self.trips.new_person(orig_id, ped_speed, vehicle_specs)
     }
-    pub(crate) fn seed_parked_car(&mut self, vehicle: Vehicle, spot: ParkingSpot) {
+    /// The car belongs to somebody living at `home`, who gets a permit for that building's
+    /// residential parking zone.
+    pub(crate) fn seed_parked_car(
+        &mut self,
+        vehicle: Vehicle,
+        spot: ParkingSpot,
+        home: BuildingID,
+        map: &Map,
+    ) {
+        if let Some(zone) = map.get_permit_zone(home) {
+            self.parking.grant_permit(vehicle.id, zone.clone());
+        }
         self.parking.reserve_spot(spot, vehicle.id);
         self.parking.add_parked_car(ParkedCar {
             vehicle,
@@ -474,13 +565,23 @@ impl Sim {
                     }
                 }
                 if !ok {
-                    self.trips.cancel_trip(
-                        self.time,
-                        create_car.trip_and_person.unwrap().0,
-                        "path is no longer valid after map edits".to_string(),
-                        Some(create_car.vehicle),
-                        &mut ctx,
-                    );
+                    if let Some((trip, _)) = create_car.trip_and_person {
+                        self.trips.cancel_trip(
+                            self.time,
+                            trip,
+                            "path is no longer valid after map edits".to_string(),
+                            Some(create_car.vehicle),
+                            &mut ctx,
+                        );
+                    } else if create_car.vehicle.vehicle_type == VehicleType::Truck {
+                        if let Some(parked_car) = create_car.maybe_parked_car {
+                            ctx.parking.remove_parked_car(parked_car);
+                        }
+                        self.freight.abandon_tour(
+                            create_car.vehicle.id,
+                            "path is no longer valid after map edits".to_string(),
+                        );
+                    }
                 } else {
                     // create_car contains a Path, which is expensive to clone. We need different
                     // parts of create_car after attempting start_car_on_lane.
@@ -551,7 +652,7 @@ impl Sim {
                             self.parking.remove_parked_car(parked_car);
                         }
                         if let Some(route) = maybe_route {
-                            self.transit.bus_created(id, route);
+                            self.transit.bus_created(self.time, id, route);
                         }
                         self.analytics
                             .record_demand(self.driving.get_path(id).unwrap(), map);
@@ -604,6 +705,8 @@ impl Sim {
                     &mut self.trips,
                     &mut self.transit,
                     &mut self.walking,
+                    &mut self.ridehail,
+                    &mut self.freight,
                 );
             }
             Command::UpdateLaggyHead(car) => {
@@ -638,6 +741,17 @@ impl Sim {
             Command::StartBus(r, _) => {
                 self.start_bus(map.get_br(r), map);
             }
+            Command::RequestRideHail(req) => {
+                self.ridehail
+                    .request(self.time, req, &mut self.trips, &mut ctx);
+            }
+            Command::StartDeliveryTour(car) => {
+                self.freight.start_tour(self.time, car, &mut ctx);
+            }
+            Command::RebalanceMicromobility => {
+                self.trips
+                    .rebalance_micromobility(&mut self.scheduler, self.time);
+            }
         }
 
         // Record events at precisely the time they occur.
@@ -649,6 +763,8 @@ impl Sim {
     fn dispatch_events(&mut self, mut events: Vec<Event>, map: &Map) {
         events.extend(self.trips.collect_events());
         events.extend(self.transit.collect_events());
+        events.extend(self.ridehail.collect_events());
+        events.extend(self.freight.collect_events());
         events.extend(self.driving.collect_events());
         events.extend(self.walking.collect_events());
         events.extend(self.intersections.collect_events());
@@ -660,6 +776,12 @@ impl Sim {
             if let Some(ref mut r) = self.recorder {
                 r.handle_event(self.time, &ev, map, &self.driving);
             }
+            if let Some(ref mut log) = self.event_log {
+                log.handle_event(self.time, &ev);
+            }
+            if let Some(ref mut events) = self.captured_events {
+                events.push((self.time, ev.clone()));
+            }
 
             self.analytics.event(ev, self.time, map);
         }
@@ -710,6 +832,7 @@ impl Sim {
                 last_update = Instant::now();
             }
         }
+        self.flush_event_log();
         timer.stop(format!("Advance sim to {}", end_time));
     }
     pub fn tiny_step(&mut self, map: &Map, maybe_cb: &mut Option<Box<dyn SimCallback>>) {
@@ -756,6 +879,31 @@ impl Sim {
         }
     }
 
+    /// Start or stop keeping every new event in memory, so callers can periodically grab them
+    /// with `take_captured_events`. Stopping discards anything not taken yet.
+    pub fn capture_events(&mut self, enabled: bool) {
+        self.captured_events = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn is_capturing_events(&self) -> bool {
+        self.captured_events.is_some()
+    }
+
+    /// Returns all events that've occurred since the last call, if events are being captured.
+    pub fn take_captured_events(&mut self) -> Vec<(Time, Event)> {
+        self.captured_events
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Make sure everything recorded so far in the event log (if any) is written to disk.
+    pub fn flush_event_log(&mut self) {
+        if let Some(ref mut log) = self.event_log {
+            log.flush();
+        }
+    }
+
     pub fn dump_before_abort(&self) {
         println!("At {}", self.time);
         if let Some(path) = self.find_previous_savestate(self.time) {
@@ -833,6 +981,41 @@ impl Sim {
     }
 }
 
+// Controlling traffic signals from outside the simulation, like with reinforcement learning
+impl Sim {
+    /// Stop a traffic signal from changing stages by itself, or return it to its normal timing.
+    /// While controlled externally, the current stage lasts until `advance_traffic_signal` is
+    /// called.
+    pub fn set_external_signal_control(
+        &mut self,
+        map: &Map,
+        i: IntersectionID,
+        enabled: bool,
+    ) -> Result<()> {
+        if map.maybe_get_traffic_signal(i).is_none() {
+            bail!("{} isn't a traffic signal", i);
+        }
+        self.intersections.set_external_signal_control(
+            self.time,
+            i,
+            enabled,
+            map,
+            &mut self.scheduler,
+        );
+        Ok(())
+    }
+
+    /// Immediately switch an externally controlled traffic signal to its next stage.
+    pub fn advance_traffic_signal(&mut self, map: &Map, i: IntersectionID) -> Result<()> {
+        if !self.intersections.is_signal_externally_controlled(i) {
+            bail!("{} isn't controlled externally", i);
+        }
+        self.intersections
+            .advance_signal(self.time, i, map, &mut self.scheduler);
+        Ok(())
+    }
+}
+
 // Live edits
 impl Sim {
     pub fn handle_live_edited_traffic_signals(&mut self, map: &Map) {
@@ -843,10 +1026,20 @@ impl Sim {
     /// Respond to arbitrary map edits without resetting the simulation. Returns the number of
     /// (trips cancelled, parked cars displaced).
     pub fn handle_live_edits(&mut self, map: &Map, timer: &mut Timer) -> (usize, usize) {
+        let (cancelled, num_parked_cars) = self.handle_live_edits_listing_trips(map, timer);
+        (cancelled.len(), num_parked_cars)
+    }
+
+    /// Like `handle_live_edits`, but returns the trips cancelled, not just how many.
+    pub fn handle_live_edits_listing_trips(
+        &mut self,
+        map: &Map,
+        timer: &mut Timer,
+    ) -> (Vec<TripID>, usize) {
         self.edits_name = map.get_edits().edits_name.clone();
 
         let (affected, num_parked_cars) = self.find_trips_affected_by_live_edits(map, timer);
-        let num_trips_cancelled = affected.len();
+        let cancelled: Vec<TripID> = affected.iter().map(|(_, trip)| *trip).collect();
         let affected_agents: BTreeSet<AgentID> = affected.iter().map(|(a, _)| *a).collect();
 
         // V1: Just cancel every trip crossing an affected area.
@@ -892,7 +1085,7 @@ impl Sim {
         self.driving.handle_live_edits(map);
         self.intersections.handle_live_edits(map);
 
-        (num_trips_cancelled, num_parked_cars)
+        (cancelled, num_parked_cars)
     }
 
     /// Returns (trips affected, number of parked cars displaced)
@@ -946,7 +1139,10 @@ impl Sim {
             affected.extend(self.walking.find_trips_to_parking(evicted_cars));
             for car in cars_parking_in_the_void {
                 let a = AgentID::Car(car);
-                affected.insert((a, self.agent_to_trip(a).unwrap()));
+                // TODO Delivery vehicles headed for a loading zone that's gone aren't handled
+                if let Some(trip) = self.agent_to_trip(a) {
+                    affected.insert((a, trip));
+                }
             }
 
             if !self.parking.is_infinite() {
@@ -965,7 +1161,12 @@ impl Sim {
 
 // Invasive debugging
 impl Sim {
-    pub fn delete_car(&mut self, id: CarID, map: &Map) {
+    /// Fails for ride-hail and delivery vehicles, since the fleet or the delivery tour would lose
+    /// track of them.
+    pub fn delete_car(&mut self, id: CarID, map: &Map) -> Result<()> {
+        if id.vehicle_type == VehicleType::RideHail || id.vehicle_type == VehicleType::Truck {
+            bail!("Can't delete {}; it belongs to a fleet", id);
+        }
         if let Some(trip) = self.agent_to_trip(AgentID::Car(id)) {
             let mut ctx = Ctx {
                 parking: &mut self.parking,
@@ -982,8 +1183,9 @@ impl Sim {
                 Some(vehicle),
                 &mut ctx,
             );
+            Ok(())
         } else {
-            println!("{} has no trip?!", id);
+            bail!("{} has no trip", id)
         }
     }
 
@@ -1029,6 +1231,21 @@ impl Sim {
     }
 }
 
+// Pinning routes for traffic assignment
+impl Sim {
+    /// Driving trips listed here follow the given path instead of pathfinding, as long as the
+    /// path still goes between the same endpoints. Call before any trips start. From then on, the
+    /// route each car trip takes is recorded for `take_chosen_routes`.
+    pub fn set_fixed_routes(&mut self, fixed: BTreeMap<TripID, Path>) {
+        self.trips.set_fixed_routes(fixed);
+    }
+
+    /// Returns the route each car trip took since `set_fixed_routes` was called.
+    pub fn take_chosen_routes(&mut self) -> BTreeMap<TripID, Path> {
+        self.trips.take_chosen_routes()
+    }
+}
+
 // Managing highlighted people
 impl Sim {
     pub fn set_highlighted_people(&mut self, people: BTreeSet<PersonID>) {
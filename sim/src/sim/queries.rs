@@ -100,6 +100,49 @@ impl Sim {
         self.transit.get_passengers(car).len()
     }
 
+    /// How many ride-hail vehicles exist, and how many are currently busy with a request.
+    pub fn ride_hail_fleet_status(&self) -> (usize, usize) {
+        (
+            self.ridehail.fleet_size(),
+            self.ridehail.num_busy_vehicles(),
+        )
+    }
+
+    pub fn num_waiting_ride_hail_requests(&self) -> usize {
+        self.ridehail.num_waiting_requests()
+    }
+
+    /// The trip a ride-hail vehicle is driving to pick up or currently carrying.
+    pub fn ride_hail_serving_trip(&self, car: CarID) -> Option<TripID> {
+        self.ridehail.serving_trip(car)
+    }
+
+    /// How many delivery tours are underway
+    pub fn num_active_delivery_tours(&self) -> usize {
+        self.freight.num_active_tours()
+    }
+
+    /// For a delivery vehicle, the next building it's headed to (or None when returning to the
+    /// depot), the number of stops made so far, and the total number of stops.
+    pub fn delivery_tour_progress(&self, car: CarID) -> Option<(Option<BuildingID>, usize, usize)> {
+        self.freight.tour_progress(car)
+    }
+
+    /// For every micromobility dock, the number of shared vehicles there now and the capacity
+    pub fn micromobility_dock_status(&self) -> BTreeMap<BuildingID, (usize, usize)> {
+        self.trips.micromobility_dock_status()
+    }
+
+    /// The number of shared bikes or scooters, and how many are in use or reserved
+    pub fn micromobility_fleet_status(&self) -> (usize, usize) {
+        self.trips.micromobility_fleet_status()
+    }
+
+    /// Where free-floating shared vehicles not in use are currently left
+    pub fn free_floating_vehicles(&self) -> Vec<(CarID, BuildingID)> {
+        self.trips.free_floating_vehicles()
+    }
+
     pub fn bus_route_id(&self, maybe_bus: CarID) -> Option<BusRouteID> {
         if maybe_bus.vehicle_type == VehicleType::Bus
             || maybe_bus.vehicle_type == VehicleType::Train
@@ -204,6 +247,8 @@ impl Sim {
             VehicleType::Bike,
             VehicleType::Bus,
             VehicleType::Train,
+            VehicleType::RideHail,
+            VehicleType::Truck,
         ] {
             let id = CarID {
                 id: idx,
@@ -218,7 +263,14 @@ impl Sim {
             id: idx,
             vehicle_type: VehicleType::Car,
         };
-        // Only cars can be parked.
+        // Only cars can be parked, besides delivery vehicles in a loading zone.
+        if self.parking.lookup_parked_car(id).is_some() {
+            return Some(id);
+        }
+        let id = CarID {
+            id: idx,
+            vehicle_type: VehicleType::Truck,
+        };
         if self.parking.lookup_parked_car(id).is_some() {
             return Some(id);
         }
@@ -343,6 +395,14 @@ impl Sim {
             .current_stage_and_remaining_time(self.time, i)
     }
 
+    pub fn time_in_current_stage(&self, i: IntersectionID) -> Duration {
+        self.intersections.time_in_current_stage(self.time, i)
+    }
+
+    pub fn is_signal_externally_controlled(&self, i: IntersectionID) -> bool {
+        self.intersections.is_signal_externally_controlled(i)
+    }
+
     // TODO This is an awkward copy of raw_throughput
     // TODO And it does NOT count buses/trains spawning
     pub fn all_arrivals_at_border(
@@ -427,7 +487,9 @@ impl Sim {
                 let max_speed = match info.mode {
                     TripMode::Walk | TripMode::Transit => Some(person.ped_speed),
                     // TODO We should really search the vehicles and grab it from there
-                    TripMode::Drive => None,
+                    TripMode::Drive | TripMode::RideHail => None,
+                    // Shared vehicles might be faster than the person's own bike
+                    TripMode::Micromobility => None,
                     // Assume just one bike
                     TripMode::Bike => {
                         person
@@ -3,9 +3,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::Time;
+use geom::{Duration, Time};
 use map_model::{BusRoute, BusRouteID, BusStopID, Map, Path, PathRequest, Position};
 
+use crate::mechanics::TIME_TO_WAIT_AT_BUS_STOP;
 use crate::sim::Ctx;
 use crate::{
     AgentID, CarID, DrivingSimState, Event, PedestrianID, PersonID, Router, TripID, TripManager,
@@ -28,6 +29,11 @@ struct Route {
     start: Path,
     end_at_border: Option<Path>,
     active_vehicles: BTreeSet<CarID>,
+    /// How long after departing a bus should reach each stop. If the route has no published
+    /// timetable, this assumes no traffic.
+    schedule: Vec<Duration>,
+    /// How long to wait at each stop
+    dwell_times: Vec<Duration>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -37,6 +43,7 @@ struct Bus {
     /// Where does each passenger want to deboard?
     passengers: Vec<(PersonID, Option<BusStopID>)>,
     state: BusState,
+    departed: Time,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -142,18 +149,42 @@ impl TransitSimState {
             } else {
                 None
             };
+            let to_first_stop = start.estimate_duration(map, None);
+            let (schedule, dwell_times) = if let Some(ref timetable) = bus_route.timetable {
+                (
+                    timetable
+                        .arrival_offsets
+                        .iter()
+                        .map(|offset| to_first_stop + *offset)
+                        .collect(),
+                    timetable.dwell_times.clone(),
+                )
+            } else {
+                let mut schedule = vec![to_first_stop];
+                for stop in &stops {
+                    if let Some(ref path) = stop.next_stop {
+                        let prev = *schedule.last().unwrap();
+                        schedule.push(
+                            prev + TIME_TO_WAIT_AT_BUS_STOP + path.estimate_duration(map, None),
+                        );
+                    }
+                }
+                (schedule, vec![TIME_TO_WAIT_AT_BUS_STOP; stops.len()])
+            };
             Route {
                 active_vehicles: BTreeSet::new(),
                 stops,
                 start,
                 end_at_border,
+                schedule,
+                dwell_times,
             }
         });
 
         self.routes[&bus_route.id].start.clone()
     }
 
-    pub fn bus_created(&mut self, bus: CarID, r: BusRouteID) {
+    pub fn bus_created(&mut self, now: Time, bus: CarID, r: BusRouteID) {
         let route = self.routes.get_mut(&r).unwrap();
         route.active_vehicles.insert(bus);
         self.buses.insert(
@@ -163,12 +194,13 @@ impl TransitSimState {
                 route: r,
                 passengers: Vec::new(),
                 state: BusState::DrivingToStop(0),
+                departed: now,
             },
         );
     }
 
-    /// If true, the bus is idling. If false, the bus actually arrived at a border and should now
-    /// vanish.
+    /// If the bus is idling, returns how long it should wait. If None, the bus actually arrived at
+    /// a border and should now vanish.
     pub fn bus_arrived_at_stop(
         &mut self,
         now: Time,
@@ -176,7 +208,7 @@ impl TransitSimState {
         trips: &mut TripManager,
         walking: &mut WalkingSimState,
         ctx: &mut Ctx,
-    ) -> bool {
+    ) -> Option<Duration> {
         let mut bus = self.buses.get_mut(&id).unwrap();
         match bus.state {
             BusState::DrivingToStop(stop_idx) => {
@@ -184,6 +216,13 @@ impl TransitSimState {
                 let stop1 = self.routes[&bus.route].stops[stop_idx].id;
                 self.events
                     .push(Event::BusArrivedAtStop(id, bus.route, stop1));
+                let scheduled = bus.departed + self.routes[&bus.route].schedule[stop_idx];
+                self.events.push(Event::BusScheduleAdherence(
+                    id,
+                    bus.route,
+                    stop1,
+                    now - scheduled,
+                ));
 
                 // Deboard existing passengers.
                 let mut still_riding = Vec::new();
@@ -244,7 +283,7 @@ impl TransitSimState {
                     }
                 }
                 self.peds_waiting.insert(stop1, still_waiting);
-                true
+                Some(self.routes[&bus.route].dwell_times[stop_idx])
             }
             BusState::DrivingOffMap => {
                 self.routes
@@ -263,7 +302,7 @@ impl TransitSimState {
                     }
                     trips.transit_rider_reached_border(now, person, id, ctx);
                 }
-                false
+                None
             }
             BusState::AtStop(_) | BusState::Done => unreachable!(),
         }
@@ -1,20 +1,22 @@
 use std::collections::{BTreeMap, VecDeque};
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_btreemap, serialize_btreemap, Counter};
 use geom::{Distance, Duration, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, PathConstraints, PathRequest, Position,
+    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, Path, PathConstraints, PathRequest,
+    Position, RoutingParams,
 };
 
 use crate::sim::Ctx;
 use crate::{
     AgentID, AgentType, AlertLocation, CarID, Command, CreateCar, CreatePedestrian, DrivingGoal,
-    Event, IndividTrip, OrigPersonID, ParkedCar, ParkingSim, ParkingSpot, PedestrianID, PersonID,
-    PersonSpec, Scenario, SidewalkPOI, SidewalkSpot, StartTripArgs, TransitSimState, TripEndpoint,
-    TripID, TripPhaseType, TripPurpose, TripSpec, Vehicle, VehicleSpec, VehicleType,
-    WalkingSimState,
+    Event, IndividTrip, MicromobilityFleet, MicromobilitySimState, OrigPersonID, ParkedCar,
+    ParkingSim, ParkingSpot, PedestrianID, PersonID, PersonSpec, RideHailRequest, Scenario,
+    Scheduler, SidewalkPOI, SidewalkSpot, StartTripArgs, TransitSimState, TripEndpoint, TripID,
+    TripPhaseType, TripPurpose, TripSpec, Vehicle, VehicleSpec, VehicleType, WalkingSimState,
 };
 
 /// Manages people, each of which executes some trips through the day. Each trip is further broken
@@ -36,8 +38,21 @@ pub(crate) struct TripManager {
     unfinished_trips: usize,
 
     car_id_counter: usize,
+    micromobility: MicromobilitySimState,
 
     events: Vec<Event>,
+
+    // Only used by traffic assignment
+    #[serde(skip_serializing, skip_deserializing)]
+    route_choices: Option<RouteChoices>,
+}
+
+/// Lets traffic assignment pin some drivers to the route they took previously, and find out what
+/// route everybody took.
+#[derive(Debug, Clone)]
+struct RouteChoices {
+    fixed: BTreeMap<TripID, Path>,
+    chosen: BTreeMap<TripID, Path>,
 }
 
 // Initialization
@@ -49,10 +64,26 @@ impl TripManager {
             active_trip_mode: BTreeMap::new(),
             unfinished_trips: 0,
             car_id_counter: 0,
+            micromobility: MicromobilitySimState::new(),
             events: Vec::new(),
+            route_choices: None,
         }
     }
 
+    pub fn set_fixed_routes(&mut self, fixed: BTreeMap<TripID, Path>) {
+        self.route_choices = Some(RouteChoices {
+            fixed,
+            chosen: BTreeMap::new(),
+        });
+    }
+
+    pub fn take_chosen_routes(&mut self) -> BTreeMap<TripID, Path> {
+        self.route_choices
+            .as_mut()
+            .map(|choices| std::mem::take(&mut choices.chosen))
+            .unwrap_or_else(BTreeMap::new)
+    }
+
     // TODO assert the specs are correct yo
     pub fn new_person(
         &mut self,
@@ -92,6 +123,25 @@ impl TripManager {
         id
     }
 
+    pub fn add_micromobility_fleet(
+        &mut self,
+        fleet: &MicromobilityFleet,
+        scheduler: &mut Scheduler,
+        map: &Map,
+    ) {
+        let counter = &mut self.car_id_counter;
+        self.micromobility.add_fleet(
+            fleet,
+            || {
+                let id = *counter;
+                *counter += 1;
+                id
+            },
+            scheduler,
+            map,
+        );
+    }
+
     pub fn new_trip(&mut self, person: PersonID, info: TripInfo) -> TripID {
         let id = TripID(self.trips.len());
         let trip = Trip {
@@ -155,6 +205,18 @@ impl TripManager {
             return;
         }
         self.trips[trip.0].started = true;
+        if let Some(car) = args.use_vehicle {
+            // Remember when the car is needed next, so the driver avoids spots with a shorter
+            // time limit
+            let trips = &self.trips;
+            let next_departure = person
+                .trips
+                .iter()
+                .skip_while(|t| **t != trip)
+                .nth(1)
+                .map(|t| trips[t.0].info.departure);
+            ctx.parking.plan_departure(car, next_departure);
+        }
 
         let info = &self.trips[trip.0].info;
         let spec = match TripSpec::maybe_new(
@@ -163,6 +225,7 @@ impl TripManager {
             info.mode,
             args.use_vehicle,
             args.retry_if_no_room,
+            now,
             ctx.map,
         ) {
             Ok(spec) => spec,
@@ -202,11 +265,13 @@ impl TripManager {
                     start_pos,
                     goal.goal_pos(constraints, ctx.map).unwrap(),
                     constraints,
-                );
+                )
+                .with_departure(now);
                 let person = person.id;
 
-                match ctx.map.pathfind(req) {
+                match self.pathfind_car(trip, req.clone(), ctx.map) {
                     Ok(path) => {
+                        self.check_toll_diversion(now, trip, &req, &path, ctx.map);
                         let router = goal.make_router(vehicle.id, path, ctx.map);
                         ctx.scheduler.push(
                             now,
@@ -379,7 +444,7 @@ impl TripManager {
                     );
                 }
             }
-            TripSpec::UsingTransit { start, stop1, .. } => {
+            TripSpec::UsingTransit { start, rides, .. } => {
                 assert_eq!(
                     person.state,
                     match start.connection {
@@ -407,7 +472,7 @@ impl TripManager {
                 );
                 person.state = PersonState::Trip(trip);
 
-                let walk_to = SidewalkSpot::bus_stop(stop1, ctx.map);
+                let walk_to = SidewalkSpot::station_entrance(rides[0].board, rides[0].walk_to);
                 let req = PathRequest::walking(start.sidewalk_pos, walk_to.sidewalk_pos);
                 match ctx.map.pathfind(req) {
                     Ok(path) => {
@@ -429,11 +494,78 @@ impl TripManager {
                     }
                 }
             }
+            TripSpec::UsingRideHail { start, goal } => {
+                assert_eq!(person.state, PersonState::Inside(start));
+                person.state = PersonState::Trip(trip);
+
+                // The person waits at the curb until the vehicle arrives.
+                self.events
+                    .push(Event::PersonLeavesBuilding(person.id, start));
+                self.events.push(Event::TripPhaseStarting(
+                    trip,
+                    person.id,
+                    None,
+                    TripPhaseType::WaitingForRideHail,
+                ));
+                ctx.scheduler.push(
+                    now,
+                    Command::RequestRideHail(RideHailRequest {
+                        trip,
+                        person: person.id,
+                        pickup: start,
+                        dropoff: goal,
+                        requested: now,
+                    }),
+                );
+            }
+            TripSpec::UsingSharedVehicle { start, goal } => {
+                assert_eq!(person.state, PersonState::Inside(start));
+                person.state = PersonState::Trip(trip);
+
+                let (car, pickup, dropoff) =
+                    match self.micromobility.reserve(trip, start, goal, ctx.map) {
+                        Ok(result) => result,
+                        Err(err) => {
+                            self.cancel_trip(now, trip, err.to_string(), None, ctx);
+                            return;
+                        }
+                    };
+                self.trips[trip.0].legs.extend(vec![
+                    TripLeg::Walk(pickup.clone()),
+                    TripLeg::Drive(car, DrivingGoal::ParkNear(dropoff)),
+                    TripLeg::Walk(SidewalkSpot::building(goal, ctx.map)),
+                ]);
+
+                let person = &self.people[self.trips[trip.0].person.0];
+                let start = SidewalkSpot::building(start, ctx.map);
+                let req = PathRequest::walking(start.sidewalk_pos, pickup.sidewalk_pos);
+                match ctx.map.pathfind(req) {
+                    Ok(path) => {
+                        ctx.scheduler.push(
+                            now,
+                            Command::SpawnPed(CreatePedestrian {
+                                id: person.ped,
+                                speed: person.ped_speed,
+                                start,
+                                goal: pickup,
+                                path,
+                                trip,
+                                person: person.id,
+                            }),
+                        );
+                    }
+                    Err(err) => {
+                        self.cancel_trip(now, trip, err.to_string(), None, ctx);
+                    }
+                }
+            }
         }
     }
 
     pub fn collect_events(&mut self) -> Vec<Event> {
-        std::mem::take(&mut self.events)
+        let mut events = std::mem::take(&mut self.events);
+        events.extend(self.micromobility.collect_events());
+        events
     }
 }
 
@@ -540,12 +672,14 @@ impl TripManager {
             ParkingSpot::Lot(_, _) => {
                 PathRequest::leave_from_driveway(base_start, end, PathConstraints::Car, ctx.map)
             }
-        };
+        }
+        .with_departure(now);
 
         let person = trip.person;
         let trip = trip.id;
-        match ctx.map.pathfind(req) {
+        match self.pathfind_car(trip, req.clone(), ctx.map) {
             Ok(path) => {
+                self.check_toll_diversion(now, trip, &req, &path, ctx.map);
                 let router = drive_to.make_router(parked_car.vehicle.id, path, ctx.map);
                 ctx.scheduler.push(
                     now,
@@ -563,6 +697,59 @@ impl TripManager {
         }
     }
 
+    /// Pathfind for a driving trip, unless traffic assignment fixed this trip to an earlier route
+    /// that still fits the request.
+    fn pathfind_car(&mut self, trip: TripID, req: PathRequest, map: &Map) -> Result<Path> {
+        let choices = match self.route_choices {
+            Some(ref mut choices) if req.constraints == PathConstraints::Car => choices,
+            _ => {
+                return map.pathfind(req);
+            }
+        };
+        let path = match choices.fixed.get(&trip) {
+            Some(path)
+                if path.get_req().start == req.start
+                    && path.get_req().end == req.end
+                    && path.get_req().constraints == req.constraints =>
+            {
+                path.clone()
+            }
+            _ => map.pathfind(req)?,
+        };
+        choices.chosen.insert(trip, path.clone());
+        Ok(path)
+    }
+
+    /// If tolls made this driver pick a different route than they otherwise would have, record
+    /// how much they saved. This costs a second pathfind, so only drivers still paying some toll
+    /// are checked; drivers avoiding tolls entirely aren't counted.
+    fn check_toll_diversion(
+        &mut self,
+        now: Time,
+        trip: TripID,
+        req: &PathRequest,
+        path: &Path,
+        map: &Map,
+    ) {
+        if req.constraints != PathConstraints::Car || !map.has_tolls() {
+            return;
+        }
+        let paid = path.get_total_toll(map, now);
+        if paid <= 0.0 {
+            return;
+        }
+        let ignore_tolls = RoutingParams {
+            value_of_time: f64::INFINITY,
+            ..map.routing_params().clone()
+        };
+        if let Ok(toll_free) = map.pathfind_with_params(req.clone(), &ignore_tolls, true) {
+            let saved = toll_free.get_total_toll(map, now) - paid;
+            if saved > 0.0 {
+                self.events.push(Event::TripDivertedByToll(trip, saved));
+            }
+        }
+    }
+
     pub fn ped_ready_to_bike(
         &mut self,
         now: Time,
@@ -603,7 +790,7 @@ impl TripManager {
             );
             return;
         };
-        let req = PathRequest::vehicle(driving_pos, end, PathConstraints::Bike);
+        let req = PathRequest::vehicle(driving_pos, end, PathConstraints::Bike).with_departure(now);
         let maybe_router = if req.start.lane() == req.end.lane() {
             // TODO Convert to a walking trip! Ideally, do this earlier and convert the trip to
             // walking, like schedule_trip does
@@ -618,15 +805,15 @@ impl TripManager {
         };
         match maybe_router {
             Ok(router) => {
+                let vehicle = if self.micromobility.is_shared(bike) {
+                    self.micromobility.pick_up(bike)
+                } else {
+                    self.people[trip.person.0].get_vehicle(bike)
+                };
                 ctx.scheduler.push(
                     now,
                     Command::SpawnCar(
-                        CreateCar::for_appearing(
-                            self.people[trip.person.0].get_vehicle(bike),
-                            router,
-                            trip.id,
-                            trip.person,
-                        ),
+                        CreateCar::for_appearing(vehicle, router, trip.id, trip.person),
                         true,
                     ),
                 );
@@ -661,6 +848,9 @@ impl TripManager {
             }
             _ => unreachable!(),
         };
+        if self.micromobility.is_shared(bike) {
+            self.micromobility.drop_off(bike);
+        }
 
         let id = trip.id;
         self.spawn_ped(now, id, bike_rack, ctx);
@@ -710,7 +900,8 @@ impl TripManager {
 
         match trip.legs[0] {
             TripLeg::Walk(ref spot) => {
-                assert_eq!(*spot, SidewalkSpot::bus_stop(stop, ctx.map));
+                // The spot might be a station entrance
+                assert_eq!(spot.connection, SidewalkPOI::BusStop(stop));
             }
             _ => unreachable!(),
         }
@@ -779,19 +970,67 @@ impl TripManager {
             .remove(&AgentID::BusPassenger(person, bus))
             .unwrap()
             .0];
-        let start = match trip.legs.pop_front().unwrap() {
-            TripLeg::RideBus(_, maybe_stop2) => SidewalkSpot::bus_stop(
-                maybe_stop2.expect("someone left a bus, even though they should've ridden off-map"),
-                ctx.map,
-            ),
+        let stop2 = match trip.legs.pop_front().unwrap() {
+            TripLeg::RideBus(_, maybe_stop2) => {
+                maybe_stop2.expect("someone left a bus, even though they should've ridden off-map")
+            }
             _ => unreachable!(),
         };
+        // Leave a station through whichever entrance is closest to the next destination
+        let start = match (ctx.map.get_station_for_stop(stop2), &trip.legs[0]) {
+            (Some(ts), TripLeg::Walk(goal)) => SidewalkSpot::station_entrance(
+                stop2,
+                ts.closest_entrance(goal.sidewalk_pos.pt(ctx.map), ctx.map),
+            ),
+            _ => SidewalkSpot::bus_stop(stop2, ctx.map),
+        };
         self.people[person.0].on_bus.take().unwrap();
 
         let id = trip.id;
         self.spawn_ped(now, id, start, ctx);
     }
 
+    pub fn ride_hail_pickup(&mut self, car: CarID, trip: TripID) {
+        let trip = &mut self.trips[trip.0];
+        match trip.legs[0] {
+            TripLeg::RideHail(_, ref mut vehicle) => {
+                assert!(vehicle.is_none());
+                *vehicle = Some(car);
+            }
+            _ => unreachable!(),
+        }
+        let id = trip.id;
+        self.agent_starting_trip_leg(AgentID::Car(car), id);
+    }
+
+    pub fn ride_hail_dropoff(
+        &mut self,
+        now: Time,
+        car: CarID,
+        blocked_time: Duration,
+        distance_crossed: Distance,
+        ctx: &mut Ctx,
+    ) {
+        let trip = &mut self.trips[self.active_trip_mode.remove(&AgentID::Car(car)).unwrap().0];
+        trip.total_blocked_time += blocked_time;
+        trip.total_distance += distance_crossed;
+
+        let bldg = match trip.legs.pop_front() {
+            Some(TripLeg::RideHail(b, Some(c))) => {
+                assert_eq!(car, c);
+                b
+            }
+            _ => unreachable!(),
+        };
+
+        self.people[trip.person.0].state = PersonState::Inside(bldg);
+        self.events
+            .push(Event::PersonEntersBuilding(trip.person, bldg));
+
+        let id = trip.id;
+        self.trip_finished(now, id, ctx);
+    }
+
     pub fn ped_reached_border(
         &mut self,
         now: Time,
@@ -1003,6 +1242,11 @@ impl TripManager {
             TripEndpoint::SuddenlyAppear(_) => unreachable!(),
         };
 
+        // Shared vehicles go back to the fleet
+        if trip.info.mode == TripMode::Micromobility {
+            self.micromobility.trip_cancelled(id);
+        }
+
         // Don't forget the car!
         if let Some(vehicle) = abandoned_vehicle {
             if vehicle.vehicle_type == VehicleType::Car {
@@ -1016,14 +1260,27 @@ impl TripManager {
                     let driving_lane = ctx.map.find_driving_lane_near_building(b);
                     if let Some(spot) = ctx
                         .parking
-                        .get_all_free_spots(Position::start(driving_lane), &vehicle, b, ctx.map)
+                        .get_all_free_spots(
+                            Position::start(driving_lane),
+                            &vehicle,
+                            b,
+                            now,
+                            ctx.map,
+                        )
                         // TODO Could pick something closer, but meh, cancelled trips are bugs
                         // anyway
                         .get(0)
                         .map(|(spot, _)| *spot)
                         .or_else(|| {
                             ctx.parking
-                                .path_to_free_parking_spot(driving_lane, &vehicle, b, ctx.map)
+                                .path_to_free_parking_spot(
+                                    driving_lane,
+                                    &vehicle,
+                                    b,
+                                    None,
+                                    now,
+                                    ctx.map,
+                                )
                                 .map(|(_, spot, _)| spot)
                         })
                     {
@@ -1054,7 +1311,9 @@ impl TripManager {
         } else {
             // If the trip was cancelled because we'e totally out of parking, don't forget to clean
             // this up.
-            if let TripLeg::Drive(c, _) = &trip.legs[0] {
+            if let Some(TripLeg::Drive(c, _)) | Some(TripLeg::RideHail(_, Some(c))) =
+                trip.legs.front()
+            {
                 if let Some(t) = self.active_trip_mode.remove(&AgentID::Car(*c)) {
                     assert_eq!(t, trip.id);
                 }
@@ -1067,6 +1326,10 @@ impl TripManager {
     pub fn trip_abruptly_cancelled(&mut self, trip: TripID, agent: AgentID) {
         assert_eq!(self.active_trip_mode.remove(&agent), Some(trip));
     }
+
+    pub fn rebalance_micromobility(&mut self, scheduler: &mut Scheduler, now: Time) {
+        self.micromobility.rebalance(now, scheduler);
+    }
 }
 
 // Queries
@@ -1102,6 +1365,11 @@ impl TripManager {
             TripLeg::Walk(_) => AgentID::Pedestrian(person.ped),
             TripLeg::Drive(c, _) => AgentID::Car(*c),
             TripLeg::RideBus(_, _) => AgentID::BusPassenger(person.id, person.on_bus.unwrap()),
+            TripLeg::RideHail(_, Some(c)) => AgentID::Car(*c),
+            // Still waiting inside for pickup
+            TripLeg::RideHail(_, None) => {
+                return TripResult::ModeChange;
+            }
         };
         if self.active_trip_mode.get(&a) == Some(&id) {
             TripResult::Ok(a)
@@ -1111,6 +1379,14 @@ impl TripManager {
         }
     }
 
+    /// True if somebody is still inside, waiting to be picked up for this trip.
+    pub fn waiting_for_ride_hail(&self, id: TripID) -> bool {
+        let trip = &self.trips[id.0];
+        trip.finished_at.is_none()
+            && trip.info.cancellation_reason.is_none()
+            && matches!(trip.legs.front(), Some(TripLeg::RideHail(_, None)))
+    }
+
     /// This will be None for parked cars and buses. Should always work for pedestrians.
     pub fn agent_to_trip(&self, id: AgentID) -> Option<TripID> {
         self.active_trip_mode.get(&id).cloned()
@@ -1156,6 +1432,7 @@ impl TripManager {
             cyclists: 0,
 
             sov_drivers: 0,
+            ride_hail_passengers: 0,
 
             buses,
             trains,
@@ -1172,7 +1449,10 @@ impl TripManager {
                     VehicleType::Bike => {
                         cnt.cyclists += 1;
                     }
-                    VehicleType::Bus | VehicleType::Train => unreachable!(),
+                    VehicleType::RideHail => {
+                        cnt.ride_hail_passengers += 1;
+                    }
+                    VehicleType::Bus | VehicleType::Train | VehicleType::Truck => unreachable!(),
                 },
                 AgentID::BusPassenger(_, c) => match c.vehicle_type {
                     VehicleType::Bus => {
@@ -1181,7 +1461,10 @@ impl TripManager {
                     VehicleType::Train => {
                         cnt.train_riders += 1;
                     }
-                    VehicleType::Car | VehicleType::Bike => unreachable!(),
+                    VehicleType::Car
+                    | VehicleType::Bike
+                    | VehicleType::RideHail
+                    | VehicleType::Truck => unreachable!(),
                 },
                 // These're counted separately
                 AgentID::Pedestrian(_) => {}
@@ -1230,6 +1513,20 @@ impl TripManager {
         let t = &self.trips[id.0];
         t.total_blocked_time
     }
+    /// For every dock, the number of shared vehicles there now and the capacity
+    pub fn micromobility_dock_status(&self) -> BTreeMap<BuildingID, (usize, usize)> {
+        self.micromobility.dock_status()
+    }
+
+    /// The number of shared vehicles, and how many are in use or reserved
+    pub fn micromobility_fleet_status(&self) -> (usize, usize) {
+        self.micromobility.fleet_status()
+    }
+
+    pub fn free_floating_vehicles(&self) -> Vec<(CarID, BuildingID)> {
+        self.micromobility.free_floating_vehicles()
+    }
+
     pub fn bldg_to_people(&self, b: BuildingID) -> Vec<PersonID> {
         let mut people = Vec::new();
         for p in &self.people {
@@ -1262,8 +1559,8 @@ impl TripManager {
                     // We can make some assumptions here.
                     let agent_type = match t.info.mode {
                         TripMode::Walk => AgentType::Pedestrian,
-                        TripMode::Bike => AgentType::Bike,
-                        TripMode::Drive => AgentType::Car,
+                        TripMode::Bike | TripMode::Micromobility => AgentType::Bike,
+                        TripMode::Drive | TripMode::RideHail => AgentType::Car,
                         // TODO Not true for long. People will be able to spawn at borders already
                         // on a bus.
                         TripMode::Transit => AgentType::Pedestrian,
@@ -1349,6 +1646,8 @@ pub(crate) enum TripLeg {
     Drive(CarID, DrivingGoal),
     /// Maybe get off at a stop, maybe ride off-map
     RideBus(BusRouteID, Option<BusStopID>),
+    /// Where to get dropped off, and the vehicle, once it's picked the person up
+    RideHail(BuildingID, Option<CarID>),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
@@ -1357,6 +1656,9 @@ pub enum TripMode {
     Bike,
     Transit,
     Drive,
+    RideHail,
+    /// Walk to a shared bike or scooter, ride it, then walk the rest of the way
+    Micromobility,
 }
 
 impl TripMode {
@@ -1366,6 +1668,8 @@ impl TripMode {
             TripMode::Bike,
             TripMode::Transit,
             TripMode::Drive,
+            TripMode::RideHail,
+            TripMode::Micromobility,
         ]
     }
 
@@ -1375,6 +1679,8 @@ impl TripMode {
             TripMode::Bike => "bike",
             TripMode::Transit => "use transit",
             TripMode::Drive => "drive",
+            TripMode::RideHail => "ride-hail",
+            TripMode::Micromobility => "use micromobility",
         }
     }
 
@@ -1385,6 +1691,8 @@ impl TripMode {
             TripMode::Bike => "biking",
             TripMode::Transit => "using transit",
             TripMode::Drive => "driving",
+            TripMode::RideHail => "riding in a ride-hail vehicle",
+            TripMode::Micromobility => "riding a shared bike or scooter",
         }
     }
 
@@ -1394,16 +1702,18 @@ impl TripMode {
             TripMode::Bike => "Bike",
             TripMode::Transit => "Bus",
             TripMode::Drive => "Car",
+            TripMode::RideHail => "Ride-hail",
+            TripMode::Micromobility => "Micromobility",
         }
     }
 
     pub fn to_constraints(self) -> PathConstraints {
         match self {
             TripMode::Walk => PathConstraints::Pedestrian,
-            TripMode::Bike => PathConstraints::Bike,
+            TripMode::Bike | TripMode::Micromobility => PathConstraints::Bike,
             // TODO WRONG
             TripMode::Transit => PathConstraints::Bus,
-            TripMode::Drive => PathConstraints::Car,
+            TripMode::Drive | TripMode::RideHail => PathConstraints::Car,
         }
     }
 
@@ -1486,6 +1796,7 @@ pub struct CommutersVehiclesCounts {
     pub cyclists: usize,
 
     pub sov_drivers: usize,
+    pub ride_hail_passengers: usize,
 
     pub buses: usize,
     pub trains: usize,
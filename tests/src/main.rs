@@ -1,5 +1,6 @@
 //! Integration tests
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::Write;
 
@@ -8,14 +9,46 @@ use rand::seq::SliceRandom;
 
 use abstio::{CityName, MapName};
 use abstutil::Timer;
-use geom::{Distance, Duration, Time};
-use map_model::{IntersectionID, Map};
-use sim::{IndividTrip, PersonSpec, Scenario, TripEndpoint, TripMode, TripPurpose};
+use geom::{Distance, Duration, Speed, Time};
+use map_model::{
+    AffectedID, BusRouteID, BusStopID, DirectedRoadID, EditCmd, EditIntersection, IntersectionID,
+    Itinerary, ItineraryLeg, LaneType, Map, ObservedTravelTimes, Path, PathConstraints,
+    PathRequest, PathStep, PathStepV2, PathV2, PermanentMapEdits, Position, RoadID,
+    TravelTimeProfiles, Traversable,
+};
+use sim::{
+    DockSpec, IndividTrip, MicromobilityFleet, MicromobilityVehicle, PersonSpec, Scenario,
+    TripEndpoint, TripID, TripMode, TripPurpose,
+};
 
 fn main() -> Result<()> {
     test_lane_changing(&import_map(abstio::path(
         "../tests/input/lane_selection.osm",
     )))?;
+    test_time_of_day_routing(import_map(abstio::path(
+        "../tests/input/parallel_routes.osm",
+    )))?;
+    test_parking_search_gives_up(&import_map(abstio::path(
+        "../tests/input/micromobility.osm",
+    )))?;
+    test_traffic_assignment(import_map(abstio::path(
+        "../tests/input/parallel_routes.osm",
+    )))?;
+    test_micromobility_rebalancing(&import_map(abstio::path(
+        "../tests/input/micromobility.osm",
+    )))?;
+    test_structural_edits_round_trip(import_map(abstio::path(
+        "../tests/input/parallel_routes.osm",
+    )))?;
+    test_broken_structural_edits(import_map(abstio::path(
+        "../tests/input/parallel_routes.osm",
+    )))?;
+    test_validate_closed_intersection(import_map(abstio::path(
+        "../tests/input/parallel_routes.osm",
+    )))?;
+    test_validate_bus_routes(import_map(abstio::path("../tests/input/light_rail.osm")))?;
+    test_light_rail(import_map(abstio::path("../tests/input/light_rail.osm")))?;
+    test_transit_schedules(import_map(abstio::path("../tests/input/light_rail.osm")))?;
     test_map_importer()?;
     check_proposals()?;
     smoke_test()?;
@@ -63,6 +96,7 @@ fn import_map(path: String) -> Map {
             private_offstreet_parking: convert_osm::PrivateOffstreetParking::FixedPerBldg(0),
             include_railroads: true,
             extra_buildings: None,
+            elevation: None,
             skip_local_roads: false,
         },
         &mut timer,
@@ -164,6 +198,710 @@ fn check_proposals() -> Result<()> {
     Ok(())
 }
 
+/// Verify that travel times by time of day make cars avoid a route only when it's congested.
+fn test_time_of_day_routing(map: Map) -> Result<()> {
+    let night = Time::START_OF_DAY + Duration::hours(3);
+    let rush_hour = Time::START_OF_DAY + Duration::hours(17);
+
+    let mut num_changed = 0;
+    for from in map.all_incoming_borders() {
+        for to in map.all_outgoing_borders() {
+            if from.id == to.id {
+                continue;
+            }
+            let start = from.get_outgoing_lanes(&map, PathConstraints::Car)[0];
+            let end = to.get_incoming_lanes(&map, PathConstraints::Car)[0];
+            let req = PathRequest::vehicle(
+                Position::start(start),
+                Position::end(end, &map),
+                PathConstraints::Car,
+            );
+            let usual = directed_roads(&map.pathfind_v2(req.clone())?);
+            // Every route has to use the first and last road
+            if usual.len() <= 2 {
+                continue;
+            }
+
+            // Make everything else along the usual route very slow, but just at rush hour
+            let mut profiles = TravelTimeProfiles::new(Duration::hours(1));
+            let bin = profiles.bin(rush_hour);
+            for dr in &usual[1..usual.len() - 1] {
+                profiles
+                    .roads
+                    .insert(*dr, vec![(bin, Duration::hours(1))].into_iter().collect());
+            }
+            let mut map_with_profiles = map.clone();
+            map_with_profiles.set_travel_time_profiles(Some(profiles));
+            let at_night =
+                directed_roads(&map_with_profiles.pathfind_v2(req.clone().with_departure(night))?);
+            let at_rush_hour =
+                directed_roads(&map_with_profiles.pathfind_v2(req.with_departure(rush_hour))?);
+            if at_night != usual {
+                anyhow::bail!(
+                    "Route from {} to {} at night is {:?}, but should be {:?}",
+                    from.id,
+                    to.id,
+                    at_night,
+                    usual
+                );
+            }
+            if at_rush_hour != usual {
+                num_changed += 1;
+            }
+        }
+    }
+    // There are two ways to go west to east
+    if num_changed == 0 {
+        anyhow::bail!("No route changed at rush hour");
+    }
+    Ok(())
+}
+
+/// Send more cars to one building than the whole map can park. Once everything nearby is full, the
+/// last drivers circle around until their search budget runs out, then give up.
+fn test_parking_search_gives_up(map: &Map) -> Result<()> {
+    let mut opts = sim::SimOptions::new("test_parking_search_gives_up");
+    opts.alerts = sim::AlertHandler::Silence;
+    opts.parking_search = sim::ParkingSearchParams {
+        max_walking_distance: Some(Distance::meters(100.0)),
+        time_budget: Duration::minutes(2),
+    };
+    let num_spots = {
+        let (filled, avail) = sim::Sim::new(map, opts.clone()).get_all_parking_spots();
+        filled.len() + avail.len()
+    };
+
+    let from = map.all_incoming_borders()[0].id;
+    let to = map.all_buildings()[0].id;
+    let mut scenario = Scenario::empty(map, "parking_search");
+    for idx in 0..num_spots + 5 {
+        scenario.people.push(PersonSpec {
+            orig_id: None,
+            trips: vec![IndividTrip::new(
+                Time::START_OF_DAY + Duration::seconds(5.0 * idx as f64),
+                TripPurpose::Shopping,
+                TripEndpoint::Border(from),
+                TripEndpoint::Bldg(to),
+                TripMode::Drive,
+            )],
+        });
+    }
+
+    let mut sim = sim::Sim::new(map, opts);
+    let mut rng = sim::SimFlags::for_test("test_parking_search_gives_up").make_rng();
+    let mut timer = Timer::throwaway();
+    scenario.instantiate(&mut sim, map, &mut rng, &mut timer);
+    while !sim.is_done() {
+        sim.tiny_step(map, &mut None);
+    }
+
+    let gave_up: Vec<_> = sim
+        .get_analytics()
+        .parking_searches
+        .iter()
+        .filter(|(_, _, _, _, found)| !found)
+        .collect();
+    if gave_up.is_empty() {
+        anyhow::bail!("Nobody gave up looking for parking");
+    }
+    for (_, trip, dt, dist, _) in gave_up {
+        if *dt < Duration::minutes(2) || *dist == Distance::ZERO {
+            anyhow::bail!(
+                "{} gave up on parking after {} and {}, without circling for the whole budget",
+                trip,
+                dt,
+                dist
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Send a rush of cars west to east. A driver pinned to a route must take it, and letting drivers
+/// switch routes against observed travel times must shrink the gap from the best routes.
+fn test_traffic_assignment(map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let mut borders: Vec<_> = map.all_incoming_borders();
+    borders.sort_by_key(|i| i.polygon.center().x() as isize);
+    let west = borders[0].id;
+    let mut borders: Vec<_> = map.all_outgoing_borders();
+    borders.sort_by_key(|i| i.polygon.center().x() as isize);
+    let east = borders[borders.len() - 1].id;
+
+    let mut scenario = Scenario::empty(&map, "traffic_assignment");
+    for idx in 0..200 {
+        scenario.people.push(PersonSpec {
+            orig_id: None,
+            trips: vec![IndividTrip::new(
+                Time::START_OF_DAY + Duration::seconds(idx as f64),
+                TripPurpose::Work,
+                TripEndpoint::Border(west),
+                TripEndpoint::Border(east),
+                TripMode::Drive,
+            )],
+        });
+    }
+
+    let run_day = |fixed: BTreeMap<TripID, Path>| {
+        let mut opts = sim::SimOptions::new("test_traffic_assignment");
+        opts.alerts = sim::AlertHandler::Silence;
+        let mut sim = sim::Sim::new(&map, opts);
+        sim.set_fixed_routes(fixed);
+        let mut rng = sim::SimFlags::for_test("test_traffic_assignment").make_rng();
+        scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+        sim.timed_step(&map, Duration::hours(1), &mut None, &mut Timer::throwaway());
+        sim.take_chosen_routes()
+    };
+
+    // Find some other route for the first driver by making their usual route very slow
+    let usual = run_day(BTreeMap::new());
+    let (trip, usual_path) = usual.into_iter().next().unwrap();
+    let mut slow = ObservedTravelTimes::new();
+    let roads: Vec<DirectedRoadID> = usual_path
+        .get_steps()
+        .iter()
+        .filter_map(|step| match step {
+            PathStep::Lane(l) => Some(map.get_l(*l).get_directed_parent()),
+            _ => None,
+        })
+        .collect();
+    for dr in &roads[1..roads.len() - 1] {
+        slow.roads.insert(*dr, Duration::hours(1));
+    }
+    let mut slow_map = map.clone();
+    slow_map.set_observed_travel_times(Some(slow), &mut timer);
+    let detour = slow_map.pathfind(usual_path.get_req().clone())?;
+    if detour == usual_path {
+        anyhow::bail!("No alternate route for {}", trip);
+    }
+    let chosen = run_day(vec![(trip, detour.clone())].into_iter().collect());
+    if chosen[&trip] != detour {
+        anyhow::bail!("{} didn't stick to the fixed route", trip);
+    }
+
+    let mut flags = sim::SimFlags::for_test("test_traffic_assignment");
+    flags.opts.alerts = sim::AlertHandler::Silence;
+    let assignment = sim::TrafficAssignment {
+        flags,
+        max_iterations: 5,
+        replan_fraction: 0.3,
+        // Run every iteration
+        gap_threshold: f64::NEG_INFINITY,
+    };
+    let results = assignment.assign(&mut map.clone(), &scenario, &mut timer)?;
+    let gaps: Vec<f64> = results
+        .iterations
+        .iter()
+        .map(|iter| iter.relative_gap.unwrap())
+        .collect();
+    // Everybody starts on the same route, so the first iteration is congested
+    if !(gaps[0] > 0.0 && gaps[gaps.len() - 1] < gaps[0]) {
+        anyhow::bail!("Relative gaps didn't shrink: {:?}", gaps);
+    }
+    Ok(())
+}
+
+/// Ride a shared bike from one dock to the other, filling it up, then verify the next rebalancing
+/// moves a bike back.
+fn test_micromobility_rebalancing(map: &Map) -> Result<()> {
+    let mut bldgs: Vec<_> = map.all_buildings().iter().collect();
+    bldgs.sort_by_key(|b| b.polygon.center().x() as isize);
+    let (west, east) = (bldgs[0].id, bldgs[bldgs.len() - 1].id);
+
+    let mut scenario = Scenario::empty(map, "micromobility");
+    scenario.people.push(PersonSpec {
+        orig_id: None,
+        trips: vec![IndividTrip::new(
+            Time::START_OF_DAY + Duration::minutes(1),
+            TripPurpose::Work,
+            TripEndpoint::Bldg(west),
+            TripEndpoint::Bldg(east),
+            TripMode::Micromobility,
+        )],
+    });
+    scenario.micromobility = Some(MicromobilityFleet {
+        vehicle: MicromobilityVehicle::Bike,
+        docks: vec![
+            DockSpec {
+                building: west,
+                capacity: 2,
+                initial_vehicles: 1,
+            },
+            DockSpec {
+                building: east,
+                capacity: 2,
+                initial_vehicles: 1,
+            },
+        ],
+        free_floating: Vec::new(),
+        max_walk: Distance::meters(50.0),
+        rebalance_every: Some(Duration::hours(1)),
+    });
+
+    let mut opts = sim::SimOptions::new("test_micromobility_rebalancing");
+    opts.alerts = sim::AlertHandler::Silence;
+    let mut sim = sim::Sim::new(map, opts);
+    let mut rng = sim::SimFlags::for_test("test_micromobility_rebalancing").make_rng();
+    let mut timer = Timer::throwaway();
+    scenario.instantiate(&mut sim, map, &mut rng, &mut timer);
+
+    // The trip is done well before the first rebalancing
+    sim.timed_step(map, Duration::minutes(30), &mut None, &mut timer);
+    let analytics = sim.get_analytics();
+    if analytics.micromobility_pickups.len() != 1 || analytics.micromobility_dropoffs.len() != 1 {
+        anyhow::bail!(
+            "Expected one pickup and dropoff, got {:?} and {:?}",
+            analytics.micromobility_pickups,
+            analytics.micromobility_dropoffs
+        );
+    }
+    let docks = sim.micromobility_dock_status();
+    if docks[&west] != (0, 2) || docks[&east] != (2, 2) {
+        anyhow::bail!("After the trip, docks are {:?}", docks);
+    }
+
+    sim.timed_step(map, Duration::hours(1), &mut None, &mut timer);
+    let docks = sim.micromobility_dock_status();
+    if docks[&west] != (1, 2) || docks[&east] != (1, 2) {
+        anyhow::bail!("After rebalancing, docks are {:?}", docks);
+    }
+    if sim.get_analytics().micromobility_rebalanced.is_empty() {
+        anyhow::bail!("Rebalancing wasn't recorded");
+    }
+    Ok(())
+}
+
+/// Change a road, split it, and make everybody stop at the new intersection. Saving and loading
+/// those edits, both onto the basemap and onto the already edited map, must reproduce them.
+fn test_structural_edits_round_trip(mut map: Map) -> Result<()> {
+    let basemap = map.clone();
+    let mut timer = Timer::throwaway();
+    let r = map
+        .all_roads()
+        .max_by_key(|r| r.untrimmed_center_pts.length())
+        .unwrap()
+        .id;
+    let speed_limit = Speed::miles_per_hour(10.0);
+
+    let mut edits = map.get_edits().clone();
+    edits
+        .commands
+        .push(map.edit_road_cmd(r, |new| new.speed_limit = speed_limit));
+    map.must_apply_edits(edits, &mut timer);
+
+    let split = map.split_road_cmd(r, map.get_r(r).untrimmed_center_pts.length() / 2.0)?;
+    let (new_i, new_r) = match split {
+        EditCmd::SplitRoad { new_i, new_r, .. } => (new_i, new_r),
+        _ => unreachable!(),
+    };
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(split);
+    map.must_apply_edits(edits, &mut timer);
+
+    // Pathfinding reuses the old node ordering, with nodes for the new road ordered last
+    map.recalculate_pathfinding_after_edits(&mut timer);
+    let first_half = map
+        .get_r(r)
+        .lanes
+        .iter()
+        .find(|l| l.is_driving())
+        .ok_or_else(|| anyhow::anyhow!("{} has no driving lanes", r))?;
+    let second_half = map
+        .get_r(new_r)
+        .lanes
+        .iter()
+        .find(|l| l.is_driving() && l.dir == first_half.dir)
+        .unwrap();
+    let (start, end) = if first_half.dst_i == new_i {
+        (first_half, second_half)
+    } else {
+        (second_half, first_half)
+    };
+    map.pathfind(PathRequest::vehicle(
+        Position::start(start.id),
+        Position::end(end.id, &map),
+        PathConstraints::Car,
+    ))?;
+
+    let mut ss = map.get_stop_sign(new_i).clone();
+    for road in ss.roads.values_mut() {
+        road.must_stop = true;
+    }
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(EditCmd::ChangeIntersection {
+        i: new_i,
+        old: map.get_i_edit(new_i),
+        new: EditIntersection::StopSign(ss.clone()),
+    });
+    map.must_apply_edits(edits, &mut timer);
+
+    // This is what save_edits writes
+    let mut compressed = map.get_edits().clone();
+    compressed.compress();
+    let json = abstutil::to_json(&compressed.to_permanent(&map));
+
+    for (name, mut loaded) in [("the basemap", basemap), ("the edited map", map)] {
+        let perma: PermanentMapEdits = abstutil::from_json(json.as_bytes())?;
+        let edits = perma.into_edits(&loaded)?;
+        loaded.must_apply_edits(edits, &mut timer);
+        if loaded.get_r(new_r).speed_limit != speed_limit {
+            anyhow::bail!(
+                "Loading onto {}, the second half of the split road lost its speed limit",
+                name
+            );
+        }
+        if loaded.get_stop_sign(new_i) != &ss {
+            anyhow::bail!("Loading onto {}, the stop sign at {} changed", name, new_i);
+        }
+    }
+    Ok(())
+}
+
+/// Deleted roads and their lanes disappear from the map, and a split whose IDs don't match the map
+/// is dropped instead of corrupting it.
+fn test_broken_structural_edits(mut map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let r = map
+        .all_roads()
+        .max_by_key(|r| r.untrimmed_center_pts.length())
+        .unwrap()
+        .id;
+
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(map.delete_road_cmd(r)?);
+    map.must_apply_edits(edits, &mut timer);
+    if map.all_roads().any(|road| road.id == r) {
+        anyhow::bail!("{} was deleted, but all_roads still has it", r);
+    }
+    if map.all_lanes().any(|lane| lane.id.road == r) {
+        anyhow::bail!("{} was deleted, but all_lanes still has its lanes", r);
+    }
+
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(EditCmd::SplitRoad {
+        r: map.all_roads().next().unwrap().id,
+        dist: Distance::meters(10.0),
+        new_i: IntersectionID(0),
+        new_r: RoadID(0),
+    });
+    map.try_apply_edits(edits, &mut timer);
+    if map.get_edits().commands.len() != 1 {
+        anyhow::bail!("A split reusing existing IDs wasn't dropped");
+    }
+    Ok(())
+}
+
+/// Every intersection with 3 roads has one going to a border, so closing it strands that road's
+/// sidewalks. Edits doing that are rejected, but fine edits stay applied when asked.
+fn test_validate_closed_intersection(mut map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let i = map
+        .all_intersections()
+        .iter()
+        .find(|i| i.roads.len() >= 3)
+        .unwrap()
+        .id;
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(EditCmd::ChangeIntersection {
+        i,
+        old: map.get_i_edit(i),
+        new: EditIntersection::Closed,
+    });
+    let report = map.apply_edits_if_valid(edits, &mut timer);
+    if report.is_ok()
+        || !report
+            .errors()
+            .any(|p| p.rule == "sidewalk_connectivity" && !p.affected.is_empty())
+    {
+        anyhow::bail!("Closing {} didn't disconnect sidewalks: {:?}", i, report);
+    }
+    if !map.get_edits().commands.is_empty() {
+        anyhow::bail!("Rejected edits weren't reverted");
+    }
+
+    let mut edits = map.get_edits().clone();
+    let r = map.all_roads().next().unwrap().id;
+    edits.commands.push(map.edit_road_cmd(r, |new| {
+        new.speed_limit = Speed::miles_per_hour(10.0);
+    }));
+    let report = map.apply_edits_if_valid(edits, &mut timer);
+    if !report.problems.is_empty() {
+        anyhow::bail!("Lowering a speed limit caused problems: {:?}", report);
+    }
+    if map.get_edits().commands.len() != 1 {
+        anyhow::bail!("Valid edits weren't kept");
+    }
+    Ok(())
+}
+
+/// Turning the track at a Red Line stop into a driving lane leaves the train nowhere to stop. Once
+/// those edits are made, the broken route isn't reported again for unrelated edits.
+fn test_validate_bus_routes(mut map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let red = map
+        .get_bus_route("Red Line")
+        .ok_or_else(|| anyhow::anyhow!("Red Line wasn't imported"))?
+        .clone();
+    let r = map
+        .get_parent(map.get_bs(red.stops[1]).driving_pos.lane())
+        .id;
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(map.edit_road_cmd(r, |new| {
+        for spec in &mut new.lanes_ltr {
+            if spec.lt == LaneType::LightRail {
+                spec.lt = LaneType::Driving;
+            }
+        }
+    }));
+
+    let report = map.validate_edits(edits.clone(), &mut timer);
+    if !report
+        .errors()
+        .any(|p| p.rule == "bus_routes" && p.affected.contains(&AffectedID::BusRoute(red.id)))
+    {
+        anyhow::bail!(
+            "Removing the track at {} didn't break the Red Line: {:?}",
+            r,
+            report
+        );
+    }
+
+    map.must_apply_edits(edits, &mut timer);
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(EditCmd::ChangeRouteSchedule {
+        id: red.id,
+        old: red.spawn_times.clone(),
+        new: vec![Time::START_OF_DAY],
+    });
+    let report = map.validate_edits(edits, &mut timer);
+    if report.problems.iter().any(|p| p.rule == "bus_routes") {
+        anyhow::bail!(
+            "The already broken Red Line was reported again: {:?}",
+            report
+        );
+    }
+    Ok(())
+}
+
+/// Two light rail lines meet at a station, each with its own platform. Check the station is
+/// grouped and reachable from the nearby sidewalks, that a trip across the map transfers there,
+/// and that two trains on the same line never occupy the same block of track.
+fn test_light_rail(mut map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let red = map
+        .get_bus_route("Red Line")
+        .ok_or_else(|| anyhow::anyhow!("Red Line wasn't imported"))?
+        .clone();
+    let blue = map
+        .get_bus_route("Blue Line")
+        .ok_or_else(|| anyhow::anyhow!("Blue Line wasn't imported"))?
+        .clone();
+    let (west, red_central) = (red.stops[0], red.stops[1]);
+    let (blue_central, east) = (blue.stops[0], blue.stops[1]);
+
+    if map.all_transit_stations().len() != 3 {
+        anyhow::bail!("Expected 3 stations, got {:?}", map.all_transit_stations());
+    }
+    let central = map
+        .get_station_for_stop(red_central)
+        .ok_or_else(|| anyhow::anyhow!("{} isn't part of a station", red_central))?;
+    if central.platforms.len() != 2 || !central.platforms.contains(&blue_central) {
+        anyhow::bail!("Central Station has platforms {:?}", central.platforms);
+    }
+    let platform_roads: BTreeSet<RoadID> = central
+        .platforms
+        .iter()
+        .map(|bs| map.get_parent(map.get_bs(*bs).sidewalk_pos.lane()).id)
+        .collect();
+    if !central
+        .entrances
+        .iter()
+        .any(|pos| !platform_roads.contains(&map.get_parent(pos.lane()).id))
+    {
+        anyhow::bail!(
+            "Central Station can only be entered from its platforms: {:?}",
+            central.entrances
+        );
+    }
+
+    // Going from one end to the other, ride the Red Line and transfer to the Blue Line
+    let rides = map
+        .should_use_transit(map.get_bs(west).sidewalk_pos, map.get_bs(east).sidewalk_pos)
+        .ok_or_else(|| anyhow::anyhow!("Walking across the map doesn't use transit"))?;
+    let actual: Vec<_> = rides
+        .iter()
+        .map(|ride| (ride.route, ride.board, ride.alight))
+        .collect();
+    let expected = vec![
+        (red.id, west, Some(red_central)),
+        (blue.id, blue_central, Some(east)),
+    ];
+    if actual != expected {
+        anyhow::bail!("Rides are {:?}, but should be {:?}", actual, expected);
+    }
+
+    // Send two Red Line trains right after each other
+    let mut edits = map.get_edits().clone();
+    edits.commands.push(EditCmd::ChangeRouteSchedule {
+        id: red.id,
+        old: red.spawn_times.clone(),
+        new: vec![
+            Time::START_OF_DAY,
+            Time::START_OF_DAY + Duration::seconds(5.0),
+        ],
+    });
+    map.must_apply_edits(edits, &mut timer);
+    map.recalculate_pathfinding_after_edits(&mut timer);
+
+    let mut scenario = Scenario::empty(&map, "light_rail");
+    scenario.only_seed_buses = Some(vec![red.full_name.clone()].into_iter().collect());
+    let mut opts = sim::SimOptions::new("test_light_rail");
+    opts.alerts = sim::AlertHandler::Silence;
+    let mut sim = sim::Sim::new(&map, opts);
+    let mut rng = sim::SimFlags::for_test("test_light_rail").make_rng();
+    scenario.instantiate(&mut sim, &map, &mut rng, &mut timer);
+
+    // The second train spawns behind the first, but has to wait for the first to clear the block
+    // of track with Central Station before entering it.
+    let block = Traversable::Lane(map.get_bs(red_central).driving_pos.lane());
+    for _ in 0..600 {
+        sim.timed_step(&map, Duration::seconds(1.0), &mut None, &mut timer);
+        let trains_in_block = sim
+            .get_draw_cars(block, &map)
+            .into_iter()
+            .filter(|car| car.id.vehicle_type == sim::VehicleType::Train)
+            .count();
+        if trains_in_block > 1 {
+            anyhow::bail!(
+                "At {}, {} trains are on {}",
+                sim.time(),
+                trains_in_block,
+                block
+            );
+        }
+    }
+    let arrivals = sim
+        .get_analytics()
+        .bus_arrivals
+        .iter()
+        .filter(|(_, _, _, bs)| *bs == red_central)
+        .count();
+    if arrivals != 2 {
+        anyhow::bail!("{} trains reached Central Station, but 2 should", arrivals);
+    }
+    Ok(())
+}
+
+/// Plan trips that depend on when trains actually leave. The only Red Line train leaves at
+/// midnight, and Blue Line trains leave at midnight, 00:02, and 00:07.
+fn test_transit_schedules(mut map: Map) -> Result<()> {
+    let mut timer = Timer::throwaway();
+    let red = map
+        .get_bus_route("Red Line")
+        .ok_or_else(|| anyhow::anyhow!("Red Line wasn't imported"))?
+        .clone();
+    let blue = map
+        .get_bus_route("Blue Line")
+        .ok_or_else(|| anyhow::anyhow!("Blue Line wasn't imported"))?
+        .clone();
+    let (west, red_central) = (red.stops[0], red.stops[1]);
+    let (blue_central, east) = (blue.stops[0], blue.stops[1]);
+    let at = |mins| Time::START_OF_DAY + Duration::minutes(mins);
+
+    let mut edits = map.get_edits().clone();
+    for (route, mins) in [(&red, vec![0]), (&blue, vec![0, 2, 7])] {
+        edits.commands.push(EditCmd::ChangeRouteSchedule {
+            id: route.id,
+            old: route.spawn_times.clone(),
+            new: mins.into_iter().map(at).collect(),
+        });
+    }
+    map.must_apply_edits(edits, &mut timer);
+    map.recalculate_pathfinding_after_edits(&mut timer);
+    let start = map.get_bs(west).sidewalk_pos;
+    let end = map.get_bs(east).sidewalk_pos;
+
+    // Leaving at midnight, ride the Red Line and transfer. The first Blue Line train leaves Central
+    // Station before the Red Line train arrives, so wait for the second one.
+    let itinerary = map
+        .plan_transit_trip(start, end, at(0))
+        .ok_or_else(|| anyhow::anyhow!("Leaving at midnight doesn't use transit"))?;
+    let rides = itinerary_rides(&itinerary);
+    if rides.len() != 2
+        || rides[0].0 != (red.id, west, Some(red_central))
+        || rides[1].0 != (blue.id, blue_central, Some(east))
+        || rides[1].1 < at(2)
+        || rides[1].1 >= at(7)
+    {
+        anyhow::bail!("Leaving at midnight, the itinerary is {:?}", itinerary);
+    }
+    let transfers: Vec<_> = itinerary
+        .legs
+        .iter()
+        .filter_map(|leg| match leg {
+            ItineraryLeg::Transfer { from, to, .. } => Some((*from, *to)),
+            _ => None,
+        })
+        .collect();
+    if transfers != vec![(red_central, blue_central)] {
+        anyhow::bail!("Leaving at midnight, the transfers are {:?}", transfers);
+    }
+    let routes: Vec<_> = itinerary
+        .rides(start, &map)
+        .into_iter()
+        .map(|ride| ride.route)
+        .collect();
+    if routes != vec![red.id, blue.id] {
+        anyhow::bail!("Leaving at midnight, the rides are on {:?}", routes);
+    }
+
+    // The Red Line train is gone by 00:03, but walking to Central Station and catching the last
+    // Blue Line train still beats walking the whole way.
+    let itinerary = map
+        .plan_transit_trip(start, end, at(3))
+        .ok_or_else(|| anyhow::anyhow!("Leaving at 00:03 doesn't use transit"))?;
+    let rides = itinerary_rides(&itinerary);
+    if rides.len() != 1 || rides[0].0 != (blue.id, blue_central, Some(east)) || rides[0].1 < at(7) {
+        anyhow::bail!("Leaving at 00:03, the itinerary is {:?}", itinerary);
+    }
+
+    // After the last train, just walk
+    if let Some(itinerary) = map.plan_transit_trip(start, end, at(8)) {
+        anyhow::bail!("Leaving at 00:08, the itinerary is {:?}", itinerary);
+    }
+    Ok(())
+}
+
+/// ((route, board, alight), depart) for every ride
+fn itinerary_rides(
+    itinerary: &Itinerary,
+) -> Vec<((BusRouteID, BusStopID, Option<BusStopID>), Time)> {
+    itinerary
+        .legs
+        .iter()
+        .filter_map(|leg| match leg {
+            ItineraryLeg::Ride {
+                route,
+                board,
+                alight,
+                depart,
+                ..
+            } => Some(((*route, *board, *alight), *depart)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn directed_roads(path: &PathV2) -> Vec<DirectedRoadID> {
+    path.get_steps()
+        .iter()
+        .filter_map(|step| match step {
+            PathStepV2::Along(dr) => Some(*dr),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Verify lane-chaging behavior is overall reasonable, by asserting all cars and bikes can
 /// complete their trip under a time limit.
 fn test_lane_changing(map: &Map) -> Result<()> {
@@ -15,6 +15,22 @@ pub struct TrafficSignal {
     /// order of ascending `start_time_seconds`, the first plan must begin at `0` (midnight), and
     /// the last plan must not start after 24 hours.
     pub plans: Vec<Plan>,
+    /// If present, buses and trains approaching the signal can change its timing.
+    #[serde(default)]
+    pub transit_priority: Option<TransitPriority>,
+}
+
+/// Transit signal priority lets a bus or train approaching the intersection hold its green a
+/// little longer, or end a conflicting stage early so its turn comes up sooner.
+///
+/// There's no equivalent for emergency vehicle preemption, since the simulation doesn't have
+/// emergency vehicles.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransitPriority {
+    /// A green can be extended by at most this many seconds for an approaching vehicle.
+    pub max_green_extension_seconds: usize,
+    /// A conflicting stage must last at least this many seconds before it can be ended early.
+    pub min_stage_before_early_green_seconds: usize,
 }
 
 /// A plan describes how a traffic signal is configured during some period of time. Multiple plans